@@ -0,0 +1,280 @@
+//! A builder gathering every construction option of a `TtlCache` in one place.
+
+use std::collections::hash_map::RandomState;
+use std::error::Error;
+use std::fmt;
+use std::hash::{BuildHasher, Hash};
+use std::sync::Arc;
+use std::time::Duration;
+
+use expiry::ExpiryIndex;
+use notify::Notifier;
+use {Clock, ExpiryPolicy, PurgePolicy, RemovalCause, TtlCache};
+
+/// Builds a `TtlCache` with any mix of the options its setters offer, checked all at once by
+/// `build`.  Options left unset keep the defaults of `TtlCache::new`.
+///
+/// # Examples
+///
+/// ```
+/// use std::time::Duration;
+/// use ttl_cache::test_util::MockClock;
+/// use ttl_cache::TtlCacheBuilder;
+///
+/// let clock = MockClock::new();
+/// let mut cache = TtlCacheBuilder::new()
+///     .capacity(100)
+///     .default_ttl(Duration::from_secs(30))
+///     .clock(clock.clone())
+///     .build()
+///     .unwrap();
+///
+/// cache.insert_default("a", 1);
+/// clock.advance(Duration::from_secs(30));
+/// assert_eq!(cache.get("a"), None);
+/// assert_eq!(cache.capacity(), Some(100));
+/// ```
+pub struct TtlCacheBuilder<K, V, S = RandomState> {
+    hash_builder: S,
+    clock: Option<Arc<dyn Clock>>,
+    capacity: Option<usize>,
+    default_ttl: Option<Duration>,
+    max_ttl: Option<Duration>,
+    time_to_idle: Option<Duration>,
+    stale_grace: Option<Duration>,
+    /// The fraction, and the seed if it's fixed
+    jitter: Option<(f64, Option<u64>)>,
+    purge_policy: PurgePolicy,
+    timer_wheel: Option<Duration>,
+    expiry_policy: Option<Arc<dyn ExpiryPolicy<K, V>>>,
+    notifier: Notifier<K>,
+    #[cfg(feature = "stats")]
+    stats: bool,
+}
+
+impl<K, V> TtlCacheBuilder<K, V> {
+    /// Starts a builder with every option at its default.
+    pub fn new() -> Self {
+        TtlCacheBuilder {
+            hash_builder: RandomState::new(),
+            clock: None,
+            capacity: None,
+            default_ttl: None,
+            max_ttl: None,
+            time_to_idle: None,
+            stale_grace: None,
+            jitter: None,
+            purge_policy: PurgePolicy::default(),
+            timer_wheel: None,
+            expiry_policy: None,
+            notifier: Notifier::default(),
+            #[cfg(feature = "stats")]
+            stats: true,
+        }
+    }
+}
+
+/// Starts a builder with every option at its default
+impl<K, V> Default for TtlCacheBuilder<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K, V, S> TtlCacheBuilder<K, V, S> {
+    /// Hashes keys with `hash_builder`, see `TtlCache::with_hasher`.
+    pub fn hasher<T: BuildHasher>(self, hash_builder: T) -> TtlCacheBuilder<K, V, T> {
+        TtlCacheBuilder {
+            hash_builder,
+            clock: self.clock,
+            capacity: self.capacity,
+            default_ttl: self.default_ttl,
+            max_ttl: self.max_ttl,
+            time_to_idle: self.time_to_idle,
+            stale_grace: self.stale_grace,
+            jitter: self.jitter,
+            purge_policy: self.purge_policy,
+            timer_wheel: self.timer_wheel,
+            expiry_policy: self.expiry_policy,
+            notifier: self.notifier,
+            #[cfg(feature = "stats")]
+            stats: self.stats,
+        }
+    }
+
+    /// Takes the current time from `clock`, see `TtlCache::with_clock`.
+    pub fn clock<C: Clock + 'static>(mut self, clock: C) -> Self {
+        self.clock = Some(Arc::new(clock));
+        self
+    }
+
+    /// Bounds the cache to `capacity` entries, see `TtlCache::set_capacity`.
+    pub fn capacity(mut self, capacity: usize) -> Self {
+        self.capacity = Some(capacity);
+        self
+    }
+
+    /// Sets the TTL used by `insert_default`, see `TtlCache::set_default_ttl`.
+    pub fn default_ttl(mut self, ttl: Duration) -> Self {
+        self.default_ttl = Some(ttl);
+        self
+    }
+
+    /// Caps every TTL the cache applies, see `TtlCache::set_max_ttl`.
+    pub fn max_ttl(mut self, max_ttl: Duration) -> Self {
+        self.max_ttl = Some(max_ttl);
+        self
+    }
+
+    /// Expires entries that go unaccessed for `time_to_idle`, see
+    /// `TtlCache::set_time_to_idle`.
+    pub fn time_to_idle(mut self, time_to_idle: Duration) -> Self {
+        self.time_to_idle = Some(time_to_idle);
+        self
+    }
+
+    /// Keeps expired entries around for `grace`, see `TtlCache::set_stale_grace`.
+    pub fn stale_grace(mut self, grace: Duration) -> Self {
+        self.stale_grace = Some(grace);
+        self
+    }
+
+    /// Randomizes TTLs by up to `fraction`, see `TtlCache::set_ttl_jitter`.  `build` fails
+    /// unless `fraction` is between 0.0 and 1.0.
+    pub fn ttl_jitter(mut self, fraction: f64) -> Self {
+        self.jitter = Some((fraction, None));
+        self
+    }
+
+    /// Like `ttl_jitter`, with a fixed seed, see `TtlCache::set_ttl_jitter_with_seed`.
+    pub fn ttl_jitter_with_seed(mut self, fraction: f64, seed: u64) -> Self {
+        self.jitter = Some((fraction, Some(seed)));
+        self
+    }
+
+    /// Sets when inserts purge expired entries, see `TtlCache::set_purge_policy`.  `build`
+    /// fails on `PurgePolicy::EveryNInserts(0)`.
+    pub fn purge_policy(mut self, policy: PurgePolicy) -> Self {
+        self.purge_policy = policy;
+        self
+    }
+
+    /// Tracks expirations with a timer wheel of the given resolution, see
+    /// `TtlCache::with_timer_wheel`.  `build` fails if `resolution` is zero.
+    pub fn timer_wheel(mut self, resolution: Duration) -> Self {
+        self.timer_wheel = Some(resolution);
+        self
+    }
+
+    /// Takes TTLs from `policy` wherever no TTL is given, see `TtlCache::set_expiry_policy`.
+    pub fn expiry_policy<P: ExpiryPolicy<K, V> + 'static>(mut self, policy: P) -> Self {
+        self.expiry_policy = Some(Arc::new(policy));
+        self
+    }
+
+    /// Calls `listener` with every entry leaving the cache, see
+    /// `TtlCache::set_eviction_listener`.
+    pub fn eviction_listener<F>(mut self, listener: F) -> Self
+    where
+        F: Fn(K, RemovalCause) + Send + Sync + 'static,
+    {
+        self.notifier = Notifier::listener(listener);
+        self
+    }
+
+    /// Whether the cache starts out counting, see `TtlCache::disable_stats`.  It does unless
+    /// told otherwise.
+    #[cfg(feature = "stats")]
+    pub fn stats(mut self, enabled: bool) -> Self {
+        self.stats = enabled;
+        self
+    }
+}
+
+impl<K: Eq + Hash, V, S: BuildHasher> TtlCacheBuilder<K, V, S> {
+    /// Creates the cache, or says which option is invalid.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ttl_cache::{BuildError, TtlCache};
+    ///
+    /// let built = TtlCache::<i32, i32>::builder().ttl_jitter(1.5).build();
+    /// assert_eq!(built.err(), Some(BuildError::TtlJitter(1.5)));
+    /// ```
+    pub fn build(self) -> Result<TtlCache<K, V, S>, BuildError> {
+        if let Some((fraction, _)) = self.jitter {
+            if !(0.0..=1.0).contains(&fraction) {
+                return Err(BuildError::TtlJitter(fraction));
+            }
+        }
+        if self.purge_policy == PurgePolicy::EveryNInserts(0) {
+            return Err(BuildError::PurgePeriod);
+        }
+        if self.timer_wheel == Some(Duration::ZERO) {
+            return Err(BuildError::WheelResolution);
+        }
+
+        let mut cache = TtlCache::with_hasher(self.hash_builder);
+        if let Some(clock) = self.clock {
+            cache.use_clock(clock);
+        }
+        if let Some(resolution) = self.timer_wheel {
+            cache.index = ExpiryIndex::timer_wheel(resolution, cache.config.now());
+        }
+        cache.config.default_ttl = self.default_ttl;
+        cache.config.max_ttl = self.max_ttl;
+        cache.config.time_to_idle = self.time_to_idle;
+        cache.config.stale_grace = self.stale_grace;
+        match self.jitter {
+            Some((fraction, Some(seed))) => cache.set_ttl_jitter_with_seed(fraction, seed),
+            Some((fraction, None)) => cache.set_ttl_jitter(fraction),
+            None => {}
+        }
+        cache.config.purge_policy = self.purge_policy;
+        cache.capacity = self.capacity;
+        cache.policy = self.expiry_policy;
+        cache.notifier = self.notifier;
+        #[cfg(feature = "stats")]
+        {
+            if !self.stats {
+                cache.disable_stats();
+            }
+        }
+        Ok(cache)
+    }
+
+    /// `build` for the constructors, which panic on invalid options as they always have
+    pub(crate) fn build_or_panic(self) -> TtlCache<K, V, S> {
+        self.build().unwrap_or_else(|err| panic!("{}", err))
+    }
+}
+
+/// Why `TtlCacheBuilder::build` turned down its options.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BuildError {
+    /// The TTL jitter fraction, which isn't between 0.0 and 1.0.
+    TtlJitter(f64),
+    /// The purge policy was `PurgePolicy::EveryNInserts(0)`.
+    PurgePeriod,
+    /// The timer wheel resolution was zero.
+    WheelResolution,
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            BuildError::TtlJitter(fraction) => {
+                write!(f, "TTL jitter must be between 0.0 and 1.0, got {}", fraction)
+            }
+            BuildError::PurgePeriod => {
+                f.write_str("PurgePolicy::EveryNInserts needs a period of at least one insert")
+            }
+            BuildError::WheelResolution => {
+                f.write_str("the timer wheel resolution must be greater than zero")
+            }
+        }
+    }
+}
+
+impl Error for BuildError {}
@@ -0,0 +1,94 @@
+//! A background thread that purges a shared cache.
+
+use std::hash::{BuildHasher, Hash};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender, TryRecvError};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use TtlCache;
+
+/// How many expired entries the cleaner removes per lock, so lookups waiting on the lock are
+/// never held up behind a large purge
+const BATCH: usize = 256;
+
+/// Spawns a thread that removes the expired entries of `cache` every `interval`, so they
+/// don't have to wait for an insert or a manual purge to free their memory.  The thread takes
+/// the lock for at most 256 removals at a time, letting other users in between batches.  It
+/// stops when the returned handle is dropped or shut down, or if the lock is poisoned.
+///
+/// # Examples
+///
+/// ```
+/// use std::sync::{Arc, Mutex};
+/// use std::time::Duration;
+/// use ttl_cache::{spawn_cleaner, TtlCache};
+///
+/// let cache = Arc::new(Mutex::new(TtlCache::new()));
+/// let cleaner = spawn_cleaner(cache.clone(), Duration::from_secs(60));
+///
+/// cache.lock().unwrap().insert(1, "a", Duration::from_secs(30));
+/// cleaner.shutdown();
+/// ```
+pub fn spawn_cleaner<K, V, S>(
+    cache: Arc<Mutex<TtlCache<K, V, S>>>,
+    interval: Duration,
+) -> CleanerHandle
+where
+    K: Eq + Hash + Send + 'static,
+    V: Send + 'static,
+    S: BuildHasher + Send + 'static,
+{
+    let (stop, stopped) = mpsc::channel::<()>();
+    let thread = thread::Builder::new()
+        .name("ttl_cache cleaner".to_string())
+        .spawn(move || {
+            // Any message or the handle going away ends the loop
+            while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                loop {
+                    let removed = match cache.lock() {
+                        Ok(mut cache) => cache.remove_expired_limit(BATCH),
+                        Err(_) => return,
+                    };
+                    if removed < BATCH || stopped.try_recv() != Err(TryRecvError::Empty) {
+                        break;
+                    }
+                }
+            }
+        })
+        .expect("failed to spawn the cache cleaner thread");
+    CleanerHandle {
+        stop: Some(stop),
+        thread: Some(thread),
+    }
+}
+
+/// The handle to a thread started by `spawn_cleaner`.  Dropping it stops the thread and waits
+/// for it to finish.
+#[derive(Debug)]
+pub struct CleanerHandle {
+    stop: Option<Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl CleanerHandle {
+    /// Stops the thread and waits for it to finish, which takes at most the batch it's in the
+    /// middle of.
+    pub fn shutdown(mut self) {
+        self.stop_and_join();
+    }
+
+    fn stop_and_join(&mut self) {
+        // Dropping the sender wakes the thread up
+        self.stop.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for CleanerHandle {
+    fn drop(&mut self) {
+        self.stop_and_join();
+    }
+}
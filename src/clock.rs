@@ -0,0 +1,216 @@
+//! Where a cache gets the current time from.
+
+use std::convert::TryFrom;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use time::{Instant, SystemTime};
+
+/// A source of the current time for a cache, set with `TtlCache::with_clock`.  Every deadline,
+/// expiry check and stats timestamp of the cache goes through it, so a clock that only moves
+/// when told to makes expiry testable without sleeping.
+///
+/// The time must never go backwards.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> Instant;
+
+    /// The wall-clock time of an instant read from this clock, which is how the cache turns
+    /// deadlines into `SystemTime`s and back.  By default it's `SystemTime::now()` moved by
+    /// however far `instant` is from `now()`.
+    fn system_time_at(&self, instant: Instant) -> SystemTime {
+        let (now, wall) = (self.now(), SystemTime::now());
+        match instant.checked_duration_since(now) {
+            Some(ahead) => wall + ahead,
+            None => wall - now.duration_since(instant),
+        }
+    }
+}
+
+/// The system's monotonic clock, which caches use unless given another one
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+impl<C: Clock + ?Sized> Clock for Arc<C> {
+    fn now(&self) -> Instant {
+        (**self).now()
+    }
+
+    fn system_time_at(&self, instant: Instant) -> SystemTime {
+        (**self).system_time_at(instant)
+    }
+}
+
+/// A clock that only reads the system clock when ticked, for caches looked up so often that
+/// `Instant::now()` shows up in profiles.  Between ticks every expiry check reads the time of
+/// the last tick from a shared atomic.  Clones share the same time, so one can be ticked from
+/// a timer thread or an event loop while another is handed to `TtlCache::with_clock`.
+///
+/// The price is precision, bounded by how often the clock is ticked.  While the clock stands
+/// still an entry may be served up to one tick interval past its deadline, and since new
+/// TTLs count from the last tick an entry may also expire up to one interval early.
+///
+/// # Examples
+///
+/// ```
+/// use std::thread::sleep;
+/// use std::time::Duration;
+/// use ttl_cache::{CoarseClock, TtlCache};
+///
+/// let clock = CoarseClock::new();
+/// let mut cache = TtlCache::with_clock(clock.clone());
+///
+/// cache.insert(1, "a", Duration::from_millis(10));
+/// sleep(Duration::from_millis(20));
+/// // Still served until the clock catches up
+/// assert_eq!(cache.get(&1), Some(&"a"));
+/// clock.tick();
+/// assert_eq!(cache.get(&1), None);
+/// ```
+#[derive(Clone, Debug)]
+pub struct CoarseClock {
+    start: Instant,
+    /// Nanoseconds since `start` as of the last tick
+    offset: Arc<AtomicU64>,
+}
+
+impl CoarseClock {
+    /// Creates a clock standing at the current time.
+    pub fn new() -> Self {
+        CoarseClock {
+            start: Instant::now(),
+            offset: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// Moves the clock to the current time.
+    pub fn tick(&self) {
+        let nanos = u64::try_from(self.start.elapsed().as_nanos()).unwrap_or(u64::MAX);
+        // A tick racing a later one must not take the clock back
+        self.offset.fetch_max(nanos, Ordering::Relaxed);
+    }
+}
+
+impl Default for CoarseClock {
+    fn default() -> Self {
+        CoarseClock::new()
+    }
+}
+
+impl Clock for CoarseClock {
+    fn now(&self) -> Instant {
+        self.start + Duration::from_nanos(self.offset.load(Ordering::Relaxed))
+    }
+}
+
+/// A clock that follows the system's wall clock, for caches whose deadlines are shared with
+/// other processes as `SystemTime`s.  Each of its instants stands for a fixed wall-clock time,
+/// so an entry inserted with `TtlCache::insert_until` expires once `SystemTime::now()` reaches
+/// its deadline, and `TtlCache::expiration_system_time` gives that deadline back unchanged.
+///
+/// The wall clock can jump.  A jump forward expires everything it passes at once.  A jump
+/// back is clamped: the clock stands still until the wall clock has caught up again, so
+/// expired entries stay expired and nothing lives longer than it would have.  Clones share
+/// that high-water mark.
+///
+/// # Examples
+///
+/// ```
+/// use std::time::{Duration, SystemTime};
+/// use ttl_cache::{SystemTimeClock, TtlCache};
+///
+/// let mut cache = TtlCache::with_clock(SystemTimeClock::new());
+/// let deadline = SystemTime::now() + Duration::from_secs(30);
+///
+/// cache.insert_until(1, "a", deadline);
+/// assert_eq!(cache.get(&1), Some(&"a"));
+/// # #[cfg(not(feature = "compact"))]
+/// assert_eq!(cache.expiration_system_time(&1), Some(deadline));
+/// ```
+#[derive(Clone, Debug)]
+pub struct SystemTimeClock {
+    origin: Instant,
+    origin_time: SystemTime,
+    /// Nanoseconds past `origin_time` of the latest reading
+    latest: Arc<AtomicU64>,
+}
+
+impl SystemTimeClock {
+    /// Creates a clock standing at the current wall-clock time.
+    pub fn new() -> Self {
+        SystemTimeClock {
+            origin: Instant::now(),
+            origin_time: SystemTime::now(),
+            latest: Arc::new(AtomicU64::new(0)),
+        }
+    }
+}
+
+impl Default for SystemTimeClock {
+    fn default() -> Self {
+        SystemTimeClock::new()
+    }
+}
+
+impl Clock for SystemTimeClock {
+    fn now(&self) -> Instant {
+        let elapsed = SystemTime::now()
+            .duration_since(self.origin_time)
+            .unwrap_or_default();
+        let nanos = u64::try_from(elapsed.as_nanos()).unwrap_or(u64::MAX);
+        let latest = self.latest.fetch_max(nanos, Ordering::SeqCst).max(nanos);
+        self.origin + Duration::from_nanos(latest)
+    }
+
+    fn system_time_at(&self, instant: Instant) -> SystemTime {
+        match instant.checked_duration_since(self.origin) {
+            Some(since) => self.origin_time + since,
+            None => self.origin_time - self.origin.duration_since(instant),
+        }
+    }
+}
+
+/// The clock of the tokio runtime.  It stands still while time is paused with
+/// `tokio::time::pause` and moves with `tokio::time::advance`, so a cache using it expires
+/// entries along with the rest of a paused-time test.  Only available with the `tokio`
+/// feature.
+///
+/// # Examples
+///
+/// ```
+/// # extern crate tokio;
+/// # extern crate ttl_cache;
+/// use std::time::Duration;
+/// use tokio::runtime::Builder;
+/// use ttl_cache::{TokioClock, TtlCache};
+///
+/// # fn main() {
+/// let runtime = Builder::new_current_thread()
+///     .enable_time()
+///     .start_paused(true)
+///     .build()
+///     .unwrap();
+/// let _guard = runtime.enter();
+/// let mut cache = TtlCache::with_clock(TokioClock);
+///
+/// cache.insert(1, "a", Duration::from_secs(5));
+/// runtime.block_on(tokio::time::advance(Duration::from_secs(6)));
+/// assert_eq!(cache.get(&1), None);
+/// # }
+/// ```
+#[cfg(feature = "tokio")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TokioClock;
+
+#[cfg(feature = "tokio")]
+impl Clock for TokioClock {
+    fn now(&self) -> Instant {
+        tokio::time::Instant::now().into_std()
+    }
+}
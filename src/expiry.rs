@@ -0,0 +1,226 @@
+//! An index of when entries expire, so purging can go straight to the expired entries instead
+//! of scanning the whole cache.
+//!
+//! The index is a min-heap of `(deadline, hash)` pairs.  Keys are not stored, which keeps the
+//! index free of `K: Clone` bounds; the hash leads back to the entries that may have expired, and
+//! their own deadlines decide whether they actually have.  An entry's deadline thereby doubles as
+//! the generation that tells a stale pair from a current one.
+//!
+//! The heap is kept loosely: every entry with a deadline has a pair at or before it, but not
+//! necessarily one at it.  Pushing a pair only when an entry is created or its deadline moves
+//! earlier is enough for that, so the common case of a deadline moving later costs nothing.  When
+//! the pair of an entry that is still live comes up, it's pushed again at the entry's current
+//! deadline.
+//!
+//! A cache made with `TtlCache::with_timer_wheel` keeps the pairs in a `TimerWheel` instead,
+//! which follows the same rules but only lets pairs out a whole tick at a time.
+//!
+//! Tasks waiting for the next pair to come due can leave an alarm: a waker that's woken once a
+//! pair due sooner than the one they're waiting for is pushed, or when the index is dropped.
+
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::mem;
+use std::task::Waker;
+use std::time::Duration;
+
+use time::Stamp;
+use wheel::TimerWheel;
+
+/// How many pairs beyond the number of entries the heap may grow to before it's rebuilt from
+/// the entries
+const SLACK: usize = 64;
+
+pub(crate) struct ExpiryIndex {
+    pairs: Pairs,
+    /// Wakers to wake once a pair due before their deadline is pushed, or on any push for
+    /// those without one
+    alarms: Vec<(Option<Stamp>, Waker)>,
+}
+
+#[derive(Clone)]
+enum Pairs {
+    Heap(BinaryHeap<Reverse<(Stamp, u64)>>),
+    Wheel(TimerWheel),
+}
+
+impl Default for ExpiryIndex {
+    fn default() -> Self {
+        ExpiryIndex::new(Pairs::Heap(BinaryHeap::new()))
+    }
+}
+
+/// The alarms stay with the cache they were left on
+impl Clone for ExpiryIndex {
+    fn clone(&self) -> Self {
+        ExpiryIndex::new(self.pairs.clone())
+    }
+}
+
+/// Wakes every waiting task, so it finds the cache gone rather than waiting forever
+impl Drop for ExpiryIndex {
+    fn drop(&mut self) {
+        for (_, waker) in self.alarms.drain(..) {
+            waker.wake();
+        }
+    }
+}
+
+impl ExpiryIndex {
+    fn new(pairs: Pairs) -> Self {
+        ExpiryIndex {
+            pairs,
+            alarms: Vec::new(),
+        }
+    }
+
+    pub(crate) fn timer_wheel(resolution: Duration, origin: Stamp) -> Self {
+        ExpiryIndex::new(Pairs::Wheel(TimerWheel::new(resolution, origin)))
+    }
+
+    /// An empty index of the same kind, with no alarms
+    pub(crate) fn empty_like(&self) -> Self {
+        match self.pairs {
+            Pairs::Heap(_) => ExpiryIndex::default(),
+            Pairs::Wheel(ref wheel) => ExpiryIndex::new(Pairs::Wheel(wheel.empty_like())),
+        }
+    }
+
+    /// Whether pairs only come out once the whole tick they're due in has passed, as with a
+    /// timer wheel
+    pub(crate) fn is_coarse(&self) -> bool {
+        matches!(self.pairs, Pairs::Wheel(_))
+    }
+
+    /// Records that the entry under `hash` now expires at `new` rather than `old`.
+    pub(crate) fn note(&mut self, hash: u64, old: Option<Stamp>, new: Option<Stamp>) {
+        match (old, new) {
+            (Some(old), Some(new)) if new >= old => {}
+            (_, Some(new)) => self.push(new, hash),
+            (_, None) => {}
+        }
+    }
+
+    pub(crate) fn push(&mut self, expiration: Stamp, hash: u64) {
+        match self.pairs {
+            Pairs::Heap(ref mut heap) => heap.push(Reverse((expiration, hash))),
+            Pairs::Wheel(ref mut wheel) => wheel.insert(expiration, hash),
+        }
+        if !self.alarms.is_empty() {
+            self.ring(expiration);
+        }
+    }
+
+    /// Pops the next pair whose deadline had been reached by `now`.
+    pub(crate) fn pop_expired(&mut self, now: Stamp) -> Option<(Stamp, u64)> {
+        match self.pairs {
+            Pairs::Heap(ref mut heap) => match heap.peek() {
+                Some(&Reverse((expiration, _))) if expiration <= now => {
+                    heap.pop().map(|Reverse(pair)| pair)
+                }
+                _ => None,
+            },
+            Pairs::Wheel(ref mut wheel) => wheel.pop_expired(now),
+        }
+    }
+
+    /// Returns the earliest time `pop_expired` may give out a pair.  Since the index is kept
+    /// loosely, that can come before any entry has actually expired.
+    #[cfg(feature = "tokio")]
+    pub(crate) fn next_due(&self) -> Option<Stamp> {
+        match self.pairs {
+            Pairs::Heap(ref heap) => heap.peek().map(|&Reverse((expiration, _))| expiration),
+            Pairs::Wheel(ref wheel) => wheel.next_due(),
+        }
+    }
+
+    /// Leaves an alarm that wakes `waker` once a pair due before `due` is pushed, or once any
+    /// pair is if there's no `due`.  The alarm goes off at most once.
+    #[cfg(feature = "tokio")]
+    pub(crate) fn set_alarm(&mut self, due: Option<Stamp>, waker: &Waker) {
+        match self.alarms.iter_mut().find(|alarm| alarm.1.will_wake(waker)) {
+            Some(alarm) => alarm.0 = due,
+            None => self.alarms.push((due, waker.clone())),
+        }
+    }
+
+    /// Wakes the tasks waiting on something due later than `expiration`
+    fn ring(&mut self, expiration: Stamp) {
+        let (ringing, waiting) = mem::take(&mut self.alarms)
+            .into_iter()
+            .partition(|&(due, _)| due.map_or(true, |due| expiration < due));
+        self.alarms = waiting;
+        for (_, waker) in ringing {
+            waker.wake();
+        }
+    }
+
+    /// Whether stale pairs have piled up enough that the heap should be rebuilt for a cache
+    /// of `len` entries
+    pub(crate) fn needs_rebuild(&self, len: usize) -> bool {
+        self.len() > len.saturating_mul(2).saturating_add(SLACK)
+    }
+
+    /// The number of pairs, stale ones included
+    fn len(&self) -> usize {
+        match self.pairs {
+            Pairs::Heap(ref heap) => heap.len(),
+            Pairs::Wheel(ref wheel) => wheel.len(),
+        }
+    }
+
+    /// Replaces the contents of the index with the given pairs.
+    pub(crate) fn rebuild<I: IntoIterator<Item = (Stamp, u64)>>(&mut self, pairs: I) {
+        match self.pairs {
+            Pairs::Heap(ref mut heap) => {
+                let pairs: Vec<_> = pairs.into_iter().map(Reverse).collect();
+                *heap = BinaryHeap::from(pairs);
+            }
+            Pairs::Wheel(ref mut wheel) => {
+                wheel.clear();
+                for (expiration, hash) in pairs {
+                    wheel.insert(expiration, hash);
+                }
+            }
+        }
+    }
+
+    pub(crate) fn clear(&mut self) {
+        match self.pairs {
+            Pairs::Heap(ref mut heap) => heap.clear(),
+            Pairs::Wheel(ref mut wheel) => wheel.clear(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SLACK;
+    use std::time::Duration;
+    use test_util::MockClock;
+    use {PurgePolicy, TtlCache};
+
+    #[test]
+    fn stays_compact_without_purges() {
+        for wheel in [false, true] {
+            let builder = TtlCache::builder();
+            let builder = if wheel {
+                builder.timer_wheel(Duration::from_secs(1))
+            } else {
+                builder
+            };
+            let mut cache = builder
+                .clock(MockClock::new())
+                .purge_policy(PurgePolicy::Manual)
+                .build()
+                .unwrap();
+            for i in 0..100_000u64 {
+                cache.insert(i, i, Duration::from_secs(60));
+                cache.remove(&i);
+                // Every deadline comes earlier than the last, so each one is pushed
+                cache.insert(u64::MAX, i, Duration::from_secs(200_000 - i));
+                assert!(cache.index.len() < 2 * SLACK);
+            }
+        }
+    }
+}
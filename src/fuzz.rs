@@ -0,0 +1,47 @@
+//! `Arbitrary` support for fuzzing.  Only available with the `arbitrary` feature.
+//!
+//! A generated cache is built with `TtlCache::from_entries_at` from arbitrary `(key, value,
+//! ttl)` items, where each TTL is a whole number of milliseconds below 2^16 counted from a base
+//! 2^15 milliseconds in the past.  About half of the entries come out already expired, and
+//! shrinking a TTL towards zero shrinks it towards expired.
+
+use std::hash::Hash;
+use std::time::Duration;
+
+use arbitrary::{Arbitrary, Result, Unstructured};
+
+use super::TtlCache;
+use time::Instant;
+
+/// How far before now generated TTLs count from
+const BASE_AGE: Duration = Duration::from_millis(1 << 15);
+
+impl<'a, K, V> Arbitrary<'a> for TtlCache<K, V>
+where
+    K: Arbitrary<'a> + Eq + Hash,
+    V: Arbitrary<'a>,
+{
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let entries = u
+            .arbitrary_iter::<(K, V, u16)>()?
+            .collect::<Result<Vec<_>>>()?;
+        Ok(from_generated(entries))
+    }
+
+    fn arbitrary_take_rest(u: Unstructured<'a>) -> Result<Self> {
+        let entries = u
+            .arbitrary_take_rest_iter::<(K, V, u16)>()?
+            .collect::<Result<Vec<_>>>()?;
+        Ok(from_generated(entries))
+    }
+}
+
+fn from_generated<K: Eq + Hash, V>(entries: Vec<(K, V, u16)>) -> TtlCache<K, V> {
+    let now = Instant::now();
+    // Shortly after boot the clock may not go back that far
+    let base = now.checked_sub(BASE_AGE).unwrap_or(now);
+    let entries = entries
+        .into_iter()
+        .map(|(k, v, ttl)| (k, v, Duration::from_millis(u64::from(ttl))));
+    TtlCache::from_entries_at(entries, base)
+}
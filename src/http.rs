@@ -0,0 +1,190 @@
+//! TTLs for cached HTTP responses, read from their `Cache-Control` and `Expires` headers.
+//! Only available with the `http` feature.
+
+use std::time::Duration;
+
+use time::SystemTime;
+
+/// The longest lifetime a header can give, 2^31 seconds.  RFC 9111 has larger values taken
+/// as this rather than rejected.
+const MAX_LIFETIME: u64 = 1 << 31;
+
+/// Returns how long a response may be cached according to its `Cache-Control` header value,
+/// from the point of view of a shared cache.
+///
+/// `s-maxage` takes precedence over `max-age`.  Returns `None` if the header forbids caching
+/// with `no-store` or `no-cache`, or gives no lifetime at all.  Directive names are matched
+/// case-insensitively and unknown ones are ignored, as is the whitespace around directives.
+/// A lifetime that isn't a number counts as zero, one too large is capped at 2^31 seconds,
+/// and only the first of a repeated directive counts.
+///
+/// # Examples
+///
+/// ```
+/// use std::time::Duration;
+/// use ttl_cache::http::ttl_from_cache_control;
+///
+/// let ttl = ttl_from_cache_control("public, max-age=60, s-maxage=300");
+/// assert_eq!(ttl, Some(Duration::from_secs(300)));
+/// assert_eq!(ttl_from_cache_control("no-store"), None);
+/// ```
+pub fn ttl_from_cache_control(header_value: &str) -> Option<Duration> {
+    match CacheControl::parse(header_value) {
+        CacheControl::Forbidden => None,
+        CacheControl::Lifetime(lifetime) => lifetime,
+    }
+}
+
+/// Returns how long a response may be cached according to its `Expires` header value, the
+/// time from `now` until the date it gives, or zero if that has passed.
+///
+/// The date may be in any of the three formats HTTP allows.  An invalid one, like the
+/// common `0`, counts as already passed.  `Expires` is only meant to be used when
+/// `Cache-Control` gives no lifetime.
+///
+/// # Examples
+///
+/// ```
+/// use std::time::{Duration, SystemTime};
+/// use ttl_cache::http::ttl_from_expires;
+///
+/// let now = SystemTime::UNIX_EPOCH + Duration::from_secs(784_111_717);
+/// let ttl = ttl_from_expires("Sun, 06 Nov 1994 08:49:37 GMT", now);
+/// assert_eq!(ttl, Duration::from_secs(60));
+/// assert_eq!(ttl_from_expires("0", now), Duration::ZERO);
+/// ```
+pub fn ttl_from_expires(header_value: &str, now: SystemTime) -> Duration {
+    parse_http_date(header_value)
+        .and_then(|date| (SystemTime::UNIX_EPOCH + date).duration_since(now).ok())
+        .unwrap_or(Duration::ZERO)
+}
+
+/// What a `Cache-Control` header says about caching
+pub(crate) enum CacheControl {
+    Forbidden,
+    /// Caching is allowed, for this long if the header says
+    Lifetime(Option<Duration>),
+}
+
+impl CacheControl {
+    pub(crate) fn parse(header_value: &str) -> Self {
+        let mut max_age = None;
+        let mut s_maxage = None;
+        for directive in Directives(header_value) {
+            let (name, value) = match directive.find('=') {
+                Some(at) => (directive[..at].trim(), Some(directive[at + 1..].trim())),
+                None => (directive, None),
+            };
+            if name.eq_ignore_ascii_case("no-store") || name.eq_ignore_ascii_case("no-cache") {
+                return CacheControl::Forbidden;
+            } else if name.eq_ignore_ascii_case("max-age") {
+                max_age = max_age.or_else(|| Some(delta_seconds(value)));
+            } else if name.eq_ignore_ascii_case("s-maxage") {
+                s_maxage = s_maxage.or_else(|| Some(delta_seconds(value)));
+            }
+        }
+        CacheControl::Lifetime(s_maxage.or(max_age))
+    }
+}
+
+/// The lifetime given by a directive's value, which may be quoted
+fn delta_seconds(value: Option<&str>) -> Duration {
+    let value = value.unwrap_or("");
+    let value = value
+        .strip_prefix('"')
+        .and_then(|quoted| quoted.strip_suffix('"'))
+        .unwrap_or(value);
+    if value.is_empty() || !value.bytes().all(|b| b.is_ascii_digit()) {
+        return Duration::ZERO;
+    }
+    // Any overflow is past the cap anyway
+    let secs = value.parse::<u64>().unwrap_or(MAX_LIFETIME);
+    Duration::from_secs(secs.min(MAX_LIFETIME))
+}
+
+/// The directives of a header, split on the commas outside quoted strings and trimmed,
+/// skipping empty ones
+struct Directives<'a>(&'a str);
+
+impl<'a> Iterator for Directives<'a> {
+    type Item = &'a str;
+
+    fn next(&mut self) -> Option<&'a str> {
+        loop {
+            if self.0.is_empty() {
+                return None;
+            }
+            let (mut quoted, mut escaped) = (false, false);
+            let mut end = self.0.len();
+            for (i, c) in self.0.char_indices() {
+                match c {
+                    _ if escaped => escaped = false,
+                    '\\' if quoted => escaped = true,
+                    '"' => quoted = !quoted,
+                    ',' if !quoted => {
+                        end = i;
+                        break;
+                    }
+                    _ => {}
+                }
+            }
+            let directive = self.0[..end].trim();
+            self.0 = self.0.get(end + 1..).unwrap_or("");
+            if !directive.is_empty() {
+                return Some(directive);
+            }
+        }
+    }
+}
+
+/// The time since the Unix epoch of an HTTP date, in the IMF-fixdate format
+/// (`Sun, 06 Nov 1994 08:49:37 GMT`) or the obsolete RFC 850 (`Sunday, 06-Nov-94 08:49:37
+/// GMT`) and asctime (`Sun Nov  6 08:49:37 1994`) ones
+fn parse_http_date(date: &str) -> Option<Duration> {
+    let fields: Vec<&str> = date.split_whitespace().collect();
+    let (day, month, year, time) = match fields[..] {
+        [weekday, day, month, year, time, "GMT"] if weekday.ends_with(',') => {
+            (day, month, year.parse().ok()?, time)
+        }
+        [weekday, date, time, "GMT"] if weekday.ends_with(',') => {
+            let mut parts = date.split('-');
+            let (day, month, year) = (parts.next()?, parts.next()?, parts.next()?);
+            if parts.next().is_some() || year.len() != 2 {
+                return None;
+            }
+            // Two digit years from 70 on are taken as the 1900s
+            let year: u64 = year.parse().ok()?;
+            (day, month, if year < 70 { 2000 + year } else { 1900 + year }, time)
+        }
+        [_, month, day, time, year] => (day, month, year.parse().ok()?, time),
+        _ => return None,
+    };
+
+    let day: u64 = day.parse().ok()?;
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+    let month = MONTHS.iter().position(|&name| name == month)? as u64 + 1;
+    let mut hms = time.split(':').map(|part| part.parse::<u64>().ok());
+    let (hour, minute, second) = (hms.next()??, hms.next()??, hms.next()??);
+    if hms.next().is_some() || !(1970..=9999).contains(&year) {
+        return None;
+    }
+    if !(1..=31).contains(&day) || hour > 23 || minute > 59 || second > 60 {
+        return None;
+    }
+
+    let days = days_since_epoch(year, month, day);
+    Some(Duration::from_secs(days * 86_400 + hour * 3600 + minute * 60 + second))
+}
+
+/// The days from 1970-01-01 to a date on or after it, by the civil calendar
+fn days_since_epoch(year: u64, month: u64, day: u64) -> u64 {
+    // Counting years from March puts the leap day at the end of the year
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year / 400;
+    let year_of_era = year % 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
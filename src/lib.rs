@@ -1,20 +1,122 @@
 //! This crate provides a time sensitive key-value cache.  When an item is inserted it is
 //! given a TTL.  Any value that are in the cache after their duration are considered invalid
 //! and will not be returned on lookups.
+//!
+//! An entry's deadline is exclusive: the entry is live strictly before it and expired from the
+//! deadline on.  That way an entry inserted with a TTL of zero is never returned, and a cache
+//! agrees with anything else that treats a deadline as the first instant a value is no longer
+//! valid, even when the clock has not moved between two calls.
 
-extern crate linked_hash_map;
+#[cfg(feature = "arbitrary")]
+extern crate arbitrary;
+#[cfg(feature = "arc-swap")]
+extern crate arc_swap;
+#[cfg(feature = "tokio")]
+extern crate futures_core;
+extern crate hashlink;
+#[cfg(feature = "metrics")]
+extern crate metrics;
+#[cfg(feature = "rayon")]
+extern crate rayon;
+#[cfg(feature = "serde")]
+extern crate serde;
+#[cfg(feature = "tokio")]
+extern crate tokio;
+#[cfg(feature = "tower")]
+extern crate tower_layer;
+#[cfg(feature = "tower")]
+extern crate tower_service;
+#[cfg(feature = "tracing")]
+extern crate tracing;
+#[cfg(feature = "wasm")]
+extern crate web_time;
 
 use std::borrow::Borrow;
+#[cfg(feature = "access-counts")]
+use std::cmp::Reverse;
 use std::collections::hash_map::RandomState;
-use std::hash::{BuildHasher, Hash};
+use std::fmt;
+use std::hash::{BuildHasher, Hash, Hasher};
+use std::iter::{FromIterator, FusedIterator};
+use std::mem;
+use std::ops::Index;
+use std::ptr;
+use std::sync::mpsc::{self, Receiver};
+use std::sync::Arc;
+#[cfg(feature = "tokio")]
+use std::task::Waker;
+use std::time::Duration;
+use std::vec;
+
+use hashlink::linked_hash_map;
+use hashlink::LinkedHashMap;
+use hashlink::linked_hash_map::RawEntryMut as RawLinkHashMapEntry;
+use hashlink::linked_hash_map::RawOccupiedEntryMut as RawOccupiedLinkHashMapEntry;
+use hashlink::linked_hash_map::RawVacantEntryMut as RawVacantLinkHashMapEntry;
+
+pub use builder::{BuildError, TtlCacheBuilder};
+pub use cleaner::{spawn_cleaner, CleanerHandle};
+pub use clock::{Clock, CoarseClock, SystemClock, SystemTimeClock};
+#[cfg(feature = "tokio")]
+pub use clock::TokioClock;
+pub use notify::RemovalCause;
+#[cfg(feature = "tokio")]
+pub use purger::{spawn_purger, PurgerHandle};
+pub use policy::ExpiryPolicy;
+#[cfg(feature = "raw")]
+pub use raw::{RawEntryBuilder, RawEntryBuilderMut, RawEntryMut, RawVacantEntryMut};
+
+#[cfg(feature = "rayon")]
+pub use par::{ParIter, ParIterMut};
+#[cfg(feature = "stats")]
+pub use stats::CacheStats;
+use expiry::ExpiryIndex;
+#[cfg(feature = "http")]
+use http::CacheControl;
+use notify::Notifier;
+#[cfg(feature = "access-counts")]
+use stats::Counter;
+#[cfg(feature = "metrics")]
+use stats::Metrics;
 #[cfg(feature = "stats")]
-use std::sync::atomic::{AtomicUsize, Ordering};
-use std::time::{Duration, Instant};
+use stats::{Counters, Window};
+use time::{Epoch, Instant, Span, Stamp, SystemTime};
+#[cfg(feature = "tracing")]
+use trace::{KeyField, KeyFmt};
 
-use linked_hash_map::LinkedHashMap;
-use linked_hash_map::Entry as LinkedHashMapEntry;
-use linked_hash_map::OccupiedEntry as OccupiedLinkHashMapEntry;
-use linked_hash_map::VacantEntry as VacantLinkHashMapEntry;
+mod builder;
+mod cleaner;
+mod clock;
+mod expiry;
+#[cfg(feature = "arbitrary")]
+mod fuzz;
+#[cfg(feature = "http")]
+pub mod http;
+#[cfg(feature = "tokio")]
+pub mod loading;
+#[cfg(feature = "rayon")]
+mod par;
+mod notify;
+mod policy;
+#[cfg(feature = "tokio")]
+mod purger;
+#[cfg(feature = "raw")]
+mod raw;
+#[cfg(feature = "serde")]
+mod serialize;
+pub mod sharded;
+#[cfg(feature = "arc-swap")]
+pub mod snapshot;
+#[cfg(feature = "stats")]
+mod stats;
+pub mod sync;
+pub mod test_util;
+mod time;
+#[cfg(feature = "tower")]
+pub mod tower;
+#[cfg(feature = "tracing")]
+mod trace;
+mod wheel;
 
 /// A view into a single location in a map, which may be vacant or occupied.
 pub enum Entry<'a, K: 'a, V: 'a, S: 'a = RandomState> {
@@ -31,15 +133,9 @@ impl<'a, K: Hash + Eq, V, S: BuildHasher> Entry<'a, K, V, S> {
             Entry::Vacant(ref e) => e.key(),
         }
     }
-}
-
-/// A view into a single occupied location in the cache that was unexpired at the moment of lookup.
-pub struct OccupiedEntry<'a, K: 'a, V: 'a, S: 'a = RandomState> {
-    entry: OccupiedLinkHashMapEntry<'a, K, InternalEntry<V>, S>
-}
 
-impl<'a, K: Hash + Eq, V, S: BuildHasher> OccupiedEntry<'a, K, V, S> {
-    /// Gets a reference to the entry key
+    /// Provides in-place mutable access to an occupied entry before any potential inserts into
+    /// the cache.  The entry's TTL is left alone.
     ///
     /// # Examples
     ///
@@ -47,166 +143,105 @@ impl<'a, K: Hash + Eq, V, S: BuildHasher> OccupiedEntry<'a, K, V, S> {
     /// use std::time::Duration;
     /// use ttl_cache::TtlCache;
     ///
-    /// let mut map = TtlCache::new(10);
+    /// let mut cache = TtlCache::new();
+    /// let duration = Duration::from_secs(30);
     ///
-    /// map.insert("foo".to_string(), 1, Duration::from_secs(30));
-    /// assert_eq!("foo", map.entry("foo".to_string()).key());
+    /// cache.entry("hits").and_modify(|v| *v += 1).or_insert(1, duration);
+    /// cache.entry("hits").and_modify(|v| *v += 1).or_insert(1, duration);
+    /// assert_eq!(cache.get("hits"), Some(&2));
     /// ```
-    pub fn key(&self) -> &K {
-        self.entry.key()
-    }
-
-    /// Gets a reference to the value in the entry.
-    pub fn get(&self) -> &V {
-        &self.entry.get().value
-    }
-
-    /// Gets a mutable reference to the value in the entry.
-    pub fn get_mut(&mut self) -> &mut V {
-        &mut self.entry.get_mut().value
-    }
-
-    /// Sets the value of the entry, and returns the entry's old value
-    pub fn insert(&mut self, value: V, duration: Duration) -> V {
-        let internal_entry = self.entry.insert(InternalEntry::new(value, duration));
-        internal_entry.value
+    pub fn and_modify<F: FnOnce(&mut V)>(self, f: F) -> Self {
+        match self {
+            Entry::Occupied(mut entry) => {
+                f(entry.get_mut());
+                Entry::Occupied(entry)
+            }
+            Entry::Vacant(entry) => Entry::Vacant(entry),
+        }
     }
-}
-
-
-
-/// A view into a single empty location in the cache
-pub struct VacantEntry<'a, K: 'a, V: 'a, S: 'a = RandomState> {
-    entry: VacantLinkHashMapEntry<'a, K, InternalEntry<V>, S>
-}
 
-impl<'a, K: 'a + Hash + Eq, V: 'a, S: BuildHasher> VacantEntry<'a, K, V, S> {
-    /// Gets a reference to the entry key
+    /// Like `and_modify`, but also restarts the entry's TTL with its original duration when
+    /// it modifies the value, keeping frequently updated entries alive.
     ///
     /// # Examples
     ///
     /// ```
+    /// use std::time::Duration;
     /// use ttl_cache::TtlCache;
     ///
-    /// let mut map = TtlCache::<String, u32>::new(10);
+    /// let mut cache = TtlCache::new();
+    /// let duration = Duration::from_secs(30);
     ///
-    /// assert_eq!("foo", map.entry("foo".to_string()).key());
+    /// cache.entry("hits").and_modify_prolong(|v| *v += 1).or_insert(1, duration);
+    /// cache.entry("hits").and_modify_prolong(|v| *v += 1).or_insert(1, duration);
+    /// assert_eq!(cache.get("hits"), Some(&2));
     /// ```
-    pub fn key(&self) -> &K {
-        self.entry.key()
-    }
-
-    /// Sets the value of the entry with the VacantEntry's key,
-    /// and returns a mutable reference to it
-    pub fn insert(self, value: V, duration: Duration) -> &'a mut V {
-        let internal_entry = self.entry.insert(InternalEntry::new(value, duration));
-        &mut internal_entry.value
-    }
-}
-
-#[derive(Clone)]
-struct InternalEntry<V> {
-    value: V,
-    expiration: Instant,
-    duration: Duration,
-}
-
-impl<V> InternalEntry<V> {
-    fn new(v: V, duration: Duration) -> Self {
-        InternalEntry {
-            value: v,
-            expiration: Instant::now() + duration,
-            duration
+    pub fn and_modify_prolong<F: FnOnce(&mut V)>(self, f: F) -> Self {
+        match self {
+            Entry::Occupied(mut entry) => {
+                f(entry.get_mut_prolong());
+                Entry::Occupied(entry)
+            }
+            Entry::Vacant(entry) => Entry::Vacant(entry),
         }
     }
 
-    fn is_expired(&self) -> bool {
-        Instant::now() > self.expiration
-    }
-
-    fn reset_duration(&mut self) {
-        self.expiration = Instant::now() + self.duration
-    }
-}
-
-/// A time sensitive cache.
-pub struct TtlCache<K: Eq + Hash, V, S: BuildHasher = RandomState> {
-    map: LinkedHashMap<K, InternalEntry<V>, S>,
-    #[cfg(feature = "stats")]
-    hits: AtomicUsize,
-    #[cfg(feature = "stats")]
-    misses: AtomicUsize,
-    #[cfg(feature = "stats")]
-    since: Instant,
-}
-
-impl<K: Eq + Hash, V> TtlCache<K, V> {
-    /// Creates an empty cache
+    /// Ensures a value is in the entry by inserting the default with the given TTL if empty,
+    /// and returns a mutable reference to the value in the entry.
+    ///
+    /// `entry()` drops an expired entry before handing out the view, so an occupied entry was
+    /// live when it was looked up.  The view borrows the cache mutably, so nothing else can
+    /// touch the entry before `or_insert` runs, but its deadline may still pass in between if
+    /// the caller holds on to the view.  In that case the existing value is returned, matching
+    /// what the lookup saw.
     ///
     /// # Examples
     ///
     /// ```
+    /// use std::time::Duration;
     /// use ttl_cache::TtlCache;
     ///
-    /// let mut cache: TtlCache<i32, &str> = TtlCache::new();
+    /// let mut cache = TtlCache::new();
+    /// let duration = Duration::from_secs(30);
+    ///
+    /// *cache.entry("hits").or_insert(0, duration) += 1;
+    /// *cache.entry("hits").or_insert(0, duration) += 1;
+    /// assert_eq!(cache.get("hits"), Some(&2));
     /// ```
-    pub fn new() -> Self {
-        TtlCache {
-            map: LinkedHashMap::new(),
-            #[cfg(feature = "stats")]
-            hits: AtomicUsize::new(0),
-            #[cfg(feature = "stats")]
-            misses: AtomicUsize::new(0),
-            #[cfg(feature = "stats")]
-            since: Instant::now(),
-        }
-    }
-}
-
-/// Creates an empty cache as the default
-impl<K: Eq + Hash, V> Default for TtlCache<K, V> {
-    fn default() -> Self {
-        Self::new()
-    }
-}
-
-impl<K: Eq + Hash, V, S: BuildHasher> TtlCache<K, V, S> {
-    /// Creates an empty cache that can hold at most `capacity` items
-    /// with the given hash builder.
-    pub fn with_hasher(hash_builder: S) -> Self {
-        TtlCache {
-            map: LinkedHashMap::with_hasher(hash_builder),
-            #[cfg(feature = "stats")]
-            hits: AtomicUsize::new(0),
-            #[cfg(feature = "stats")]
-            misses: AtomicUsize::new(0),
-            #[cfg(feature = "stats")]
-            since: Instant::now(),
+    pub fn or_insert(self, default: V, ttl: Duration) -> &'a mut V {
+        match self {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(default, ttl),
         }
     }
 
-    /// Check if the cache contains the given key.
+    /// Ensures a value is in the entry by inserting the result of the default function with
+    /// the given TTL if empty, and returns a mutable reference to the value in the entry.  The
+    /// function is only called when the entry is vacant.
     ///
     /// # Examples
+    ///
     /// ```
     /// use std::time::Duration;
     /// use ttl_cache::TtlCache;
     ///
-    /// let mut cache = TtlCache::new(10);
-    /// cache.insert(1, "a", Duration::from_secs(30));
-    /// assert_eq!(cache.contains_key(&1), true);
+    /// let mut cache = TtlCache::new();
+    /// let duration = Duration::from_secs(30);
+    ///
+    /// cache.entry("blob").or_insert_with(duration, || vec![0u8; 1024]);
+    /// cache.entry("blob").or_insert_with(duration, || unreachable!());
+    /// assert_eq!(cache.get("blob").map(Vec::len), Some(1024));
     /// ```
-    pub fn contains_key<Q: ?Sized>(&self, key: &Q) -> bool
-    where
-        K: Borrow<Q>,
-        Q: Hash + Eq,
-    {
-        // Expiration check is handled by get
-        self.get(key).is_some()
+    pub fn or_insert_with<F: FnOnce() -> V>(self, ttl: Duration, default: F) -> &'a mut V {
+        match self {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(default(), ttl),
+        }
     }
 
-    /// Inserts a key-value pair into the cache with an individual ttl for the key. If the key
-    /// already existed and hasn't expired, the old value is returned.
+    /// Ensures a value is in the entry by inserting the result of the default function with
+    /// the given TTL if empty, and returns a mutable reference to the value in the entry.  The
+    /// function gets a reference to the key and is only called when the entry is vacant.
     ///
     /// # Examples
     ///
@@ -214,22 +249,27 @@ impl<K: Eq + Hash, V, S: BuildHasher> TtlCache<K, V, S> {
     /// use std::time::Duration;
     /// use ttl_cache::TtlCache;
     ///
-    /// let mut cache = TtlCache::new(2);
+    /// let mut cache = TtlCache::new();
+    /// let duration = Duration::from_secs(30);
     ///
-    /// cache.insert(1, "a", Duration::from_secs(20));
-    /// cache.insert(2, "b", Duration::from_secs(60));
-    /// assert_eq!(cache.get(&1), Some(&"a"));
-    /// assert_eq!(cache.get(&2), Some(&"b"));
+    /// let shard = cache
+    ///     .entry("shard-7".to_string())
+    ///     .or_insert_with_key(duration, |key| key["shard-".len()..].parse::<u32>().unwrap());
+    /// assert_eq!(*shard, 7);
     /// ```
-    pub fn insert(&mut self, k: K, v: V, ttl: Duration) -> Option<V> {
-        self.remove_expired();
-        let to_insert = InternalEntry::new(v, ttl);
-        let old_val = self.map.insert(k, to_insert);
-        old_val.and_then(|x| if x.is_expired() { None } else { Some(x.value) })
+    pub fn or_insert_with_key<F: FnOnce(&K) -> V>(self, ttl: Duration, default: F) -> &'a mut V {
+        match self {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                let value = default(entry.key());
+                entry.insert(value, ttl)
+            }
+        }
     }
 
-    /// Returns a reference to the value corresponding to the given key in the cache, if
-    /// it contains an unexpired entry.
+    /// Ensures a value is in the entry by inserting the value returned by the default function
+    /// with the TTL it returns alongside it if empty, and returns a mutable reference to the
+    /// value in the entry.  The function is only called when the entry is vacant.
     ///
     /// # Examples
     ///
@@ -237,38 +277,30 @@ impl<K: Eq + Hash, V, S: BuildHasher> TtlCache<K, V, S> {
     /// use std::time::Duration;
     /// use ttl_cache::TtlCache;
     ///
-    /// let mut cache = TtlCache::new(2);
-    /// let duration = Duration::from_secs(30);
+    /// fn fetch(url: &str) -> (String, Duration) {
+    ///     // e.g. the body along with the response's max-age
+    ///     (format!("body of {}", url), Duration::from_secs(300))
+    /// }
     ///
-    /// cache.insert(1, "a", duration);
-    /// cache.insert(2, "b", duration);
-    /// cache.insert(2, "c", duration);
-    /// cache.insert(3, "d", duration);
+    /// let mut cache = TtlCache::new();
+    /// let url = "https://example.com/";
     ///
-    /// assert_eq!(cache.get(&1), None);
-    /// assert_eq!(cache.get(&2), Some(&"c"));
+    /// let body = cache.entry(url).or_insert_with_ttl(|| fetch(url));
+    /// assert_eq!(body, "body of https://example.com/");
+    /// assert!(cache.remaining_ttl(url).unwrap() > Duration::from_secs(299));
     /// ```
-    pub fn get<Q: ?Sized>(&self, k: &Q) -> Option<&V>
-    where
-        K: Borrow<Q>,
-        Q: Hash + Eq,
-    {
-        let to_ret = self.map
-            .get(k)
-            .and_then(|x| if x.is_expired() { None } else { Some(&x.value) });
-        #[cfg(feature = "stats")]
-        {
-            if to_ret.is_some() {
-                self.hits.fetch_add(1, Ordering::Relaxed);
-            } else {
-                self.misses.fetch_add(1, Ordering::Relaxed);
+    pub fn or_insert_with_ttl<F: FnOnce() -> (V, Duration)>(self, default: F) -> &'a mut V {
+        match self {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                let (value, ttl) = default();
+                entry.insert(value, ttl)
             }
         }
-        to_ret
     }
 
-    /// Returns a mutable reference to the value corresponding to the given key in the cache, if
-    /// it contains an unexpired entry.
+    /// Like `or_insert_with_ttl`, but for a default function that can fail.  If it returns an
+    /// error, nothing is inserted and the error is passed on.
     ///
     /// # Examples
     ///
@@ -276,42 +308,32 @@ impl<K: Eq + Hash, V, S: BuildHasher> TtlCache<K, V, S> {
     /// use std::time::Duration;
     /// use ttl_cache::TtlCache;
     ///
-    /// let mut cache = TtlCache::new(2);
-    /// let duration = Duration::from_secs(30);
+    /// let mut cache: TtlCache<&str, u32> = TtlCache::new();
     ///
-    /// cache.insert(1, "a", duration);
-    /// cache.insert(2, "b", duration);
-    /// cache.insert(2, "c", duration);
-    /// cache.insert(3, "d", duration);
+    /// let loaded = cache.entry("a").or_try_insert_with_ttl(|| Err("backend down"));
+    /// assert_eq!(loaded, Err("backend down"));
+    /// assert!(!cache.contains_key("a"));
     ///
-    /// assert_eq!(cache.get_mut(&1), None);
-    /// assert_eq!(cache.get_mut(&2), Some(&mut "c"));
+    /// let loaded = cache
+    ///     .entry("a")
+    ///     .or_try_insert_with_ttl::<(), _>(|| Ok((1, Duration::from_secs(30))));
+    /// assert_eq!(loaded, Ok(&mut 1));
     /// ```
-    pub fn get_mut<Q: ?Sized>(&mut self, k: &Q) -> Option<&mut V>
+    pub fn or_try_insert_with_ttl<E, F>(self, default: F) -> Result<&'a mut V, E>
     where
-        K: Borrow<Q>,
-        Q: Hash + Eq,
+        F: FnOnce() -> Result<(V, Duration), E>,
     {
-        let to_ret = self.map.get_mut(k).and_then(|x| {
-            if x.is_expired() {
-                None
-            } else {
-                Some(&mut x.value)
-            }
-        });
-        #[cfg(feature = "stats")]
-        {
-            if to_ret.is_some() {
-                self.hits.fetch_add(1, Ordering::Relaxed);
-            } else {
-                self.misses.fetch_add(1, Ordering::Relaxed);
+        match self {
+            Entry::Occupied(entry) => Ok(entry.into_mut()),
+            Entry::Vacant(entry) => {
+                let (value, ttl) = default()?;
+                Ok(entry.insert(value, ttl))
             }
         }
-        to_ret
     }
 
-    /// Returns a mutable reference to the value corresponding to the given key in the cache, if
-    /// it contains an unexpired entry and resets the expiration.
+    /// Ensures a value is in the entry by inserting `V::default()` with the given TTL if empty,
+    /// and returns a mutable reference to the value in the entry.
     ///
     /// # Examples
     ///
@@ -319,43 +341,31 @@ impl<K: Eq + Hash, V, S: BuildHasher> TtlCache<K, V, S> {
     /// use std::time::Duration;
     /// use ttl_cache::TtlCache;
     ///
-    /// let mut cache = TtlCache::new(2);
+    /// let mut cache: TtlCache<&str, u64> = TtlCache::new();
     /// let duration = Duration::from_secs(30);
     ///
-    /// cache.insert(1, "a", duration);
-    /// cache.insert(2, "b", duration);
-    /// cache.insert(2, "c", duration);
-    /// cache.insert(3, "d", duration);
-    ///
-    /// assert_eq!(cache.get_mut_prolong(&1), None);
-    /// assert_eq!(cache.get_mut_prolong(&2), Some(&mut "c"));
+    /// *cache.entry("requests").or_default(duration) += 1;
+    /// *cache.entry("requests").or_default(duration) += 1;
+    /// assert_eq!(cache.get("requests"), Some(&2));
     /// ```
-    pub fn get_mut_prolong<Q: ?Sized>(&mut self, k: &Q) -> Option<&mut V>
+    pub fn or_default(self, ttl: Duration) -> &'a mut V
     where
-        K: Borrow<Q>,
-        Q: Hash + Eq,
+        V: Default,
     {
-        let to_ret = self.map.get_mut(k).and_then(|x| {
-            if x.is_expired() {
-                None
-            } else {
-                x.reset_duration();
-                Some(&mut x.value)
-            }
-        });
-        #[cfg(feature = "stats")]
-        {
-            if to_ret.is_some() {
-                self.hits.fetch_add(1, Ordering::Relaxed);
-            } else {
-                self.misses.fetch_add(1, Ordering::Relaxed);
-            }
+        match self {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(V::default(), ttl),
         }
-        to_ret
     }
 
-    /// Sets the expiration of the entry pointed to by the given key to
-    /// now + the originally given duration
+    /// Ensures a value is in the entry by inserting the default with the TTL the cache's expiry
+    /// policy gives, or else the cache's default TTL, if empty, and returns a mutable reference
+    /// to the value in the entry.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the entry is vacant and the cache has neither an expiry policy nor a default
+    /// TTL.
     ///
     /// # Examples
     ///
@@ -363,25 +373,34 @@ impl<K: Eq + Hash, V, S: BuildHasher> TtlCache<K, V, S> {
     /// use std::time::Duration;
     /// use ttl_cache::TtlCache;
     ///
-    /// let mut cache = TtlCache::new(2);
+    /// let mut cache = TtlCache::with_default_ttl(Duration::from_secs(30));
     ///
-    /// cache.insert(2, "a", Duration::from_secs(30));
-    /// 
-    /// cache.reset_ttl(&2)
+    /// *cache.entry("hits").or_insert_default_ttl(0) += 1;
+    /// *cache.entry("hits").or_insert_default_ttl(0) += 1;
+    /// assert_eq!(cache.get("hits"), Some(&2));
     /// ```
-    pub fn reset_ttl<Q: ?Sized>(&mut self, k: &Q)
-    where
-        K: Borrow<Q>,
-        Q: Hash + Eq,
-    {
-        if let Some(entry) = self.map.get_mut(k) {
-            if !entry.is_expired() {
-                entry.reset_duration()
-            }
+    pub fn or_insert_default_ttl(self, default: V) -> &'a mut V {
+        match self {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert_default_ttl(default),
         }
     }
+}
 
-    /// Removes the given key from the cache and returns its corresponding value.
+/// A view into a single occupied location in the cache that was unexpired at the moment of lookup.
+pub struct OccupiedEntry<'a, K: 'a, V: 'a, S: 'a = RandomState> {
+    entry: RawOccupiedLinkHashMapEntry<'a, K, InternalEntry<V>, S>,
+    hash: u64,
+    config: &'a mut TtlConfig,
+    index: &'a mut ExpiryIndex,
+    policy: Option<&'a dyn ExpiryPolicy<K, V>>,
+    notifier: &'a Notifier<K>,
+    #[cfg(feature = "stats")]
+    counters: &'a mut Counters,
+}
+
+impl<'a, K: Hash + Eq, V, S: BuildHasher> OccupiedEntry<'a, K, V, S> {
+    /// Gets a reference to the entry key
     ///
     /// # Examples
     ///
@@ -389,220 +408,4079 @@ impl<K: Eq + Hash, V, S: BuildHasher> TtlCache<K, V, S> {
     /// use std::time::Duration;
     /// use ttl_cache::TtlCache;
     ///
-    /// let mut cache = TtlCache::new(2);
-    ///
-    /// cache.insert(2, "a", Duration::from_secs(30));
+    /// let mut map = TtlCache::new();
     ///
-    /// assert_eq!(cache.remove(&1), None);
-    /// assert_eq!(cache.remove(&2), Some("a"));
-    /// assert_eq!(cache.remove(&2), None);
+    /// map.insert("foo".to_string(), 1, Duration::from_secs(30));
+    /// assert_eq!("foo", map.entry("foo".to_string()).key());
     /// ```
-    pub fn remove<Q: ?Sized>(&mut self, k: &Q) -> Option<V>
-    where
-        K: Borrow<Q>,
-        Q: Hash + Eq,
-    {
-        self.map
-            .remove(k)
-            .and_then(|x| if x.is_expired() { None } else { Some(x.value) })
+    pub fn key(&self) -> &K {
+        self.entry.key()
     }
 
-    /// Clears all values out of the cache
-    pub fn clear(&mut self) {
-        self.map.clear();
+    /// Gets a reference to the value in the entry.
+    pub fn get(&self) -> &V {
+        &self.entry.get().value
     }
 
-
-    pub fn entry(&mut self, k: K) -> Entry<K, V, S> {
-        let should_remove = self.map.get(&k).map(|value| value.is_expired()).unwrap_or(false);
-        if should_remove {
-            self.map.remove(&k);
-        }
-        match self.map.entry(k){
-            LinkedHashMapEntry::Occupied(entry) => {
-                Entry::Occupied(OccupiedEntry {
-                    entry
-                })
-            }
-            LinkedHashMapEntry::Vacant(entry) => {
-                Entry::Vacant(VacantEntry{
-                    entry
-                })
-            }
-        }
+    /// Gets a mutable reference to the value in the entry.
+    pub fn get_mut(&mut self) -> &mut V {
+        &mut self.entry.get_mut().value
     }
 
-    /// Returns an iterator over the cache's key-value pairs in oldest to youngest order.
+    /// Gets a mutable reference to the value in the entry and restarts the entry's TTL with its
+    /// original duration, like `TtlCache::get_mut_prolong`.  The entry keeps its position in the
+    /// cache.  Permanent entries are left untouched.
     ///
     /// # Examples
     ///
     /// ```
     /// use std::time::Duration;
-    /// use ttl_cache::TtlCache;
-    ///
-    /// let mut cache = TtlCache::new(2);
-    /// let duration = Duration::from_secs(30);
+    /// use ttl_cache::{Entry, TtlCache};
     ///
-    /// cache.insert(1, 10, duration);
-    /// cache.insert(2, 20, duration);
-    /// cache.insert(3, 30, duration);
+    /// let mut cache = TtlCache::new();
+    /// cache.insert("session", 0, Duration::from_secs(30));
     ///
-    /// let kvs: Vec<_> = cache.iter().collect();
-    /// assert_eq!(kvs, [(&2, &20), (&3, &30)]);
+    /// match cache.entry("session") {
+    ///     Entry::Occupied(mut entry) => *entry.get_mut_prolong() += 1,
+    ///     Entry::Vacant(entry) => {
+    ///         entry.insert(1, Duration::from_secs(30));
+    ///     }
+    /// }
+    /// assert_eq!(cache.get("session"), Some(&1));
+    /// assert!(cache.remaining_ttl("session").unwrap() > Duration::from_secs(29));
     /// ```
-    pub fn iter(&mut self) -> Iter<K, V> {
-        self.remove_expired();
-        Iter(self.map.iter())
+    pub fn get_mut_prolong(&mut self) -> &mut V {
+        self.reset_ttl();
+        &mut self.entry.get_mut().value
     }
 
-    /// Returns an iterator over the cache's key-value pairs in oldest to youngest order with
-    /// mutable references to the values.
-    ///
+    /// Returns how long the entry has left to live.  Permanent entries report `Duration::MAX`.
     ///
     /// # Examples
     ///
     /// ```
     /// use std::time::Duration;
-    /// use ttl_cache::TtlCache;
-    ///
-    /// let mut cache = TtlCache::new(2);
-    /// let duration = Duration::from_secs(30);
-    ///
-    /// cache.insert(1, 10, duration);
-    /// cache.insert(2, 20, duration);
-    /// cache.insert(3, 30, duration);
+    /// use ttl_cache::{Entry, TtlCache};
     ///
-    /// let mut n = 2;
+    /// let mut cache = TtlCache::new();
+    /// cache.insert("foo", 1, Duration::from_secs(30));
     ///
-    /// for (k, v) in cache.iter_mut() {
-    ///     assert_eq!(*k, n);
-    ///     assert_eq!(*v, n * 10);
-    ///     *v *= 10;
-    ///     n += 1;
+    /// if let Entry::Occupied(entry) = cache.entry("foo") {
+    ///     let remaining = entry.remaining_ttl();
+    ///     assert!(remaining > Duration::from_secs(29));
+    ///     assert!(remaining <= Duration::from_secs(30));
     /// }
-    ///
+    /// ```
+    pub fn remaining_ttl(&self) -> Duration {
+        self.entry.get().remaining_ttl(self.config.now())
+    }
+
+    /// Returns the instant the entry expires at, or `None` if it never expires.  With the
+    /// `compact` feature it's rounded down to the millisecond.
+    pub fn expiration(&self) -> Option<Instant> {
+        let epoch = self.config.epoch;
+        self.entry.get().expiration.and_then(|expiration| epoch.instant(expiration))
+    }
+
+    /// Returns the wall-clock time the entry expires at, or `None` if it never expires.  See
+    /// `TtlCache::expiration_system_time`.
+    pub fn expiration_system_time(&self) -> Option<SystemTime> {
+        let config = &self.config;
+        self.entry.get().expiration.and_then(|expiration| config.system_time(expiration))
+    }
+
+    /// Restarts the entry's TTL with its original duration, like `TtlCache::reset_ttl`.
+    /// Permanent entries are left untouched.
+    pub fn reset_ttl(&mut self) {
+        let old = self.entry.get().expiration;
+        self.entry.get_mut().reset_duration(self.config, self.config.now());
+        self.reindex(old);
+    }
+
+    /// Replaces the entry's duration with `ttl` and restarts it from now.  Later resets use
+    /// the new duration.  A permanent entry becomes a regular one.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use ttl_cache::{Entry, TtlCache};
+    ///
+    /// let mut cache = TtlCache::new();
+    /// cache.insert("foo", 1, Duration::from_secs(30));
+    ///
+    /// if let Entry::Occupied(mut entry) = cache.entry("foo") {
+    ///     entry.set_ttl(Duration::from_secs(5));
+    /// }
+    /// assert!(cache.remaining_ttl("foo").unwrap() <= Duration::from_secs(5));
+    /// ```
+    pub fn set_ttl(&mut self, ttl: Duration) {
+        let old = self.entry.get().expiration;
+        self.entry.get_mut().set_duration(ttl, self.config, self.config.now());
+        self.reindex(old);
+    }
+
+    /// Converts the entry into a mutable reference to its value with a lifetime bound to the
+    /// cache itself
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use ttl_cache::{Entry, TtlCache};
+    ///
+    /// fn lookup(cache: &mut TtlCache<String, u32>, k: String) -> Option<&mut u32> {
+    ///     match cache.entry(k) {
+    ///         Entry::Occupied(entry) => Some(entry.into_mut()),
+    ///         Entry::Vacant(_) => None,
+    ///     }
+    /// }
+    ///
+    /// let mut cache = TtlCache::new();
+    /// cache.insert("foo".to_string(), 1, Duration::from_secs(30));
+    ///
+    /// *lookup(&mut cache, "foo".to_string()).unwrap() += 1;
+    /// assert_eq!(cache.get("foo"), Some(&2));
+    /// assert_eq!(lookup(&mut cache, "bar".to_string()), None);
+    /// ```
+    pub fn into_mut(self) -> &'a mut V {
+        &mut self.entry.into_mut().value
+    }
+
+    /// Sets the value of the entry, and returns the entry's old value
+    pub fn insert(&mut self, value: V, duration: Duration) -> V {
+        let internal_entry = self.config.new_entry(value, duration, self.config.now());
+        self.replace(internal_entry).value
+    }
+
+    /// Takes the value out of the entry, removing it from the cache, and returns it
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use ttl_cache::{Entry, TtlCache};
+    ///
+    /// let mut cache = TtlCache::new();
+    ///
+    /// cache.insert("foo", 1, Duration::from_secs(30));
+    /// if let Entry::Occupied(entry) = cache.entry("foo") {
+    ///     assert_eq!(entry.remove(), 1);
+    /// }
+    /// assert!(!cache.contains_key("foo"));
+    /// ```
+    pub fn remove(self) -> V {
+        #[cfg(feature = "metrics")]
+        self.counters.remove(1);
+        let (k, internal_entry) = self.entry.remove_entry();
+        self.notifier.send(k, RemovalCause::Removed);
+        internal_entry.value
+    }
+
+    /// Removes the entry from the cache and returns the stored key along with the value
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::sync::Arc;
+    /// use std::time::Duration;
+    /// use ttl_cache::{Entry, TtlCache};
+    ///
+    /// let mut cache = TtlCache::new();
+    /// let key: Arc<str> = Arc::from("foo");
+    ///
+    /// cache.insert(key.clone(), 1, Duration::from_secs(30));
+    /// if let Entry::Occupied(entry) = cache.entry(Arc::from("foo")) {
+    ///     let (stored_key, value) = entry.remove_entry();
+    ///     assert!(Arc::ptr_eq(&stored_key, &key));
+    ///     assert_eq!(value, 1);
+    /// }
+    /// assert!(!cache.contains_key("foo"));
+    /// ```
+    pub fn remove_entry(self) -> (K, V) {
+        #[cfg(feature = "metrics")]
+        self.counters.remove(1);
+        let (k, internal_entry) = self.entry.remove_entry();
+        (k, internal_entry.value)
+    }
+
+    /// Replaces the value of the entry, and returns the entry's old value.  The entry keeps
+    /// its current expiration, duration and position in the cache.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use ttl_cache::{Entry, TtlCache};
+    ///
+    /// let mut cache = TtlCache::new();
+    /// cache.insert("foo", 1, Duration::from_secs(30));
+    ///
+    /// if let Entry::Occupied(mut entry) = cache.entry("foo") {
+    ///     let expiration = entry.expiration();
+    ///     assert_eq!(entry.insert_keep_ttl(2), 1);
+    ///     assert_eq!(entry.expiration(), expiration);
+    /// }
+    /// assert_eq!(cache.get("foo"), Some(&2));
+    /// ```
+    pub fn insert_keep_ttl(&mut self, value: V) -> V {
+        #[cfg(feature = "stats")]
+        {
+            self.entry.get_mut().inserted = self.config.now();
+            self.counters.insert(self.entry.get().inserted_ttl(), true);
+        }
+        #[cfg(feature = "access-counts")]
+        {
+            self.entry.get_mut().accesses = Counter::default();
+        }
+        mem::replace(&mut self.entry.get_mut().value, value)
+    }
+
+    /// Sets the value of the entry with an absolute expiration, and returns the entry's old
+    /// value.  See `VacantEntry::insert_with_expiration` for how the deadline is applied.
+    pub fn insert_with_expiration(&mut self, value: V, expires_at: Instant) -> V {
+        let internal_entry = self.config.entry_expiring_at(value, expires_at, self.config.now());
+        self.replace(internal_entry).value
+    }
+
+    /// Sets the value of the entry with the TTL the cache's expiry policy gives for an update,
+    /// or else the cache's default TTL, and returns the entry's old value
+    ///
+    /// # Panics
+    ///
+    /// Panics if the cache has neither an expiry policy nor a default TTL.
+    pub fn insert_default_ttl(&mut self, value: V) -> V {
+        let now = self.config.now();
+        let old = self.entry.get();
+        let internal_entry =
+            self.config.entry_on_update(self.policy, self.entry.key(), value, old, now);
+        self.replace(internal_entry).value
+    }
+
+    /// Swaps in a fresh entry, moving it to the back like `TtlCache::insert` does
+    fn replace(&mut self, internal_entry: InternalEntry<V>) -> InternalEntry<V> {
+        let old = self.entry.get().expiration;
+        self.index.note(self.hash, old, internal_entry.expiration);
+        self.entry.to_back();
+        #[cfg(feature = "stats")]
+        self.counters.insert(internal_entry.inserted_ttl(), true);
+        self.entry.replace_value(internal_entry)
+    }
+
+    /// Tells the index the entry's deadline moved from `old`
+    fn reindex(&mut self, old: Option<Stamp>) {
+        self.index.note(self.hash, old, self.entry.get().expiration);
+    }
+}
+
+
+
+/// A view into a single empty location in the cache
+pub struct VacantEntry<'a, K: 'a, V: 'a, S: 'a = RandomState> {
+    key: K,
+    slot: VacantSlot<'a, K, V, S>,
+    hash: u64,
+    config: &'a mut TtlConfig,
+    index: &'a mut ExpiryIndex,
+    notifier: &'a Notifier<K>,
+    #[cfg(feature = "stats")]
+    counters: &'a mut Counters,
+    policy: Option<&'a dyn ExpiryPolicy<K, V>>,
+}
+
+/// Where a vacant entry's value goes.  An expired entry found by the lookup is reused in place
+/// rather than removed up front, which would cost a second lookup to find the vacant slot.
+enum VacantSlot<'a, K: 'a, V: 'a, S: 'a> {
+    Empty(RawVacantLinkHashMapEntry<'a, K, InternalEntry<V>, S>),
+    Expired(RawOccupiedLinkHashMapEntry<'a, K, InternalEntry<V>, S>),
+}
+
+impl<'a, K: 'a + Hash + Eq, V: 'a, S: BuildHasher> VacantEntry<'a, K, V, S> {
+    /// Gets a reference to the entry key
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ttl_cache::TtlCache;
+    ///
+    /// let mut map = TtlCache::<String, u32>::new();
+    ///
+    /// assert_eq!("foo", map.entry("foo".to_string()).key());
+    /// ```
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+
+    /// Takes ownership of the key, leaving the cache untouched.  This lets a caller give the key
+    /// back without inserting, so it never has to be cloned up front.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use ttl_cache::{Entry, TtlCache};
+    ///
+    /// let mut cache: TtlCache<String, u32> = TtlCache::new();
+    /// let mut loads_left = 1;
+    ///
+    /// // Loads on a miss while the budget lasts, otherwise hands the key back unused
+    /// let mut lookup = |cache: &mut TtlCache<String, u32>, key: String| match cache.entry(key) {
+    ///     Entry::Occupied(entry) => Ok(*entry.get()),
+    ///     Entry::Vacant(entry) if loads_left > 0 => {
+    ///         loads_left -= 1;
+    ///         let value = entry.key().len() as u32;
+    ///         Ok(*entry.insert(value, Duration::from_secs(30)))
+    ///     }
+    ///     Entry::Vacant(entry) => Err(entry.into_key()),
+    /// };
+    ///
+    /// assert_eq!(lookup(&mut cache, "foo".to_string()), Ok(3));
+    /// assert_eq!(lookup(&mut cache, "foo".to_string()), Ok(3));
+    /// assert_eq!(lookup(&mut cache, "quux".to_string()), Err("quux".to_string()));
+    /// ```
+    pub fn into_key(self) -> K {
+        #[cfg(feature = "stats")]
+        self.slot.count(self.counters, None);
+        self.slot.discard(self.notifier);
+        self.key
+    }
+
+    /// Sets the value of the entry with the VacantEntry's key,
+    /// and returns a mutable reference to it
+    pub fn insert(self, value: V, duration: Duration) -> &'a mut V {
+        let internal_entry = self.config.new_entry(value, duration, self.config.now());
+        self.insert_entry(internal_entry)
+    }
+
+    /// Sets the value of the entry with the VacantEntry's key and an absolute expiration, and
+    /// returns a mutable reference to it.  The deadline is not jittered, but a deadline further
+    /// away than the cache's max TTL is pulled in to it.  The time left until the deadline
+    /// becomes the entry's duration for later resets.
+    ///
+    /// A deadline that has already passed still inserts the entry, but it is expired right
+    /// away: lookups won't return it and the next purge drops it.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::time::{Duration, Instant};
+    /// use ttl_cache::{Entry, TtlCache};
+    ///
+    /// let mut cache = TtlCache::new();
+    /// let expires_at = Instant::now() + Duration::from_secs(30);
+    ///
+    /// if let Entry::Vacant(entry) = cache.entry("foo") {
+    ///     entry.insert_with_expiration(1, expires_at);
+    /// }
+    /// if let Entry::Occupied(entry) = cache.entry("foo") {
+    ///     assert!(entry.expiration() <= Some(expires_at));
+    /// }
+    /// ```
+    pub fn insert_with_expiration(self, value: V, expires_at: Instant) -> &'a mut V {
+        let internal_entry = self.config.entry_expiring_at(value, expires_at, self.config.now());
+        self.insert_entry(internal_entry)
+    }
+
+    /// Sets the value of the entry with the VacantEntry's key and the TTL the cache's expiry
+    /// policy gives for a new entry, or else the cache's default TTL, and returns a mutable
+    /// reference to it
+    ///
+    /// # Panics
+    ///
+    /// Panics if the cache has neither an expiry policy nor a default TTL.
+    pub fn insert_default_ttl(self, value: V) -> &'a mut V {
+        let now = self.config.now();
+        let internal_entry = self.config.entry_on_create(self.policy, &self.key, value, now);
+        self.insert_entry(internal_entry)
+    }
+
+    fn insert_entry(self, internal_entry: InternalEntry<V>) -> &'a mut V {
+        #[cfg(feature = "stats")]
+        self.slot.count(self.counters, Some(&internal_entry));
+        self.slot.insert(self.key, internal_entry, self.hash, self.index, self.notifier)
+    }
+}
+
+impl<'a, K: 'a + Hash + Eq, V: 'a, S: BuildHasher> VacantSlot<'a, K, V, S> {
+    /// Stores the entry at the back of the cache, over the expired one if there is one, and
+    /// records its deadline in the index under `hash`
+    fn insert(
+        self,
+        key: K,
+        internal_entry: InternalEntry<V>,
+        hash: u64,
+        index: &mut ExpiryIndex,
+        notifier: &Notifier<K>,
+    ) -> &'a mut V {
+        let old = match self {
+            VacantSlot::Empty(_) => None,
+            VacantSlot::Expired(ref entry) => entry.get().expiration,
+        };
+        index.note(hash, old, internal_entry.expiration);
+        match self {
+            VacantSlot::Empty(entry) => &mut entry.insert(key, internal_entry).1.value,
+            VacantSlot::Expired(mut entry) => {
+                entry.to_back();
+                notifier.send(entry.replace_key(key), RemovalCause::Expired);
+                entry.replace_value(internal_entry);
+                &mut entry.into_mut().value
+            }
+        }
+    }
+
+    /// Gives up on inserting, dropping the expired entry if there is one
+    fn discard(self, notifier: &Notifier<K>) {
+        if let VacantSlot::Expired(entry) = self {
+            notifier.send(entry.remove_entry().0, RemovalCause::Expired);
+        }
+    }
+
+    /// Counts the expired entry, if there is one, as it is about to be replaced or dropped,
+    /// along with the entry being inserted if there is one
+    #[cfg(feature = "stats")]
+    fn count(&self, counters: &mut Counters, inserting: Option<&InternalEntry<V>>) {
+        if let VacantSlot::Expired(_) = *self {
+            counters.expire(1);
+        }
+        if let Some(entry) = inserting {
+            counters.insert(entry.inserted_ttl(), false);
+        }
+    }
+}
+
+/// The outcome of looking a key up in the map, with expired entries counted as vacant
+enum Lookup<'a, K: 'a, V: 'a, S: 'a> {
+    Live(RawOccupiedLinkHashMapEntry<'a, K, InternalEntry<V>, S>),
+    Vacant(VacantSlot<'a, K, V, S>),
+}
+
+impl<'a, K: 'a + Hash + Eq, V: 'a, S: BuildHasher> Lookup<'a, K, V, S> {
+    fn new<Q>(
+        map: &'a mut LinkedHashMap<K, InternalEntry<V>, S>,
+        hash: u64,
+        k: &Q,
+        now: Stamp,
+    ) -> Self
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        Lookup::from_raw(map.raw_entry_mut().from_key_hashed_nocheck(hash, k), now)
+    }
+
+    fn from_raw(entry: RawLinkHashMapEntry<'a, K, InternalEntry<V>, S>, now: Stamp) -> Self {
+        match entry {
+            RawLinkHashMapEntry::Occupied(entry) => {
+                if entry.get().is_expired(now) {
+                    Lookup::Vacant(VacantSlot::Expired(entry))
+                } else {
+                    Lookup::Live(entry)
+                }
+            }
+            RawLinkHashMapEntry::Vacant(entry) => Lookup::Vacant(VacantSlot::Empty(entry)),
+        }
+    }
+
+    #[cfg(feature = "stats")]
+    fn count(&self, counters: &Counters, now: Stamp) {
+        match *self {
+            Lookup::Live(ref entry) => counters.hit(entry.get(), now),
+            Lookup::Vacant(VacantSlot::Expired(_)) => counters.miss(true),
+            Lookup::Vacant(VacantSlot::Empty(_)) => counters.miss(false),
+        }
+    }
+}
+
+/// A view into a single location in a map, which may be vacant or occupied, looked up by a
+/// borrowed key.  Returned by `TtlCache::entry_ref`.
+pub enum EntryRef<'a, 'q, K: 'a, Q: 'q + ?Sized, V: 'a, S: 'a = RandomState> {
+    /// An occupied Entry.
+    Occupied(OccupiedEntry<'a, K, V, S>),
+    /// A vacant Entry.
+    Vacant(VacantEntryRef<'a, 'q, K, Q, V, S>),
+}
+
+impl<'a, 'q, K, Q, V, S> EntryRef<'a, 'q, K, Q, V, S>
+where
+    K: Hash + Eq + Borrow<Q>,
+    Q: ?Sized + Hash + Eq,
+    S: BuildHasher,
+{
+    /// Gets a reference to the entry key
+    pub fn key(&self) -> &Q {
+        match *self {
+            EntryRef::Occupied(ref e) => e.key().borrow(),
+            EntryRef::Vacant(ref e) => e.key(),
+        }
+    }
+
+    /// Provides in-place mutable access to an occupied entry before any potential inserts into
+    /// the cache.  The entry's TTL is left alone.
+    pub fn and_modify<F: FnOnce(&mut V)>(self, f: F) -> Self {
+        match self {
+            EntryRef::Occupied(mut entry) => {
+                f(entry.get_mut());
+                EntryRef::Occupied(entry)
+            }
+            EntryRef::Vacant(entry) => EntryRef::Vacant(entry),
+        }
+    }
+
+    /// Ensures a value is in the entry by inserting the default with the given TTL if empty,
+    /// and returns a mutable reference to the value in the entry.  The key is only turned into
+    /// an owned one when the entry is vacant.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use ttl_cache::TtlCache;
+    ///
+    /// let mut cache: TtlCache<String, u32> = TtlCache::new();
+    /// let duration = Duration::from_secs(30);
+    ///
+    /// *cache.entry_ref("hits").or_insert(0, duration) += 1;
+    /// *cache.entry_ref("hits").or_insert(0, duration) += 1;
+    /// assert_eq!(cache.get("hits"), Some(&2));
+    /// ```
+    pub fn or_insert(self, default: V, ttl: Duration) -> &'a mut V
+    where
+        Q: ToOwned<Owned = K>,
+    {
+        match self {
+            EntryRef::Occupied(entry) => entry.into_mut(),
+            EntryRef::Vacant(entry) => entry.insert(default, ttl),
+        }
+    }
+
+    /// Ensures a value is in the entry by inserting the result of the default function with
+    /// the given TTL if empty, and returns a mutable reference to the value in the entry.  The
+    /// function is only called when the entry is vacant.
+    pub fn or_insert_with<F: FnOnce() -> V>(self, ttl: Duration, default: F) -> &'a mut V
+    where
+        Q: ToOwned<Owned = K>,
+    {
+        match self {
+            EntryRef::Occupied(entry) => entry.into_mut(),
+            EntryRef::Vacant(entry) => entry.insert(default(), ttl),
+        }
+    }
+
+    /// Ensures a value is in the entry by inserting `V::default()` with the given TTL if empty,
+    /// and returns a mutable reference to the value in the entry.
+    pub fn or_default(self, ttl: Duration) -> &'a mut V
+    where
+        Q: ToOwned<Owned = K>,
+        V: Default,
+    {
+        match self {
+            EntryRef::Occupied(entry) => entry.into_mut(),
+            EntryRef::Vacant(entry) => entry.insert(V::default(), ttl),
+        }
+    }
+}
+
+/// A view into a single empty location in the cache, looked up by a borrowed key
+pub struct VacantEntryRef<'a, 'q, K: 'a, Q: 'q + ?Sized, V: 'a, S: 'a = RandomState> {
+    key: &'q Q,
+    slot: VacantSlot<'a, K, V, S>,
+    hash: u64,
+    config: &'a mut TtlConfig,
+    index: &'a mut ExpiryIndex,
+    notifier: &'a Notifier<K>,
+    #[cfg(feature = "stats")]
+    counters: &'a mut Counters,
+}
+
+impl<'a, 'q, K, Q, V, S> VacantEntryRef<'a, 'q, K, Q, V, S>
+where
+    K: 'a + Hash + Eq + Borrow<Q>,
+    Q: ?Sized + Hash + Eq,
+    V: 'a,
+    S: BuildHasher,
+{
+    /// Gets a reference to the key the entry was looked up with
+    pub fn key(&self) -> &'q Q {
+        self.key
+    }
+
+    /// Sets the value of the entry under an owned copy of the borrowed key, and returns a
+    /// mutable reference to it
+    pub fn insert(self, value: V, duration: Duration) -> &'a mut V
+    where
+        Q: ToOwned<Owned = K>,
+    {
+        self.insert_with(Q::to_owned, value, duration)
+    }
+
+    /// Sets the value of the entry under the key built by `make_key` from the borrowed key,
+    /// and returns a mutable reference to it.  This is for keys that can't be built through
+    /// `ToOwned`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the built key is not equal to the borrowed one.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::sync::Arc;
+    /// use std::time::Duration;
+    /// use ttl_cache::{EntryRef, TtlCache};
+    ///
+    /// let mut cache: TtlCache<Arc<str>, u32> = TtlCache::new();
+    ///
+    /// if let EntryRef::Vacant(entry) = cache.entry_ref("foo") {
+    ///     entry.insert_with(Arc::from, 1, Duration::from_secs(30));
+    /// }
+    /// assert_eq!(cache.get("foo"), Some(&1));
+    /// ```
+    pub fn insert_with<F>(self, make_key: F, value: V, duration: Duration) -> &'a mut V
+    where
+        F: FnOnce(&'q Q) -> K,
+    {
+        let key = make_key(self.key);
+        assert!(
+            key.borrow() == self.key,
+            "the key built for a VacantEntryRef must equal the key it was looked up with"
+        );
+        let internal_entry = self.config.new_entry(value, duration, self.config.now());
+        #[cfg(feature = "stats")]
+        self.slot.count(self.counters, Some(&internal_entry));
+        self.slot.insert(key, internal_entry, self.hash, self.index, self.notifier)
+    }
+}
+
+impl<'a, K: fmt::Debug, V: fmt::Debug, S> fmt::Debug for Entry<'a, K, V, S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Entry::Occupied(ref e) => f.debug_tuple("Entry").field(e).finish(),
+            Entry::Vacant(ref e) => f.debug_tuple("Entry").field(e).finish(),
+        }
+    }
+}
+
+impl<'a, K: fmt::Debug, V: fmt::Debug, S> fmt::Debug for OccupiedEntry<'a, K, V, S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OccupiedEntry")
+            .field("key", self.entry.key())
+            .field("value", &self.entry.get().value)
+            .field("remaining_ttl", &self.entry.get().remaining_ttl(self.config.now()))
+            .finish()
+    }
+}
+
+impl<'a, K: fmt::Debug, V, S> fmt::Debug for VacantEntry<'a, K, V, S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("VacantEntry").field(&self.key).finish()
+    }
+}
+
+impl<'a, 'q, K, Q, V, S> fmt::Debug for EntryRef<'a, 'q, K, Q, V, S>
+where
+    K: fmt::Debug,
+    Q: ?Sized + fmt::Debug,
+    V: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            EntryRef::Occupied(ref e) => f.debug_tuple("EntryRef").field(e).finish(),
+            EntryRef::Vacant(ref e) => f.debug_tuple("EntryRef").field(e).finish(),
+        }
+    }
+}
+
+impl<'a, 'q, K, Q: ?Sized + fmt::Debug, V, S> fmt::Debug for VacantEntryRef<'a, 'q, K, Q, V, S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("VacantEntryRef").field(&self.key).finish()
+    }
+}
+
+/// When `insert` and friends purge expired entries.  Set with `TtlCache::set_purge_policy`.
+///
+/// Purging goes straight to the expired entries, but after a mass expiration there can be many
+/// of them.  By default each insert only removes a few, so the backlog drains over the inserts
+/// that follow instead of stalling the first one.  The other policies tune that work or leave
+/// it to the caller.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum PurgePolicy {
+    /// Every insert removes up to a small, fixed number of expired entries first.  That is
+    /// more than one insert adds, so the cost stays amortized O(1) and any backlog is
+    /// eventually cleared.  This is the default.
+    #[default]
+    OnInsert,
+    /// Inserts never purge; expired entries stay until `remove_expired` is called or they are
+    /// overwritten or removed.
+    Manual,
+    /// Every nth insert removes all expired entries.
+    EveryNInserts(usize),
+    /// Every insert removes at most this many expired entries.
+    Budget(usize),
+}
+
+/// How many expired entries a single insert removes under `PurgePolicy::OnInsert`
+const ON_INSERT_PURGE_LIMIT: usize = 64;
+
+/// Whether a value returned by `TtlCache::get_fresh_or_stale` is still within its TTL.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Freshness {
+    /// The entry has not expired.
+    Fresh,
+    /// The entry has expired, but is still within the cache's stale grace window.
+    Stale,
+}
+
+/// The cache-level settings every TTL passes through before it is applied to an entry.
+#[derive(Clone, Default)]
+struct TtlConfig {
+    default_ttl: Option<Duration>,
+    max_ttl: Option<Duration>,
+    time_to_idle: Option<Duration>,
+    stale_grace: Option<Duration>,
+    jitter: Option<Jitter>,
+    purge_policy: PurgePolicy,
+    epoch: Epoch,
+    /// `None` for the system clock, which is then called directly
+    clock: Option<Arc<dyn Clock>>,
+}
+
+impl TtlConfig {
+    fn now(&self) -> Stamp {
+        self.epoch.stamp(self.clock_now())
+    }
+
+    fn clock_now(&self) -> Instant {
+        match self.clock {
+            Some(ref clock) => clock.now(),
+            None => Instant::now(),
+        }
+    }
+
+    /// Whether an expired entry is also past the stale grace window, and so may be removed
+    fn is_past_grace<V>(&self, entry: &InternalEntry<V>, now: Stamp) -> bool {
+        match self.purge_cutoff(now) {
+            Some(cutoff) => entry.is_expired(cutoff),
+            None => false,
+        }
+    }
+
+    /// How far entries must have expired by to be past the stale grace window, `None` if none
+    /// can be yet
+    fn purge_cutoff(&self, now: Stamp) -> Option<Stamp> {
+        match self.stale_grace {
+            Some(grace) => now.checked_sub(grace),
+            None => Some(now),
+        }
+    }
+
+    /// The wall-clock time of a deadline, `None` if it's too far out for an `Instant`
+    fn system_time(&self, stamp: Stamp) -> Option<SystemTime> {
+        let instant = self.epoch.instant(stamp)?;
+        Some(match self.clock {
+            Some(ref clock) => clock.system_time_at(instant),
+            None => SystemClock.system_time_at(instant),
+        })
+    }
+
+    fn default_ttl(&self) -> Duration {
+        self.default_ttl
+            .expect("no default TTL is configured for this cache")
+    }
+
+    fn effective_ttl(&mut self, ttl: Duration) -> Duration {
+        let ttl = match self.jitter {
+            Some(ref mut jitter) => jitter.apply(ttl),
+            None => ttl,
+        };
+        self.capped(ttl)
+    }
+
+    fn capped(&self, ttl: Duration) -> Duration {
+        match self.max_ttl {
+            Some(max_ttl) if ttl > max_ttl => max_ttl,
+            _ => ttl,
+        }
+    }
+
+    /// An entry for a key that had no unexpired entry, with the TTL the policy gives or else
+    /// the default TTL
+    fn entry_on_create<K, V>(
+        &mut self,
+        policy: Option<&dyn ExpiryPolicy<K, V>>,
+        k: &K,
+        v: V,
+        now: Stamp,
+    ) -> InternalEntry<V> {
+        match policy {
+            Some(policy) => {
+                let ttl = policy.expiry_after_create(k, &v);
+                self.entry_with_ttl(v, ttl, now)
+            }
+            None => {
+                let ttl = self.default_ttl();
+                self.new_entry(v, ttl, now)
+            }
+        }
+    }
+
+    /// An entry replacing the unexpired `old`, with the TTL the policy gives or else the
+    /// default TTL.  If the policy keeps the TTL left, the entry keeps its deadline.
+    fn entry_on_update<K, V>(
+        &mut self,
+        policy: Option<&dyn ExpiryPolicy<K, V>>,
+        k: &K,
+        v: V,
+        old: &InternalEntry<V>,
+        now: Stamp,
+    ) -> InternalEntry<V> {
+        let policy = match policy {
+            Some(policy) => policy,
+            None => return self.entry_on_create(None, k, v, now),
+        };
+        let remaining = old.ttl_left(now);
+        let ttl = policy.expiry_after_update(k, &v, remaining);
+        if ttl == remaining {
+            InternalEntry {
+                value: v,
+                expiration: old.expiration,
+                deadline: old.deadline,
+                duration: old.duration,
+                renewal: old.renewal,
+                #[cfg(feature = "access-counts")]
+                accesses: Counter::default(),
+                #[cfg(feature = "stats")]
+                inserted: now,
+            }
+        } else {
+            self.entry_with_ttl(v, ttl, now)
+        }
+    }
+
+    fn entry_with_ttl<V>(&mut self, v: V, ttl: Option<Duration>, now: Stamp) -> InternalEntry<V> {
+        match ttl {
+            Some(ttl) => self.new_entry(v, ttl, now),
+            None => InternalEntry::permanent(v, now),
+        }
+    }
+
+    fn new_entry<V>(&mut self, v: V, ttl: Duration, now: Stamp) -> InternalEntry<V> {
+        let effective_ttl = self.effective_ttl(ttl);
+        let mut entry = InternalEntry::new_at(v, ttl, effective_ttl, now);
+        entry.touch(self.time_to_idle, now);
+        entry
+    }
+
+    /// An absolute deadline is taken as is: it gets no jitter, but is still pulled in to
+    /// `max_ttl` from now.  The time left until it becomes the entry's duration for resets.
+    fn entry_expiring_at<V>(&self, v: V, expires_at: Instant, now: Stamp) -> InternalEntry<V> {
+        let expires_at = self.epoch.stamp(expires_at);
+        self.entry_expiring_in(v, expires_at.saturating_duration_since(now), now)
+    }
+
+    /// Like `entry_expiring_at`, for a wall-clock deadline.  One that's already passed gives an
+    /// expired entry.
+    fn entry_expiring_at_system_time<V>(
+        &self,
+        v: V,
+        expires_at: SystemTime,
+        now: Stamp,
+    ) -> InternalEntry<V> {
+        let left = match self.system_time(now) {
+            Some(wall_now) => expires_at.duration_since(wall_now).unwrap_or_default(),
+            None => Duration::ZERO,
+        };
+        self.entry_expiring_in(v, left, now)
+    }
+
+    fn entry_expiring_in<V>(&self, v: V, left: Duration, now: Stamp) -> InternalEntry<V> {
+        let ttl = match self.max_ttl {
+            Some(max_ttl) if left > max_ttl => max_ttl,
+            _ => left,
+        };
+        let mut entry = InternalEntry::new_at(v, left, ttl, now);
+        entry.touch(self.time_to_idle, now);
+        entry
+    }
+}
+
+/// Hashes a key the same way the map does, for the raw entry lookups
+fn make_hash<Q, S>(hash_builder: &S, k: &Q) -> u64
+where
+    Q: ?Sized + Hash,
+    S: BuildHasher,
+{
+    let mut hasher = hash_builder.build_hasher();
+    k.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Spreads TTLs over `[ttl * (1 - fraction), ttl * (1 + fraction)]` so entries inserted
+/// together don't all expire together.
+#[derive(Clone)]
+struct Jitter {
+    fraction: f64,
+    // splitmix64, which is plenty for spreading out deadlines and keeps the crate free of
+    // dependencies
+    state: u64,
+}
+
+impl Jitter {
+    fn new(fraction: f64, seed: u64) -> Self {
+        assert!(
+            (0.0..=1.0).contains(&fraction),
+            "TTL jitter must be between 0.0 and 1.0, got {}",
+            fraction
+        );
+        Jitter {
+            fraction,
+            state: seed,
+        }
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Returns a jitter with the same fraction and a seed drawn from this one, so the two
+    /// don't hand out the same sequence of factors
+    fn fork(&mut self) -> Jitter {
+        Jitter {
+            fraction: self.fraction,
+            state: self.next_u64(),
+        }
+    }
+
+    fn apply(&mut self, ttl: Duration) -> Duration {
+        // A uniformly distributed float in [0, 1) built from the top 53 bits
+        let unit = (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64;
+        let factor = 1.0 - self.fraction + 2.0 * self.fraction * unit;
+        let secs = ttl.as_secs_f64() * factor;
+        if secs >= Duration::MAX.as_secs_f64() {
+            Duration::MAX
+        } else {
+            Duration::from_secs_f64(secs)
+        }
+    }
+}
+
+#[derive(Clone)]
+struct InternalEntry<V> {
+    value: V,
+    /// When the entry expires: its TTL deadline, or earlier once it has gone unaccessed for
+    /// the cache's time-to-idle.  `None` for entries that never expire: permanent ones, and
+    /// ones whose TTL reaches further than an `Instant` can
+    expiration: Option<Stamp>,
+    /// The TTL deadline, which accesses only extend for sliding entries
+    deadline: Option<Stamp>,
+    duration: Span,
+    renewal: Renewal,
+    /// Hits on the entry since it was inserted
+    #[cfg(feature = "access-counts")]
+    accesses: Counter,
+    /// When the value was inserted, which resetting the TTL leaves alone
+    #[cfg(feature = "stats")]
+    inserted: Stamp,
+}
+
+/// What an access does to an entry's TTL
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Renewal {
+    /// The TTL runs out at a fixed deadline
+    Fixed,
+    /// Every access restarts the TTL, like `TtlCache::get_mut_prolong`
+    Sliding,
+}
+
+impl<V> InternalEntry<V> {
+    /// `duration` is what the entry restarts from on a reset and `ttl` the adjusted duration
+    /// it starts out with.
+    fn new_at(v: V, duration: Duration, ttl: Duration, now: Stamp) -> Self {
+        let deadline = now.checked_add(ttl);
+        InternalEntry {
+            value: v,
+            expiration: deadline,
+            deadline,
+            duration: Span::new(duration),
+            renewal: Renewal::Fixed,
+            #[cfg(feature = "access-counts")]
+            accesses: Counter::default(),
+            #[cfg(feature = "stats")]
+            inserted: now,
+        }
+    }
+
+    #[cfg_attr(not(feature = "stats"), allow(unused_variables))]
+    fn permanent(v: V, now: Stamp) -> Self {
+        InternalEntry {
+            value: v,
+            expiration: None,
+            deadline: None,
+            duration: Span::new(Duration::MAX),
+            renewal: Renewal::Fixed,
+            #[cfg(feature = "access-counts")]
+            accesses: Counter::default(),
+            #[cfg(feature = "stats")]
+            inserted: now,
+        }
+    }
+
+    /// Records an access at `now` the way the entry's renewal asks for, then lets the policy
+    /// have its say
+    fn access<K>(
+        &mut self,
+        k: &K,
+        policy: Option<&dyn ExpiryPolicy<K, V>>,
+        config: &mut TtlConfig,
+        now: Stamp,
+    ) {
+        match self.renewal {
+            Renewal::Fixed => self.touch(config.time_to_idle, now),
+            Renewal::Sliding => self.reset_duration(config, now),
+        }
+        if let Some(policy) = policy {
+            self.after_read(k, policy, config, now);
+        }
+    }
+
+    fn after_read<K>(
+        &mut self,
+        k: &K,
+        policy: &dyn ExpiryPolicy<K, V>,
+        config: &TtlConfig,
+        now: Stamp,
+    ) {
+        let remaining = self.ttl_left(now);
+        let ttl = policy.expiry_after_read(k, &self.value, remaining);
+        if ttl != remaining {
+            self.deadline = ttl.and_then(|ttl| now.checked_add(config.capped(ttl)));
+            self.touch(config.time_to_idle, now);
+        }
+    }
+
+    /// The TTL the value was inserted with, `None` if it never expires
+    #[cfg(feature = "stats")]
+    fn inserted_ttl(&self) -> Option<Duration> {
+        self.deadline
+            .map(|deadline| deadline.saturating_duration_since(self.inserted))
+    }
+
+    /// How long past its expiration the entry is at `now`
+    #[cfg(feature = "tracing")]
+    fn overdue(&self, now: Stamp) -> Duration {
+        self.expiration
+            .map_or(Duration::ZERO, |expiration| now.saturating_duration_since(expiration))
+    }
+
+    fn ttl_left(&self, now: Stamp) -> Option<Duration> {
+        self.expiration
+            .map(|expiration| expiration.saturating_duration_since(now))
+    }
+
+    /// Records an access at `now`: the entry expires once it has gone unaccessed for
+    /// `time_to_idle`, but never past its TTL deadline.  Entries that never expire don't idle
+    /// out either.
+    fn touch(&mut self, time_to_idle: Option<Duration>, now: Stamp) {
+        self.expiration = match (self.deadline, time_to_idle) {
+            (Some(deadline), Some(time_to_idle)) => match now.checked_add(time_to_idle) {
+                Some(idle) if idle < deadline => Some(idle),
+                _ => Some(deadline),
+            },
+            (deadline, _) => deadline,
+        };
+    }
+
+    /// The deadline is exclusive, see the crate docs
+    fn is_expired(&self, now: Stamp) -> bool {
+        match self.expiration {
+            Some(expiration) => now >= expiration,
+            None => false,
+        }
+    }
+
+    fn remaining_ttl(&self, now: Stamp) -> Duration {
+        match self.expiration {
+            Some(expiration) => expiration.checked_duration_since(now).unwrap_or_default(),
+            None => Duration::MAX,
+        }
+    }
+
+    fn set_duration(&mut self, duration: Duration, config: &mut TtlConfig, now: Stamp) {
+        self.duration = Span::new(duration);
+        self.deadline = now.checked_add(config.effective_ttl(duration));
+        self.touch(config.time_to_idle, now);
+    }
+
+    fn reset_duration(&mut self, config: &mut TtlConfig, now: Stamp) {
+        if self.deadline.is_some() {
+            self.deadline = now.checked_add(config.effective_ttl(self.duration.get()));
+            self.touch(config.time_to_idle, now);
+        }
+    }
+}
+
+/// A time sensitive cache.
+///
+/// A cache is `Send` whenever its keys, values and hash builder are, and `Sync` too without the
+/// `stats` feature.  With it, lookups through `&self` bump plain `Cell` counters rather than
+/// atomics, so a single thread pays nothing extra for them, but the cache is no longer `Sync`.
+/// To look up from several threads at once, put it behind a `Mutex`, or use
+/// `sync::SharedTtlCache` or `sharded::ShardedTtlCache`, which do that.  Entries also remember
+/// when their value was inserted, for `avg_age_at_hit`.  The `access-counts` feature builds on
+/// `stats` and keeps a hit count on every entry as well, for `access_count` and
+/// `top_n_by_hits`, at the cost of a word per entry.  The `metrics` feature also builds on
+/// `stats`, emitting events through the `metrics` facade as they happen once
+/// `register_metrics` is called.
+///
+/// The `tracing` feature emits `tracing` events as entries are inserted, removed, expire and
+/// get evicted, see `trace_keys`.
+///
+/// With the `compact` feature, entries keep their deadlines and durations as whole milliseconds
+/// counted from when the cache was created, which saves 24 bytes per entry at the cost of
+/// millisecond precision.
+///
+/// On `wasm32-unknown-unknown`, where `std::time::Instant::now` panics, enable the `wasm`
+/// feature.  Every `Instant` the cache takes or returns is then a `web_time::Instant`, which
+/// is the same type as `std::time::Instant` on every other target.
+pub struct TtlCache<K: Eq + Hash, V, S: BuildHasher = RandomState> {
+    map: LinkedHashMap<K, InternalEntry<V>, S>,
+    config: TtlConfig,
+    index: ExpiryIndex,
+    /// Inserts since the last purge, for `PurgePolicy::EveryNInserts`
+    inserts_since_purge: usize,
+    capacity: Option<usize>,
+    policy: Option<Arc<dyn ExpiryPolicy<K, V>>>,
+    notifier: Notifier<K>,
+    #[cfg(feature = "stats")]
+    counters: Counters,
+    #[cfg(feature = "stats")]
+    since: Instant,
+    #[cfg(feature = "tracing")]
+    key_fmt: Option<KeyFmt<K>>,
+}
+
+impl<K: Eq + Hash, V> TtlCache<K, V> {
+    /// Creates an empty cache
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ttl_cache::TtlCache;
+    ///
+    /// let mut cache: TtlCache<i32, &str> = TtlCache::new();
+    /// ```
+    pub fn new() -> Self {
+        Self::with_hasher(RandomState::new())
+    }
+
+    /// Starts a builder for a cache with any mix of options, see `TtlCacheBuilder`.
+    pub fn builder() -> TtlCacheBuilder<K, V> {
+        TtlCacheBuilder::new()
+    }
+
+    /// Creates an empty cache with a default TTL used by `insert_default` and the
+    /// `*_default_ttl` entry methods
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use ttl_cache::TtlCache;
+    ///
+    /// let mut cache = TtlCache::with_default_ttl(Duration::from_secs(30));
+    ///
+    /// cache.insert_default(1, "a");
+    /// assert_eq!(cache.get(&1), Some(&"a"));
+    /// ```
+    pub fn with_default_ttl(ttl: Duration) -> Self {
+        Self::builder().default_ttl(ttl).build_or_panic()
+    }
+
+    /// Creates an empty cache that tracks expirations with a timer wheel of the given
+    /// resolution rather than a binary heap.  Inserting gets cheaper, at O(1) instead of
+    /// O(log n), which pays off at very high insert rates of short lived entries.
+    ///
+    /// In exchange, purging is coarser: an expired entry is only purged once the whole tick of
+    /// `resolution` its deadline falls in has passed, so it may linger for up to that long.
+    /// Lookups are unaffected and never return an expired value.  Neither is eviction for
+    /// `set_capacity`, which looks through the entries for the ones expired within the current
+    /// tick before evicting a live one.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `resolution` is zero.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use ttl_cache::TtlCache;
+    ///
+    /// let mut cache = TtlCache::with_timer_wheel(Duration::from_millis(100));
+    ///
+    /// cache.insert(1, "a", Duration::from_secs(30));
+    /// assert_eq!(cache.get(&1), Some(&"a"));
+    /// ```
+    pub fn with_timer_wheel(resolution: Duration) -> Self {
+        Self::builder().timer_wheel(resolution).build_or_panic()
+    }
+
+    /// Creates an empty cache that takes the current time from `clock` rather than the system
+    /// clock.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use ttl_cache::test_util::MockClock;
+    /// use ttl_cache::TtlCache;
+    ///
+    /// let clock = MockClock::new();
+    /// let mut cache = TtlCache::with_clock(clock.clone());
+    ///
+    /// cache.insert(1, "a", Duration::from_secs(30));
+    /// clock.advance(Duration::from_secs(30));
+    /// assert_eq!(cache.get(&1), None);
+    /// ```
+    pub fn with_clock<C: Clock + 'static>(clock: C) -> Self {
+        Self::builder().clock(clock).build_or_panic()
+    }
+
+    /// Creates an empty cache whose TTLs come from `policy` wherever no TTL is given, see
+    /// `ExpiryPolicy`.
+    pub fn with_expiry_policy<P: ExpiryPolicy<K, V> + 'static>(policy: P) -> Self {
+        Self::builder().expiry_policy(policy).build_or_panic()
+    }
+
+    /// Creates a cache from `(key, value, ttl)` items, the counterpart of
+    /// `into_iter_with_ttl`.  A TTL too long to represent, like the `Duration::MAX` reported for
+    /// permanent entries, gives an entry that never expires.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use ttl_cache::TtlCache;
+    ///
+    /// let mut cache = TtlCache::new();
+    /// cache.insert(1, "a", Duration::from_secs(30));
+    /// cache.insert_permanent(2, "b");
+    ///
+    /// let moved = TtlCache::from_entries(cache.into_iter_with_ttl());
+    /// assert_eq!(moved.get(&1), Some(&"a"));
+    /// assert_eq!(moved.remaining_ttl(&2), Some(Duration::MAX));
+    /// ```
+    pub fn from_entries<I>(entries: I) -> Self
+    where
+        I: IntoIterator<Item = (K, V, Duration)>,
+    {
+        Self::from_entries_at(entries, Instant::now())
+    }
+
+    /// Creates a cache from `(key, value, ttl)` items whose TTLs count from `base` rather than
+    /// from now, so the same items and `base` always give the same deadlines.  An item whose
+    /// deadline has already passed is loaded as an expired entry.  Items are taken as is, with
+    /// no jitter or `max_ttl`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::time::{Duration, Instant};
+    /// use ttl_cache::TtlCache;
+    ///
+    /// let base = Instant::now();
+    /// let cache = TtlCache::from_entries_at(
+    ///     vec![(1, "a", Duration::from_secs(30)), (2, "b", Duration::ZERO)],
+    ///     base,
+    /// );
+    ///
+    /// assert_eq!(cache.get(&1), Some(&"a"));
+    /// assert_eq!(cache.get(&2), None);
+    /// ```
+    pub fn from_entries_at<I>(entries: I, base: Instant) -> Self
+    where
+        I: IntoIterator<Item = (K, V, Duration)>,
+    {
+        let mut cache = Self::new();
+        let epoch = cache.config.epoch;
+        for (k, v, ttl) in entries {
+            // A deadline too far out to represent never comes
+            let deadline = base.checked_add(ttl).map(|deadline| epoch.stamp(deadline));
+            let entry = InternalEntry {
+                value: v,
+                expiration: deadline,
+                deadline,
+                duration: Span::new(ttl),
+                renewal: Renewal::Fixed,
+                #[cfg(feature = "access-counts")]
+                accesses: Counter::default(),
+                #[cfg(feature = "stats")]
+                inserted: epoch.stamp(base),
+            };
+            cache.insert_entry(k, entry);
+        }
+        cache
+    }
+}
+
+/// Creates an empty cache as the default
+impl<K: Eq + Hash, V> Default for TtlCache<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: Eq + Hash, V, S: BuildHasher> TtlCache<K, V, S> {
+    /// Creates an empty cache with the given hash builder.
+    pub fn with_hasher(hash_builder: S) -> Self {
+        TtlCache {
+            map: LinkedHashMap::with_hasher(hash_builder),
+            config: TtlConfig::default(),
+            index: ExpiryIndex::default(),
+            inserts_since_purge: 0,
+            capacity: None,
+            policy: None,
+            notifier: Notifier::default(),
+            #[cfg(feature = "stats")]
+            counters: Counters::default(),
+            #[cfg(feature = "stats")]
+            since: Instant::now(),
+            #[cfg(feature = "tracing")]
+            key_fmt: None,
+        }
+    }
+
+    /// Creates an empty cache with the given hash builder that takes the current time from
+    /// `clock`, see `TtlCache::with_clock`.
+    pub fn with_hasher_and_clock<C: Clock + 'static>(hash_builder: S, clock: C) -> Self {
+        TtlCacheBuilder::new()
+            .hasher(hash_builder)
+            .clock(clock)
+            .build_or_panic()
+    }
+
+    /// Moves a new cache onto `clock`, starting its epoch from the clock's current time
+    fn use_clock(&mut self, clock: Arc<dyn Clock>) {
+        let now = clock.now();
+        self.config.epoch = Epoch::starting_at(now);
+        self.config.clock = Some(clock);
+        #[cfg(feature = "stats")]
+        {
+            self.since = now;
+        }
+    }
+
+    /// Creates a cache with the given hash builder from `(key, value, ttl)` items, like
+    /// `FromIterator` does for the default hasher.  Later duplicates override earlier ones.
+    pub fn from_iter_with_hasher<I>(iter: I, hash_builder: S) -> Self
+    where
+        I: IntoIterator<Item = (K, V, Duration)>,
+    {
+        let mut cache = TtlCache::with_hasher(hash_builder);
+        cache.extend(iter);
+        cache
+    }
+
+    /// Returns the TTL used by `insert_default`, if one is configured.
+    pub fn default_ttl(&self) -> Option<Duration> {
+        self.config.default_ttl
+    }
+
+    /// Sets the TTL used by `insert_default` and the `*_default_ttl` entry methods.  Entries
+    /// already in the cache keep their TTL.
+    pub fn set_default_ttl(&mut self, ttl: Duration) {
+        self.config.default_ttl = Some(ttl);
+    }
+
+    /// Returns the longest TTL the cache will apply, if one is configured.
+    pub fn max_ttl(&self) -> Option<Duration> {
+        self.config.max_ttl
+    }
+
+    /// Caps every TTL the cache applies at `max_ttl`.  Longer durations given to `insert`, the
+    /// entry API, or the default TTL are silently clamped, and resetting the TTL of an entry
+    /// inserted before the cap was lowered also restarts it with at most `max_ttl`.  Permanent
+    /// entries are not affected.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use ttl_cache::TtlCache;
+    ///
+    /// let mut cache = TtlCache::new();
+    /// cache.set_max_ttl(Duration::from_secs(60));
+    ///
+    /// cache.insert(1, "a", Duration::from_secs(60 * 60));
+    /// assert!(cache.remaining_ttl(&1).unwrap() <= Duration::from_secs(60));
+    /// ```
+    pub fn set_max_ttl(&mut self, max_ttl: Duration) {
+        self.config.max_ttl = Some(max_ttl);
+    }
+
+    /// Removes the cap set by `set_max_ttl`.
+    pub fn clear_max_ttl(&mut self) {
+        self.config.max_ttl = None;
+    }
+
+    /// Returns the most entries the cache holds, if it's bounded.
+    pub fn capacity(&self) -> Option<usize> {
+        self.capacity
+    }
+
+    /// Bounds the cache to `capacity` entries.  An insert that takes it over evicts an expired
+    /// entry if there is one, since those can never be returned anyway, and otherwise the
+    /// oldest live entry, the one inserted longest ago.  If the cache already holds more, the
+    /// excess is evicted right away.
+    ///
+    /// Inserts through the entry API can't evict while the entry borrows the cache, so they may
+    /// take it over capacity until the next insert through the cache itself.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use ttl_cache::test_util::MockClock;
+    /// use ttl_cache::TtlCache;
+    ///
+    /// let clock = MockClock::new();
+    /// let mut cache = TtlCache::with_clock(clock.clone());
+    /// cache.set_capacity(2);
+    ///
+    /// cache.insert(1, "a", Duration::from_secs(60 * 60));
+    /// cache.insert(2, "b", Duration::from_secs(1));
+    /// clock.advance(Duration::from_secs(2));
+    /// cache.insert(3, "c", Duration::from_secs(60 * 60));
+    /// assert_eq!(cache.get(&1), Some(&"a"));
+    ///
+    /// cache.insert(4, "d", Duration::from_secs(60 * 60));
+    /// assert_eq!(cache.get(&1), None);
+    /// ```
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = Some(capacity);
+        self.evict_over_capacity();
+    }
+
+    /// Removes the bound set by `set_capacity`.
+    pub fn clear_capacity(&mut self) {
+        self.capacity = None;
+    }
+
+    /// Has the cache take TTLs from `policy` wherever no TTL is given, in place of the default
+    /// TTL, see `ExpiryPolicy`.  Entries already in the cache keep their deadlines.
+    pub fn set_expiry_policy<P: ExpiryPolicy<K, V> + 'static>(&mut self, policy: P) {
+        self.policy = Some(Arc::new(policy));
+    }
+
+    /// Removes the policy set by `set_expiry_policy`, going back to the default TTL.
+    pub fn clear_expiry_policy(&mut self) {
+        self.policy = None;
+    }
+
+    /// Returns a receiver that gets the key and cause of every entry leaving the cache from now
+    /// on, for reacting to removals on another thread.  Replaces the receiver from any earlier
+    /// call, which stops getting notifications.
+    ///
+    /// Notifications carry the key the removal took out of the cache, so only removals that
+    /// drop the key are reported.  `OccupiedEntry::remove_entry` hands it back instead, and
+    /// changing a value in place through the entry API, `get_mut` or `swap_values` removes
+    /// nothing.  Entries moved out by `split_off` aren't removals either, and a clone of the
+    /// cache starts out without a receiver.
+    ///
+    /// The channel is unbounded: the cache never waits on a slow receiver, so notifications
+    /// queue up in memory until they are received.  See `expiry_receiver_bounded` for a
+    /// channel that drops them instead.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::thread;
+    /// use std::time::Duration;
+    /// use ttl_cache::test_util::MockClock;
+    /// use ttl_cache::{RemovalCause, TtlCache};
+    ///
+    /// let clock = MockClock::new();
+    /// let mut cache = TtlCache::with_clock(clock.clone());
+    /// let removals = cache.expiry_receiver();
+    /// let worker = thread::spawn(move || removals.iter().collect::<Vec<_>>());
+    ///
+    /// cache.insert("a", 1, Duration::from_secs(1));
+    /// cache.insert("b", 2, Duration::from_secs(60));
+    /// clock.advance(Duration::from_secs(2));
+    /// cache.remove_expired();
+    /// cache.remove(&"b");
+    ///
+    /// // Dropping the cache closes the channel
+    /// drop(cache);
+    /// assert_eq!(
+    ///     worker.join().unwrap(),
+    ///     [("a", RemovalCause::Expired), ("b", RemovalCause::Removed)]
+    /// );
+    /// ```
+    pub fn expiry_receiver(&mut self) -> Receiver<(K, RemovalCause)> {
+        let (sender, receiver) = mpsc::channel();
+        self.notifier = Notifier::unbounded(sender);
+        receiver
+    }
+
+    /// Like `expiry_receiver`, but the channel holds at most `bound` notifications.  While
+    /// it's full, further ones are dropped rather than making the cache wait for the receiver.
+    pub fn expiry_receiver_bounded(&mut self, bound: usize) -> Receiver<(K, RemovalCause)> {
+        let (sender, receiver) = mpsc::sync_channel(bound);
+        self.notifier = Notifier::bounded(sender);
+        receiver
+    }
+
+    /// Calls `listener` with the key and cause of every entry leaving the cache from now on,
+    /// in place of a receiver.  It covers the same removals as `expiry_receiver` and replaces
+    /// the receiver or listener set before.
+    ///
+    /// The listener runs inside the cache call that removed the entry, while the cache is
+    /// borrowed, so it should be quick and can't use the cache itself.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::sync::atomic::{AtomicUsize, Ordering};
+    /// use std::sync::Arc;
+    /// use std::time::Duration;
+    /// use ttl_cache::{RemovalCause, TtlCache};
+    ///
+    /// let evictions = Arc::new(AtomicUsize::new(0));
+    /// let mut cache = TtlCache::new();
+    /// cache.set_capacity(1);
+    /// let counter = evictions.clone();
+    /// cache.set_eviction_listener(move |_, cause| {
+    ///     if cause == RemovalCause::Evicted {
+    ///         counter.fetch_add(1, Ordering::Relaxed);
+    ///     }
+    /// });
+    ///
+    /// cache.insert(1, "a", Duration::from_secs(30));
+    /// cache.insert(2, "b", Duration::from_secs(30));
+    /// assert_eq!(evictions.load(Ordering::Relaxed), 1);
+    /// ```
+    pub fn set_eviction_listener<F>(&mut self, listener: F)
+    where
+        F: Fn(K, RemovalCause) + Send + Sync + 'static,
+    {
+        self.notifier = Notifier::listener(listener);
+    }
+
+    /// Stops sending notifications, closing the channel from `expiry_receiver` or dropping
+    /// the listener from `set_eviction_listener`.
+    pub fn clear_expiry_receiver(&mut self) {
+        self.notifier = Notifier::default();
+    }
+
+    /// Returns how long entries may go unaccessed before they expire, if a time-to-idle is
+    /// configured.
+    pub fn time_to_idle(&self) -> Option<Duration> {
+        self.config.time_to_idle
+    }
+
+    /// Expires entries that go unaccessed for `time_to_idle`, on top of their TTL.  An access
+    /// pushes the idle deadline back, but never past the TTL deadline, so an entry expires at
+    /// whichever of the two comes first.
+    ///
+    /// Only lookups through `&mut self` count as accesses: `get_mut`, `get_mut_prolong` and
+    /// `touch`, as well as inserting the entry or resetting its TTL.  `get`, `contains_key` and
+    /// iterating take `&self` and leave the idle deadline alone, since recording the access
+    /// there would take a lock or atomic on every entry.  Call `touch` alongside them where a
+    /// read should keep the entry alive.
+    ///
+    /// Entries that never expire don't idle out either.  Entries already in the cache pick
+    /// the new setting up on their next access.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use ttl_cache::test_util::MockClock;
+    /// use ttl_cache::TtlCache;
+    ///
+    /// let clock = MockClock::new();
+    /// let mut cache = TtlCache::with_clock(clock.clone());
+    /// cache.set_time_to_idle(Duration::from_secs(30 * 60));
+    ///
+    /// cache.insert("session", 1, Duration::from_secs(8 * 60 * 60));
+    /// clock.advance(Duration::from_secs(20 * 60));
+    /// assert_eq!(cache.get_mut("session"), Some(&mut 1));
+    /// clock.advance(Duration::from_secs(20 * 60));
+    /// assert!(cache.touch("session"));
+    /// clock.advance(Duration::from_secs(30 * 60));
+    /// assert_eq!(cache.get("session"), None);
+    /// ```
+    pub fn set_time_to_idle(&mut self, time_to_idle: Duration) {
+        self.config.time_to_idle = Some(time_to_idle);
+    }
+
+    /// Removes the time-to-idle set by `set_time_to_idle`.  Entries already in the cache keep
+    /// their idle deadline until their next access.
+    pub fn clear_time_to_idle(&mut self) {
+        self.config.time_to_idle = None;
+    }
+
+    /// Returns how long expired entries are kept around for `get_fresh_or_stale`, if a stale
+    /// grace window is configured.
+    pub fn stale_grace(&self) -> Option<Duration> {
+        self.config.stale_grace
+    }
+
+    /// Keeps expired entries for `grace` past their deadline, so `get_fresh_or_stale` can
+    /// still serve them as stale while the caller refreshes them.  Every other lookup treats
+    /// them as expired as before, but purges and the lazy removal in `get_mut` leave them be
+    /// until the window has passed too.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use ttl_cache::test_util::MockClock;
+    /// use ttl_cache::{Freshness, TtlCache};
+    ///
+    /// let clock = MockClock::new();
+    /// let mut cache = TtlCache::with_clock(clock.clone());
+    /// cache.set_stale_grace(Duration::from_secs(10));
+    ///
+    /// cache.insert(1, "a", Duration::from_secs(30));
+    /// clock.advance(Duration::from_secs(35));
+    /// cache.remove_expired();
+    /// assert_eq!(cache.get(&1), None);
+    /// assert_eq!(cache.get_fresh_or_stale(&1), Some((&"a", Freshness::Stale)));
+    /// ```
+    pub fn set_stale_grace(&mut self, grace: Duration) {
+        self.config.stale_grace = Some(grace);
+    }
+
+    /// Removes the window set by `set_stale_grace`, so expired entries can be purged right
+    /// away again.
+    pub fn clear_stale_grace(&mut self) {
+        self.config.stale_grace = None;
+    }
+
+    /// Returns the TTL jitter fraction, if jitter is enabled.
+    pub fn ttl_jitter(&self) -> Option<f64> {
+        self.config.jitter.as_ref().map(|jitter| jitter.fraction)
+    }
+
+    /// Randomizes every TTL the cache applies from now on, multiplying it by a random factor in
+    /// `[1 - fraction, 1 + fraction]`, so that entries inserted together with the same TTL
+    /// don't all expire at once.  This covers `insert`, the entry API and `reset_ttl`; the
+    /// applied expiration can be observed through `remaining_ttl`.  Jittered TTLs are still
+    /// capped by `set_max_ttl`.  Jitter is off by default.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `fraction` is not between 0.0 and 1.0.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use ttl_cache::TtlCache;
+    ///
+    /// let mut cache = TtlCache::new();
+    /// cache.set_ttl_jitter(0.1);
+    ///
+    /// cache.insert(1, "a", Duration::from_secs(600));
+    /// let ttl = cache.remaining_ttl(&1).unwrap();
+    /// assert!(ttl <= Duration::from_secs(660));
+    /// assert!(ttl >= Duration::from_secs(539));
+    /// ```
+    pub fn set_ttl_jitter(&mut self, fraction: f64) {
+        let seed = RandomState::new().build_hasher().finish();
+        self.set_ttl_jitter_with_seed(fraction, seed);
+    }
+
+    /// Like `set_ttl_jitter`, but with a fixed seed for the random factors so the sequence of
+    /// applied TTLs is reproducible, e.g. in tests.
+    pub fn set_ttl_jitter_with_seed(&mut self, fraction: f64, seed: u64) {
+        self.config.jitter = Some(Jitter::new(fraction, seed));
+    }
+
+    /// Turns TTL jitter back off.
+    pub fn clear_ttl_jitter(&mut self) {
+        self.config.jitter = None;
+    }
+
+    /// Returns when inserts purge expired entries.
+    pub fn purge_policy(&self) -> PurgePolicy {
+        self.config.purge_policy
+    }
+
+    /// Sets when `insert`, `insert_permanent`, `insert_default` and `extend` purge expired
+    /// entries.  See `PurgePolicy` for the options.
+    ///
+    /// # Panics
+    ///
+    /// Panics if given `PurgePolicy::EveryNInserts(0)`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use ttl_cache::{PurgePolicy, TtlCache};
+    ///
+    /// let mut cache = TtlCache::new();
+    /// cache.set_purge_policy(PurgePolicy::Manual);
+    ///
+    /// cache.insert(1, "a", Duration::from_secs(30));
+    /// // ... and from time to time, off the hot path:
+    /// cache.remove_expired();
+    /// ```
+    pub fn set_purge_policy(&mut self, policy: PurgePolicy) {
+        assert!(
+            policy != PurgePolicy::EveryNInserts(0),
+            "PurgePolicy::EveryNInserts needs a period of at least one insert"
+        );
+        self.config.purge_policy = policy;
+        self.inserts_since_purge = 0;
+    }
+
+    /// Check if the cache contains the given key.
+    ///
+    /// # Examples
+    /// ```
+    /// use std::time::Duration;
+    /// use ttl_cache::TtlCache;
+    ///
+    /// let mut cache = TtlCache::new();
+    /// cache.insert(1, "a", Duration::from_secs(30));
+    /// assert_eq!(cache.contains_key(&1), true);
+    /// ```
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        // Expiration check is handled by get
+        self.get(key).is_some()
+    }
+
+    /// Inserts a key-value pair into the cache with an individual ttl for the key. If the key
+    /// already existed and hasn't expired, the old value is returned.
+    ///
+    /// An entry expires once its TTL has run out, so one inserted with `Duration::ZERO` is
+    /// already expired: lookups never return it, and the next purge drops it.  A TTL too long
+    /// to count down from now, like `Duration::MAX`, makes an entry that never expires.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use ttl_cache::TtlCache;
+    ///
+    /// let mut cache = TtlCache::new();
+    ///
+    /// cache.insert(1, "a", Duration::from_secs(20));
+    /// cache.insert(2, "b", Duration::from_secs(60));
+    /// assert_eq!(cache.get(&1), Some(&"a"));
+    /// assert_eq!(cache.get(&2), Some(&"b"));
+    /// ```
+    pub fn insert(&mut self, k: K, v: V, ttl: Duration) -> Option<V> {
+        let now = self.config.now();
+        self.purge_on_insert(now);
+        let to_insert = self.config.new_entry(v, ttl, now);
+        self.store(k, to_insert, now)
+    }
+
+    /// Inserts a key-value pair into the cache with a sliding expiration: every access restarts
+    /// its TTL, the way `get_mut_prolong` does, so it only expires once it goes unaccessed for
+    /// `ttl`.  If the key already existed and hasn't expired, the old value is returned.
+    /// Entries inserted any other way keep a fixed deadline, so one cache can mix both.
+    ///
+    /// Accesses are the lookups through `&mut self`: `get_mut`, `get_mut_prolong` and `touch`.
+    /// `get` and the other lookups through `&self` can't restart the TTL, since that would take
+    /// a lock or atomic on every entry, so pair them with `touch` where a read should count.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use ttl_cache::test_util::MockClock;
+    /// use ttl_cache::TtlCache;
+    ///
+    /// let clock = MockClock::new();
+    /// let mut cache = TtlCache::with_clock(clock.clone());
+    ///
+    /// cache.insert_sliding("session", 1, Duration::from_secs(30));
+    /// cache.insert("token", 2, Duration::from_secs(30));
+    /// clock.advance(Duration::from_secs(20));
+    /// assert_eq!(cache.get_mut("session"), Some(&mut 1));
+    /// assert_eq!(cache.get_mut("token"), Some(&mut 2));
+    /// clock.advance(Duration::from_secs(20));
+    /// assert_eq!(cache.get("session"), Some(&1));
+    /// assert_eq!(cache.get("token"), None);
+    /// ```
+    pub fn insert_sliding(&mut self, k: K, v: V, ttl: Duration) -> Option<V> {
+        let now = self.config.now();
+        self.purge_on_insert(now);
+        let mut to_insert = self.config.new_entry(v, ttl, now);
+        to_insert.renewal = Renewal::Sliding;
+        self.store(k, to_insert, now)
+    }
+
+    /// Inserts a key-value pair into the cache that never expires.  Resetting the TTL of a
+    /// permanent entry does nothing, and it can only leave the cache by being removed or
+    /// overwritten.  If the key already existed and hasn't expired, the old value is returned.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use ttl_cache::TtlCache;
+    ///
+    /// let mut cache = TtlCache::new();
+    ///
+    /// cache.insert_permanent("config", "seeded at boot");
+    /// assert_eq!(cache.get("config"), Some(&"seeded at boot"));
+    /// assert_eq!(cache.remaining_ttl("config"), Some(Duration::MAX));
+    /// ```
+    pub fn insert_permanent(&mut self, k: K, v: V) -> Option<V> {
+        let now = self.config.now();
+        self.purge_on_insert(now);
+        self.store(k, InternalEntry::permanent(v, now), now)
+    }
+
+    /// Inserts a key-value pair into the cache using the cache's default TTL.  If the key
+    /// already existed and hasn't expired, the old value is returned.
+    ///
+    /// With an expiry policy, the policy gives the TTL instead: `expiry_after_create` for a key
+    /// with no unexpired entry, and `expiry_after_update` for one replacing an unexpired entry.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the cache has neither an expiry policy nor a default TTL.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use ttl_cache::TtlCache;
+    ///
+    /// let mut cache = TtlCache::with_default_ttl(Duration::from_secs(30));
+    ///
+    /// assert_eq!(cache.insert_default(1, "a"), None);
+    /// assert_eq!(cache.insert_default(1, "b"), Some("a"));
+    /// ```
+    pub fn insert_default(&mut self, k: K, v: V) -> Option<V> {
+        let now = self.config.now();
+        self.purge_on_insert(now);
+        let to_insert = self.default_entry(&k, v, now);
+        self.store(k, to_insert, now)
+    }
+
+    /// The entry `insert_default` puts under `k`, from the expiry policy or the default TTL
+    fn default_entry(&mut self, k: &K, v: V, now: Stamp) -> InternalEntry<V> {
+        let policy = self.policy.as_deref();
+        match self.map.get(k) {
+            Some(old) if !old.is_expired(now) => {
+                self.config.entry_on_update(policy, k, v, old, now)
+            }
+            _ => self.config.entry_on_create(policy, k, v, now),
+        }
+    }
+
+    /// Inserts a key-value pair into the cache that expires at a wall-clock time.  If the key
+    /// already existed and hasn't expired, the old value is returned.
+    ///
+    /// The deadline is taken as is, with no jitter, but is still pulled in to `max_ttl` from
+    /// now.  A deadline that has already passed gives an expired entry.  The time left until
+    /// it is what `reset_ttl` restarts the entry from.
+    ///
+    /// The cache's clock decides how the deadline is kept.  With a `SystemTimeClock` the entry
+    /// expires once `SystemTime::now()` reaches the deadline.  With any other clock the
+    /// deadline becomes a TTL when inserted, and later changes to the wall clock don't move it.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::time::{Duration, SystemTime};
+    /// use ttl_cache::TtlCache;
+    ///
+    /// let mut cache = TtlCache::new();
+    /// let now = SystemTime::now();
+    ///
+    /// cache.insert_until(1, "a", now + Duration::from_secs(30));
+    /// cache.insert_until(2, "b", now - Duration::from_secs(30));
+    /// assert_eq!(cache.get(&1), Some(&"a"));
+    /// assert_eq!(cache.get(&2), None);
+    /// ```
+    pub fn insert_until(&mut self, k: K, v: V, expires_at: SystemTime) -> Option<V> {
+        let now = self.config.now();
+        self.purge_on_insert(now);
+        let to_insert = self.config.entry_expiring_at_system_time(v, expires_at, now);
+        self.store(k, to_insert, now)
+    }
+
+    /// Inserts an HTTP response for as long as its `Cache-Control` header value allows, see
+    /// `http::ttl_from_cache_control`, or for `default` if the header gives no lifetime.  If
+    /// the key already existed and hasn't expired, the old value is returned in `Ok`.
+    ///
+    /// When the header forbids caching or gives a lifetime of zero, nothing is inserted and the
+    /// value is handed back in `Err`.  Any entry already under the key is removed, as the
+    /// response supersedes it.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use ttl_cache::TtlCache;
+    ///
+    /// let mut cache = TtlCache::new();
+    /// let default = Duration::from_secs(60);
+    ///
+    /// let stored = cache.insert_with_cache_control("/index", "<html>", "max-age=300", default);
+    /// assert_eq!(stored, Ok(None));
+    /// assert!(cache.remaining_ttl("/index").unwrap() > default);
+    ///
+    /// let stored = cache.insert_with_cache_control("/index", "<p>", "no-store", default);
+    /// assert_eq!(stored, Err("<p>"));
+    /// assert_eq!(cache.get("/index"), None);
+    /// ```
+    #[cfg(feature = "http")]
+    pub fn insert_with_cache_control(
+        &mut self,
+        k: K,
+        v: V,
+        headers: &str,
+        default: Duration,
+    ) -> Result<Option<V>, V> {
+        let ttl = match CacheControl::parse(headers) {
+            CacheControl::Forbidden => Duration::ZERO,
+            CacheControl::Lifetime(lifetime) => lifetime.unwrap_or(default),
+        };
+        if ttl == Duration::ZERO {
+            self.remove(&k);
+            return Err(v);
+        }
+        Ok(self.insert(k, v, ttl))
+    }
+
+    /// Returns a reference to the value corresponding to the given key in the cache, if
+    /// it contains an unexpired entry.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use ttl_cache::TtlCache;
+    ///
+    /// let mut cache = TtlCache::new();
+    /// let duration = Duration::from_secs(30);
+    ///
+    /// cache.insert(1, "a", duration);
+    /// cache.insert(2, "b", duration);
+    /// cache.insert(2, "c", duration);
+    /// cache.insert(3, "d", duration);
+    ///
+    /// assert_eq!(cache.get(&4), None);
+    /// assert_eq!(cache.get(&2), Some(&"c"));
+    /// ```
+    pub fn get<Q>(&self, k: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        let now = self.config.now();
+        let found = self.map.get(k);
+        let live = found.filter(|x| !x.is_expired(now));
+        #[cfg(feature = "stats")]
+        self.counters.lookup(live, found.is_some(), now);
+        live.map(|x| &x.value)
+    }
+
+    /// Returns a reference to the value corresponding to the given key along with whether it
+    /// has expired, if the cache contains an entry for it that is unexpired or still within
+    /// the stale grace window set with `set_stale_grace`.  Without a window it behaves like
+    /// `get`.  With the `stats` feature a stale value counts as a hit.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use ttl_cache::test_util::MockClock;
+    /// use ttl_cache::{Freshness, TtlCache};
+    ///
+    /// let clock = MockClock::new();
+    /// let mut cache = TtlCache::with_clock(clock.clone());
+    /// cache.set_stale_grace(Duration::from_secs(10));
+    ///
+    /// cache.insert(1, "a", Duration::from_secs(30));
+    /// assert_eq!(cache.get_fresh_or_stale(&1), Some((&"a", Freshness::Fresh)));
+    /// clock.advance(Duration::from_secs(30));
+    /// assert_eq!(cache.get_fresh_or_stale(&1), Some((&"a", Freshness::Stale)));
+    /// clock.advance(Duration::from_secs(10));
+    /// assert_eq!(cache.get_fresh_or_stale(&1), None);
+    /// ```
+    pub fn get_fresh_or_stale<Q>(&self, k: &Q) -> Option<(&V, Freshness)>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        let now = self.config.now();
+        let found = self.map.get(k);
+        let live = found.filter(|x| !self.config.is_past_grace(x, now));
+        #[cfg(feature = "stats")]
+        self.counters.lookup(live, found.is_some(), now);
+        live.map(|x| {
+            if x.is_expired(now) {
+                (&x.value, Freshness::Stale)
+            } else {
+                (&x.value, Freshness::Fresh)
+            }
+        })
+    }
+
+    /// Returns a mutable reference to the value corresponding to the given key in the cache, if
+    /// it contains an unexpired entry.  An expired entry for the key is removed on the way.
+    /// The lookup counts as an access to the entry, which restarts the TTL of an entry inserted
+    /// with `insert_sliding`, see also `set_time_to_idle`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use ttl_cache::TtlCache;
+    ///
+    /// let mut cache = TtlCache::new();
+    /// let duration = Duration::from_secs(30);
+    ///
+    /// cache.insert(1, "a", duration);
+    /// cache.insert(2, "b", duration);
+    /// cache.insert(2, "c", duration);
+    /// cache.insert(3, "d", duration);
+    ///
+    /// assert_eq!(cache.get_mut(&4), None);
+    /// assert_eq!(cache.get_mut(&2), Some(&mut "c"));
+    /// ```
+    pub fn get_mut<Q>(&mut self, k: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        let now = self.config.now();
+        let hash = make_hash(self.map.hasher(), k);
+        let config = &mut self.config;
+        let index = &mut self.index;
+        let policy = self.policy.as_deref();
+        match self.map.raw_entry_mut().from_key_hashed_nocheck(hash, k) {
+            RawLinkHashMapEntry::Occupied(entry) => {
+                if entry.get().is_expired(now) {
+                    if config.is_past_grace(entry.get(), now) {
+                        #[cfg(feature = "tracing")]
+                        tracing::trace!(
+                            key = ?KeyField { key: entry.key(), hash, fmt: self.key_fmt },
+                            overdue = ?entry.get().overdue(now),
+                            "expired"
+                        );
+                        self.notifier.send(entry.remove_entry().0, RemovalCause::Expired);
+                        #[cfg(feature = "stats")]
+                        self.counters.expire(1);
+                    }
+                    #[cfg(feature = "stats")]
+                    self.counters.miss(true);
+                    None
+                } else {
+                    let (key, x) = entry.into_key_value();
+                    let old = x.expiration;
+                    x.access(key, policy, config, now);
+                    index.note(hash, old, x.expiration);
+                    #[cfg(feature = "stats")]
+                    self.counters.hit(x, now);
+                    Some(&mut x.value)
+                }
+            }
+            RawLinkHashMapEntry::Vacant(_) => {
+                #[cfg(feature = "stats")]
+                self.counters.miss(false);
+                None
+            }
+        }
+    }
+
+    /// Returns a mutable reference to the value corresponding to the given key in the cache, if
+    /// it contains an unexpired entry and resets the expiration.  An expired entry for the key
+    /// is removed on the way.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use ttl_cache::TtlCache;
+    ///
+    /// let mut cache = TtlCache::new();
+    /// let duration = Duration::from_secs(30);
+    ///
+    /// cache.insert(1, "a", duration);
+    /// cache.insert(2, "b", duration);
+    /// cache.insert(2, "c", duration);
+    /// cache.insert(3, "d", duration);
+    ///
+    /// assert_eq!(cache.get_mut_prolong(&4), None);
+    /// assert_eq!(cache.get_mut_prolong(&2), Some(&mut "c"));
+    /// ```
+    pub fn get_mut_prolong<Q>(&mut self, k: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        let now = self.config.now();
+        let hash = make_hash(self.map.hasher(), k);
+        let config = &mut self.config;
+        let index = &mut self.index;
+        let policy = self.policy.as_deref();
+        match self.map.raw_entry_mut().from_key_hashed_nocheck(hash, k) {
+            RawLinkHashMapEntry::Occupied(entry) => {
+                if entry.get().is_expired(now) {
+                    if config.is_past_grace(entry.get(), now) {
+                        #[cfg(feature = "tracing")]
+                        tracing::trace!(
+                            key = ?KeyField { key: entry.key(), hash, fmt: self.key_fmt },
+                            overdue = ?entry.get().overdue(now),
+                            "expired"
+                        );
+                        self.notifier.send(entry.remove_entry().0, RemovalCause::Expired);
+                        #[cfg(feature = "stats")]
+                        self.counters.expire(1);
+                    }
+                    #[cfg(feature = "stats")]
+                    self.counters.miss(true);
+                    None
+                } else {
+                    let (key, x) = entry.into_key_value();
+                    let old = x.expiration;
+                    x.reset_duration(config, now);
+                    if let Some(policy) = policy {
+                        x.after_read(key, policy, config, now);
+                    }
+                    index.note(hash, old, x.expiration);
+                    #[cfg(feature = "stats")]
+                    self.counters.hit(x, now);
+                    Some(&mut x.value)
+                }
+            }
+            RawLinkHashMapEntry::Vacant(_) => {
+                #[cfg(feature = "stats")]
+                self.counters.miss(false);
+                None
+            }
+        }
+    }
+
+    /// Returns how long the entry for the given key has left to live, or `None` if there is no
+    /// unexpired entry for it.  Permanent entries report `Duration::MAX`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use ttl_cache::TtlCache;
+    ///
+    /// let mut cache = TtlCache::new();
+    ///
+    /// cache.insert(1, "a", Duration::from_secs(30));
+    ///
+    /// assert!(cache.remaining_ttl(&1).unwrap() <= Duration::from_secs(30));
+    /// assert_eq!(cache.remaining_ttl(&2), None);
+    /// ```
+    pub fn remaining_ttl<Q>(&self, k: &Q) -> Option<Duration>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        let now = self.config.now();
+        self.map
+            .get(k)
+            .and_then(|x| if x.is_expired(now) { None } else { Some(x.remaining_ttl(now)) })
+    }
+
+    /// Returns the wall-clock time the entry for the given key expires at.  Returns `None` if
+    /// there is no unexpired entry for the key, or if it never expires.
+    ///
+    /// With a `SystemTimeClock` this is exactly the deadline the entry expires at, and can be
+    /// compared with `SystemTime::now()` in another process.  With any other clock it's worked
+    /// out from the current wall-clock time and the TTL left.  With the `compact` feature it's
+    /// rounded to the millisecond.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::time::{Duration, SystemTime};
+    /// use ttl_cache::TtlCache;
+    ///
+    /// let mut cache = TtlCache::new();
+    /// let before = SystemTime::now();
+    ///
+    /// cache.insert(1, "a", Duration::from_secs(30));
+    /// cache.insert_permanent(2, "b");
+    ///
+    /// assert!(cache.expiration_system_time(&1).unwrap() > before);
+    /// assert_eq!(cache.expiration_system_time(&2), None);
+    /// assert_eq!(cache.expiration_system_time(&3), None);
+    /// ```
+    pub fn expiration_system_time<Q>(&self, k: &Q) -> Option<SystemTime>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        let now = self.config.now();
+        self.map
+            .get(k)
+            .filter(|x| !x.is_expired(now))
+            .and_then(|x| x.expiration)
+            .and_then(|expiration| self.config.system_time(expiration))
+    }
+
+    /// Sets the expiration of the entry pointed to by the given key to
+    /// now + the originally given duration.  Permanent entries are left untouched.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use ttl_cache::TtlCache;
+    ///
+    /// let mut cache = TtlCache::new();
+    ///
+    /// cache.insert(2, "a", Duration::from_secs(30));
+    /// 
+    /// cache.reset_ttl(&2)
+    /// ```
+    pub fn reset_ttl<Q>(&mut self, k: &Q)
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        let hash = make_hash(self.map.hasher(), k);
+        if let RawLinkHashMapEntry::Occupied(mut entry) =
+            self.map.raw_entry_mut().from_key_hashed_nocheck(hash, k)
+        {
+            let now = self.config.now();
+            let entry = entry.get_mut();
+            if !entry.is_expired(now) {
+                let old = entry.expiration;
+                entry.reset_duration(&mut self.config, now);
+                self.index.note(hash, old, entry.expiration);
+            }
+        }
+    }
+
+    /// Records an access to the entry for the given key the way `get_mut` does, restarting the
+    /// TTL of an entry inserted with `insert_sliding` and pushing back its idle deadline, see
+    /// `set_time_to_idle`.  Returns whether the cache contains an unexpired entry for the key.
+    pub fn touch<Q>(&mut self, k: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        let now = self.config.now();
+        let hash = make_hash(self.map.hasher(), k);
+        match self.map.raw_entry_mut().from_key_hashed_nocheck(hash, k) {
+            RawLinkHashMapEntry::Occupied(mut entry) if !entry.get().is_expired(now) => {
+                let (key, entry) = entry.get_key_value_mut();
+                let old = entry.expiration;
+                entry.access(key, self.policy.as_deref(), &mut self.config, now);
+                self.index.note(hash, old, entry.expiration);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Removes the given key from the cache and returns its corresponding value.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use ttl_cache::TtlCache;
+    ///
+    /// let mut cache = TtlCache::new();
+    ///
+    /// cache.insert(2, "a", Duration::from_secs(30));
+    ///
+    /// assert_eq!(cache.remove(&1), None);
+    /// assert_eq!(cache.remove(&2), Some("a"));
+    /// assert_eq!(cache.remove(&2), None);
+    /// ```
+    pub fn remove<Q>(&mut self, k: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        let now = self.config.now();
+        #[cfg(feature = "stats")]
+        self.counters.note_len(self.map.len());
+        let removed = self.map.remove_entry(k);
+        self.compact_index();
+        #[cfg(feature = "tracing")]
+        if let Some((ref key, ref x)) = removed {
+            if x.is_expired(now) {
+                tracing::trace!(key = ?self.key_field(key), overdue = ?x.overdue(now), "expired");
+            } else {
+                tracing::trace!(key = ?self.key_field(key), "removed");
+            }
+        }
+        let removed = removed.map(|(key, x)| {
+            self.notifier.send(key, RemovalCause::Removed.or_expired(&x, now));
+            x
+        });
+        let removed = self.live_value(removed, now);
+        #[cfg(feature = "metrics")]
+        if removed.is_some() {
+            self.counters.remove(1);
+        }
+        removed
+    }
+
+    /// Exchanges the values stored under two keys.  Each key keeps its own expiration, only the
+    /// values move.  Returns false, leaving the cache untouched, if either key is missing or
+    /// expired.
+    ///
+    /// The values move through the oldest entry, which is taken out for the swap and put back
+    /// in front, so this takes a handful of lookups however large the cache is.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use ttl_cache::TtlCache;
+    ///
+    /// let mut cache = TtlCache::new();
+    ///
+    /// cache.insert("blue", "config v1", Duration::from_secs(30));
+    /// cache.insert("green", "config v2", Duration::from_secs(60));
+    ///
+    /// assert!(cache.swap_values("blue", "green"));
+    /// assert_eq!(cache.get("blue"), Some(&"config v2"));
+    /// assert_eq!(cache.get("green"), Some(&"config v1"));
+    /// assert!(!cache.swap_values("blue", "red"));
+    /// ```
+    pub fn swap_values<Q>(&mut self, a: &Q, b: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        let now = self.config.now();
+        let live = |entry: Option<&InternalEntry<V>>| entry.map_or(false, |x| !x.is_expired(now));
+        if !live(self.map.get(a)) || !live(self.map.get(b)) {
+            return false;
+        }
+        if a == b {
+            return true;
+        }
+        // Only one value can be borrowed from the map at a time, so the oldest entry's value
+        // stands in as the temporary, unless it's one of the two anyway
+        let (front, mut held) = self.map.pop_front().expect("both keys are in the map");
+        let mut swap_with = |k: &Q| {
+            let entry = self.map.get_mut(k).expect("both keys are in the map");
+            mem::swap(&mut held.value, &mut entry.value);
+        };
+        if front.borrow() == a {
+            swap_with(b);
+        } else if front.borrow() == b {
+            swap_with(a);
+        } else {
+            swap_with(a);
+            swap_with(b);
+            swap_with(a);
+        }
+        self.map.cursor_front_mut().insert_before(front, held);
+        true
+    }
+
+    /// Clears all values out of the cache
+    pub fn clear(&mut self) {
+        #[cfg(feature = "stats")]
+        self.counters.note_len(self.map.len());
+        #[cfg(feature = "tracing")]
+        tracing::debug!(count = self.map.len(), "cleared");
+        #[cfg(feature = "metrics")]
+        self.counters.remove(self.map.len());
+        if self.notifier.is_on() {
+            let now = self.config.now();
+            for (k, x) in self.map.drain() {
+                self.notifier.send(k, RemovalCause::Removed.or_expired(&x, now));
+            }
+        }
+        self.map.clear();
+        self.index.clear();
+    }
+
+    /// Moves every unexpired entry for which `pred` returns true into a new cache and returns
+    /// it.  Entries keep their original expiration and relative order in whichever cache they
+    /// end up in.  Expired entries are dropped from both, except those still within the stale
+    /// grace window set with `set_stale_grace`, which are split like the rest.  The new cache
+    /// has the same settings, capacity and grace window included, but no eviction listener.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use ttl_cache::TtlCache;
+    ///
+    /// let mut cache = TtlCache::new();
+    /// let duration = Duration::from_secs(30);
+    ///
+    /// cache.insert("tenant-a/1", 1, duration);
+    /// cache.insert("tenant-b/1", 2, duration);
+    /// cache.insert("tenant-a/2", 3, duration);
+    ///
+    /// let tenant_a = cache.split_off(|k, _| k.starts_with("tenant-a/"));
+    ///
+    /// assert_eq!(tenant_a.iter().collect::<Vec<_>>(), [(&"tenant-a/1", &1), (&"tenant-a/2", &3)]);
+    /// assert_eq!(cache.iter().collect::<Vec<_>>(), [(&"tenant-b/1", &2)]);
+    /// ```
+    pub fn split_off<F>(&mut self, mut pred: F) -> TtlCache<K, V, S>
+    where
+        F: FnMut(&K, &V) -> bool,
+        S: Clone,
+    {
+        let hash_builder = self.map.hasher().clone();
+        let mut split = TtlCache::with_hasher(hash_builder.clone());
+        // The same settings, and as the entries keep their deadlines, the same epoch
+        split.config = self.config.clone();
+        // but not the same jitter, or the two caches' deadlines would move in lockstep
+        split.config.jitter = self.config.jitter.as_mut().map(Jitter::fork);
+        split.capacity = self.capacity;
+        split.policy = self.policy.clone();
+        split.index = self.index.empty_like();
+        #[cfg(feature = "tracing")]
+        {
+            split.key_fmt = self.key_fmt;
+        }
+        #[cfg(feature = "stats")]
+        self.counters.note_len(self.map.len());
+        let old_map = mem::replace(&mut self.map, LinkedHashMap::with_hasher(hash_builder));
+        let now = self.config.now();
+        for (k, entry) in old_map {
+            if self.config.is_past_grace(&entry, now) {
+                #[cfg(feature = "stats")]
+                self.counters.expire(1);
+                self.notifier.send(k, RemovalCause::Expired);
+                continue;
+            }
+            if pred(&k, &entry.value) {
+                split.map.insert(k, entry);
+            } else {
+                self.map.insert(k, entry);
+            }
+        }
+        #[cfg(feature = "metrics")]
+        self.counters.remove(split.map.len());
+        self.rebuild_index();
+        split.rebuild_index();
+        split
+    }
+
+    /// Gets the given key's corresponding entry in the cache for in-place manipulation.  An
+    /// expired entry for the key shows up as vacant.  With the `stats` feature, an occupied
+    /// entry counts as a hit and a vacant one as a miss.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use ttl_cache::TtlCache;
+    ///
+    /// let mut cache = TtlCache::new();
+    /// let duration = Duration::from_secs(30);
+    ///
+    /// for word in "a b a c a".split(' ') {
+    ///     *cache.entry(word).or_insert(0, duration) += 1;
+    /// }
+    /// assert_eq!(cache.get("a"), Some(&3));
+    /// ```
+    pub fn entry(&mut self, k: K) -> Entry<'_, K, V, S> {
+        self.compact_index();
+        let hash = make_hash(self.map.hasher(), &k);
+        let now = self.config.now();
+        let config = &mut self.config;
+        let index = &mut self.index;
+        let policy = self.policy.as_deref();
+        let lookup = Lookup::new(&mut self.map, hash, &k, now);
+        #[cfg(feature = "stats")]
+        lookup.count(&self.counters, now);
+        match lookup {
+            Lookup::Live(entry) => Entry::Occupied(OccupiedEntry {
+                entry,
+                hash,
+                config,
+                index,
+                policy,
+                notifier: &self.notifier,
+                #[cfg(feature = "stats")]
+                counters: &mut self.counters,
+            }),
+            Lookup::Vacant(slot) => Entry::Vacant(VacantEntry {
+                key: k,
+                slot,
+                hash,
+                config,
+                index,
+                policy,
+                notifier: &self.notifier,
+                #[cfg(feature = "stats")]
+                counters: &mut self.counters,
+            }),
+        }
+    }
+
+    /// Like `entry`, but looks the key up by reference, so no owned key is needed unless the
+    /// entry turns out to be vacant and a value is inserted.  An expired entry for the key
+    /// shows up as vacant and the lookup is counted in the stats, just like with `entry`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use ttl_cache::TtlCache;
+    ///
+    /// let mut cache: TtlCache<String, usize> = TtlCache::new();
+    /// let duration = Duration::from_secs(30);
+    ///
+    /// for word in "a b a c a".split(' ') {
+    ///     // Only allocates a `String` the first time a word is seen
+    ///     *cache.entry_ref(word).or_insert(0, duration) += 1;
+    /// }
+    /// assert_eq!(cache.get("a"), Some(&3));
+    /// ```
+    pub fn entry_ref<'q, Q>(&mut self, k: &'q Q) -> EntryRef<'_, 'q, K, Q, V, S>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        let hash = make_hash(self.map.hasher(), k);
+        let now = self.config.now();
+        let config = &mut self.config;
+        let index = &mut self.index;
+        let lookup = Lookup::new(&mut self.map, hash, k, now);
+        #[cfg(feature = "stats")]
+        lookup.count(&self.counters, now);
+        match lookup {
+            Lookup::Live(entry) => EntryRef::Occupied(OccupiedEntry {
+                entry,
+                hash,
+                config,
+                index,
+                policy: self.policy.as_deref(),
+                notifier: &self.notifier,
+                #[cfg(feature = "stats")]
+                counters: &mut self.counters,
+            }),
+            Lookup::Vacant(slot) => EntryRef::Vacant(VacantEntryRef {
+                key: k,
+                slot,
+                hash,
+                config,
+                index,
+                notifier: &self.notifier,
+                #[cfg(feature = "stats")]
+                counters: &mut self.counters,
+            }),
+        }
+    }
+
+    /// Returns an iterator over the cache's key-value pairs in oldest to youngest order.
+    /// Expired entries are skipped but not removed, so iterating only needs shared access, e.g.
+    /// through an `RwLock` read guard.  They stay in memory until something purges them, like
+    /// `remove_expired`, `insert` or `iter_mut`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use ttl_cache::TtlCache;
+    ///
+    /// let mut cache = TtlCache::new();
+    /// let duration = Duration::from_secs(30);
+    ///
+    /// cache.insert(1, 10, duration);
+    /// cache.insert(2, 20, duration);
+    /// cache.insert(3, 30, duration);
+    ///
+    /// let kvs: Vec<_> = cache.iter().collect();
+    /// assert_eq!(kvs, [(&1, &10), (&2, &20), (&3, &30)]);
+    /// ```
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        Iter {
+            inner: self.map.iter(),
+            now: self.config.now(),
+        }
+    }
+
+    /// Returns an iterator over the cache's key-value pairs that come after the given key, in
+    /// oldest to youngest order.  Passing the last key seen on one page of results picks up
+    /// where that page left off.  If the key's entry has expired but is still in the cache,
+    /// iteration starts from the next younger entry all the same.  If the key is not in the
+    /// cache at all, the iterator is empty.
+    ///
+    /// The underlying map can't start an iterator in the middle, so finding the key's position
+    /// walks the entries from the oldest one.  That only compares addresses, not keys, but it
+    /// is still linear in the key's position.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use ttl_cache::TtlCache;
+    ///
+    /// let mut cache = TtlCache::new();
+    /// for k in 0..10 {
+    ///     cache.insert(k, k * 10, Duration::from_secs(30));
+    /// }
+    ///
+    /// let page: Vec<_> = cache.iter().take(4).collect();
+    /// let last = *page.last().unwrap().0;
+    /// let next_page: Vec<_> = cache.iter_from(&last).take(4).collect();
+    /// assert_eq!(next_page, [(&4, &40), (&5, &50), (&6, &60), (&7, &70)]);
+    /// ```
+    pub fn iter_from<Q>(&self, k: &Q) -> Iter<'_, K, V>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        let mut inner = self.map.iter();
+        match self.map.get_key_value(k) {
+            Some((cursor, _)) => {
+                for (key, _) in &mut inner {
+                    if ptr::eq(key, cursor) {
+                        break;
+                    }
+                }
+            }
+            None => inner.by_ref().for_each(drop),
+        }
+        Iter {
+            inner,
+            now: self.config.now(),
+        }
+    }
+
+    /// Returns an iterator over the cache's key-value pairs in oldest to youngest order with
+    /// mutable references to the values.
+    ///
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use ttl_cache::TtlCache;
+    ///
+    /// let mut cache = TtlCache::new();
+    /// let duration = Duration::from_secs(30);
+    ///
+    /// cache.insert(1, 10, duration);
+    /// cache.insert(2, 20, duration);
+    /// cache.insert(3, 30, duration);
+    ///
+    /// let mut n = 1;
+    ///
+    /// for (k, v) in cache.iter_mut() {
+    ///     assert_eq!(*k, n);
+    ///     assert_eq!(*v, n * 10);
+    ///     *v *= 10;
+    ///     n += 1;
+    /// }
+    ///
     /// assert_eq!(n, 4);
+    /// assert_eq!(cache.get(&1), Some(&100));
     /// assert_eq!(cache.get(&2), Some(&200));
     /// assert_eq!(cache.get(&3), Some(&300));
     /// ```
-    pub fn iter_mut(&mut self) -> IterMut<K, V> {
-        self.remove_expired();
-        IterMut(self.map.iter_mut())
+    pub fn iter_mut(&mut self) -> IterMut<'_, K, V> {
+        self.remove_expired();
+        IterMut {
+            inner: self.map.iter_mut(),
+            now: self.config.now(),
+        }
+    }
+
+    /// Returns an iterator over the cache's unexpired entries in oldest to youngest order,
+    /// along with how long each has left to live.  The remaining TTLs are all measured against
+    /// the moment the iterator was created, so they form a consistent snapshot; an entry that
+    /// expires while the iterator is in use is still returned with a remaining TTL of zero.
+    /// Permanent entries report `Duration::MAX`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use ttl_cache::TtlCache;
+    ///
+    /// let mut cache = TtlCache::new();
+    /// let duration = Duration::from_secs(30);
+    ///
+    /// cache.insert(1, 10, duration);
+    /// cache.insert(2, 20, duration);
+    ///
+    /// for (k, v, ttl) in cache.iter_with_ttl() {
+    ///     assert_eq!(*v, k * 10);
+    ///     assert!(ttl <= duration);
+    /// }
+    /// ```
+    pub fn iter_with_ttl(&self) -> IterWithTtl<'_, K, V> {
+        IterWithTtl {
+            inner: self.map.iter(),
+            now: self.config.now(),
+        }
+    }
+
+    /// Returns an iterator over the cache's unexpired entries, ordered by when they expire,
+    /// soonest first.  Entries with the same expiration keep their oldest to youngest order and
+    /// permanent entries come last.
+    ///
+    /// The entries are collected and sorted up front, which costs O(n log n) time and O(n)
+    /// memory per call.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use ttl_cache::TtlCache;
+    ///
+    /// let mut cache = TtlCache::new();
+    ///
+    /// cache.insert("slow", 1, Duration::from_secs(60));
+    /// cache.insert("fast", 2, Duration::from_secs(5));
+    /// cache.insert_permanent("forever", 3);
+    /// cache.insert("medium", 4, Duration::from_secs(30));
+    ///
+    /// let keys: Vec<_> = cache.iter_by_expiration().map(|(k, _)| *k).collect();
+    /// assert_eq!(keys, ["fast", "medium", "slow", "forever"]);
+    /// ```
+    pub fn iter_by_expiration(&self) -> IterByExpiration<'_, K, V> {
+        let now = self.config.now();
+        let mut entries: Vec<_> = self
+            .map
+            .iter()
+            .filter(|entry| !entry.1.is_expired(now))
+            .collect();
+        // A stable sort, so ties stay in insertion order
+        entries.sort_by_key(|entry| (entry.1.expiration.is_none(), entry.1.expiration));
+        let entries: Vec<_> = entries.into_iter().map(|(k, x)| (k, &x.value)).collect();
+        IterByExpiration(entries.into_iter())
+    }
+
+    /// Creates a consuming iterator over the cache's unexpired entries in oldest to youngest
+    /// order, moving out each key and value along with how long it has left to live.  Like
+    /// `iter_with_ttl`, the remaining TTLs are measured against the moment the iterator was
+    /// created, and permanent entries report `Duration::MAX`.  `TtlCache::from_entries` loads
+    /// the entries back.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use ttl_cache::TtlCache;
+    ///
+    /// let mut cache = TtlCache::new();
+    /// cache.insert("a", String::from("x"), Duration::from_secs(30));
+    ///
+    /// for (k, v, ttl) in cache.into_iter_with_ttl() {
+    ///     assert_eq!((k, v.as_str()), ("a", "x"));
+    ///     assert!(ttl <= Duration::from_secs(30));
+    /// }
+    /// ```
+    pub fn into_iter_with_ttl(self) -> IntoIterWithTtl<K, V> {
+        IntoIterWithTtl {
+            inner: self.map.into_iter(),
+            now: self.config.now(),
+        }
+    }
+
+    /// Like `iter_with_ttl`, but with mutable references to the values.
+    pub fn iter_mut_with_ttl(&mut self) -> IterMutWithTtl<'_, K, V> {
+        self.remove_expired();
+        IterMutWithTtl {
+            inner: self.map.iter_mut(),
+            now: self.config.now(),
+        }
+    }
+
+    /// The cache will keep track of some basic stats during its usage that can be helpful
+    /// for performance tuning or monitoring.  This method will reset these counters, and
+    /// returns a snapshot of them as they were, captured at the instant the new counters start
+    /// from.  Reading and resetting in one call means every lookup lands in exactly one of two
+    /// consecutive snapshots.
+    /// # Examples
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use ttl_cache::test_util::MockClock;
+    /// use ttl_cache::TtlCache;
+    ///
+    /// let clock = MockClock::new();
+    /// let mut cache = TtlCache::with_clock(clock.clone());
+    ///
+    /// cache.insert(1, "a", Duration::from_secs(20));
+    /// cache.insert(2, "b", Duration::from_secs(1));
+    /// clock.advance(Duration::from_secs(2));
+    /// let _ = cache.get(&1);
+    /// let _ = cache.get(&2);
+    /// let _ = cache.get(&3);
+    /// assert_eq!(cache.miss_count(), 2);
+    /// let stats = cache.reset_stats_counter();
+    /// assert_eq!((stats.hits, stats.misses), (1, 2));
+    /// assert_eq!(cache.miss_count(), 0);
+    /// ```
+    #[cfg(feature = "stats")]
+    pub fn reset_stats_counter(&mut self) -> CacheStats {
+        let stats = self.stats();
+        let enabled = self.counters.enabled;
+        self.counters.reset(enabled, stats.captured_at);
+        self.since = stats.captured_at;
+        stats
+    }
+
+    /// Starts counting again after `disable_stats`, from zero and with a new stats window.
+    /// Stats are enabled when a cache is created, so this does nothing unless they were
+    /// disabled.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use ttl_cache::TtlCache;
+    ///
+    /// let mut cache = TtlCache::new();
+    /// cache.disable_stats();
+    /// cache.insert(1, "a", Duration::from_secs(20));
+    /// let _ = cache.get(&1);
+    /// assert_eq!(cache.hit_count(), 0);
+    ///
+    /// cache.enable_stats();
+    /// let _ = cache.get(&1);
+    /// assert_eq!(cache.hit_count(), 1);
+    /// ```
+    #[cfg(feature = "stats")]
+    pub fn enable_stats(&mut self) {
+        if !self.counters.enabled {
+            self.counters.enabled = true;
+            self.since = self.config.clock_now();
+        }
+    }
+
+    /// Stops counting and zeroes the counters, so `stats` and the counts read zero until
+    /// `enable_stats` is called.  A cache with stats disabled only pays a branch per counted
+    /// operation, which lets a process build with the `stats` feature and only keep stats on
+    /// the caches it is watching.
+    #[cfg(feature = "stats")]
+    pub fn disable_stats(&mut self) {
+        let now = self.config.clock_now();
+        self.counters.reset(false, now);
+    }
+
+    /// Returns whether the cache is counting, see `disable_stats`.
+    #[cfg(feature = "stats")]
+    pub fn stats_enabled(&self) -> bool {
+        self.counters.enabled
+    }
+
+    /// Returns the number of unexpired cache hits since the last time the counters were reset.
+    /// # Examples
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use ttl_cache::test_util::MockClock;
+    /// use ttl_cache::TtlCache;
+    ///
+    /// let clock = MockClock::new();
+    /// let mut cache = TtlCache::with_clock(clock.clone());
+    ///
+    /// cache.insert(1, "a", Duration::from_secs(20));
+    /// cache.insert(2, "b", Duration::from_secs(1));
+    /// clock.advance(Duration::from_secs(2));
+    /// assert!(cache.get(&1).is_some());
+    /// assert!(cache.get(&2).is_none());
+    /// assert!(cache.get(&3).is_none());
+    /// assert_eq!(cache.hit_count(), 1);
+    #[cfg(feature = "stats")]
+    pub fn hit_count(&self) -> u64 {
+        self.counters.totals.hits.get()
+    }
+
+    /// Returns the number of cache misses since the last time the counters were reset.  Entries
+    /// that have expired count as a miss; `miss_absent_count` and `miss_expired_count` tell the
+    /// two kinds apart.
+    /// # Examples
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use ttl_cache::test_util::MockClock;
+    /// use ttl_cache::TtlCache;
+    ///
+    /// let clock = MockClock::new();
+    /// let mut cache = TtlCache::with_clock(clock.clone());
+    ///
+    /// cache.insert(1, "a", Duration::from_secs(20));
+    /// cache.insert(2, "b", Duration::from_secs(1));
+    /// clock.advance(Duration::from_secs(2));
+    /// let _ = cache.get(&1);
+    /// let _ = cache.get(&2);
+    /// let _ = cache.get(&3);
+    /// assert_eq!(cache.miss_count(), 2);
+    #[cfg(feature = "stats")]
+    pub fn miss_count(&self) -> u64 {
+        self.counters.totals.misses.get()
+    }
+
+    /// Returns how many of the misses counted by `miss_count` found no entry for the key at
+    /// all, which means the working set is larger than what the cache holds.
+    /// # Examples
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use ttl_cache::test_util::MockClock;
+    /// use ttl_cache::TtlCache;
+    ///
+    /// let clock = MockClock::new();
+    /// let mut cache = TtlCache::with_clock(clock.clone());
+    ///
+    /// cache.insert(1, "a", Duration::from_secs(1));
+    /// clock.advance(Duration::from_secs(2));
+    /// let _ = cache.get(&1);
+    /// let _ = cache.get(&2);
+    /// assert_eq!(cache.miss_absent_count(), 1);
+    #[cfg(feature = "stats")]
+    pub fn miss_absent_count(&self) -> u64 {
+        self.counters.totals.misses.get().wrapping_sub(self.counters.totals.expired_misses.get())
+    }
+
+    /// Returns how many of the misses counted by `miss_count` found an entry for the key that
+    /// had expired, which means TTLs may be too short for how the keys are used.  A value
+    /// still within the stale grace window counts as a hit for `get_fresh_or_stale`.
+    /// # Examples
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use ttl_cache::test_util::MockClock;
+    /// use ttl_cache::TtlCache;
+    ///
+    /// let clock = MockClock::new();
+    /// let mut cache = TtlCache::with_clock(clock.clone());
+    ///
+    /// cache.insert(1, "a", Duration::from_secs(1));
+    /// clock.advance(Duration::from_secs(2));
+    /// let _ = cache.get(&1);
+    /// let _ = cache.get(&2);
+    /// assert_eq!(cache.miss_expired_count(), 1);
+    #[cfg(feature = "stats")]
+    pub fn miss_expired_count(&self) -> u64 {
+        self.counters.totals.expired_misses.get()
+    }
+
+    /// Returns the number of lookups since the last time the counters were reset, which is
+    /// `hit_count() + miss_count()`.
+    /// # Examples
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use ttl_cache::TtlCache;
+    ///
+    /// let mut cache = TtlCache::new();
+    ///
+    /// cache.insert(1, "a", Duration::from_secs(20));
+    /// let _ = cache.get(&1);
+    /// let _ = cache.get(&2);
+    /// assert_eq!(cache.lookup_count(), 2);
+    #[cfg(feature = "stats")]
+    pub fn lookup_count(&self) -> u64 {
+        self.counters.totals.hits.get().saturating_add(self.counters.totals.misses.get())
+    }
+
+    /// Returns the fraction of lookups that were hits since the last time the counters were
+    /// reset, between 0.0 and 1.0.  With no lookups at all this is 0.0 rather than NaN, so it
+    /// can be graphed or compared without a special case.
+    /// # Examples
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use ttl_cache::TtlCache;
+    ///
+    /// let mut cache = TtlCache::new();
+    /// assert_eq!(cache.hit_ratio(), 0.0);
+    ///
+    /// cache.insert(1, "a", Duration::from_secs(20));
+    /// let _ = cache.get(&1);
+    /// let _ = cache.get(&1);
+    /// let _ = cache.get(&1);
+    /// let _ = cache.get(&2);
+    /// assert_eq!(cache.hit_ratio(), 0.75);
+    #[cfg(feature = "stats")]
+    pub fn hit_ratio(&self) -> f64 {
+        stats::hit_ratio(self.counters.totals.hits.get(), self.counters.totals.misses.get())
+    }
+
+    /// Returns the average age of the values hit since the last time the counters were reset,
+    /// measured from when each value was inserted, or `None` if there were no hits.  Resetting
+    /// an entry's TTL or renewing it on access doesn't make it any younger; only inserting a
+    /// new value does.  An average far below the TTLs means entries are kept well past the
+    /// point they are still being used.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use ttl_cache::test_util::MockClock;
+    /// use ttl_cache::TtlCache;
+    ///
+    /// let clock = MockClock::new();
+    /// let mut cache = TtlCache::with_clock(clock.clone());
+    /// assert_eq!(cache.avg_age_at_hit(), None);
+    ///
+    /// cache.insert(1, "a", Duration::from_secs(600));
+    /// clock.advance(Duration::from_secs(2));
+    /// let _ = cache.get(&1);
+    /// clock.advance(Duration::from_secs(2));
+    /// let _ = cache.get(&1);
+    /// assert_eq!(cache.avg_age_at_hit(), Some(Duration::from_secs(3)));
+    /// assert_eq!(cache.max_age_at_hit(), Some(Duration::from_secs(4)));
+    /// ```
+    #[cfg(feature = "stats")]
+    pub fn avg_age_at_hit(&self) -> Option<Duration> {
+        self.counters.totals.avg_hit_age()
+    }
+
+    /// Returns the age of the oldest value hit since the last time the counters were reset, or
+    /// `None` if there were no hits.  Ages are measured the same way as for `avg_age_at_hit`.
+    #[cfg(feature = "stats")]
+    pub fn max_age_at_hit(&self) -> Option<Duration> {
+        self.counters.totals.max_hit_age()
+    }
+
+    /// Returns the most entries the cache held since the last time the counters were reset,
+    /// for capacity planning.
+    ///
+    /// The count is the same as `CacheStats::len`, so expired entries not purged yet count
+    /// too.  It is taken after every insert, before every purge, removal or clear, and when
+    /// read; values inserted through the entry API are caught on the next of those.  With the
+    /// default purge policy, expired entries are purged ahead of inserts, so the peak follows
+    /// the live entries closely.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use ttl_cache::test_util::MockClock;
+    /// use ttl_cache::TtlCache;
+    ///
+    /// let clock = MockClock::new();
+    /// let mut cache = TtlCache::with_clock(clock.clone());
+    ///
+    /// cache.insert(1, "a", Duration::from_secs(1));
+    /// cache.insert(2, "b", Duration::from_secs(1));
+    /// clock.advance(Duration::from_secs(2));
+    /// cache.insert(3, "c", Duration::from_secs(1));
+    /// assert_eq!(cache.peak_len(), 2);
+    /// ```
+    #[cfg(feature = "stats")]
+    pub fn peak_len(&self) -> usize {
+        self.counters.note_len(self.map.len());
+        self.counters.peak_len.get() as usize
+    }
+
+    /// Returns how many values were inserted with each TTL since the last time the counters were
+    /// reset, as pairs of a bucket's upper bound and its count.  The buckets go up to 1 second,
+    /// 10 seconds, 1 minute, 10 minutes, 1 hour, 1 day and `Duration::MAX`, each taking the TTLs
+    /// from the bound before it up to but not including its own, so a 1 second TTL lands in the
+    /// 10 second bucket.  Values that never expire land in the last bucket.
+    ///
+    /// A value inserted through `insert_keep_ttl` counts with the TTL it had left.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use ttl_cache::TtlCache;
+    ///
+    /// let mut cache = TtlCache::new();
+    /// cache.insert(1, "a", Duration::from_millis(500));
+    /// cache.insert(2, "b", Duration::from_secs(30));
+    /// cache.insert(3, "c", Duration::from_secs(45));
+    /// cache.insert_permanent(4, "d");
+    ///
+    /// let histogram = cache.ttl_histogram();
+    /// assert_eq!(histogram[0], (Duration::from_secs(1), 1));
+    /// assert_eq!(histogram[2], (Duration::from_secs(60), 2));
+    /// assert_eq!(histogram[6], (Duration::MAX, 1));
+    /// ```
+    #[cfg(feature = "stats")]
+    pub fn ttl_histogram(&self) -> &[(Duration, u64)] {
+        &self.counters.ttls
+    }
+
+    /// Returns the number of entries dropped because they had expired since the last time the
+    /// counters were reset.  That covers purges, the lazy removal in `get_mut`, inserting or
+    /// removing over an expired entry, and an expired entry given up through the entry API.
+    /// # Examples
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use ttl_cache::test_util::MockClock;
+    /// use ttl_cache::TtlCache;
+    ///
+    /// let clock = MockClock::new();
+    /// let mut cache = TtlCache::with_clock(clock.clone());
+    ///
+    /// cache.insert(1, "a", Duration::from_secs(1));
+    /// cache.insert(2, "b", Duration::from_secs(1));
+    /// clock.advance(Duration::from_secs(2));
+    /// cache.insert(1, "c", Duration::from_secs(1));
+    /// cache.remove_expired();
+    /// assert_eq!(cache.expired_count(), 2);
+    #[cfg(feature = "stats")]
+    pub fn expired_count(&self) -> u64 {
+        self.counters.totals.expired.get()
+    }
+
+    /// Returns the number of live entries evicted to keep the cache within the capacity set
+    /// with `set_capacity`, since the last time the counters were reset.  Expired entries
+    /// dropped to make room count towards `expired_count` instead.
+    /// # Examples
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use ttl_cache::TtlCache;
+    ///
+    /// let mut cache = TtlCache::new();
+    /// cache.set_capacity(1);
+    ///
+    /// cache.insert(1, "a", Duration::from_secs(30));
+    /// cache.insert(2, "b", Duration::from_secs(30));
+    /// assert_eq!(cache.evicted_count(), 1);
+    #[cfg(feature = "stats")]
+    pub fn evicted_count(&self) -> u64 {
+        self.counters.totals.evicted.get()
+    }
+
+    /// Returns the number of values inserted since the last time the counters were reset, by
+    /// `insert` and its variants, `extend` and the entry API.  Inserts over a live value are
+    /// included, see `replace_count`.
+    /// # Examples
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use ttl_cache::TtlCache;
+    ///
+    /// let mut cache = TtlCache::new();
+    ///
+    /// cache.insert(1, "a", Duration::from_secs(30));
+    /// cache.insert(1, "b", Duration::from_secs(30));
+    /// *cache.entry(2).or_insert("c", Duration::from_secs(30)) = "d";
+    /// assert_eq!(cache.insert_count(), 3);
+    #[cfg(feature = "stats")]
+    pub fn insert_count(&self) -> u64 {
+        self.counters.totals.inserts.get()
+    }
+
+    /// Returns how many of the inserts counted by `insert_count` replaced a live value, rather
+    /// than filling a vacant key or one whose entry had expired.
+    /// # Examples
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use ttl_cache::test_util::MockClock;
+    /// use ttl_cache::TtlCache;
+    ///
+    /// let clock = MockClock::new();
+    /// let mut cache = TtlCache::with_clock(clock.clone());
+    ///
+    /// cache.insert(1, "a", Duration::from_secs(1));
+    /// cache.insert(1, "b", Duration::from_secs(1));
+    /// clock.advance(Duration::from_secs(2));
+    /// cache.insert(1, "c", Duration::from_secs(1));
+    /// assert_eq!((cache.insert_count(), cache.replace_count()), (3, 1));
+    #[cfg(feature = "stats")]
+    pub fn replace_count(&self) -> u64 {
+        self.counters.totals.replaces.get()
+    }
+
+    /// Returns how many hits the unexpired entry for the given key has had since it was
+    /// inserted, or `None` if there is no unexpired entry for it.  Hits are counted by the same
+    /// lookups as `hit_count`, while stats are enabled, and start over when the value is
+    /// replaced.  Only available with the `access-counts` feature, which adds a counter to
+    /// every entry.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use ttl_cache::TtlCache;
+    ///
+    /// let mut cache = TtlCache::new();
+    /// cache.insert(1, "a", Duration::from_secs(20));
+    /// let _ = cache.get(&1);
+    /// let _ = cache.get(&1);
+    /// assert_eq!(cache.access_count(&1), Some(2));
+    ///
+    /// cache.insert(1, "b", Duration::from_secs(20));
+    /// assert_eq!(cache.access_count(&1), Some(0));
+    /// assert_eq!(cache.access_count(&2), None);
+    /// ```
+    #[cfg(feature = "access-counts")]
+    pub fn access_count<Q>(&self, k: &Q) -> Option<u64>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        let now = self.config.now();
+        self.map
+            .get(k)
+            .filter(|x| !x.is_expired(now))
+            .map(|x| x.accesses.get())
+    }
+
+    /// Returns the keys of the `n` unexpired entries with the most hits, see `access_count`,
+    /// along with their counts, hottest first.  Entries with the same count are listed oldest
+    /// first.  This goes through every entry, so it is meant for occasional reports rather
+    /// than the hot path.  Only available with the `access-counts` feature.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use ttl_cache::TtlCache;
+    ///
+    /// let mut cache = TtlCache::new();
+    /// for k in 0..4 {
+    ///     cache.insert(k, k, Duration::from_secs(20));
+    ///     for _ in 0..k % 3 {
+    ///         let _ = cache.get(&k);
+    ///     }
+    /// }
+    /// assert_eq!(cache.top_n_by_hits(3), [(&2, 2), (&1, 1), (&0, 0)]);
+    /// ```
+    #[cfg(feature = "access-counts")]
+    pub fn top_n_by_hits(&self, n: usize) -> Vec<(&K, u64)> {
+        let now = self.config.now();
+        let mut counts: Vec<(&K, u64)> = self
+            .map
+            .iter()
+            .filter(|&(_, x)| !x.is_expired(now))
+            .map(|(k, x)| (k, x.accesses.get()))
+            .collect();
+        counts.sort_by_key(|&(_, count)| Reverse(count));
+        counts.truncate(n);
+        counts
+    }
+
+    /// Keeps the stats for the last `buckets` stretches of `bucket_width` each as well, so
+    /// `windowed_stats` can report on a recent window without the counters being reset.  Each
+    /// count also goes into the bucket for the current stretch, which is claimed on the first
+    /// count in it, so idle time costs nothing.  Setting a window starts it empty.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `bucket_width` is zero or `buckets` is 0.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use ttl_cache::test_util::MockClock;
+    /// use ttl_cache::TtlCache;
+    ///
+    /// let clock = MockClock::new();
+    /// let mut cache = TtlCache::with_clock(clock.clone());
+    /// // Five minutes, in five second buckets
+    /// cache.set_stats_window(Duration::from_secs(5), 60);
+    ///
+    /// cache.insert(1, "a", Duration::from_secs(600));
+    /// let _ = cache.get(&2);
+    /// clock.advance(Duration::from_secs(120));
+    /// let _ = cache.get(&1);
+    ///
+    /// let last_minute = cache.windowed_stats(Duration::from_secs(60));
+    /// assert_eq!((last_minute.hits, last_minute.misses), (1, 0));
+    /// let last_five = cache.windowed_stats(Duration::from_secs(300));
+    /// assert_eq!((last_five.hits, last_five.misses), (1, 1));
+    /// ```
+    #[cfg(feature = "stats")]
+    pub fn set_stats_window(&mut self, bucket_width: Duration, buckets: usize) {
+        assert!(bucket_width > Duration::ZERO, "stats window buckets must have a width");
+        assert!(buckets > 0, "a stats window needs at least one bucket");
+        let window = Window::new(
+            self.config.clock.clone(),
+            bucket_width,
+            buckets,
+            self.config.clock_now(),
+        );
+        self.counters.window = Some(Box::new(window));
+    }
+
+    /// Returns the bucket width and number of buckets set with `set_stats_window`.
+    #[cfg(feature = "stats")]
+    pub fn stats_window(&self) -> Option<(Duration, usize)> {
+        self.counters
+            .window
+            .as_ref()
+            .map(|window| (window.width(), window.len()))
+    }
+
+    /// Stops keeping the stats window set with `set_stats_window`.
+    #[cfg(feature = "stats")]
+    pub fn clear_stats_window(&mut self) {
+        self.counters.window = None;
+    }
+
+    /// Names the cache, so its stats snapshots can be told apart from other caches'.  The name
+    /// becomes the `cache` label of `CacheStats::to_prometheus`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ttl_cache::TtlCache;
+    ///
+    /// let mut cache: TtlCache<u32, u32> = TtlCache::new();
+    /// cache.set_name("sessions");
+    /// assert_eq!(cache.name(), Some("sessions"));
+    /// assert_eq!(cache.stats().name, Some("sessions"));
+    /// ```
+    #[cfg(feature = "stats")]
+    pub fn set_name(&mut self, name: &'static str) {
+        self.counters.name = Some(name);
+    }
+
+    /// Returns the name set with `set_name`.
+    #[cfg(feature = "stats")]
+    pub fn name(&self) -> Option<&'static str> {
+        self.counters.name
+    }
+
+    /// Emits the cache's events through the `metrics` facade as they happen.  Only available
+    /// with the `metrics` feature.
+    ///
+    /// Hits, misses, expired entries dropped, evictions over capacity and inserts go to
+    /// counters named `prefix` followed by `_hits`, `_misses`, `_expired`, `_evicted` and
+    /// `_inserts`, and the number of entries, counting expired ones not purged yet, to a gauge
+    /// named `prefix` followed by `_len`.  Every metric carries `labels`.  The metrics are
+    /// registered with the recorder installed when this is called, and registering again
+    /// replaces them.  Emitting does not depend on whether the stats counters are enabled, and
+    /// a clone of the cache does not emit until registered itself.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use ttl_cache::TtlCache;
+    ///
+    /// let mut cache = TtlCache::new();
+    /// cache.register_metrics("sessions", &[("region", "eu")]);
+    /// cache.insert(1, "a", Duration::from_secs(30));
+    /// let _ = cache.get(&1);
+    /// ```
+    #[cfg(feature = "metrics")]
+    pub fn register_metrics(&mut self, prefix: &str, labels: &[(&str, &str)]) {
+        let metrics = Metrics::register(prefix, labels, self.map.len());
+        self.counters.metrics = Some(Box::new(metrics));
+    }
+
+    /// Stops emitting the metrics registered with `register_metrics`.
+    #[cfg(feature = "metrics")]
+    pub fn clear_metrics(&mut self) {
+        self.counters.metrics = None;
+    }
+
+    /// Logs keys with their `Debug` output in the cache's `tracing` events, rather than as
+    /// their hash.  Only available with the `tracing` feature.
+    ///
+    /// The cache emits an `inserted` event with the key and TTL for every insert, `removed`
+    /// for every `remove` of a live entry, and `expired`, with how long overdue the entry was,
+    /// for every expired entry it drops, all at trace level.  Evicting a live entry over
+    /// capacity emits `evicted` at debug level, as do `clear`, with the number of entries, and
+    /// every purge that removed anything, with how many it removed and how long it took.
+    /// Changes made through the entry API are not traced.  With the `log` feature the events
+    /// also go to the `log` crate when no `tracing` subscriber is installed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use ttl_cache::TtlCache;
+    ///
+    /// let mut cache = TtlCache::new();
+    /// cache.trace_keys();
+    /// // Emits `inserted key="session-1" ttl=Some(30s)` at trace level
+    /// cache.insert("session-1", 1, Duration::from_secs(30));
+    /// ```
+    #[cfg(feature = "tracing")]
+    pub fn trace_keys(&mut self)
+    where
+        K: fmt::Debug,
+    {
+        self.key_fmt = Some(<K as fmt::Debug>::fmt);
+    }
+
+    /// Returns a snapshot of the stats counted within the last `window`, from the ring set up
+    /// with `set_stats_window`.  The window is rounded up to whole buckets, including the one
+    /// in progress, and capped at the whole ring; the snapshot's `since` is where the oldest
+    /// of those buckets starts, or when the ring was set up if that is later.
+    ///
+    /// # Panics
+    ///
+    /// Panics if no stats window is set.
+    #[cfg(feature = "stats")]
+    pub fn windowed_stats(&self, window: Duration) -> CacheStats {
+        let ring = self
+            .counters
+            .window
+            .as_ref()
+            .expect("windowed_stats needs a stats window, see set_stats_window");
+        let mut stats = ring.snapshot(window, self.config.clock_now(), self.map.len());
+        stats.name = self.counters.name;
+        stats
+    }
+
+    /// Returns the Instant when we started gathering stats.  This is either when the cache was
+    /// created or when it was last reset, whichever happened most recently.
+    #[cfg(feature = "stats")]
+    pub fn stats_since(&self) -> Instant {
+        self.since
+    }
+
+    /// Returns how long the stats have been counting, since the cache was created or the
+    /// counters were last reset.
+    /// # Examples
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use ttl_cache::test_util::MockClock;
+    /// use ttl_cache::TtlCache;
+    ///
+    /// let clock = MockClock::new();
+    /// let cache = TtlCache::<u32, u32>::with_clock(clock.clone());
+    /// clock.advance(Duration::from_secs(90));
+    /// assert_eq!(cache.stats_elapsed(), Duration::from_secs(90));
+    #[cfg(feature = "stats")]
+    pub fn stats_elapsed(&self) -> Duration {
+        self.config.clock_now().saturating_duration_since(self.since)
+    }
+
+    /// Returns the average number of lookups per second since the counters started, see
+    /// `lookup_count` and `stats_elapsed`.  Right after they start, with no time passed yet,
+    /// this is 0.0.
+    /// # Examples
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use ttl_cache::test_util::MockClock;
+    /// use ttl_cache::TtlCache;
+    ///
+    /// let clock = MockClock::new();
+    /// let mut cache = TtlCache::with_clock(clock.clone());
+    /// cache.insert(1, "a", Duration::from_secs(60));
+    /// for _ in 0..30 {
+    ///     let _ = cache.get(&1);
+    /// }
+    /// assert_eq!(cache.lookups_per_second(), 0.0);
+    /// clock.advance(Duration::from_secs(10));
+    /// assert_eq!(cache.lookups_per_second(), 3.0);
+    #[cfg(feature = "stats")]
+    pub fn lookups_per_second(&self) -> f64 {
+        stats::rate(self.lookup_count(), self.stats_elapsed())
     }
 
-    /// The cache will keep track of some basic stats during its usage that can be helpful
-    /// for performance tuning or monitoring.  This method will reset these counters.
+    /// Returns the average number of inserts per second since the counters started, see
+    /// `insert_count` and `stats_elapsed`.  Right after they start, with no time passed yet,
+    /// this is 0.0.
     /// # Examples
     ///
     /// ```
-    /// use std::thread::sleep;
     /// use std::time::Duration;
+    /// use ttl_cache::test_util::MockClock;
     /// use ttl_cache::TtlCache;
     ///
-    /// let mut cache = TtlCache::new(2);
+    /// let clock = MockClock::new();
+    /// let mut cache = TtlCache::with_clock(clock.clone());
+    /// for i in 0..5 {
+    ///     cache.insert(i, i, Duration::from_secs(60));
+    /// }
+    /// clock.advance(Duration::from_secs(2));
+    /// assert_eq!(cache.inserts_per_second(), 2.5);
+    #[cfg(feature = "stats")]
+    pub fn inserts_per_second(&self) -> f64 {
+        stats::rate(self.insert_count(), self.stats_elapsed())
+    }
+
+    /// Returns a snapshot of the stats counters, taken all at once so the numbers agree with
+    /// each other.  The snapshot holds no reference to the cache and can be sent to another
+    /// thread, and its `Display` renders it as a single log line.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use ttl_cache::TtlCache;
     ///
+    /// let mut cache = TtlCache::new();
     /// cache.insert(1, "a", Duration::from_secs(20));
-    /// cache.insert(2, "b", Duration::from_millis(1));
-    /// sleep(Duration::from_millis(10));
     /// let _ = cache.get(&1);
     /// let _ = cache.get(&2);
-    /// let _ = cache.get(&3);
-    /// assert_eq!(cache.miss_count(), 2);
-    /// cache.reset_stats_counter();
-    /// assert_eq!(cache.miss_count(), 0);
+    ///
+    /// let stats = cache.stats();
+    /// assert_eq!((stats.hits, stats.misses), (1, 1));
+    /// assert_eq!((stats.inserts, stats.len), (1, 1));
+    /// assert_eq!(stats.hit_ratio(), 0.5);
+    /// println!("cache: {}", stats);
+    /// ```
     #[cfg(feature = "stats")]
-    pub fn reset_stats_counter(&mut self) {
-        self.hits = AtomicUsize::new(0);
-        self.misses = AtomicUsize::new(0);
-        self.since = Instant::now();
+    pub fn stats(&self) -> CacheStats {
+        let now = self.config.clock_now();
+        let mut stats = self.counters.totals.snapshot(self.since, now, self.map.len());
+        stats.name = self.counters.name;
+        stats
     }
 
-    /// Returns the number of unexpired cache hits since the last time the counters were reset.
+    /// Removes every expired entry from the cache, freeing the memory they hold.  Entries with
+    /// different TTLs don't expire in insertion order, so the cache keeps an index of when
+    /// they expire and goes straight to the expired ones, leaving the rest untouched.  Entries
+    /// still within the stale grace window set with `set_stale_grace` are kept.
+    ///
     /// # Examples
     ///
     /// ```
-    /// use std::thread::sleep;
     /// use std::time::Duration;
+    /// use ttl_cache::test_util::MockClock;
     /// use ttl_cache::TtlCache;
     ///
-    /// let mut cache = TtlCache::new(2);
+    /// let clock = MockClock::new();
+    /// let mut cache = TtlCache::with_clock(clock.clone());
     ///
-    /// cache.insert(1, "a", Duration::from_secs(20));
-    /// cache.insert(2, "b", Duration::from_millis(1));
-    /// sleep(Duration::from_millis(10));
-    /// assert!(cache.get(&1).is_some());
-    /// assert!(cache.get(&2).is_none());
-    /// assert!(cache.get(&3).is_none());
-    /// assert_eq!(cache.hit_count(), 1);
-    #[cfg(feature = "stats")]
-    pub fn hit_count(&self) -> usize {
-        self.hits.load(Ordering::Relaxed)
+    /// cache.insert(1, "a", Duration::from_secs(60));
+    /// cache.insert(2, "b", Duration::from_secs(1));
+    /// clock.advance(Duration::from_secs(2));
+    /// cache.remove_expired();
+    /// assert_eq!(cache.iter_mut().count(), 1);
+    /// ```
+    pub fn remove_expired(&mut self) {
+        self.purge(usize::MAX, self.config.now());
     }
 
-    /// Returns the number of cache misses since the last time the counters were reset.  Entries
-    /// that have expired count as a miss.
+    /// Removes at most `max` expired entries and returns how many it removed, for cleaning up
+    /// a little at a time.  Call it again until it returns less than `max` to clear every
+    /// expired entry.  Live entries in between don't stop it, since it goes by the expiry index
+    /// rather than insertion order.
+    ///
     /// # Examples
     ///
     /// ```
-    /// use std::thread::sleep;
     /// use std::time::Duration;
+    /// use ttl_cache::test_util::MockClock;
     /// use ttl_cache::TtlCache;
     ///
-    /// let mut cache = TtlCache::new(2);
+    /// let clock = MockClock::new();
+    /// let mut cache = TtlCache::with_clock(clock.clone());
     ///
-    /// cache.insert(1, "a", Duration::from_secs(20));
-    /// cache.insert(2, "b", Duration::from_millis(1));
-    /// sleep(Duration::from_millis(10));
-    /// let _ = cache.get(&1);
-    /// let _ = cache.get(&2);
-    /// let _ = cache.get(&3);
-    /// assert_eq!(cache.miss_count(), 2);
-    #[cfg(feature = "stats")]
-    pub fn miss_count(&self) -> usize {
-        self.misses.load(Ordering::Relaxed)
+    /// cache.insert(1, "a", Duration::from_secs(60));
+    /// cache.insert(2, "b", Duration::from_secs(1));
+    /// cache.insert(3, "c", Duration::from_secs(1));
+    /// clock.advance(Duration::from_secs(2));
+    /// assert_eq!(cache.remove_expired_limit(1), 1);
+    /// assert_eq!(cache.remove_expired_limit(1), 1);
+    /// assert_eq!(cache.remove_expired_limit(1), 0);
+    /// assert_eq!(cache.iter_mut().count(), 1);
+    /// ```
+    pub fn remove_expired_limit(&mut self, max: usize) -> usize {
+        self.purge(max, self.config.now())
     }
 
-    /// Returns the Instant when we started gathering stats.  This is either when the cache was
-    /// created or when it was last reset, whichever happened most recently.
-    #[cfg(feature = "stats")]
-    pub fn stats_since(&self) -> Instant {
-        self.since
+    /// Takes out every entry that a purge would remove now, soonest deadline first, for
+    /// `ExpiredStream`.  They aren't reported to the expiry receiver, since their keys go to
+    /// the stream instead.
+    #[cfg(feature = "tokio")]
+    pub(crate) fn take_expired(&mut self) -> Vec<(K, V)> {
+        let now = self.config.now();
+        let mut taken = Vec::new();
+        if let Some(cutoff) = self.config.purge_cutoff(now) {
+            self.remove_expired_by(usize::MAX, cutoff, Some(&mut taken));
+        }
+        // Loose pairs can lead to entries a little out of order, so they're sorted here
+        taken.sort_by_key(|&(expiration, _, _)| expiration);
+        taken.into_iter().map(|(_, k, v)| (k, v)).collect()
     }
 
-    pub fn remove_expired(&mut self) {
-        let should_pop_head = |map: &LinkedHashMap<K, InternalEntry<V>, S>| match map.front() {
-            Some(entry) => entry.1.is_expired(),
-            None => false,
+    /// Returns the earliest time `take_expired` may find anything, and leaves `waker` to be
+    /// woken if an entry that comes due sooner is added in the meantime.
+    #[cfg(feature = "tokio")]
+    pub(crate) fn next_due(&mut self, waker: &Waker) -> Option<Instant> {
+        let due = self.index.next_due();
+        self.index.set_alarm(due, waker);
+        let grace = self.config.stale_grace.unwrap_or_default();
+        due.and_then(|due| due.checked_add(grace))
+            .and_then(|due| self.config.epoch.instant(due))
+    }
+
+    /// Purges according to the purge policy, ahead of an insert
+    fn purge_on_insert(&mut self, now: Stamp) {
+        match self.config.purge_policy {
+            PurgePolicy::OnInsert => {
+                self.purge(ON_INSERT_PURGE_LIMIT, now);
+            }
+            PurgePolicy::Manual => {}
+            PurgePolicy::EveryNInserts(n) => {
+                self.inserts_since_purge += 1;
+                if self.inserts_since_purge >= n {
+                    self.inserts_since_purge = 0;
+                    self.purge(usize::MAX, now);
+                }
+            }
+            PurgePolicy::Budget(max) => {
+                self.purge(max, now);
+            }
+        }
+    }
+
+    /// Removes at most `max` entries that had expired by `now` and are past the stale grace
+    /// window, and returns how many it removed
+    fn purge(&mut self, max: usize, now: Stamp) -> usize {
+        #[cfg(feature = "tracing")]
+        let started = Instant::now();
+        let removed = match self.config.purge_cutoff(now) {
+            Some(cutoff) => self.remove_expired_by(max, cutoff, None),
+            None => 0,
         };
-        while should_pop_head(&self.map) {
-            self.map.pop_front();
+        #[cfg(feature = "tracing")]
+        if removed > 0 {
+            tracing::debug!(count = removed, took = ?started.elapsed(), "purged");
+        }
+        removed
+    }
+
+    /// Removes at most `max` entries that had expired by `cutoff` and returns how many it
+    /// removed.  They go into `taken` along with their deadlines if it's given, and are
+    /// reported to the expiry receiver otherwise.
+    fn remove_expired_by(
+        &mut self,
+        max: usize,
+        cutoff: Stamp,
+        mut taken: Option<&mut Vec<(Stamp, K, V)>>,
+    ) -> usize {
+        #[cfg(feature = "stats")]
+        self.counters.note_len(self.map.len());
+        self.compact_index();
+        let mut removed = 0;
+        while removed < max {
+            match self.index.pop_expired(cutoff) {
+                Some((expiration, hash)) => {
+                    removed += self.remove_expired_with_hash(
+                        expiration,
+                        hash,
+                        cutoff,
+                        max - removed,
+                        taken.as_deref_mut(),
+                    )
+                }
+                None => break,
+            }
+        }
+        #[cfg(feature = "stats")]
+        self.counters.expire(removed);
+        removed
+    }
+
+    /// Inserts an entry under the given key like `insert_entry`, and returns the value it
+    /// replaced unless that had expired by `now`
+    fn store(&mut self, k: K, internal_entry: InternalEntry<V>, now: Stamp) -> Option<V> {
+        #[cfg(feature = "tracing")]
+        tracing::trace!(key = ?self.key_field(&k), ttl = ?internal_entry.ttl_left(now), "inserted");
+        #[cfg(feature = "stats")]
+        let ttl = internal_entry.inserted_ttl();
+        let old_val = self.insert_entry(k, internal_entry);
+        let old_val = self.live_value(old_val, now);
+        #[cfg(feature = "stats")]
+        {
+            self.counters.insert(ttl, old_val.is_some());
+            self.counters.note_len(self.map.len());
+        }
+        old_val
+    }
+
+    /// Returns the value of an entry taken out of the map, unless it had expired by `now`, in
+    /// which case it is dropped and counted as expired
+    fn live_value(&self, removed: Option<InternalEntry<V>>, now: Stamp) -> Option<V> {
+        match removed {
+            Some(x) if x.is_expired(now) => {
+                #[cfg(feature = "stats")]
+                self.counters.expire(1);
+                None
+            }
+            x => x.map(|x| x.value),
+        }
+    }
+
+    /// Removes up to `max` entries under `hash` that had expired by `now`, and returns how
+    /// many it removed.  The live ones get their current deadline pushed back onto the index,
+    /// since the pair that led here may have been the only one they had.  If the budget runs
+    /// out first, the pair goes back too.  The removed ones go into `taken` if it's given.
+    fn remove_expired_with_hash(
+        &mut self,
+        expiration: Stamp,
+        hash: u64,
+        now: Stamp,
+        max: usize,
+        mut taken: Option<&mut Vec<(Stamp, K, V)>>,
+    ) -> usize {
+        // Different keys can share a hash, so look until no unvisited entry is left under it
+        let mut live: Vec<*const K> = Vec::new();
+        let mut removed = 0;
+        loop {
+            let found = self
+                .map
+                .raw_entry_mut()
+                .from_hash(hash, |k| !live.iter().any(|&seen| ptr::eq(seen, k)));
+            match found {
+                RawLinkHashMapEntry::Occupied(entry) => {
+                    if entry.get().is_expired(now) {
+                        if removed == max {
+                            self.index.push(expiration, hash);
+                            break;
+                        }
+                        #[cfg(feature = "tracing")]
+                        tracing::trace!(
+                            key = ?KeyField { key: entry.key(), hash, fmt: self.key_fmt },
+                            overdue = ?entry.get().overdue(self.config.now()),
+                            "expired"
+                        );
+                        let (k, x) = entry.remove_entry();
+                        match taken {
+                            Some(ref mut taken) => {
+                                taken.push((x.expiration.unwrap_or(now), k, x.value))
+                            }
+                            None => self.notifier.send(k, RemovalCause::Expired),
+                        }
+                        removed += 1;
+                    } else {
+                        if let Some(expiration) = entry.get().expiration {
+                            self.index.push(expiration, hash);
+                        }
+                        live.push(entry.key());
+                    }
+                }
+                RawLinkHashMapEntry::Vacant(_) => break,
+            }
+        }
+        removed
+    }
+
+    /// Inserts an entry under the given key, keeping the index up to date, and returns the entry
+    /// it replaced
+    fn insert_entry(&mut self, k: K, internal_entry: InternalEntry<V>) -> Option<InternalEntry<V>> {
+        self.compact_index();
+        let hash = make_hash(self.map.hasher(), &k);
+        let index = &mut self.index;
+        match self.map.raw_entry_mut().from_key_hashed_nocheck(hash, &k) {
+            RawLinkHashMapEntry::Occupied(mut entry) => {
+                index.note(hash, entry.get().expiration, internal_entry.expiration);
+                entry.to_back();
+                if self.notifier.is_on() {
+                    // The cache keeps the key it has, so the equal one passed in stands for it
+                    let cause = RemovalCause::Replaced.or_expired(entry.get(), self.config.now());
+                    self.notifier.send(k, cause);
+                }
+                Some(entry.replace_value(internal_entry))
+            }
+            RawLinkHashMapEntry::Vacant(entry) => {
+                index.note(hash, None, internal_entry.expiration);
+                entry.insert_hashed_nocheck(hash, k, internal_entry);
+                self.evict_over_capacity();
+                None
+            }
+        }
+    }
+
+    /// Evicts entries until the cache is back within its capacity: expired ones first, even
+    /// within the stale grace window, and only then the oldest live ones
+    fn evict_over_capacity(&mut self) {
+        let capacity = match self.capacity {
+            Some(capacity) => capacity,
+            None => return,
+        };
+        let excess = self.map.len().saturating_sub(capacity);
+        if excess == 0 {
+            return;
+        }
+        let now = self.config.now();
+        let mut excess = excess - self.remove_expired_by(excess, now, None);
+        if excess > 0 && self.index.is_coarse() {
+            excess -= self.remove_expired_unindexed(excess, now);
+        }
+        for _ in 0..excess {
+            if let Some((key, _)) = self.map.pop_front() {
+                #[cfg(feature = "tracing")]
+                tracing::debug!(key = ?self.key_field(&key), "evicted");
+                self.notifier.send(key, RemovalCause::Evicted);
+            }
+        }
+        #[cfg(feature = "stats")]
+        self.counters.evict(excess);
+    }
+
+    /// Removes at most `max` entries that had expired by `now` but that the index holds back
+    /// until their tick has passed, looking from the oldest entry, and returns how many it
+    /// removed
+    fn remove_expired_unindexed(&mut self, max: usize, now: Stamp) -> usize {
+        let hash_builder = self.map.hasher();
+        let expired: Vec<_> = self
+            .map
+            .iter()
+            .filter(|&(_, x)| x.is_expired(now))
+            .map(|(k, x)| (x.expiration.unwrap_or(now), make_hash(hash_builder, k)))
+            .take(max)
+            .collect();
+        let mut removed = 0;
+        for (expiration, hash) in expired {
+            removed += self.remove_expired_with_hash(expiration, hash, now, max - removed, None);
+        }
+        #[cfg(feature = "stats")]
+        self.counters.expire(removed);
+        removed
+    }
+
+    /// Rebuilds the index once stale pairs have piled up, keeping it in proportion to the
+    /// entries whatever the purge policy
+    fn compact_index(&mut self) {
+        if self.index.needs_rebuild(self.map.len()) {
+            self.rebuild_index();
+        }
+    }
+
+    /// Rebuilds the index from the entries, dropping every stale pair
+    fn rebuild_index(&mut self) {
+        let hash_builder = self.map.hasher();
+        let pairs = self.map.iter().filter_map(|(k, x)| {
+            x.expiration
+                .map(|expiration| (expiration, make_hash(hash_builder, k)))
+        });
+        self.index.rebuild(pairs);
+    }
+
+    /// Formats `key` for an event
+    #[cfg(feature = "tracing")]
+    fn key_field<'a>(&self, key: &'a K) -> KeyField<'a, K> {
+        KeyField {
+            key,
+            hash: make_hash(self.map.hasher(), key),
+            fmt: self.key_fmt,
+        }
+    }
+}
+
+/// Helpers for caches that keep their values behind an `Arc`, so a lookup can hand out an owned
+/// handle instead of a reference tied to the cache, or to the lock around it.
+impl<K: Eq + Hash, V, S: BuildHasher> TtlCache<K, Arc<V>, S> {
+    /// Wraps the value in an `Arc` and inserts it with the given TTL, see `insert`.
+    pub fn insert_arc(&mut self, k: K, v: V, ttl: Duration) -> Option<Arc<V>> {
+        self.insert(k, Arc::new(v), ttl)
+    }
+
+    /// Returns a new handle on the unexpired value for the key.  The value lives on as long as
+    /// a handle does, even after its entry expires or is removed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::sync::{Arc, Mutex};
+    /// use std::time::Duration;
+    /// use ttl_cache::TtlCache;
+    ///
+    /// let cache = Mutex::new(TtlCache::new());
+    /// cache.lock().unwrap().insert_arc("report", vec![0u8; 1024], Duration::from_secs(30));
+    ///
+    /// // The lock is only held for the lookup
+    /// let report = cache.lock().unwrap().get_arc("report").unwrap();
+    /// cache.lock().unwrap().clear();
+    /// assert_eq!(report.len(), 1024);
+    /// assert_eq!(Arc::strong_count(&report), 1);
+    /// ```
+    pub fn get_arc<Q>(&self, k: &Q) -> Option<Arc<V>>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        self.get(k).cloned()
+    }
+}
+
+/// Formats the unexpired entries like a map, with each value followed by its remaining TTL, e.g.
+/// `{"foo": 42 (ttl: 12.3s), "bar": 7 (ttl: permanent)}`.  With the `stats` feature the hit and
+/// miss counts follow the map.
+impl<K: Eq + Hash + fmt::Debug, V: fmt::Debug, S: BuildHasher> fmt::Debug for TtlCache<K, V, S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let now = self.config.now();
+        f.debug_map()
+            .entries(
+                self.map
+                    .iter()
+                    .filter(|entry| !entry.1.is_expired(now))
+                    .map(|(k, x)| (k, DebugValue(x, now))),
+            )
+            .finish()?;
+        #[cfg(feature = "stats")]
+        write!(f, " (hits: {}, misses: {})", self.hit_count(), self.miss_count())?;
+        Ok(())
+    }
+}
+
+struct DebugValue<'a, V: 'a>(&'a InternalEntry<V>, Stamp);
+
+impl<'a, V: fmt::Debug> fmt::Debug for DebugValue<'a, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.value.fmt(f)?;
+        match self.0.expiration {
+            Some(_) => write!(f, " (ttl: {:.1?})", self.0.remaining_ttl(self.1)),
+            None => write!(f, " (ttl: permanent)"),
+        }
+    }
+}
+
+/// Looks up the value for a key like `get`, counting towards the stats the same way.
+///
+/// # Panics
+///
+/// Panics if the cache has no unexpired entry for the key.  There is no `IndexMut`, matching
+/// `HashMap`.
+///
+/// # Examples
+///
+/// ```
+/// use std::time::Duration;
+/// use ttl_cache::TtlCache;
+///
+/// let mut cache = TtlCache::new();
+/// cache.insert("foo", 1, Duration::from_secs(30));
+///
+/// assert_eq!(cache["foo"], 1);
+/// ```
+impl<K, Q, V, S> Index<&Q> for TtlCache<K, V, S>
+where
+    K: Eq + Hash + Borrow<Q>,
+    Q: ?Sized + Eq + Hash,
+    S: BuildHasher,
+{
+    type Output = V;
+
+    fn index(&self, key: &Q) -> &V {
+        self.get(key).expect("no unexpired entry for key")
+    }
+}
+
+/// Builds a cache from `(key, value, ttl)` items.  Later duplicates override earlier ones.
+///
+/// # Examples
+///
+/// ```
+/// use std::time::Duration;
+/// use ttl_cache::TtlCache;
+///
+/// let ttl = Duration::from_secs(30);
+/// let pairs = vec![("a", 1), ("b", 2), ("a", 3)];
+///
+/// let cache: TtlCache<_, _> = pairs.into_iter().map(|(k, v)| (k, v, ttl)).collect();
+/// assert_eq!(cache.get("a"), Some(&3));
+/// assert_eq!(cache.get("b"), Some(&2));
+/// ```
+impl<K: Eq + Hash, V> FromIterator<(K, V, Duration)> for TtlCache<K, V> {
+    fn from_iter<I: IntoIterator<Item = (K, V, Duration)>>(iter: I) -> Self {
+        TtlCache::from_iter_with_hasher(iter, RandomState::new())
+    }
+}
+
+/// Inserts every item with its own TTL, following the same rules as `insert`.  Expired entries
+/// are purged once up front following the purge policy, rather than for each item.
+impl<K: Eq + Hash, V, S: BuildHasher> Extend<(K, V, Duration)> for TtlCache<K, V, S> {
+    fn extend<I: IntoIterator<Item = (K, V, Duration)>>(&mut self, iter: I) {
+        let now = self.config.now();
+        self.purge_on_insert(now);
+        let iter = iter.into_iter();
+        self.map.reserve(iter.size_hint().0);
+        for (k, v, ttl) in iter {
+            let to_insert = self.config.new_entry(v, ttl, now);
+            self.store(k, to_insert, now);
+        }
+    }
+}
+
+/// Inserts every item with the cache's expiry policy or default TTL, following the same rules
+/// as `insert_default`.
+///
+/// # Panics
+///
+/// Panics if the cache has neither an expiry policy nor a default TTL.
+impl<K: Eq + Hash, V, S: BuildHasher> Extend<(K, V)> for TtlCache<K, V, S> {
+    fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, iter: I) {
+        let now = self.config.now();
+        self.purge_on_insert(now);
+        let iter = iter.into_iter();
+        self.map.reserve(iter.size_hint().0);
+        for (k, v) in iter {
+            let to_insert = self.default_entry(&k, v, now);
+            self.store(k, to_insert, now);
         }
     }
 }
 
-impl<K: Eq + Hash, V> Clone for TtlCache<K, V>
+/// Only the entries that can still be returned are copied, the unexpired ones and those within
+/// the stale grace window, keeping their expirations and order.
+impl<K: Eq + Hash, V, S: BuildHasher> Clone for TtlCache<K, V, S>
 where
     K: Clone,
     V: Clone,
+    S: Clone,
 {
-    fn clone(&self) -> TtlCache<K, V> {
-        TtlCache {
-            map: self.map.clone(),
-            #[cfg(feature = "stats")]
-            hits: AtomicUsize::new(self.hits.load(Ordering::Relaxed)),
+    fn clone(&self) -> TtlCache<K, V, S> {
+        let now = self.config.now();
+        let mut map = LinkedHashMap::with_hasher(self.map.hasher().clone());
+        for (k, x) in self.map.iter() {
+            if !self.config.is_past_grace(x, now) {
+                map.insert(k.clone(), x.clone());
+            }
+        }
+        let mut clone = TtlCache {
+            map,
+            config: self.config.clone(),
+            index: self.index.clone(),
+            inserts_since_purge: 0,
+            capacity: self.capacity,
+            policy: self.policy.clone(),
+            // Removals from the clone aren't the original's to report
+            notifier: Notifier::default(),
             #[cfg(feature = "stats")]
-            misses: AtomicUsize::new(self.misses.load(Ordering::Relaxed)),
+            counters: self.counters.clone(),
             #[cfg(feature = "stats")]
             since: self.since,
+            #[cfg(feature = "tracing")]
+            key_fmt: self.key_fmt,
+        };
+        // The clone's events are its own, so it only emits metrics once registered itself
+        #[cfg(feature = "metrics")]
+        {
+            clone.counters.metrics = None;
         }
+        clone.rebuild_index();
+        clone
     }
 }
 
-pub struct Iter<'a, K: 'a, V: 'a>(linked_hash_map::Iter<'a, K, InternalEntry<V>>);
+/// An iterator over the unexpired entries of a cache.  Whether an entry has expired is judged
+/// against the time the iterator was created, so every entry is checked against the same
+/// instant.
+pub struct Iter<'a, K: 'a, V: 'a> {
+    inner: linked_hash_map::Iter<'a, K, InternalEntry<V>>,
+    now: Stamp,
+}
 
 impl<'a, K, V> Clone for Iter<'a, K, V> {
     fn clone(&self) -> Iter<'a, K, V> {
-        Iter(self.0.clone())
+        Iter {
+            inner: self.inner.clone(),
+            now: self.now,
+        }
     }
 }
 
@@ -610,72 +4488,224 @@ impl<'a, K, V> Iterator for Iter<'a, K, V> {
     type Item = (&'a K, &'a V);
 
     fn next(&mut self) -> Option<(&'a K, &'a V)> {
-        match self.0.next() {
-            Some(entry) => {
-                if entry.1.is_expired() {
-                    self.next()
-                } else {
-                    Some((entry.0, &entry.1.value))
-                }
+        for entry in self.inner.by_ref() {
+            if !entry.1.is_expired(self.now) {
+                return Some((entry.0, &entry.1.value));
             }
-            None => None,
         }
+        None
     }
 
     fn size_hint(&self) -> (usize, Option<usize>) {
-        self.0.size_hint()
+        // Any of the remaining entries may turn out to be expired and get skipped
+        (0, self.inner.size_hint().1)
     }
 }
 
 impl<'a, K, V> DoubleEndedIterator for Iter<'a, K, V> {
     fn next_back(&mut self) -> Option<(&'a K, &'a V)> {
-        match self.0.next_back() {
-            Some(entry) => {
-                if entry.1.is_expired() {
-                    // The entries are in order of time.  So if the previous entry is expired, every
-                    // else before it will be expired too.
-                    None
-                } else {
-                    Some((entry.0, &entry.1.value))
-                }
+        while let Some(entry) = self.inner.next_back() {
+            if !entry.1.is_expired(self.now) {
+                return Some((entry.0, &entry.1.value));
             }
-            None => None,
         }
+        None
     }
 }
 
-pub struct IterMut<'a, K: 'a, V: 'a>(linked_hash_map::IterMut<'a, K, InternalEntry<V>>);
+// Both ends of the underlying iterator share one count of the entries left, so once they meet
+// it keeps returning None from either end.
+impl<'a, K, V> FusedIterator for Iter<'a, K, V> {}
+
+/// A mutable iterator over the unexpired entries of a cache, checked against the time it was
+/// created like `Iter`.
+pub struct IterMut<'a, K: 'a, V: 'a> {
+    inner: linked_hash_map::IterMut<'a, K, InternalEntry<V>>,
+    now: Stamp,
+}
 
 impl<'a, K, V> Iterator for IterMut<'a, K, V> {
     type Item = (&'a K, &'a mut V);
     fn next(&mut self) -> Option<(&'a K, &'a mut V)> {
-        match self.0.next() {
-            Some(entry) => {
-                if entry.1.is_expired() {
-                    self.next()
-                } else {
-                    Some((entry.0, &mut entry.1.value))
-                }
+        for entry in self.inner.by_ref() {
+            if !entry.1.is_expired(self.now) {
+                return Some((entry.0, &mut entry.1.value));
             }
-            None => None,
         }
+        None
     }
     fn size_hint(&self) -> (usize, Option<usize>) {
-        self.0.size_hint()
+        // Any of the remaining entries may turn out to be expired and get skipped
+        (0, self.inner.size_hint().1)
     }
 }
 
 impl<'a, K, V> DoubleEndedIterator for IterMut<'a, K, V> {
     fn next_back(&mut self) -> Option<(&'a K, &'a mut V)> {
-        match self.0.next_back() {
-            Some(entry) => {
-                if entry.1.is_expired() {
-                    None
-                } else {
-                    Some((entry.0, &mut entry.1.value))
-                }
+        while let Some(entry) = self.inner.next_back() {
+            if !entry.1.is_expired(self.now) {
+                return Some((entry.0, &mut entry.1.value));
+            }
+        }
+        None
+    }
+}
+
+impl<'a, K, V> FusedIterator for IterMut<'a, K, V> {}
+
+/// An iterator over the unexpired entries of a cache ordered by expiration.  Created by
+/// `TtlCache::iter_by_expiration`.
+pub struct IterByExpiration<'a, K: 'a, V: 'a>(vec::IntoIter<(&'a K, &'a V)>);
+
+impl<'a, K, V> Clone for IterByExpiration<'a, K, V> {
+    fn clone(&self) -> IterByExpiration<'a, K, V> {
+        IterByExpiration(self.0.clone())
+    }
+}
+
+impl<'a, K, V> Iterator for IterByExpiration<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<(&'a K, &'a V)> {
+        self.0.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
+}
+
+impl<'a, K, V> DoubleEndedIterator for IterByExpiration<'a, K, V> {
+    fn next_back(&mut self) -> Option<(&'a K, &'a V)> {
+        self.0.next_back()
+    }
+}
+
+// The entries were filtered up front, so the count is exact
+impl<'a, K, V> ExactSizeIterator for IterByExpiration<'a, K, V> {}
+
+impl<'a, K, V> FusedIterator for IterByExpiration<'a, K, V> {}
+
+/// An iterator over the unexpired entries of a cache and their remaining TTLs.  Created by
+/// `TtlCache::iter_with_ttl`.
+pub struct IterWithTtl<'a, K: 'a, V: 'a> {
+    inner: linked_hash_map::Iter<'a, K, InternalEntry<V>>,
+    now: Stamp,
+}
+
+impl<'a, K, V> Clone for IterWithTtl<'a, K, V> {
+    fn clone(&self) -> IterWithTtl<'a, K, V> {
+        IterWithTtl {
+            inner: self.inner.clone(),
+            now: self.now,
+        }
+    }
+}
+
+impl<'a, K, V> Iterator for IterWithTtl<'a, K, V> {
+    type Item = (&'a K, &'a V, Duration);
+
+    fn next(&mut self) -> Option<(&'a K, &'a V, Duration)> {
+        for entry in self.inner.by_ref() {
+            if !entry.1.is_expired(self.now) {
+                return Some((entry.0, &entry.1.value, entry.1.remaining_ttl(self.now)));
+            }
+        }
+        None
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, self.inner.size_hint().1)
+    }
+}
+
+impl<'a, K, V> DoubleEndedIterator for IterWithTtl<'a, K, V> {
+    fn next_back(&mut self) -> Option<(&'a K, &'a V, Duration)> {
+        while let Some(entry) = self.inner.next_back() {
+            if !entry.1.is_expired(self.now) {
+                return Some((entry.0, &entry.1.value, entry.1.remaining_ttl(self.now)));
+            }
+        }
+        None
+    }
+}
+
+impl<'a, K, V> FusedIterator for IterWithTtl<'a, K, V> {}
+
+/// A mutable iterator over the unexpired entries of a cache and their remaining TTLs.  Created
+/// by `TtlCache::iter_mut_with_ttl`.
+pub struct IterMutWithTtl<'a, K: 'a, V: 'a> {
+    inner: linked_hash_map::IterMut<'a, K, InternalEntry<V>>,
+    now: Stamp,
+}
+
+impl<'a, K, V> Iterator for IterMutWithTtl<'a, K, V> {
+    type Item = (&'a K, &'a mut V, Duration);
+
+    fn next(&mut self) -> Option<(&'a K, &'a mut V, Duration)> {
+        for entry in self.inner.by_ref() {
+            if !entry.1.is_expired(self.now) {
+                let ttl = entry.1.remaining_ttl(self.now);
+                return Some((entry.0, &mut entry.1.value, ttl));
+            }
+        }
+        None
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, self.inner.size_hint().1)
+    }
+}
+
+impl<'a, K, V> DoubleEndedIterator for IterMutWithTtl<'a, K, V> {
+    fn next_back(&mut self) -> Option<(&'a K, &'a mut V, Duration)> {
+        while let Some(entry) = self.inner.next_back() {
+            if !entry.1.is_expired(self.now) {
+                let ttl = entry.1.remaining_ttl(self.now);
+                return Some((entry.0, &mut entry.1.value, ttl));
+            }
+        }
+        None
+    }
+}
+
+impl<'a, K, V> FusedIterator for IterMutWithTtl<'a, K, V> {}
+
+/// A consuming iterator over the unexpired entries of a cache and their remaining TTLs.
+/// Created by `TtlCache::into_iter_with_ttl`.
+pub struct IntoIterWithTtl<K, V> {
+    inner: linked_hash_map::IntoIter<K, InternalEntry<V>>,
+    now: Stamp,
+}
+
+impl<K, V> Iterator for IntoIterWithTtl<K, V> {
+    type Item = (K, V, Duration);
+
+    fn next(&mut self) -> Option<(K, V, Duration)> {
+        for entry in self.inner.by_ref() {
+            if !entry.1.is_expired(self.now) {
+                let ttl = entry.1.remaining_ttl(self.now);
+                return Some((entry.0, entry.1.value, ttl));
+            }
+        }
+        None
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, self.inner.size_hint().1)
+    }
+}
+
+impl<K, V> DoubleEndedIterator for IntoIterWithTtl<K, V> {
+    fn next_back(&mut self) -> Option<(K, V, Duration)> {
+        while let Some(entry) = self.inner.next_back() {
+            if !entry.1.is_expired(self.now) {
+                let ttl = entry.1.remaining_ttl(self.now);
+                return Some((entry.0, entry.1.value, ttl));
             }
-            None => None,
         }
+        None
     }
 }
+
+impl<K, V> FusedIterator for IntoIterWithTtl<K, V> {}
@@ -0,0 +1,387 @@
+//! A shared cache that fills missing keys from async loaders, running one load per key at a
+//! time, and hands expired entries to a stream.  Only available with the `tokio` feature.
+
+use std::collections::hash_map::RandomState;
+use std::collections::{HashMap, VecDeque};
+use std::future::Future;
+use std::hash::{BuildHasher, Hash};
+use std::mem;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError, Weak};
+use std::task::{Context, Poll, Waker};
+use std::time::Duration;
+
+use futures_core::Stream;
+use tokio::time::{self, Sleep};
+
+use sync::{self, Lock, SharedTtlCache};
+use TtlCache;
+
+/// A `SharedTtlCache` whose misses are filled by async loaders, with concurrent callers for
+/// the same key sharing a single load.
+///
+/// The first caller to miss on a key becomes its leader and runs its loader; callers that miss
+/// while the load is under way wait for it instead, and their own loaders are dropped without
+/// being polled.  The leader inserts the value once with its TTL and every waiter gets a clone.
+///
+/// A leader that is dropped before its loader finishes, whether cancelled or unwinding from a
+/// panic in the loader, hands the key over: the first waiter polled after that runs its own
+/// loader as the new leader.  Nothing is left behind for the key, so later calls load it as
+/// usual.
+///
+/// # Examples
+///
+/// ```
+/// # extern crate tokio;
+/// # extern crate ttl_cache;
+/// use std::future;
+/// use std::time::Duration;
+/// use tokio::runtime::Builder;
+/// use ttl_cache::loading::LoadingTtlCache;
+///
+/// # fn main() {
+/// let runtime = Builder::new_current_thread().build().unwrap();
+/// let cache = LoadingTtlCache::new();
+///
+/// let ttl = Duration::from_secs(30);
+/// let v = runtime.block_on(cache.get_or_insert_with("config", ttl, future::ready(1)));
+/// assert_eq!(v, 1);
+///
+/// // The value is cached, so the second loader is dropped unpolled
+/// let v = runtime.block_on(cache.get_or_insert_with("config", ttl, future::ready(2)));
+/// assert_eq!(v, 1);
+/// assert_eq!(cache.cache().get_cloned("config"), Some(1));
+/// # }
+/// ```
+pub struct LoadingTtlCache<K: Eq + Hash, V, S: BuildHasher = RandomState> {
+    cache: SharedTtlCache<K, V, S>,
+    loads: Arc<Mutex<HashMap<K, Arc<Load<V>>>>>,
+}
+
+impl<K: Eq + Hash, V> LoadingTtlCache<K, V> {
+    /// Creates an empty cache.
+    pub fn new() -> Self {
+        LoadingTtlCache::from(TtlCache::new())
+    }
+}
+
+/// Creates an empty cache as the default
+impl<K: Eq + Hash, V> Default for LoadingTtlCache<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Shares a cache set up any way a `TtlCache` can be
+impl<K: Eq + Hash, V, S: BuildHasher> From<TtlCache<K, V, S>> for LoadingTtlCache<K, V, S> {
+    fn from(cache: TtlCache<K, V, S>) -> Self {
+        LoadingTtlCache::from(SharedTtlCache::from(cache))
+    }
+}
+
+/// Loads into an already shared cache, alongside its other users
+impl<K: Eq + Hash, V, S: BuildHasher> From<SharedTtlCache<K, V, S>> for LoadingTtlCache<K, V, S> {
+    fn from(cache: SharedTtlCache<K, V, S>) -> Self {
+        LoadingTtlCache {
+            cache,
+            loads: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+}
+
+/// Clones share the cache and the loads under way
+impl<K: Eq + Hash, V, S: BuildHasher> Clone for LoadingTtlCache<K, V, S> {
+    fn clone(&self) -> Self {
+        LoadingTtlCache {
+            cache: self.cache.clone(),
+            loads: self.loads.clone(),
+        }
+    }
+}
+
+impl<K: Eq + Hash + Clone, V: Clone, S: BuildHasher> LoadingTtlCache<K, V, S> {
+    /// Returns the underlying cache, for everything other than loading.  Values inserted
+    /// through it are seen by loads that haven't started yet, but don't cut short the ones
+    /// under way.
+    pub fn cache(&self) -> &SharedTtlCache<K, V, S> {
+        &self.cache
+    }
+
+    /// Returns a clone of the unexpired value for the key, or waits for `load` to produce one
+    /// and inserts it with the given TTL.  If another call is already loading the key, waits
+    /// for its value instead and drops `load` unpolled.
+    ///
+    /// The returned future holds its own handle on the cache, so it can be spawned.
+    pub fn get_or_insert_with<F>(&self, k: K, ttl: Duration, load: F) -> GetOrInsertWith<K, V, S, F>
+    where
+        F: Future<Output = V>,
+    {
+        GetOrInsertWith {
+            cache: self.clone(),
+            key: k,
+            ttl,
+            load,
+            state: State::Start,
+        }
+    }
+}
+
+impl<K: Eq + Hash, V, S: BuildHasher> LoadingTtlCache<K, V, S> {
+    /// Returns a stream that takes each entry out of the cache once it expires and yields it,
+    /// so the cache can double as a delay queue.  Entries come out in the order of their
+    /// deadlines, once they are past any stale grace window, at the time a purge would
+    /// remove them.  They aren't sent to the cache's expiry receiver.
+    ///
+    /// The stream sleeps on tokio's timer until the next deadline, and is woken early when an
+    /// entry due sooner is inserted.  The cache's clock should follow the same time, so with
+    /// paused time in tests give the cache a `TokioClock`.
+    ///
+    /// Any number of streams can be taken from the same cache: each expired entry goes to
+    /// exactly one of them, whichever gets to it first.  A stream doesn't keep the cache
+    /// alive.  Once every other handle on the cache is dropped, it yields the entries it has
+    /// already taken out and then ends.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate futures_core;
+    /// # extern crate tokio;
+    /// # extern crate ttl_cache;
+    /// use std::future::Future;
+    /// use std::pin::Pin;
+    /// use std::task::{Context, Poll};
+    /// use std::time::Duration;
+    /// use futures_core::Stream;
+    /// use tokio::runtime::Builder;
+    /// use ttl_cache::loading::LoadingTtlCache;
+    /// use ttl_cache::{TokioClock, TtlCache};
+    ///
+    /// // What `StreamExt::next` from the futures crate does
+    /// struct Next<'a, S: 'a>(&'a mut S);
+    ///
+    /// impl<'a, S: Stream + Unpin> Future for Next<'a, S> {
+    ///     type Output = Option<S::Item>;
+    ///
+    ///     fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+    ///         Pin::new(&mut *self.0).poll_next(cx)
+    ///     }
+    /// }
+    ///
+    /// # fn main() {
+    /// let runtime = Builder::new_current_thread()
+    ///     .enable_time()
+    ///     .start_paused(true)
+    ///     .build()
+    ///     .unwrap();
+    /// let _guard = runtime.enter();
+    /// let cache = LoadingTtlCache::from(TtlCache::with_clock(TokioClock));
+    /// let mut expired = cache.expired_stream();
+    ///
+    /// cache.cache().insert("retry-later", 1, Duration::from_secs(10));
+    /// cache.cache().insert("retry-soon", 2, Duration::from_secs(5));
+    ///
+    /// assert_eq!(runtime.block_on(Next(&mut expired)), Some(("retry-soon", 2)));
+    /// assert_eq!(runtime.block_on(Next(&mut expired)), Some(("retry-later", 1)));
+    /// drop(cache);
+    /// assert_eq!(runtime.block_on(Next(&mut expired)), None);
+    /// # }
+    /// ```
+    pub fn expired_stream(&self) -> ExpiredStream<K, V, S> {
+        ExpiredStream {
+            cache: self.cache.downgrade(),
+            taken: VecDeque::new(),
+            sleep: None,
+        }
+    }
+
+    fn loads(&self) -> MutexGuard<'_, HashMap<K, Arc<Load<V>>>> {
+        self.loads.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Forgets `load` as the key's load in progress, if it still is
+    fn end_load(&self, k: &K, load: &Arc<Load<V>>) {
+        let mut loads = self.loads();
+        if loads
+            .get(k)
+            .map_or(false, |current| Arc::ptr_eq(current, load))
+        {
+            loads.remove(k);
+        }
+    }
+}
+
+/// A load in progress, shared by its leader and waiters
+struct Load<V> {
+    outcome: Mutex<Outcome<V>>,
+}
+
+enum Outcome<V> {
+    Running(Vec<Waker>),
+    Loaded(V),
+    Abandoned,
+}
+
+impl<V> Load<V> {
+    fn outcome(&self) -> MutexGuard<'_, Outcome<V>> {
+        self.outcome.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn finish(&self, outcome: Outcome<V>) {
+        if let Outcome::Running(waiters) = mem::replace(&mut *self.outcome(), outcome) {
+            for waiter in waiters {
+                waiter.wake();
+            }
+        }
+    }
+}
+
+enum State<V> {
+    Start,
+    Leading(Arc<Load<V>>),
+    Waiting(Arc<Load<V>>),
+    Done,
+}
+
+/// The future returned by `LoadingTtlCache::get_or_insert_with`.
+#[must_use = "futures do nothing unless polled"]
+pub struct GetOrInsertWith<K: Eq + Hash, V, S: BuildHasher, F> {
+    cache: LoadingTtlCache<K, V, S>,
+    key: K,
+    ttl: Duration,
+    load: F,
+    state: State<V>,
+}
+
+impl<K, V, S, F> Future for GetOrInsertWith<K, V, S, F>
+where
+    K: Eq + Hash + Clone,
+    V: Clone,
+    S: BuildHasher,
+    F: Future<Output = V>,
+{
+    type Output = V;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<V> {
+        // Only `load` is pinned; it is never moved out, and nothing else relies on pinning
+        let this = unsafe { self.get_unchecked_mut() };
+        loop {
+            match mem::replace(&mut this.state, State::Done) {
+                State::Start => {
+                    // The cache is checked with the loads locked, so a leader can't insert
+                    // and end its load in between
+                    let mut loads = this.cache.loads();
+                    if let Some(v) = this.cache.cache.get_cloned(&this.key) {
+                        return Poll::Ready(v);
+                    }
+                    this.state = match loads.get(&this.key) {
+                        Some(load) => State::Waiting(load.clone()),
+                        None => {
+                            let load = Arc::new(Load {
+                                outcome: Mutex::new(Outcome::Running(Vec::new())),
+                            });
+                            loads.insert(this.key.clone(), load.clone());
+                            State::Leading(load)
+                        }
+                    };
+                }
+                State::Leading(load) => {
+                    // Left in place while the loader runs, so a panic in it abandons the load
+                    this.state = State::Leading(load);
+                    let loader = unsafe { Pin::new_unchecked(&mut this.load) };
+                    let v = match loader.poll(cx) {
+                        Poll::Ready(v) => v,
+                        Poll::Pending => return Poll::Pending,
+                    };
+                    let load = match mem::replace(&mut this.state, State::Done) {
+                        State::Leading(load) => load,
+                        _ => unreachable!(),
+                    };
+                    this.cache
+                        .cache
+                        .insert(this.key.clone(), v.clone(), this.ttl);
+                    this.cache.end_load(&this.key, &load);
+                    load.finish(Outcome::Loaded(v.clone()));
+                    return Poll::Ready(v);
+                }
+                State::Waiting(load) => {
+                    let mut outcome = load.outcome();
+                    match *outcome {
+                        Outcome::Loaded(ref v) => return Poll::Ready(v.clone()),
+                        Outcome::Abandoned => this.state = State::Start,
+                        Outcome::Running(ref mut waiters) => {
+                            if !waiters.iter().any(|w| w.will_wake(cx.waker())) {
+                                waiters.push(cx.waker().clone());
+                            }
+                            drop(outcome);
+                            this.state = State::Waiting(load);
+                            return Poll::Pending;
+                        }
+                    }
+                }
+                State::Done => panic!("`GetOrInsertWith` polled after completion"),
+            }
+        }
+    }
+}
+
+/// Hands the load over to the waiters if the leader is dropped before it finishes
+impl<K: Eq + Hash, V, S: BuildHasher, F> Drop for GetOrInsertWith<K, V, S, F> {
+    fn drop(&mut self) {
+        if let State::Leading(ref load) = self.state {
+            self.cache.end_load(&self.key, load);
+            load.finish(Outcome::Abandoned);
+        }
+    }
+}
+
+/// The stream returned by `LoadingTtlCache::expired_stream`.
+#[must_use = "streams do nothing unless polled"]
+pub struct ExpiredStream<K: Eq + Hash, V, S: BuildHasher = RandomState> {
+    cache: Weak<Lock<TtlCache<K, V, S>>>,
+    /// Entries taken out of the cache and not yielded yet
+    taken: VecDeque<(K, V)>,
+    sleep: Option<Pin<Box<Sleep>>>,
+}
+
+/// Nothing in the stream is pinned, only ever moved in and out of it
+impl<K: Eq + Hash, V, S: BuildHasher> Unpin for ExpiredStream<K, V, S> {}
+
+impl<K: Eq + Hash, V, S: BuildHasher> Stream for ExpiredStream<K, V, S> {
+    type Item = (K, V);
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<(K, V)>> {
+        loop {
+            if let Some(entry) = self.taken.pop_front() {
+                return Poll::Ready(Some(entry));
+            }
+            let cache = match self.cache.upgrade() {
+                Some(cache) => cache,
+                None => return Poll::Ready(None),
+            };
+            let due = {
+                let mut cache = sync::write(&cache);
+                let taken = cache.take_expired();
+                if !taken.is_empty() {
+                    self.taken.extend(taken);
+                    continue;
+                }
+                cache.next_due(cx.waker())
+            };
+            // Nothing is due until the alarm left with the cache goes off
+            let due = match due {
+                Some(due) => time::Instant::from_std(due),
+                None => return Poll::Pending,
+            };
+            let sleep = match self.sleep {
+                Some(ref mut sleep) => {
+                    sleep.as_mut().reset(due);
+                    sleep
+                }
+                None => self.sleep.get_or_insert(Box::pin(time::sleep_until(due))),
+            };
+            if sleep.as_mut().poll(cx).is_pending() {
+                return Poll::Pending;
+            }
+        }
+    }
+}
@@ -0,0 +1,96 @@
+//! Removal notifications sent down a channel or to a listener, see `TtlCache::expiry_receiver`
+//! and `TtlCache::set_eviction_listener`.
+
+use std::sync::mpsc::{Sender, SyncSender};
+use std::sync::{Mutex, PoisonError};
+
+use time::Stamp;
+use InternalEntry;
+
+/// Why an entry left the cache, as sent by the receiver from `TtlCache::expiry_receiver`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum RemovalCause {
+    /// The entry expired and was purged, or dropped by the operation that found it expired.
+    Expired,
+    /// The entry was evicted to keep the cache within its capacity.
+    Evicted,
+    /// The entry was removed by `remove`, `clear` or `OccupiedEntry::remove`.
+    Removed,
+    /// The entry's value was replaced by an `insert` under the same key.
+    Replaced,
+}
+
+impl RemovalCause {
+    /// The cause for an entry taken out at `now`: `Expired` if it had expired by then,
+    /// whatever took it out
+    pub(crate) fn or_expired<V>(self, entry: &InternalEntry<V>, now: Stamp) -> Self {
+        if entry.is_expired(now) {
+            RemovalCause::Expired
+        } else {
+            self
+        }
+    }
+}
+
+/// Where removals are reported, if anywhere
+pub(crate) struct Notifier<K> {
+    sink: Option<Sink<K>>,
+}
+
+enum Sink<K> {
+    // `Sender` is only `Sync` on newer compilers, so it goes behind a lock
+    Unbounded(Mutex<Sender<(K, RemovalCause)>>),
+    Bounded(SyncSender<(K, RemovalCause)>),
+    Listener(Box<dyn Fn(K, RemovalCause) + Send + Sync>),
+}
+
+impl<K> Notifier<K> {
+    pub(crate) fn unbounded(sender: Sender<(K, RemovalCause)>) -> Self {
+        Notifier {
+            sink: Some(Sink::Unbounded(Mutex::new(sender))),
+        }
+    }
+
+    pub(crate) fn bounded(sender: SyncSender<(K, RemovalCause)>) -> Self {
+        Notifier {
+            sink: Some(Sink::Bounded(sender)),
+        }
+    }
+
+    pub(crate) fn listener<F>(listener: F) -> Self
+    where
+        F: Fn(K, RemovalCause) + Send + Sync + 'static,
+    {
+        Notifier {
+            sink: Some(Sink::Listener(Box::new(listener))),
+        }
+    }
+
+    /// Whether removals are reported, so callers can skip work done only for the report
+    pub(crate) fn is_on(&self) -> bool {
+        self.sink.is_some()
+    }
+
+    /// Reports a removal.  Never blocks on a channel: a full bounded one drops the
+    /// notification, and one whose receiver is gone drops them all.
+    pub(crate) fn send(&self, k: K, cause: RemovalCause) {
+        match self.sink {
+            Some(Sink::Unbounded(ref sender)) => {
+                let sender = sender.lock().unwrap_or_else(PoisonError::into_inner);
+                let _ = sender.send((k, cause));
+            }
+            Some(Sink::Bounded(ref sender)) => {
+                let _ = sender.try_send((k, cause));
+            }
+            Some(Sink::Listener(ref listener)) => listener(k, cause),
+            None => {}
+        }
+    }
+}
+
+/// Reports nothing
+impl<K> Default for Notifier<K> {
+    fn default() -> Self {
+        Notifier { sink: None }
+    }
+}
@@ -0,0 +1,111 @@
+//! Parallel iteration with rayon.  Only available with the `rayon` feature.
+//!
+//! The live entries are gathered up front against a single instant, so every entry handed to
+//! the parallel closures was unexpired at that moment and expired ones never reach them.
+
+use std::hash::{BuildHasher, Hash};
+use std::time::Duration;
+
+use rayon::iter::plumbing::UnindexedConsumer;
+use rayon::iter::{IntoParallelIterator, ParallelExtend, ParallelIterator};
+use rayon::vec;
+
+use super::TtlCache;
+
+impl<'a, K, V, S> IntoParallelIterator for &'a TtlCache<K, V, S>
+where
+    K: Eq + Hash + Sync,
+    V: Sync,
+    S: BuildHasher,
+{
+    type Item = (&'a K, &'a V);
+    type Iter = ParIter<'a, K, V>;
+
+    fn into_par_iter(self) -> ParIter<'a, K, V> {
+        let now = self.config.now();
+        let entries: Vec<_> = self
+            .map
+            .iter()
+            .filter(|entry| !entry.1.is_expired(now))
+            .map(|(k, x)| (k, &x.value))
+            .collect();
+        ParIter(entries.into_par_iter())
+    }
+}
+
+impl<'a, K, V, S> IntoParallelIterator for &'a mut TtlCache<K, V, S>
+where
+    K: Eq + Hash + Sync,
+    V: Send,
+    S: BuildHasher,
+{
+    type Item = (&'a K, &'a mut V);
+    type Iter = ParIterMut<'a, K, V>;
+
+    fn into_par_iter(self) -> ParIterMut<'a, K, V> {
+        self.remove_expired();
+        let now = self.config.now();
+        let entries: Vec<_> = self
+            .map
+            .iter_mut()
+            .filter(|entry| !entry.1.is_expired(now))
+            .map(|(k, x)| (k, &mut x.value))
+            .collect();
+        ParIterMut(entries.into_par_iter())
+    }
+}
+
+/// Loads `(key, value, ttl)` items produced in parallel.  The items are collected first and
+/// then inserted like `Extend` does, since the cache itself can only be written from one
+/// thread.
+impl<K, V, S> ParallelExtend<(K, V, Duration)> for TtlCache<K, V, S>
+where
+    K: Eq + Hash + Send,
+    V: Send,
+    S: BuildHasher,
+{
+    fn par_extend<I>(&mut self, par_iter: I)
+    where
+        I: IntoParallelIterator<Item = (K, V, Duration)>,
+    {
+        let items: Vec<_> = par_iter.into_par_iter().collect();
+        self.extend(items);
+    }
+}
+
+/// A parallel iterator over the unexpired entries of a cache.  Created by `par_iter`.
+pub struct ParIter<'a, K: 'a, V: 'a>(vec::IntoIter<(&'a K, &'a V)>);
+
+impl<'a, K: Sync, V: Sync> ParallelIterator for ParIter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn drive_unindexed<C>(self, consumer: C) -> C::Result
+    where
+        C: UnindexedConsumer<Self::Item>,
+    {
+        self.0.drive_unindexed(consumer)
+    }
+
+    fn opt_len(&self) -> Option<usize> {
+        self.0.opt_len()
+    }
+}
+
+/// A parallel iterator over the unexpired entries of a cache with mutable references to the
+/// values.  Created by `par_iter_mut`.
+pub struct ParIterMut<'a, K: 'a, V: 'a>(vec::IntoIter<(&'a K, &'a mut V)>);
+
+impl<'a, K: Sync, V: Send> ParallelIterator for ParIterMut<'a, K, V> {
+    type Item = (&'a K, &'a mut V);
+
+    fn drive_unindexed<C>(self, consumer: C) -> C::Result
+    where
+        C: UnindexedConsumer<Self::Item>,
+    {
+        self.0.drive_unindexed(consumer)
+    }
+
+    fn opt_len(&self) -> Option<usize> {
+        self.0.opt_len()
+    }
+}
@@ -0,0 +1,65 @@
+//! Deadlines worked out from the entries themselves.
+
+use std::time::Duration;
+
+/// Decides how long entries live, for a cache set up with `TtlCache::with_expiry_policy`.  Useful
+/// when the TTL comes from the value itself, like a token that carries its own expiry.
+///
+/// The policy is asked wherever the cache would otherwise fall back on its default TTL:
+/// `insert_default`, extending the cache with `(key, value)` pairs and the `*_default_ttl`
+/// entry methods.  Methods that take an explicit TTL or deadline bypass it.  Each hook
+/// returns the TTL to count down from now, `None` for an entry that never expires, and the
+/// result still goes through `max_ttl` like any other TTL.
+///
+/// # Examples
+///
+/// ```
+/// use std::time::Duration;
+/// use ttl_cache::{ExpiryPolicy, TtlCache};
+///
+/// struct Token {
+///     valid_for: Duration,
+/// }
+///
+/// struct TokenExpiry;
+///
+/// impl ExpiryPolicy<&'static str, Token> for TokenExpiry {
+///     fn expiry_after_create(&self, _: &&'static str, token: &Token) -> Option<Duration> {
+///         Some(token.valid_for)
+///     }
+/// }
+///
+/// let mut cache = TtlCache::with_expiry_policy(TokenExpiry);
+/// cache.insert_default("alice", Token { valid_for: Duration::from_secs(60) });
+/// assert!(cache.remaining_ttl("alice").unwrap() <= Duration::from_secs(60));
+/// ```
+pub trait ExpiryPolicy<K, V>: Send + Sync {
+    /// The TTL of an entry inserted for a key that had no unexpired entry.
+    fn expiry_after_create(&self, key: &K, value: &V) -> Option<Duration>;
+
+    /// The TTL of an entry after a lookup that counts as an access: `get_mut`,
+    /// `get_mut_prolong` or `touch`.  `remaining` is the TTL it has left after the access
+    /// itself, `None` if it never expires.  By default the entry is left as it is.
+    fn expiry_after_read(
+        &self,
+        key: &K,
+        value: &V,
+        remaining: Option<Duration>,
+    ) -> Option<Duration> {
+        let _ = (key, value);
+        remaining
+    }
+
+    /// The TTL of an entry whose value was replaced without an explicit TTL.  `value` is the
+    /// new value and `remaining` the TTL the entry had left, `None` if it never expires.  By
+    /// default the entry keeps its deadline.
+    fn expiry_after_update(
+        &self,
+        key: &K,
+        value: &V,
+        remaining: Option<Duration>,
+    ) -> Option<Duration> {
+        let _ = (key, value);
+        remaining
+    }
+}
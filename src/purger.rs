@@ -0,0 +1,156 @@
+//! A tokio task that purges a shared cache, the async counterpart to the cleaner thread.
+
+use std::future::Future;
+use std::hash::{BuildHasher, Hash};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use tokio::sync::{Mutex, OwnedMutexGuard};
+use tokio::task::JoinHandle;
+use tokio::time::{self, Instant, Interval, MissedTickBehavior};
+
+use TtlCache;
+
+/// How many expired entries the purger removes per lock, so tasks waiting on the lock are
+/// never held up behind a large purge
+const BATCH: usize = 256;
+
+/// Spawns a task on the current tokio runtime that removes the expired entries of `cache`
+/// every `interval`, so they don't have to wait for an insert or a manual purge to free their
+/// memory.  The task takes the lock for at most 256 removals at a time and yields to the
+/// runtime between batches.  It stops when the returned handle is dropped or shut down.
+///
+/// Ticks run on tokio's clock, so they follow paused time in tests.  A tick missed because
+/// the lock was busy is put off rather than made up for with a burst.
+///
+/// # Panics
+///
+/// Panics if called outside a tokio runtime, or if `interval` is zero.
+///
+/// # Examples
+///
+/// ```
+/// # extern crate tokio;
+/// # extern crate ttl_cache;
+/// use std::sync::Arc;
+/// use std::time::Duration;
+/// use tokio::runtime::Builder;
+/// use tokio::sync::Mutex;
+/// use ttl_cache::{spawn_purger, TtlCache};
+///
+/// # fn main() {
+/// let runtime = Builder::new_current_thread().enable_time().build().unwrap();
+/// let _guard = runtime.enter();
+///
+/// let cache = Arc::new(Mutex::new(TtlCache::new()));
+/// let purger = spawn_purger(cache.clone(), Duration::from_secs(60));
+///
+/// cache.try_lock().unwrap().insert(1, "a", Duration::from_secs(30));
+/// runtime.block_on(purger.shutdown());
+/// # }
+/// ```
+pub fn spawn_purger<K, V, S>(
+    cache: Arc<Mutex<TtlCache<K, V, S>>>,
+    interval: Duration,
+) -> PurgerHandle
+where
+    K: Eq + Hash + Send + 'static,
+    V: Send + 'static,
+    S: BuildHasher + Send + 'static,
+{
+    // The first purge is an interval away, like the cleaner thread's
+    let mut ticks = time::interval_at(Instant::now() + interval, interval);
+    ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let task = tokio::spawn(Purger {
+        cache,
+        ticks,
+        lock: None,
+    });
+    PurgerHandle { task: Some(task) }
+}
+
+/// The handle to a task started by `spawn_purger`.  Dropping it stops the task.
+#[derive(Debug)]
+pub struct PurgerHandle {
+    task: Option<JoinHandle<()>>,
+}
+
+impl PurgerHandle {
+    /// Stops the task, returning a future that finishes once it has.  The task only ever
+    /// stops between batches, so that takes at most the batch it's in the middle of.
+    pub fn shutdown(mut self) -> impl Future<Output = ()> {
+        let task = self.task.take().expect("the purger task is only taken here");
+        task.abort();
+        Shutdown { task }
+    }
+}
+
+impl Drop for PurgerHandle {
+    fn drop(&mut self) {
+        if let Some(ref task) = self.task {
+            task.abort();
+        }
+    }
+}
+
+/// Waits for an aborted purger task to be done with
+struct Shutdown {
+    task: JoinHandle<()>,
+}
+
+impl Future for Shutdown {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        // The task was aborted, so it's expected to end in a cancellation error
+        Pin::new(&mut self.task).poll(cx).map(|_| ())
+    }
+}
+
+type Lock<K, V, S> = Pin<Box<dyn Future<Output = OwnedMutexGuard<TtlCache<K, V, S>>> + Send>>;
+
+/// The purger task: waits for a tick, then locks the cache and removes a batch, again and
+/// again until a batch comes up short
+struct Purger<K: Eq + Hash, V, S: BuildHasher> {
+    cache: Arc<Mutex<TtlCache<K, V, S>>>,
+    ticks: Interval,
+    lock: Option<Lock<K, V, S>>,
+}
+
+impl<K, V, S> Future for Purger<K, V, S>
+where
+    K: Eq + Hash + Send + 'static,
+    V: Send + 'static,
+    S: BuildHasher + Send + 'static,
+{
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        loop {
+            let mut cache = match self.lock {
+                Some(ref mut lock) => match lock.as_mut().poll(cx) {
+                    Poll::Ready(cache) => cache,
+                    Poll::Pending => return Poll::Pending,
+                },
+                None => match self.ticks.poll_tick(cx) {
+                    Poll::Ready(_) => {
+                        self.lock = Some(Box::pin(self.cache.clone().lock_owned()));
+                        continue;
+                    }
+                    Poll::Pending => return Poll::Pending,
+                },
+            };
+            self.lock = None;
+            if cache.remove_expired_limit(BATCH) == BATCH {
+                // More may be left, so queue up for the lock again after letting the rest of
+                // the runtime run
+                drop(cache);
+                self.lock = Some(Box::pin(self.cache.clone().lock_owned()));
+                cx.waker().wake_by_ref();
+                return Poll::Pending;
+            }
+        }
+    }
+}
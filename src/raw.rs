@@ -0,0 +1,260 @@
+//! A raw entry API for callers that already know a key's hash, or that look entries up by
+//! something other than the key.  Only available with the `raw` feature.
+//!
+//! Expiry works the same as with `TtlCache::entry`: an expired match is treated as vacant.
+
+use std::borrow::Borrow;
+use std::hash::{BuildHasher, Hash};
+use std::time::Duration;
+
+use super::{
+    make_hash, ExpiryIndex, InternalEntry, Lookup, Notifier, OccupiedEntry, TtlCache, TtlConfig,
+    VacantSlot,
+};
+#[cfg(feature = "stats")]
+use super::Counters;
+
+impl<K: Eq + Hash, V, S: BuildHasher> TtlCache<K, V, S> {
+    /// Returns a reference to the cache's hash builder, for computing the hashes the raw entry
+    /// API takes.
+    pub fn hasher(&self) -> &S {
+        self.map.hasher()
+    }
+
+    /// Creates a raw immutable entry builder for looking up entries by hash.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::hash::{BuildHasher, Hash, Hasher};
+    /// use std::time::Duration;
+    /// use ttl_cache::TtlCache;
+    ///
+    /// let mut cache = TtlCache::new();
+    /// cache.insert("foo", 1, Duration::from_secs(30));
+    ///
+    /// let mut hasher = cache.hasher().build_hasher();
+    /// "foo".hash(&mut hasher);
+    /// let hash = hasher.finish();
+    ///
+    /// assert_eq!(cache.raw_entry().from_hash(hash, |k| *k == "foo"), Some((&"foo", &1)));
+    /// ```
+    pub fn raw_entry(&self) -> RawEntryBuilder<'_, K, V, S> {
+        RawEntryBuilder { cache: self }
+    }
+
+    /// Creates a raw entry builder for looking up, inserting and modifying entries by hash.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::hash::{BuildHasher, Hash, Hasher};
+    /// use std::time::Duration;
+    /// use ttl_cache::{RawEntryMut, TtlCache};
+    ///
+    /// let mut cache = TtlCache::new();
+    ///
+    /// let mut hasher = cache.hasher().build_hasher();
+    /// "foo".hash(&mut hasher);
+    /// let hash = hasher.finish();
+    ///
+    /// match cache.raw_entry_mut().from_hash(hash, |k| *k == "foo") {
+    ///     RawEntryMut::Occupied(_) => unreachable!(),
+    ///     RawEntryMut::Vacant(entry) => {
+    ///         entry.insert_hashed_nocheck(hash, "foo", 1, Duration::from_secs(30));
+    ///     }
+    /// }
+    /// assert_eq!(cache.get("foo"), Some(&1));
+    /// ```
+    pub fn raw_entry_mut(&mut self) -> RawEntryBuilderMut<'_, K, V, S> {
+        self.compact_index();
+        RawEntryBuilderMut { cache: self }
+    }
+}
+
+/// A builder for looking up unexpired entries by hash.  Created by `TtlCache::raw_entry`.
+pub struct RawEntryBuilder<'a, K: 'a + Eq + Hash, V: 'a, S: 'a + BuildHasher> {
+    cache: &'a TtlCache<K, V, S>,
+}
+
+impl<'a, K: Eq + Hash, V, S: BuildHasher> RawEntryBuilder<'a, K, V, S> {
+    /// Looks up an entry by its key.
+    pub fn from_key<Q>(self, k: &Q) -> Option<(&'a K, &'a V)>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        let found = self.cache.map.raw_entry().from_key(k);
+        self.live(found)
+    }
+
+    /// Looks up an entry by its key and the key's precomputed hash.  The hash is not checked
+    /// against the key.
+    pub fn from_key_hashed_nocheck<Q>(self, hash: u64, k: &Q) -> Option<(&'a K, &'a V)>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        let found = self.cache.map.raw_entry().from_key_hashed_nocheck(hash, k);
+        self.live(found)
+    }
+
+    /// Looks up the entry with the given hash whose key `is_match` accepts.
+    pub fn from_hash<F>(self, hash: u64, is_match: F) -> Option<(&'a K, &'a V)>
+    where
+        F: FnMut(&K) -> bool,
+    {
+        let found = self.cache.map.raw_entry().from_hash(hash, is_match);
+        self.live(found)
+    }
+
+    fn live(self, found: Option<(&'a K, &'a InternalEntry<V>)>) -> Option<(&'a K, &'a V)> {
+        let now = self.cache.config.now();
+        let live = found.filter(|&(_, x)| !x.is_expired(now));
+        #[cfg(feature = "stats")]
+        self.cache.counters.lookup(live.map(|(_, x)| x), found.is_some(), now);
+        live.map(|(k, x)| (k, &x.value))
+    }
+}
+
+/// A builder for raw entries.  Created by `TtlCache::raw_entry_mut`.
+pub struct RawEntryBuilderMut<'a, K: 'a + Eq + Hash, V: 'a, S: 'a + BuildHasher> {
+    cache: &'a mut TtlCache<K, V, S>,
+}
+
+impl<'a, K: Eq + Hash, V, S: BuildHasher> RawEntryBuilderMut<'a, K, V, S> {
+    /// Looks up an entry by its key.
+    pub fn from_key<Q>(self, k: &Q) -> RawEntryMut<'a, K, V, S>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        let hash = make_hash(self.cache.map.hasher(), k);
+        self.from_key_hashed_nocheck(hash, k)
+    }
+
+    /// Looks up an entry by its key and the key's precomputed hash.  The hash is not checked
+    /// against the key.
+    pub fn from_key_hashed_nocheck<Q>(self, hash: u64, k: &Q) -> RawEntryMut<'a, K, V, S>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        self.from_hash(hash, |o| k.eq(o.borrow()))
+    }
+
+    /// Looks up the entry with the given hash whose key `is_match` accepts.
+    pub fn from_hash<F>(self, hash: u64, is_match: F) -> RawEntryMut<'a, K, V, S>
+    where
+        F: FnMut(&K) -> bool,
+    {
+        let cache = self.cache;
+        let found = cache.map.raw_entry_mut().from_hash(hash, is_match);
+        let now = cache.config.now();
+        let lookup = Lookup::from_raw(found, now);
+        #[cfg(feature = "stats")]
+        lookup.count(&cache.counters, now);
+        let config = &mut cache.config;
+        let index = &mut cache.index;
+        match lookup {
+            Lookup::Live(entry) => RawEntryMut::Occupied(OccupiedEntry {
+                entry,
+                hash,
+                config,
+                index,
+                policy: cache.policy.as_deref(),
+                notifier: &cache.notifier,
+                #[cfg(feature = "stats")]
+                counters: &mut cache.counters,
+            }),
+            Lookup::Vacant(slot) => RawEntryMut::Vacant(RawVacantEntryMut {
+                slot,
+                hash,
+                config,
+                index,
+                notifier: &cache.notifier,
+                #[cfg(feature = "stats")]
+                counters: &mut cache.counters,
+            }),
+        }
+    }
+}
+
+/// A view into a single entry in the cache found through the raw entry API, which may be
+/// vacant or occupied.  An expired entry shows up as vacant.
+pub enum RawEntryMut<'a, K: 'a, V: 'a, S: 'a> {
+    /// An occupied Entry.
+    Occupied(OccupiedEntry<'a, K, V, S>),
+    /// A vacant Entry.
+    Vacant(RawVacantEntryMut<'a, K, V, S>),
+}
+
+impl<'a, K: Hash + Eq, V, S: BuildHasher> RawEntryMut<'a, K, V, S> {
+    /// Ensures a value is in the entry by inserting the given key and value with the given TTL
+    /// if empty, and returns a mutable reference to the value in the entry.
+    pub fn or_insert(self, default_key: K, default_val: V, ttl: Duration) -> &'a mut V {
+        match self {
+            RawEntryMut::Occupied(entry) => entry.into_mut(),
+            RawEntryMut::Vacant(entry) => entry.insert(default_key, default_val, ttl),
+        }
+    }
+
+    /// Ensures a value is in the entry by inserting the key and value returned by the default
+    /// function with the given TTL if empty, and returns a mutable reference to the value in
+    /// the entry.
+    pub fn or_insert_with<F>(self, ttl: Duration, default: F) -> &'a mut V
+    where
+        F: FnOnce() -> (K, V),
+    {
+        match self {
+            RawEntryMut::Occupied(entry) => entry.into_mut(),
+            RawEntryMut::Vacant(entry) => {
+                let (k, v) = default();
+                entry.insert(k, v, ttl)
+            }
+        }
+    }
+}
+
+/// A view into a single empty location in the cache found through the raw entry API
+pub struct RawVacantEntryMut<'a, K: 'a, V: 'a, S: 'a> {
+    slot: VacantSlot<'a, K, V, S>,
+    hash: u64,
+    config: &'a mut TtlConfig,
+    index: &'a mut ExpiryIndex,
+    notifier: &'a Notifier<K>,
+    #[cfg(feature = "stats")]
+    counters: &'a mut Counters,
+}
+
+impl<'a, K: 'a + Hash + Eq, V: 'a, S: BuildHasher> RawVacantEntryMut<'a, K, V, S> {
+    /// Sets the value of the entry under the given key, and returns a mutable reference to it.
+    /// The key must be one the lookup would have matched.
+    pub fn insert(self, key: K, value: V, duration: Duration) -> &'a mut V {
+        let internal_entry = self.config.new_entry(value, duration, self.config.now());
+        #[cfg(feature = "stats")]
+        self.slot.count(self.counters, Some(&internal_entry));
+        self.slot.insert(key, internal_entry, self.hash, self.index, self.notifier)
+    }
+
+    /// Like `insert`, but with the key's precomputed hash.  The hash is not checked against the
+    /// key.
+    pub fn insert_hashed_nocheck(
+        self,
+        hash: u64,
+        key: K,
+        value: V,
+        duration: Duration,
+    ) -> &'a mut V {
+        let internal_entry = self.config.new_entry(value, duration, self.config.now());
+        #[cfg(feature = "stats")]
+        self.slot.count(self.counters, Some(&internal_entry));
+        match self.slot {
+            VacantSlot::Empty(entry) => {
+                self.index.note(hash, None, internal_entry.expiration);
+                &mut entry.insert_hashed_nocheck(hash, key, internal_entry).1.value
+            }
+            slot => slot.insert(key, internal_entry, hash, self.index, self.notifier),
+        }
+    }
+}
@@ -0,0 +1,280 @@
+//! Serde support.  Only available with the `serde` feature.
+//!
+//! An `Instant` means nothing outside the process that took it, so a cache is saved as the wall
+//! clock time of the snapshot along with each unexpired entry's key, value and remaining TTL
+//! (`None` for permanent entries).  Loading takes the wall clock time that passed since the
+//! snapshot off every remaining TTL and drops the entries that ran out in the meantime.  A
+//! loaded entry restarts from its remaining TTL when its TTL is reset.  Both wall clock times
+//! are read through the cache's clock, see `Clock::system_time_at`.
+
+use std::hash::{BuildHasher, Hash};
+use std::time::{Duration, UNIX_EPOCH};
+
+#[cfg(feature = "stats")]
+use std::fmt;
+
+#[cfg(feature = "stats")]
+use serde::de::{self, IgnoredAny, MapAccess, SeqAccess, Visitor};
+#[cfg(feature = "stats")]
+use serde::ser::SerializeStruct;
+use serde::ser::{SerializeSeq, SerializeTuple};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+#[cfg(feature = "stats")]
+use super::time::Instant;
+#[cfg(feature = "stats")]
+use super::CacheStats;
+use super::{Clock, InternalEntry, Stamp, TtlCache, TtlConfig};
+
+impl<K, V, S> Serialize for TtlCache<K, V, S>
+where
+    K: Eq + Hash + Serialize,
+    V: Serialize,
+    S: BuildHasher,
+{
+    fn serialize<Se: Serializer>(&self, serializer: Se) -> Result<Se::Ok, Se::Error> {
+        let now = self.config.now();
+        let saved_at = wall_clock(&self.config, now);
+        let mut snapshot = serializer.serialize_tuple(2)?;
+        snapshot.serialize_element(&saved_at)?;
+        snapshot.serialize_element(&Entries { cache: self, now })?;
+        snapshot.end()
+    }
+}
+
+struct Entries<'a, K: 'a + Eq + Hash, V: 'a, S: 'a + BuildHasher> {
+    cache: &'a TtlCache<K, V, S>,
+    now: Stamp,
+}
+
+impl<'a, K, V, S> Serialize for Entries<'a, K, V, S>
+where
+    K: Eq + Hash + Serialize,
+    V: Serialize,
+    S: BuildHasher,
+{
+    fn serialize<Se: Serializer>(&self, serializer: Se) -> Result<Se::Ok, Se::Error> {
+        let mut seq = serializer.serialize_seq(None)?;
+        for (k, x) in self.cache.map.iter() {
+            if x.is_expired(self.now) {
+                continue;
+            }
+            let remaining_ttl = x.expiration.map(|_| x.remaining_ttl(self.now));
+            seq.serialize_element(&(k, &x.value, remaining_ttl))?;
+        }
+        seq.end()
+    }
+}
+
+impl<'de, K, V, S> Deserialize<'de> for TtlCache<K, V, S>
+where
+    K: Eq + Hash + Deserialize<'de>,
+    V: Deserialize<'de>,
+    S: BuildHasher + Default,
+{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        TtlCache::with_hasher(S::default()).load(deserializer)
+    }
+}
+
+impl<K: Eq + Hash, V, S: BuildHasher + Default> TtlCache<K, V, S> {
+    /// Loads a cache saved with `serde` onto `clock`, which also tells how much wall clock
+    /// time has passed since the snapshot.  Only available with the `serde` feature.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use ttl_cache::test_util::MockClock;
+    /// use ttl_cache::TtlCache;
+    ///
+    /// let clock = MockClock::new();
+    /// let mut cache = TtlCache::with_clock(clock.clone());
+    /// cache.insert(1, 10, Duration::from_secs(30));
+    /// let json = serde_json::to_string(&cache).unwrap();
+    ///
+    /// clock.advance(Duration::from_secs(10));
+    /// let mut de = serde_json::Deserializer::from_str(&json);
+    /// let loaded: TtlCache<i32, i32> = TtlCache::deserialize_with_clock(&mut de, clock).unwrap();
+    /// assert_eq!(loaded.remaining_ttl(&1), Some(Duration::from_secs(20)));
+    /// ```
+    pub fn deserialize_with_clock<'de, D, C>(deserializer: D, clock: C) -> Result<Self, D::Error>
+    where
+        K: Deserialize<'de>,
+        V: Deserialize<'de>,
+        D: Deserializer<'de>,
+        C: Clock + 'static,
+    {
+        TtlCache::with_hasher_and_clock(S::default(), clock).load(deserializer)
+    }
+
+    /// Fills a new cache from a snapshot, aging it by the wall clock time since it was saved
+    fn load<'de, D>(mut self, deserializer: D) -> Result<Self, D::Error>
+    where
+        K: Deserialize<'de>,
+        V: Deserialize<'de>,
+        D: Deserializer<'de>,
+    {
+        let (saved_at, entries) =
+            <(Duration, Vec<(K, V, Option<Duration>)>)>::deserialize(deserializer)?;
+        let now = self.config.now();
+        // A wall clock that went backwards since the snapshot counts as no time passing
+        let elapsed = wall_clock(&self.config, now)
+            .checked_sub(saved_at)
+            .unwrap_or_default();
+        for (k, v, remaining_ttl) in entries {
+            let entry = match remaining_ttl {
+                Some(remaining_ttl) => match remaining_ttl.checked_sub(elapsed) {
+                    Some(ttl) if ttl > Duration::ZERO => InternalEntry::new_at(v, ttl, ttl, now),
+                    _ => continue,
+                },
+                None => InternalEntry::permanent(v, now),
+            };
+            self.insert_entry(k, entry);
+        }
+        Ok(self)
+    }
+}
+
+/// The cache clock's wall clock time at `now`, as time since the Unix epoch
+fn wall_clock(config: &TtlConfig, now: Stamp) -> Duration {
+    config
+        .system_time(now)
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .unwrap_or_default()
+}
+
+/// The fields of a serialized `CacheStats`, in order
+#[cfg(feature = "stats")]
+const STATS_FIELDS: &[&str] = &[
+    "hits",
+    "misses",
+    "expired_misses",
+    "expired",
+    "evicted",
+    "inserts",
+    "replaces",
+    "len",
+    "window",
+];
+
+/// The instants in a stats snapshot mean nothing outside the process either, so the window
+/// between them is saved instead
+#[cfg(feature = "stats")]
+impl Serialize for CacheStats {
+    fn serialize<Se: Serializer>(&self, serializer: Se) -> Result<Se::Ok, Se::Error> {
+        let mut stats = serializer.serialize_struct("CacheStats", STATS_FIELDS.len())?;
+        stats.serialize_field("hits", &self.hits)?;
+        stats.serialize_field("misses", &self.misses)?;
+        stats.serialize_field("expired_misses", &self.expired_misses)?;
+        stats.serialize_field("expired", &self.expired)?;
+        stats.serialize_field("evicted", &self.evicted)?;
+        stats.serialize_field("inserts", &self.inserts)?;
+        stats.serialize_field("replaces", &self.replaces)?;
+        stats.serialize_field("len", &self.len)?;
+        stats.serialize_field("window", &self.elapsed())?;
+        stats.end()
+    }
+}
+
+/// A loaded snapshot is taken to have been captured when it was loaded, with the counters
+/// starting the saved window before that.  Missing counts load as zero, so snapshots saved
+/// before a field was added still load, and unknown fields are skipped.
+#[cfg(feature = "stats")]
+impl<'de> Deserialize<'de> for CacheStats {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_struct("CacheStats", STATS_FIELDS, StatsVisitor)
+    }
+}
+
+#[cfg(feature = "stats")]
+struct StatsVisitor;
+
+#[cfg(feature = "stats")]
+impl StatsVisitor {
+    fn stats(window: Duration) -> CacheStats {
+        let captured_at = Instant::now();
+        CacheStats {
+            hits: 0,
+            misses: 0,
+            since: captured_at.checked_sub(window).unwrap_or(captured_at),
+            captured_at,
+            expired_misses: 0,
+            expired: 0,
+            evicted: 0,
+            inserts: 0,
+            replaces: 0,
+            len: 0,
+            name: None,
+        }
+    }
+}
+
+#[cfg(feature = "stats")]
+impl<'de> Visitor<'de> for StatsVisitor {
+    type Value = CacheStats;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a cache stats snapshot")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<CacheStats, A::Error> {
+        let mut counts = [0u64; 7];
+        for (i, count) in counts.iter_mut().enumerate() {
+            *count = seq
+                .next_element()?
+                .ok_or_else(|| de::Error::invalid_length(i, &self))?;
+        }
+        let len = seq
+            .next_element()?
+            .ok_or_else(|| de::Error::invalid_length(counts.len(), &self))?;
+        let window = seq
+            .next_element()?
+            .ok_or_else(|| de::Error::invalid_length(counts.len() + 1, &self))?;
+        let [hits, misses, expired_misses, expired, evicted, inserts, replaces] = counts;
+        Ok(CacheStats {
+            hits,
+            misses,
+            expired_misses,
+            expired,
+            evicted,
+            inserts,
+            replaces,
+            len,
+            ..StatsVisitor::stats(window)
+        })
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<CacheStats, A::Error> {
+        let mut stats = StatsVisitor::stats(Duration::ZERO);
+        while let Some(key) = map.next_key::<String>()? {
+            let count = match key.as_str() {
+                "hits" => &mut stats.hits,
+                "misses" => &mut stats.misses,
+                "expired_misses" => &mut stats.expired_misses,
+                "expired" => &mut stats.expired,
+                "evicted" => &mut stats.evicted,
+                "inserts" => &mut stats.inserts,
+                "replaces" => &mut stats.replaces,
+                "len" => {
+                    stats.len = map.next_value()?;
+                    continue;
+                }
+                "window" => {
+                    let window = map.next_value()?;
+                    stats.since = stats
+                        .captured_at
+                        .checked_sub(window)
+                        .unwrap_or(stats.captured_at);
+                    continue;
+                }
+                _ => {
+                    map.next_value::<IgnoredAny>()?;
+                    continue;
+                }
+            };
+            *count = map.next_value()?;
+        }
+        Ok(stats)
+    }
+}
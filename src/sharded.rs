@@ -0,0 +1,271 @@
+//! A cache split into independently locked shards, for many threads at once.
+
+use std::borrow::Borrow;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hash};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::thread;
+use std::time::Duration;
+
+#[cfg(feature = "stats")]
+use stats::CacheStats;
+use {make_hash, Clock, TtlCache};
+
+/// A thread-safe cache made of several `TtlCache`s, each behind its own lock, with every key
+/// living in the shard its hash picks.  Threads working on keys in different shards don't
+/// wait on each other, and each shard expires its entries just like a lone cache.
+///
+/// Only the core of the cache API is here.  For the rest, `lock_shard` hands out the shard a
+/// key lives in.  A panic while a shard is locked doesn't poison it for other threads.
+///
+/// For large values, store them as `Arc<V>` with `insert_arc` and read them with `get_arc`,
+/// which hands out a new handle rather than cloning the value.
+///
+/// # Examples
+///
+/// ```
+/// use std::sync::Arc;
+/// use std::thread;
+/// use std::time::Duration;
+/// use ttl_cache::sharded::ShardedTtlCache;
+///
+/// let cache = Arc::new(ShardedTtlCache::new());
+/// let workers: Vec<_> = (0..4)
+///     .map(|worker| {
+///         let cache = cache.clone();
+///         thread::spawn(move || {
+///             cache.insert(worker, worker * 10, Duration::from_secs(30));
+///         })
+///     })
+///     .collect();
+/// for worker in workers {
+///     worker.join().unwrap();
+/// }
+///
+/// assert_eq!(cache.get_cloned(&2), Some(20));
+/// assert_eq!(cache.get_with(&3, |v| v + 1), Some(31));
+/// assert_eq!(cache.len(), 4);
+/// ```
+pub struct ShardedTtlCache<K: Eq + Hash, V, S: BuildHasher = RandomState> {
+    shards: Box<[Mutex<TtlCache<K, V, S>>]>,
+    hash_builder: S,
+}
+
+impl<K: Eq + Hash, V> ShardedTtlCache<K, V> {
+    /// Creates an empty cache with a shard for each thread the machine can run in parallel,
+    /// or a single shard if that can't be told.
+    pub fn new() -> Self {
+        let shards = thread::available_parallelism().map_or(1, |n| n.get());
+        Self::with_shards(shards)
+    }
+
+    /// Creates an empty cache with `shards` shards.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `shards` is zero.
+    pub fn with_shards(shards: usize) -> Self {
+        Self::with_shards_and_hasher(shards, RandomState::new())
+    }
+
+    /// Creates an empty cache with `shards` shards that take the current time from `clock`,
+    /// see `TtlCache::with_clock`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `shards` is zero.
+    pub fn with_shards_and_clock<C: Clock + Clone + 'static>(shards: usize, clock: C) -> Self {
+        let hash_builder = RandomState::new();
+        Self::build(shards, hash_builder.clone(), |hash_builder| {
+            TtlCache::with_hasher_and_clock(hash_builder, clock.clone())
+        })
+    }
+}
+
+/// Creates an empty cache as the default
+impl<K: Eq + Hash, V> Default for ShardedTtlCache<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: Eq + Hash, V, S: BuildHasher + Clone> ShardedTtlCache<K, V, S> {
+    /// Creates an empty cache with `shards` shards and the given hash builder, which picks
+    /// the shards and is cloned into each of them.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `shards` is zero.
+    pub fn with_shards_and_hasher(shards: usize, hash_builder: S) -> Self {
+        Self::build(shards, hash_builder, TtlCache::with_hasher)
+    }
+
+    fn build<F>(shards: usize, hash_builder: S, shard: F) -> Self
+    where
+        F: Fn(S) -> TtlCache<K, V, S>,
+    {
+        assert!(shards > 0, "a sharded cache needs at least one shard");
+        ShardedTtlCache {
+            shards: (0..shards)
+                .map(|_| Mutex::new(shard(hash_builder.clone())))
+                .collect(),
+            hash_builder,
+        }
+    }
+}
+
+impl<K: Eq + Hash, V, S: BuildHasher> ShardedTtlCache<K, V, S> {
+    /// Returns the number of shards.
+    pub fn shards(&self) -> usize {
+        self.shards.len()
+    }
+
+    /// Locks the shard `k` lives in, for anything the rest of this API doesn't cover.  Other
+    /// threads wait on the whole shard while the guard is held.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use ttl_cache::sharded::ShardedTtlCache;
+    ///
+    /// let cache = ShardedTtlCache::with_shards(4);
+    /// cache.lock_shard("hits").entry("hits").or_insert(0, Duration::from_secs(60));
+    /// *cache.lock_shard("hits").get_mut("hits").unwrap() += 1;
+    /// assert_eq!(cache.get_cloned("hits"), Some(1));
+    /// ```
+    pub fn lock_shard<Q>(&self, k: &Q) -> MutexGuard<'_, TtlCache<K, V, S>>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        // The shards' maps place keys by the low bits of the same hash and tell them apart by
+        // the top ones, so the shard is picked from the bits in between
+        let hash = make_hash(&self.hash_builder, k);
+        let shard = (hash >> 20) as usize % self.shards.len();
+        lock(&self.shards[shard])
+    }
+
+    /// Inserts a key-value pair with the given TTL, see `TtlCache::insert`.
+    pub fn insert(&self, k: K, v: V, ttl: Duration) -> Option<V> {
+        self.lock_shard(&k).insert(k, v, ttl)
+    }
+
+    /// Calls `f` with the unexpired value for the key and returns what it returns, without
+    /// cloning the value.  The key's shard stays locked while `f` runs.
+    pub fn get_with<Q, F, R>(&self, k: &Q, f: F) -> Option<R>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+        F: FnOnce(&V) -> R,
+    {
+        self.lock_shard(k).get(k).map(f)
+    }
+
+    /// Returns a clone of the unexpired value for the key.
+    pub fn get_cloned<Q>(&self, k: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+        V: Clone,
+    {
+        self.get_with(k, V::clone)
+    }
+
+    /// Checks if the cache holds an unexpired value for the key.
+    pub fn contains_key<Q>(&self, k: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        self.lock_shard(k).contains_key(k)
+    }
+
+    /// Removes the key, returning its value if it hadn't expired, see `TtlCache::remove`.
+    pub fn remove<Q>(&self, k: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        self.lock_shard(k).remove(k)
+    }
+
+    /// Removes every expired entry, one shard at a time.
+    pub fn remove_expired(&self) {
+        for shard in self.shards.iter() {
+            lock(shard).remove_expired();
+        }
+    }
+
+    /// Clears all values out of the cache, one shard at a time.
+    pub fn clear(&self) {
+        for shard in self.shards.iter() {
+            lock(shard).clear();
+        }
+    }
+
+    /// Returns the number of entries held across the shards, counting expired ones not purged
+    /// yet.  The shards are counted one after another, so with other threads at work the
+    /// total is only a close estimate.
+    pub fn len(&self) -> usize {
+        self.shards.iter().map(|shard| lock(shard).map.len()).sum()
+    }
+
+    /// Returns true if no shard holds any entries, expired or not.
+    pub fn is_empty(&self) -> bool {
+        self.shards.iter().all(|shard| lock(shard).map.is_empty())
+    }
+
+    /// Calls `f` with every unexpired entry, locking one shard at a time.  Each shard is seen
+    /// as it was while locked, but not all of them at the same moment.
+    pub fn for_each<F: FnMut(&K, &V)>(&self, mut f: F) {
+        for shard in self.shards.iter() {
+            for (k, v) in lock(shard).iter() {
+                f(k, v);
+            }
+        }
+    }
+
+    /// Returns the stats of all the shards added together.  Each shard's snapshot is taken in
+    /// turn; the result starts from the earliest of them and is captured at the latest.
+    #[cfg(feature = "stats")]
+    pub fn stats(&self) -> CacheStats {
+        let mut shards = self.shards.iter().map(|shard| lock(shard).stats());
+        let first = shards.next().expect("a sharded cache has at least one shard");
+        shards.fold(first, |mut total, stats| {
+            total.hits += stats.hits;
+            total.misses += stats.misses;
+            total.expired_misses += stats.expired_misses;
+            total.expired += stats.expired;
+            total.evicted += stats.evicted;
+            total.inserts += stats.inserts;
+            total.replaces += stats.replaces;
+            total.len += stats.len;
+            total.since = total.since.min(stats.since);
+            total.captured_at = total.captured_at.max(stats.captured_at);
+            total
+        })
+    }
+}
+
+impl<K: Eq + Hash, V, S: BuildHasher> ShardedTtlCache<K, Arc<V>, S> {
+    /// Wraps the value in an `Arc` and inserts it with the given TTL, see `TtlCache::insert`.
+    pub fn insert_arc(&self, k: K, v: V, ttl: Duration) -> Option<Arc<V>> {
+        self.insert(k, Arc::new(v), ttl)
+    }
+
+    /// Returns a new handle on the unexpired value for the key.  The key's shard is only
+    /// locked for the lookup, and the value lives on as long as the handle does.
+    pub fn get_arc<Q>(&self, k: &Q) -> Option<Arc<V>>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        self.get_cloned(k)
+    }
+}
+
+/// Locks a shard, taking it over from a thread that panicked while holding it
+fn lock<T>(shard: &Mutex<T>) -> MutexGuard<'_, T> {
+    shard.lock().unwrap_or_else(PoisonError::into_inner)
+}
@@ -0,0 +1,380 @@
+//! A cache for read-mostly workloads, whose lookups never wait on a lock.
+
+use std::borrow::Borrow;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hash};
+#[cfg(feature = "stats")]
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::Duration;
+
+use arc_swap::ArcSwap;
+use hashlink::LinkedHashMap;
+
+#[cfg(feature = "stats")]
+use stats::CacheStats;
+use time::Stamp;
+use {TtlCache, TtlConfig};
+
+/// A `TtlCache` read through an immutable snapshot and written through a single writer, for
+/// workloads where lookups vastly outnumber changes.  Clones share the same cache.
+///
+/// Lookups (`get_cloned`, `with`, `contains_key` and `snapshot`) read the latest published
+/// snapshot without taking any lock, so they never wait on each other or on a writer.  They
+/// still check deadlines against the cache's clock, so an entry stops being returned once it
+/// expires even if nothing has been published since.
+///
+/// Changes (`insert`, `remove`, `purge` and `write`) go through the writer's cache under a
+/// mutex.  Each one then purges the expired entries and publishes a copy of the cache for
+/// lookups to see, which costs a clone of every unexpired entry.  Batch changes together with
+/// `write`, and store large values as `Arc<V>` with `insert_arc` so a copy only clones the
+/// handles.  A lookup that started before a publish keeps reading the snapshot it started
+/// with.  A panic while the writer is locked doesn't poison it for other threads.
+///
+/// With the `stats` feature, lookups are counted in atomics shared by the clones rather than
+/// in the writer's cache, so the cache stays `Send` and `Sync`, see `stats`.  Lookups made
+/// directly on a `Snapshot` aren't counted.
+///
+/// # Examples
+///
+/// ```
+/// use std::thread;
+/// use std::time::Duration;
+/// use ttl_cache::snapshot::SnapshotTtlCache;
+///
+/// let cache = SnapshotTtlCache::new();
+/// cache.insert("config", String::from("v1"), Duration::from_secs(30));
+///
+/// let reader = cache.clone();
+/// let len = thread::spawn(move || reader.with("config", |v| v.len()))
+///     .join()
+///     .unwrap();
+/// assert_eq!(len, Some(2));
+/// assert_eq!(cache.get_cloned("config"), Some(String::from("v1")));
+/// ```
+pub struct SnapshotTtlCache<K: Eq + Hash, V, S: BuildHasher = RandomState> {
+    inner: Arc<Inner<K, V, S>>,
+}
+
+struct Inner<K: Eq + Hash, V, S: BuildHasher> {
+    published: ArcSwap<Snapshot<K, V, S>>,
+    writer: Mutex<TtlCache<K, V, S>>,
+    #[cfg(feature = "stats")]
+    lookups: Lookups,
+}
+
+/// The lookups made through a `SnapshotTtlCache`, which readers on any thread bump at once
+#[cfg(feature = "stats")]
+#[derive(Default)]
+struct Lookups {
+    hits: AtomicU64,
+    misses: AtomicU64,
+    expired_misses: AtomicU64,
+}
+
+/// An immutable copy of the entries of a `SnapshotTtlCache`, as its latest change left them.
+/// Created by `SnapshotTtlCache::snapshot`.
+///
+/// Entries still expire against the cache's clock, so one stops being returned once its
+/// deadline passes, though nothing is ever removed from a snapshot.
+pub struct Snapshot<K, V, S = RandomState> {
+    /// Each value with its expiration, `None` for one that never expires
+    entries: LinkedHashMap<K, (V, Option<Stamp>), S>,
+    /// For the clock the expirations are checked against
+    config: TtlConfig,
+}
+
+impl<K: Eq + Hash, V, S: BuildHasher> Snapshot<K, V, S> {
+    /// Copies the unexpired entries of `cache`, along with its clock
+    fn of(cache: &TtlCache<K, V, S>) -> Self
+    where
+        K: Clone,
+        V: Clone,
+        S: Clone,
+    {
+        let now = cache.config.now();
+        let mut entries = LinkedHashMap::with_hasher(cache.map.hasher().clone());
+        for (k, x) in cache.map.iter() {
+            if !x.is_expired(now) {
+                entries.insert(k.clone(), (x.value.clone(), x.expiration));
+            }
+        }
+        Snapshot {
+            entries,
+            config: cache.config.clone(),
+        }
+    }
+
+    /// Returns a reference to the unexpired value for the key.
+    pub fn get<Q>(&self, k: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        self.lookup(k).0
+    }
+
+    /// Checks if the snapshot holds an unexpired value for the key.
+    pub fn contains_key<Q>(&self, k: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        self.get(k).is_some()
+    }
+
+    /// Returns an iterator over the unexpired key-value pairs in oldest to youngest order.
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> + '_ {
+        let now = self.config.now();
+        self.entries
+            .iter()
+            .filter(move |&(_, &(_, expiration))| is_live(expiration, now))
+            .map(|(k, (v, _))| (k, v))
+    }
+
+    /// Returns the unexpired value for the key, and whether there was an entry for it at all
+    fn lookup<Q>(&self, k: &Q) -> (Option<&V>, bool)
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        let now = self.config.now();
+        match self.entries.get(k) {
+            Some(&(ref v, expiration)) => (Some(v).filter(|_| is_live(expiration, now)), true),
+            None => (None, false),
+        }
+    }
+}
+
+fn is_live(expiration: Option<Stamp>, now: Stamp) -> bool {
+    expiration.map_or(true, |expiration| now < expiration)
+}
+
+impl<K: Eq + Hash + Clone, V: Clone> SnapshotTtlCache<K, V> {
+    /// Creates an empty cache.
+    pub fn new() -> Self {
+        SnapshotTtlCache::from(TtlCache::new())
+    }
+}
+
+/// Creates an empty cache as the default
+impl<K: Eq + Hash + Clone, V: Clone> Default for SnapshotTtlCache<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Publishes a cache set up any way a `TtlCache` can be
+impl<K, V, S> From<TtlCache<K, V, S>> for SnapshotTtlCache<K, V, S>
+where
+    K: Eq + Hash + Clone,
+    V: Clone,
+    S: BuildHasher + Clone,
+{
+    fn from(mut cache: TtlCache<K, V, S>) -> Self {
+        cache.remove_expired();
+        SnapshotTtlCache {
+            inner: Arc::new(Inner {
+                published: ArcSwap::from_pointee(Snapshot::of(&cache)),
+                writer: Mutex::new(cache),
+                #[cfg(feature = "stats")]
+                lookups: Lookups::default(),
+            }),
+        }
+    }
+}
+
+/// Clones share the cache rather than copying it
+impl<K: Eq + Hash, V, S: BuildHasher> Clone for SnapshotTtlCache<K, V, S> {
+    fn clone(&self) -> Self {
+        SnapshotTtlCache {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<K: Eq + Hash, V, S: BuildHasher> SnapshotTtlCache<K, V, S> {
+    /// Returns a clone of the unexpired value for the key.
+    pub fn get_cloned<Q>(&self, k: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+        V: Clone,
+    {
+        self.with(k, V::clone)
+    }
+
+    /// Calls `f` with the unexpired value for the key and returns what it returns, without
+    /// cloning the value.  `f` reads the latest snapshot and holds up no writer.
+    #[cfg_attr(not(feature = "stats"), allow(unused_variables))]
+    pub fn with<Q, F, R>(&self, k: &Q, f: F) -> Option<R>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+        F: FnOnce(&V) -> R,
+    {
+        let snapshot = self.inner.published.load();
+        let (value, present) = snapshot.lookup(k);
+        #[cfg(feature = "stats")]
+        self.inner.lookups.count(value.is_some(), present);
+        value.map(f)
+    }
+
+    /// Checks if the cache holds an unexpired value for the key.
+    pub fn contains_key<Q>(&self, k: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        self.with(k, |_| ()).is_some()
+    }
+
+    /// Returns the latest published snapshot, for lookups the rest of this API doesn't cover
+    /// or for reading several keys consistently.  Later changes don't show up in it, but its
+    /// entries still expire on time.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use ttl_cache::snapshot::SnapshotTtlCache;
+    ///
+    /// let cache = SnapshotTtlCache::new();
+    /// cache.insert(1, "a", Duration::from_secs(30));
+    ///
+    /// let snapshot = cache.snapshot();
+    /// cache.insert(2, "b", Duration::from_secs(30));
+    /// assert_eq!(snapshot.iter().count(), 1);
+    /// assert_eq!(cache.snapshot().iter().count(), 2);
+    /// ```
+    pub fn snapshot(&self) -> Arc<Snapshot<K, V, S>> {
+        self.inner.published.load_full()
+    }
+
+    /// Returns the writer's stats with the lookups made through this cache added to its hits
+    /// and misses.  Only available with the `stats` feature.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use ttl_cache::snapshot::SnapshotTtlCache;
+    ///
+    /// let cache = SnapshotTtlCache::new();
+    /// cache.insert(1, "a", Duration::from_secs(30));
+    /// assert_eq!(cache.get_cloned(&1), Some("a"));
+    /// assert!(!cache.contains_key(&2));
+    ///
+    /// let stats = cache.stats();
+    /// assert_eq!((stats.hits, stats.misses, stats.inserts), (1, 1, 1));
+    /// ```
+    #[cfg(feature = "stats")]
+    pub fn stats(&self) -> CacheStats {
+        let mut stats = self.writer_lock().stats();
+        let lookups = &self.inner.lookups;
+        stats.hits += lookups.hits.load(Ordering::Relaxed);
+        stats.misses += lookups.misses.load(Ordering::Relaxed);
+        stats.expired_misses += lookups.expired_misses.load(Ordering::Relaxed);
+        stats
+    }
+
+    fn writer_lock(&self) -> MutexGuard<'_, TtlCache<K, V, S>> {
+        self.inner.writer.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+#[cfg(feature = "stats")]
+impl Lookups {
+    /// Counts a hit, or else a miss, which `present` tells apart into one that found an
+    /// expired entry and one that found nothing
+    fn count(&self, hit: bool, present: bool) {
+        if hit {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.misses.fetch_add(1, Ordering::Relaxed);
+            if present {
+                self.expired_misses.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+impl<K, V, S> SnapshotTtlCache<K, V, S>
+where
+    K: Eq + Hash + Clone,
+    V: Clone,
+    S: BuildHasher + Clone,
+{
+    /// Inserts a key-value pair with the given TTL and publishes the change, see
+    /// `TtlCache::insert`.
+    pub fn insert(&self, k: K, v: V, ttl: Duration) -> Option<V> {
+        self.write(|cache| cache.insert(k, v, ttl))
+    }
+
+    /// Removes the key and publishes the change, returning its value if it hadn't expired,
+    /// see `TtlCache::remove`.
+    pub fn remove<Q>(&self, k: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        self.write(|cache| cache.remove(k))
+    }
+
+    /// Removes every expired entry and publishes what's left, freeing the memory the
+    /// current snapshot holds for them once the lookups reading it are done.
+    pub fn purge(&self) {
+        self.write(|_| ())
+    }
+
+    /// Calls `f` with the writer's cache, then purges it and publishes it, for changes the
+    /// rest of this API doesn't cover.  Lookups see all of the changes `f` makes or none of
+    /// them.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use ttl_cache::snapshot::SnapshotTtlCache;
+    ///
+    /// let cache = SnapshotTtlCache::new();
+    /// cache.write(|cache| {
+    ///     cache.insert("min", 1, Duration::from_secs(60));
+    ///     cache.insert("max", 9, Duration::from_secs(60));
+    /// });
+    /// assert_eq!(cache.get_cloned("max"), Some(9));
+    /// ```
+    pub fn write<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce(&mut TtlCache<K, V, S>) -> R,
+    {
+        let mut cache = self.writer_lock();
+        let result = f(&mut cache);
+        cache.remove_expired();
+        // Published under the writer's lock, so snapshots go out in the order of the changes
+        self.inner.published.store(Arc::new(Snapshot::of(&cache)));
+        result
+    }
+}
+
+impl<K, V, S> SnapshotTtlCache<K, Arc<V>, S>
+where
+    K: Eq + Hash + Clone,
+    S: BuildHasher + Clone,
+{
+    /// Wraps the value in an `Arc`, inserts it with the given TTL and publishes the change,
+    /// see `TtlCache::insert`.
+    pub fn insert_arc(&self, k: K, v: V, ttl: Duration) -> Option<Arc<V>> {
+        self.insert(k, Arc::new(v), ttl)
+    }
+
+    /// Returns a new handle on the unexpired value for the key, which lives on as long as
+    /// the handle does.
+    pub fn get_arc<Q>(&self, k: &Q) -> Option<Arc<V>>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        self.get_cloned(k)
+    }
+}
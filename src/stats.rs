@@ -0,0 +1,679 @@
+//! Hit and miss accounting.  Only available with the `stats` feature.
+
+use std::cell::Cell;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+#[cfg(feature = "metrics")]
+use metrics;
+#[cfg(feature = "metrics")]
+use metrics::{Gauge, Label};
+
+use clock::Clock;
+use time::{Instant, Stamp};
+use InternalEntry;
+
+/// A stats counter bumped through `&self` by lookups.
+///
+/// The count is a plain `Cell`, so a lookup pays for a load and a store rather than an atomic
+/// read-modify-write, at the price of the cache not being `Sync` with the `stats` feature.
+/// Counts are 64 bits wide on every target, so they don't wrap after four billion events on
+/// 32-bit ones.
+#[derive(Clone, Default)]
+pub(crate) struct Counter(Cell<u64>);
+
+impl Counter {
+    pub(crate) fn get(&self) -> u64 {
+        self.0.get()
+    }
+
+    pub(crate) fn incr(&self) {
+        self.add(1);
+    }
+
+    pub(crate) fn add(&self, n: u64) {
+        self.0.set(self.get().wrapping_add(n));
+    }
+
+    /// Raises the count to `n` if it is lower
+    pub(crate) fn max(&self, n: u64) {
+        if n > self.get() {
+            self.0.set(n);
+        }
+    }
+
+    fn set(&self, n: u64) {
+        self.0.set(n);
+    }
+}
+
+/// The upper bounds of the TTL histogram buckets, exclusive except for the last, which also
+/// takes entries that never expire
+const TTL_BOUNDS: [Duration; 7] = [
+    Duration::from_secs(1),
+    Duration::from_secs(10),
+    Duration::from_secs(60),
+    Duration::from_secs(10 * 60),
+    Duration::from_secs(60 * 60),
+    Duration::from_secs(24 * 60 * 60),
+    Duration::MAX,
+];
+
+/// One set of the counts a cache keeps
+#[derive(Clone, Default)]
+pub(crate) struct Counts {
+    pub(crate) hits: Counter,
+    pub(crate) misses: Counter,
+    pub(crate) expired_misses: Counter,
+    pub(crate) expired: Counter,
+    pub(crate) evicted: Counter,
+    pub(crate) inserts: Counter,
+    pub(crate) replaces: Counter,
+    /// The ages of the entries hit, in microseconds
+    pub(crate) hit_age_total: Counter,
+    pub(crate) hit_age_max: Counter,
+}
+
+impl Counts {
+    fn clear(&self) {
+        for counter in self.all() {
+            counter.set(0);
+        }
+    }
+
+    fn all(&self) -> [&Counter; 9] {
+        [
+            &self.hits,
+            &self.misses,
+            &self.expired_misses,
+            &self.expired,
+            &self.evicted,
+            &self.inserts,
+            &self.replaces,
+            &self.hit_age_total,
+            &self.hit_age_max,
+        ]
+    }
+
+    /// Returns the average age of the entries hit, or `None` without any hits
+    pub(crate) fn avg_hit_age(&self) -> Option<Duration> {
+        match self.hits.get() {
+            0 => None,
+            hits => Some(Duration::from_micros(self.hit_age_total.get() / hits)),
+        }
+    }
+
+    /// Returns the age of the oldest entry hit, or `None` without any hits
+    pub(crate) fn max_hit_age(&self) -> Option<Duration> {
+        match self.hits.get() {
+            0 => None,
+            _ => Some(Duration::from_micros(self.hit_age_max.get())),
+        }
+    }
+
+    /// Returns a snapshot of the counts, with `since` and `captured_at` marking the window
+    /// they cover
+    pub(crate) fn snapshot(&self, since: Instant, captured_at: Instant, len: usize) -> CacheStats {
+        let mut stats = CacheStats {
+            hits: 0,
+            misses: 0,
+            since,
+            captured_at,
+            expired_misses: 0,
+            expired: 0,
+            evicted: 0,
+            inserts: 0,
+            replaces: 0,
+            len,
+            name: None,
+        };
+        self.add_to(&mut stats);
+        stats
+    }
+
+    fn add_to(&self, stats: &mut CacheStats) {
+        stats.hits += self.hits.get();
+        stats.misses += self.misses.get();
+        stats.expired_misses += self.expired_misses.get();
+        stats.expired += self.expired.get();
+        stats.evicted += self.evicted.get();
+        stats.inserts += self.inserts.get();
+        stats.replaces += self.replaces.get();
+    }
+}
+
+/// The counters a cache keeps, bundled so entries and lookups can borrow them all at once.
+/// While disabled, counting is a single branch and the counters stay at zero.
+#[derive(Clone)]
+pub(crate) struct Counters {
+    pub(crate) totals: Counts,
+    /// Inserts by TTL, next to the upper bound of each bucket in `TTL_BOUNDS`
+    pub(crate) ttls: [(Duration, u64); TTL_BOUNDS.len()],
+    /// The most entries the cache was seen holding, see `TtlCache::peak_len`
+    pub(crate) peak_len: Counter,
+    pub(crate) window: Option<Box<Window>>,
+    pub(crate) enabled: bool,
+    /// The cache's name, passed on to snapshots
+    pub(crate) name: Option<&'static str>,
+    /// Where events go as they happen, see `TtlCache::register_metrics`
+    #[cfg(feature = "metrics")]
+    pub(crate) metrics: Option<Box<Metrics>>,
+}
+
+impl Default for Counters {
+    fn default() -> Counters {
+        Counters {
+            totals: Counts::default(),
+            ttls: TTL_BOUNDS.map(|bound| (bound, 0)),
+            peak_len: Counter::default(),
+            window: None,
+            enabled: true,
+            name: None,
+            #[cfg(feature = "metrics")]
+            metrics: None,
+        }
+    }
+}
+
+impl Counters {
+    /// Zeroes the counters, the window's included, and enables or disables counting
+    pub(crate) fn reset(&mut self, enabled: bool, now: Instant) {
+        self.totals = Counts::default();
+        self.ttls = TTL_BOUNDS.map(|bound| (bound, 0));
+        self.peak_len = Counter::default();
+        if let Some(ref mut window) = self.window {
+            **window = Window::new(window.clock.clone(), window.width, window.buckets.len(), now);
+        }
+        self.enabled = enabled;
+    }
+
+    /// Counts into the totals and the window's current bucket
+    fn record<F: Fn(&Counts)>(&self, count: F) {
+        if self.enabled {
+            count(&self.totals);
+            if let Some(ref window) = self.window {
+                count(window.current());
+            }
+        }
+    }
+
+    /// Hands an event to the registered metrics, if any
+    #[cfg(feature = "metrics")]
+    fn emit<F: Fn(&Metrics)>(&self, event: F) {
+        if let Some(ref metrics) = self.metrics {
+            event(metrics);
+        }
+    }
+
+    /// Counts a lookup that found the live `entry`, or else a miss, which `present` tells
+    /// apart into one that found an expired entry and one that found nothing
+    pub(crate) fn lookup<V>(&self, entry: Option<&InternalEntry<V>>, present: bool, now: Stamp) {
+        match entry {
+            Some(entry) => self.hit(entry, now),
+            None => self.miss(present),
+        }
+    }
+
+    /// Counts a hit at `now` on `entry`, along with how long ago its value was inserted.  With
+    /// the `access-counts` feature the hit is also counted on the entry.
+    pub(crate) fn hit<V>(&self, entry: &InternalEntry<V>, now: Stamp) {
+        #[cfg(feature = "metrics")]
+        self.emit(|metrics| metrics.hits.increment(1));
+        if !self.enabled {
+            return;
+        }
+        let age = now.saturating_duration_since(entry.inserted).as_micros() as u64;
+        self.record(|counts| {
+            counts.hits.incr();
+            counts.hit_age_total.add(age);
+            counts.hit_age_max.max(age);
+        });
+        #[cfg(feature = "access-counts")]
+        entry.accesses.incr();
+    }
+
+    /// Counts a miss, and whether it found an expired entry for the key
+    pub(crate) fn miss(&self, expired: bool) {
+        #[cfg(feature = "metrics")]
+        self.emit(|metrics| metrics.misses.increment(1));
+        self.record(|counts| {
+            counts.misses.incr();
+            if expired {
+                counts.expired_misses.incr();
+            }
+        });
+    }
+
+    /// Counts entries dropped because they had expired
+    pub(crate) fn expire(&self, n: usize) {
+        if n > 0 {
+            #[cfg(feature = "metrics")]
+            self.emit(|metrics| {
+                metrics.expired.increment(n as u64);
+                metrics.len.decrement(n as f64);
+            });
+            self.record(|counts| counts.expired.add(n as u64));
+        }
+    }
+
+    /// Counts live entries evicted over capacity
+    pub(crate) fn evict(&self, n: usize) {
+        if n > 0 {
+            #[cfg(feature = "metrics")]
+            self.emit(|metrics| {
+                metrics.evicted.increment(n as u64);
+                metrics.len.decrement(n as f64);
+            });
+            self.record(|counts| counts.evicted.add(n as u64));
+        }
+    }
+
+    /// Raises the high-water mark of entries to `len`
+    pub(crate) fn note_len(&self, len: usize) {
+        if self.enabled {
+            self.peak_len.max(len as u64);
+        }
+    }
+
+    /// Notes live entries taken out of the cache, which only the `metrics` length gauge
+    /// follows
+    #[cfg(feature = "metrics")]
+    pub(crate) fn remove(&self, n: usize) {
+        if n > 0 {
+            self.emit(|metrics| metrics.len.decrement(n as f64));
+        }
+    }
+
+    /// Counts an insert with `ttl`, `None` for a value that never expires, and whether it
+    /// replaced a live value
+    pub(crate) fn insert(&mut self, ttl: Option<Duration>, replaced: bool) {
+        #[cfg(feature = "metrics")]
+        self.emit(|metrics| {
+            metrics.inserts.increment(1);
+            if !replaced {
+                metrics.len.increment(1.0);
+            }
+        });
+        if !self.enabled {
+            return;
+        }
+        self.record(|counts| {
+            counts.inserts.incr();
+            if replaced {
+                counts.replaces.incr();
+            }
+        });
+        let ttl = ttl.unwrap_or(Duration::MAX);
+        let bucket = TTL_BOUNDS.iter().position(|&bound| ttl < bound);
+        self.ttls[bucket.unwrap_or(TTL_BOUNDS.len() - 1)].1 += 1;
+    }
+}
+
+/// Handles to the metrics a cache emits through the `metrics` facade, registered once so
+/// emitting an event is only a call through the recorder's handle.
+#[cfg(feature = "metrics")]
+#[derive(Clone)]
+pub(crate) struct Metrics {
+    hits: metrics::Counter,
+    misses: metrics::Counter,
+    expired: metrics::Counter,
+    evicted: metrics::Counter,
+    inserts: metrics::Counter,
+    len: Gauge,
+}
+
+#[cfg(feature = "metrics")]
+impl Metrics {
+    /// Registers the metrics with the current recorder, named `prefix` followed by `_hits`,
+    /// `_misses`, `_expired`, `_evicted`, `_inserts` and `_len`, starting the length gauge at
+    /// `len`
+    pub(crate) fn register(prefix: &str, labels: &[(&str, &str)], len: usize) -> Metrics {
+        let labels: Vec<Label> = labels
+            .iter()
+            .map(|&(key, value)| Label::new(key.to_owned(), value.to_owned()))
+            .collect();
+        let counter = |name| metrics::counter!(format!("{}_{}", prefix, name), labels.clone());
+        let metrics = Metrics {
+            hits: counter("hits"),
+            misses: counter("misses"),
+            expired: counter("expired"),
+            evicted: counter("evicted"),
+            inserts: counter("inserts"),
+            len: metrics::gauge!(format!("{}_len", prefix), labels.clone()),
+        };
+        metrics.len.set(len as f64);
+        metrics
+    }
+}
+
+/// A ring of counts for consecutive stretches of time, for `TtlCache::windowed_stats`.
+///
+/// Buckets are claimed lazily by whatever counts first in their stretch, which clears what
+/// an earlier lap of the ring left there.  Each bucket remembers which stretch it holds, so
+/// buckets nothing counted in since a long idle gap are told apart from current ones without
+/// visiting the ring.
+pub(crate) struct Window {
+    clock: Option<Arc<dyn Clock>>,
+    start: Instant,
+    width: Duration,
+    buckets: Box<[Bucket]>,
+}
+
+struct Bucket {
+    /// Which stretch the counts are for, counting from `Window::start`
+    seq: Cell<usize>,
+    counts: Counts,
+}
+
+impl Window {
+    pub(crate) fn new(
+        clock: Option<Arc<dyn Clock>>,
+        width: Duration,
+        buckets: usize,
+        now: Instant,
+    ) -> Window {
+        Window {
+            clock,
+            start: now,
+            width,
+            buckets: (0..buckets)
+                .map(|_| Bucket {
+                    seq: Cell::new(usize::MAX),
+                    counts: Counts::default(),
+                })
+                .collect(),
+        }
+    }
+
+    pub(crate) fn width(&self) -> Duration {
+        self.width
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.buckets.len()
+    }
+
+    fn now(&self) -> Instant {
+        match self.clock {
+            Some(ref clock) => clock.now(),
+            None => Instant::now(),
+        }
+    }
+
+    fn seq(&self, now: Instant) -> usize {
+        let elapsed = now.saturating_duration_since(self.start).as_nanos() as u64;
+        (elapsed / self.width.as_nanos() as u64) as usize
+    }
+
+    /// The bucket for the stretch of time it is now, cleared first if it still holds an
+    /// older one
+    fn current(&self) -> &Counts {
+        let seq = self.seq(self.now());
+        let bucket = &self.buckets[seq % self.buckets.len()];
+        if bucket.seq.get() != seq {
+            bucket.counts.clear();
+            bucket.seq.set(seq);
+        }
+        &bucket.counts
+    }
+
+    /// Adds up the buckets covering `window` back from `now`, which is rounded up to whole
+    /// buckets and capped at the whole ring
+    pub(crate) fn snapshot(&self, window: Duration, now: Instant, len: usize) -> CacheStats {
+        let seq = self.seq(now);
+        let width = self.width.as_nanos();
+        let wanted = (window.as_nanos() + width - 1) / width;
+        let count = (wanted.min(self.buckets.len() as u128) as usize).clamp(1, seq + 1);
+        let first = seq + 1 - count;
+        let since = self.start + Duration::from_nanos((first as u128 * width) as u64);
+        let mut stats = Counts::default().snapshot(since, now, len);
+        for seq in first..=seq {
+            let bucket = &self.buckets[seq % self.buckets.len()];
+            if bucket.seq.get() == seq {
+                bucket.counts.add_to(&mut stats);
+            }
+        }
+        stats
+    }
+}
+
+impl Clone for Window {
+    fn clone(&self) -> Window {
+        Window {
+            clock: self.clock.clone(),
+            start: self.start,
+            width: self.width,
+            buckets: self
+                .buckets
+                .iter()
+                .map(|bucket| Bucket {
+                    seq: bucket.seq.clone(),
+                    counts: bucket.counts.clone(),
+                })
+                .collect(),
+        }
+    }
+}
+
+/// The fraction of lookups that were hits, or 0.0 with no lookups at all
+pub(crate) fn hit_ratio(hits: u64, misses: u64) -> f64 {
+    match hits.saturating_add(misses) {
+        0 => 0.0,
+        lookups => hits as f64 / lookups as f64,
+    }
+}
+
+/// How many times per second `count` things happened over `elapsed`, or 0.0 if no time
+/// passed at all
+pub(crate) fn rate(count: u64, elapsed: Duration) -> f64 {
+    if elapsed == Duration::ZERO {
+        0.0
+    } else {
+        count as f64 / elapsed.as_secs_f64()
+    }
+}
+
+/// A snapshot of a cache's stats counters.  Created by `TtlCache::stats`.
+///
+/// `Display` renders it as `hits=1234 misses=56 hit_ratio=95.7% window=3600s`, the window being
+/// how long the counters had been running when the snapshot was taken.  With the `serde`
+/// feature it serializes as a map of the counts and `len`, with the window in place of the
+/// two instants, and loads back as a snapshot captured at load time over the same window.
+///
+/// More fields may be added in later versions, so the struct can't be built or matched
+/// exhaustively outside this crate.
+#[derive(Clone, Copy, Debug)]
+#[non_exhaustive]
+pub struct CacheStats {
+    /// The number of unexpired cache hits
+    pub hits: u64,
+    /// The number of cache misses, counting lookups of expired entries
+    pub misses: u64,
+    /// When the counters started, either when the cache was created or last reset
+    pub since: Instant,
+    /// When the snapshot was taken
+    pub captured_at: Instant,
+    /// How many of the misses found an expired entry for the key rather than none, see
+    /// `TtlCache::miss_expired_count`
+    pub expired_misses: u64,
+    /// The number of entries dropped because they had expired, see `TtlCache::expired_count`
+    pub expired: u64,
+    /// The number of live entries evicted over capacity, see `TtlCache::evicted_count`
+    pub evicted: u64,
+    /// The number of values inserted, see `TtlCache::insert_count`
+    pub inserts: u64,
+    /// The number of inserts that replaced a live value, see `TtlCache::replace_count`
+    pub replaces: u64,
+    /// The number of entries the cache held, counting expired ones not purged yet
+    pub len: usize,
+    /// The cache's name, see `TtlCache::set_name`
+    pub name: Option<&'static str>,
+}
+
+impl CacheStats {
+    /// Returns the fraction of lookups that were hits, between 0.0 and 1.0.  With no lookups at
+    /// all this is 0.0.
+    pub fn hit_ratio(&self) -> f64 {
+        hit_ratio(self.hits, self.misses)
+    }
+
+    /// Returns how long the counters had been running when the snapshot was taken.
+    pub fn elapsed(&self) -> Duration {
+        self.captured_at.saturating_duration_since(self.since)
+    }
+
+    /// Returns the average number of hits per second over `elapsed`, so every consumer of
+    /// the snapshot gets the same rate.  With no time elapsed this is 0.0.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use ttl_cache::test_util::MockClock;
+    /// use ttl_cache::TtlCache;
+    ///
+    /// let clock = MockClock::new();
+    /// let mut cache = TtlCache::with_clock(clock.clone());
+    /// cache.insert(1, "a", Duration::from_secs(60));
+    /// for key in 0..4 {
+    ///     let _ = cache.get(&(key % 2));
+    /// }
+    /// clock.advance(Duration::from_secs(4));
+    ///
+    /// let stats = cache.stats();
+    /// clock.advance(Duration::from_secs(60));
+    /// assert_eq!(stats.hits_per_second(), 0.5);
+    /// assert_eq!(stats.misses_per_second(), 0.5);
+    /// assert_eq!(stats.lookups_per_second(), 1.0);
+    /// assert_eq!(stats.inserts_per_second(), 0.25);
+    /// ```
+    pub fn hits_per_second(&self) -> f64 {
+        rate(self.hits, self.elapsed())
+    }
+
+    /// Returns the average number of misses per second over `elapsed`.
+    pub fn misses_per_second(&self) -> f64 {
+        rate(self.misses, self.elapsed())
+    }
+
+    /// Returns the average number of lookups per second over `elapsed`.
+    pub fn lookups_per_second(&self) -> f64 {
+        rate(self.hits.saturating_add(self.misses), self.elapsed())
+    }
+
+    /// Returns the average number of inserts per second over `elapsed`.
+    pub fn inserts_per_second(&self) -> f64 {
+        rate(self.inserts, self.elapsed())
+    }
+
+    /// Renders the snapshot in the Prometheus text exposition format, for services that serve
+    /// their own `/metrics` endpoint.  Only available with the `prometheus` feature.
+    ///
+    /// Hits, misses, expired entries dropped, evictions and inserts come out as counters named
+    /// `namespace` followed by `_hits_total`, `_misses_total`, `_expired_total`,
+    /// `_evicted_total` and `_inserts_total`, and `len` as a gauge named `namespace` followed
+    /// by `_len`.  An empty namespace leaves the names bare.  If the cache has a name, every
+    /// sample carries it as a `cache` label.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use ttl_cache::TtlCache;
+    ///
+    /// let mut cache = TtlCache::new();
+    /// cache.set_name("sessions");
+    /// cache.insert(1, "a", Duration::from_secs(30));
+    /// let _ = cache.get(&1);
+    ///
+    /// let text = cache.stats().to_prometheus("myapp");
+    /// assert!(text.contains("# TYPE myapp_hits_total counter\n"));
+    /// assert!(text.contains("myapp_hits_total{cache=\"sessions\"} 1\n"));
+    /// ```
+    #[cfg(feature = "prometheus")]
+    pub fn to_prometheus(&self, namespace: &str) -> String {
+        let labels = match self.name {
+            Some(name) => format!("{{cache=\"{}\"}}", escape_label(name)),
+            None => String::new(),
+        };
+        let values = [
+            self.hits,
+            self.misses,
+            self.expired,
+            self.evicted,
+            self.inserts,
+            self.len as u64,
+        ];
+        let mut text = String::new();
+        for (&(name, kind, help), value) in PROMETHEUS_METRICS.iter().zip(values.iter()) {
+            let name = match namespace {
+                "" => name.to_owned(),
+                namespace => format!("{}_{}", namespace, name),
+            };
+            text += &format!("# HELP {} {}\n", name, help);
+            text += &format!("# TYPE {} {}\n", name, kind);
+            text += &format!("{}{} {}\n", name, labels, value);
+        }
+        text
+    }
+}
+
+/// The name, type and help text of each metric `CacheStats::to_prometheus` renders
+#[cfg(feature = "prometheus")]
+const PROMETHEUS_METRICS: [(&str, &str, &str); 6] = [
+    ("hits_total", "counter", "Lookups that found a live value."),
+    ("misses_total", "counter", "Lookups that found no live value."),
+    ("expired_total", "counter", "Entries dropped because they had expired."),
+    ("evicted_total", "counter", "Live entries evicted over capacity."),
+    ("inserts_total", "counter", "Values inserted."),
+    ("len", "gauge", "Entries held, counting expired ones not purged yet."),
+];
+
+/// Escapes a Prometheus label value
+#[cfg(feature = "prometheus")]
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+impl fmt::Display for CacheStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "hits={} misses={} hit_ratio={:.1}% window={}s",
+            self.hits,
+            self.misses,
+            self.hit_ratio() * 100.0,
+            self.elapsed().as_secs()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Counters;
+    use time::{Epoch, Instant};
+    use InternalEntry;
+
+    #[test]
+    fn counts_past_u32() {
+        let now = Instant::now();
+        let stamp = Epoch::default().stamp(now);
+        let entry = InternalEntry::permanent((), stamp);
+        let counters = Counters::default();
+        let start = u64::from(u32::MAX) - 1;
+        counters.totals.hits.set(start);
+        counters.totals.misses.set(start);
+        for _ in 0..3 {
+            counters.hit(&entry, stamp);
+            counters.miss(false);
+        }
+        let stats = counters.totals.snapshot(now, now, 1);
+        assert_eq!(stats.hits, u64::from(u32::MAX) + 2);
+        assert_eq!(stats.misses, u64::from(u32::MAX) + 2);
+        assert_eq!(stats.hit_ratio(), 0.5);
+    }
+}
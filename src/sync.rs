@@ -0,0 +1,255 @@
+//! A cache behind a shared lock, for handing to several threads.
+
+use std::borrow::Borrow;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hash};
+use std::ops::{Deref, DerefMut};
+#[cfg(feature = "tokio")]
+use std::sync::Weak;
+use std::sync::{Arc, PoisonError};
+#[cfg(feature = "stats")]
+use std::sync::{Mutex, MutexGuard};
+#[cfg(not(feature = "stats"))]
+use std::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::time::Duration;
+
+use TtlCache;
+
+/// A `TtlCache` behind an `Arc<RwLock<_>>`, with methods that take the lock for just the one
+/// call and return owned values, so no guard or reference into the cache outlives it.  Clones
+/// share the same cache.
+///
+/// Lookups (`get_cloned`, `with`, `contains_key` and `read`) take the read lock, so any number
+/// of them run at once.  Everything that changes the cache (`insert`, `remove`, `purge` and
+/// `write`) takes the write lock and waits for the lookups in progress to finish.  Whether
+/// waiting writers hold off new lookups is up to the platform's `RwLock`, so calling back into
+/// the same cache from the closure given to `with` or `read` may deadlock.  A panic while the
+/// lock is held doesn't poison it for other threads.
+///
+/// With the `stats` feature, lookups count into the cache, which makes it not `Sync`, so the
+/// lock is a `Mutex` and lookups take it exclusively like everything else.
+///
+/// For large values, store them as `Arc<V>` with `insert_arc` and read them with `get_arc`,
+/// which hands out a new handle rather than cloning the value.
+///
+/// # Examples
+///
+/// ```
+/// use std::thread;
+/// use std::time::Duration;
+/// use ttl_cache::sync::SharedTtlCache;
+///
+/// let cache = SharedTtlCache::new();
+/// cache.insert("config", String::from("v1"), Duration::from_secs(30));
+///
+/// let reader = cache.clone();
+/// let len = thread::spawn(move || reader.with("config", |v| v.len()))
+///     .join()
+///     .unwrap();
+/// assert_eq!(len, Some(2));
+/// assert_eq!(cache.get_cloned("config"), Some(String::from("v1")));
+/// ```
+pub struct SharedTtlCache<K: Eq + Hash, V, S: BuildHasher = RandomState> {
+    inner: Arc<Lock<TtlCache<K, V, S>>>,
+}
+
+/// The lock around a shared cache, which only allows lookups at once while they don't count
+/// into the cache
+#[cfg(not(feature = "stats"))]
+pub(crate) type Lock<T> = RwLock<T>;
+#[cfg(feature = "stats")]
+pub(crate) type Lock<T> = Mutex<T>;
+
+/// Takes the lock for a lookup, which no panic can leave poisoned
+#[cfg(not(feature = "stats"))]
+fn read<T>(lock: &Lock<T>) -> RwLockReadGuard<'_, T> {
+    lock.read().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Takes the lock for a lookup, which no panic can leave poisoned
+#[cfg(feature = "stats")]
+fn read<T>(lock: &Lock<T>) -> MutexGuard<'_, T> {
+    write(lock)
+}
+
+/// Takes the lock for a change, which no panic can leave poisoned
+#[cfg(not(feature = "stats"))]
+pub(crate) fn write<T>(lock: &Lock<T>) -> RwLockWriteGuard<'_, T> {
+    lock.write().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Takes the lock for a change, which no panic can leave poisoned
+#[cfg(feature = "stats")]
+pub(crate) fn write<T>(lock: &Lock<T>) -> MutexGuard<'_, T> {
+    lock.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+impl<K: Eq + Hash, V> SharedTtlCache<K, V> {
+    /// Creates an empty cache.
+    pub fn new() -> Self {
+        SharedTtlCache::from(TtlCache::new())
+    }
+}
+
+/// Creates an empty cache as the default
+impl<K: Eq + Hash, V> Default for SharedTtlCache<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Shares a cache set up any way a `TtlCache` can be
+impl<K: Eq + Hash, V, S: BuildHasher> From<TtlCache<K, V, S>> for SharedTtlCache<K, V, S> {
+    fn from(cache: TtlCache<K, V, S>) -> Self {
+        SharedTtlCache {
+            inner: Arc::new(Lock::new(cache)),
+        }
+    }
+}
+
+/// Clones share the cache rather than copying it
+impl<K: Eq + Hash, V, S: BuildHasher> Clone for SharedTtlCache<K, V, S> {
+    fn clone(&self) -> Self {
+        SharedTtlCache {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<K: Eq + Hash, V, S: BuildHasher> SharedTtlCache<K, V, S> {
+    /// Returns a clone of the unexpired value for the key.
+    pub fn get_cloned<Q>(&self, k: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+        V: Clone,
+    {
+        self.with(k, V::clone)
+    }
+
+    /// Calls `f` with the unexpired value for the key and returns what it returns, without
+    /// cloning the value.  The read lock is held while `f` runs.
+    pub fn with<Q, F, R>(&self, k: &Q, f: F) -> Option<R>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+        F: FnOnce(&V) -> R,
+    {
+        self.read_lock().get(k).map(f)
+    }
+
+    /// Checks if the cache holds an unexpired value for the key.
+    pub fn contains_key<Q>(&self, k: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        self.read_lock().contains_key(k)
+    }
+
+    /// Inserts a key-value pair with the given TTL, see `TtlCache::insert`.
+    pub fn insert(&self, k: K, v: V, ttl: Duration) -> Option<V> {
+        self.write_lock().insert(k, v, ttl)
+    }
+
+    /// Removes the key, returning its value if it hadn't expired, see `TtlCache::remove`.
+    pub fn remove<Q>(&self, k: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        self.write_lock().remove(k)
+    }
+
+    /// Removes every expired entry, see `TtlCache::remove_expired`.
+    pub fn purge(&self) {
+        self.write_lock().remove_expired();
+    }
+
+    /// Calls `f` with the cache under the read lock, for lookups the rest of this API doesn't
+    /// cover.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use ttl_cache::sync::SharedTtlCache;
+    ///
+    /// let cache = SharedTtlCache::new();
+    /// cache.insert(1, "a", Duration::from_secs(30));
+    /// assert_eq!(cache.read(|cache| cache.iter().count()), 1);
+    /// ```
+    pub fn read<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce(&TtlCache<K, V, S>) -> R,
+    {
+        f(&self.read_lock())
+    }
+
+    /// Calls `f` with the cache under the write lock, for changes the rest of this API
+    /// doesn't cover.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use ttl_cache::sync::SharedTtlCache;
+    ///
+    /// let cache = SharedTtlCache::new();
+    /// cache.write(|cache| {
+    ///     *cache.entry("hits").or_insert(0, Duration::from_secs(60)) += 1;
+    /// });
+    /// assert_eq!(cache.get_cloned("hits"), Some(1));
+    /// ```
+    pub fn write<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce(&mut TtlCache<K, V, S>) -> R,
+    {
+        f(&mut self.write_lock())
+    }
+
+    /// A handle on the cache that doesn't keep it alive, for `ExpiredStream`
+    #[cfg(feature = "tokio")]
+    pub(crate) fn downgrade(&self) -> Weak<Lock<TtlCache<K, V, S>>> {
+        Arc::downgrade(&self.inner)
+    }
+
+    fn read_lock(&self) -> impl Deref<Target = TtlCache<K, V, S>> + '_ {
+        read(&self.inner)
+    }
+
+    fn write_lock(&self) -> impl DerefMut<Target = TtlCache<K, V, S>> + '_ {
+        write(&self.inner)
+    }
+}
+
+impl<K: Eq + Hash, V, S: BuildHasher> SharedTtlCache<K, Arc<V>, S> {
+    /// Wraps the value in an `Arc` and inserts it with the given TTL, see `TtlCache::insert`.
+    pub fn insert_arc(&self, k: K, v: V, ttl: Duration) -> Option<Arc<V>> {
+        self.insert(k, Arc::new(v), ttl)
+    }
+
+    /// Returns a new handle on the unexpired value for the key.  The read lock is only held
+    /// for the lookup, and the value lives on as long as the handle does.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use ttl_cache::sync::SharedTtlCache;
+    ///
+    /// let cache = SharedTtlCache::new();
+    /// cache.insert_arc("page", String::from("<html>"), Duration::from_secs(30));
+    ///
+    /// let page = cache.get_arc("page").unwrap();
+    /// cache.remove("page");
+    /// assert_eq!(*page, "<html>");
+    /// ```
+    pub fn get_arc<Q>(&self, k: &Q) -> Option<Arc<V>>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        self.get_cloned(k)
+    }
+}
@@ -0,0 +1,109 @@
+//! Helpers for testing code that uses a cache.
+
+use std::convert::TryFrom;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use clock::Clock;
+use time::Instant;
+
+/// A clock that only moves when told to, for testing expiry without sleeping.  Clones share
+/// the same time, so a test can keep one and hand the other to `TtlCache::with_clock`.
+///
+/// # Examples
+///
+/// ```
+/// use std::time::Duration;
+/// use ttl_cache::test_util::MockClock;
+/// use ttl_cache::TtlCache;
+///
+/// let clock = MockClock::new();
+/// let mut cache = TtlCache::with_clock(clock.clone());
+///
+/// cache.insert(1, "a", Duration::from_secs(30));
+/// clock.advance(Duration::from_secs(29));
+/// assert_eq!(cache.get(&1), Some(&"a"));
+/// clock.advance(Duration::from_secs(2));
+/// assert_eq!(cache.get(&1), None);
+/// ```
+#[derive(Clone, Debug)]
+pub struct MockClock {
+    start: Instant,
+    /// The wall-clock time at `start`
+    start_time: SystemTime,
+    /// Nanoseconds since `start`
+    offset: Arc<AtomicU64>,
+}
+
+impl MockClock {
+    /// Creates a clock standing at the current time.
+    pub fn new() -> Self {
+        MockClock {
+            start: Instant::now(),
+            start_time: SystemTime::now(),
+            offset: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// Moves the clock forward by `by`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if that takes the clock more than `u64::MAX` nanoseconds, some 584 years, past
+    /// where it started.
+    pub fn advance(&self, by: Duration) {
+        let by = nanos(by);
+        let advanced = self
+            .offset
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |offset| offset.checked_add(by));
+        advanced.expect("a MockClock can't be advanced that far");
+    }
+
+    /// Moves the clock to `to`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `to` is earlier than the clock's current time, since a clock must never go
+    /// backwards, or too far out for `advance`.
+    pub fn set(&self, to: Instant) {
+        let to = nanos(
+            to.checked_duration_since(self.start)
+                .expect("a MockClock can't be set back"),
+        );
+        let set = self
+            .offset
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |offset| {
+                if to >= offset {
+                    Some(to)
+                } else {
+                    None
+                }
+            });
+        set.expect("a MockClock can't be set back");
+    }
+}
+
+impl Default for MockClock {
+    fn default() -> Self {
+        MockClock::new()
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> Instant {
+        self.start + Duration::from_nanos(self.offset.load(Ordering::SeqCst))
+    }
+
+    /// Wall-clock time moves with the clock, so advancing it ages saved snapshots too
+    fn system_time_at(&self, instant: Instant) -> SystemTime {
+        match instant.checked_duration_since(self.start) {
+            Some(since) => self.start_time + since,
+            None => self.start_time - self.start.duration_since(instant),
+        }
+    }
+}
+
+fn nanos(duration: Duration) -> u64 {
+    u64::try_from(duration.as_nanos()).expect("a MockClock can't be advanced that far")
+}
@@ -0,0 +1,203 @@
+//! How entries keep time.
+//!
+//! By default a `Stamp` is simply an `Instant`.  With the `compact` feature it's the number of
+//! milliseconds since the cache's `Epoch` instead, which halves what every entry spends on its
+//! deadline, and a `Span` holds a duration in milliseconds rather than a full `Duration`.
+//! Stamps are only ever compared with stamps of the same cache; the public API deals in
+//! `Instant`s and converts at the edges.
+//!
+//! With the `wasm` feature, `Instant` and `SystemTime` come from `web_time`, which reads the
+//! browser's clocks on `wasm32-unknown-unknown` where the std ones panic, and are just the std
+//! types everywhere else.
+//!
+//! Compact stamps round instants down to the millisecond and durations up, so deadlines are
+//! kept to within a millisecond and a zero TTL still expires right away.
+
+#[cfg(feature = "compact")]
+use std::convert::TryFrom;
+#[cfg(feature = "compact")]
+use std::num::NonZeroU64;
+#[cfg(feature = "compact")]
+use std::ops::Add;
+use std::time::Duration;
+
+#[cfg(not(feature = "wasm"))]
+pub(crate) use std::time::{Instant, SystemTime};
+#[cfg(feature = "wasm")]
+pub(crate) use web_time::{Instant, SystemTime};
+
+#[cfg(not(feature = "compact"))]
+pub(crate) type Stamp = Instant;
+
+/// Milliseconds since the epoch, plus one so an `Option<Stamp>` is no bigger than a stamp
+#[cfg(feature = "compact")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) struct Stamp(NonZeroU64);
+
+#[cfg(feature = "compact")]
+impl Stamp {
+    fn from_millis(millis: u64) -> Option<Stamp> {
+        millis.checked_add(1).and_then(NonZeroU64::new).map(Stamp)
+    }
+
+    fn millis(self) -> u64 {
+        self.0.get() - 1
+    }
+
+    pub(crate) fn checked_add(self, duration: Duration) -> Option<Stamp> {
+        let millis = u64::try_from(ceil_millis(duration)).ok()?;
+        Stamp::from_millis(self.millis().checked_add(millis)?)
+    }
+
+    pub(crate) fn checked_sub(self, duration: Duration) -> Option<Stamp> {
+        let millis = u64::try_from(ceil_millis(duration)).ok()?;
+        Stamp::from_millis(self.millis().checked_sub(millis)?)
+    }
+
+    pub(crate) fn checked_duration_since(self, earlier: Stamp) -> Option<Duration> {
+        self.millis().checked_sub(earlier.millis()).map(Duration::from_millis)
+    }
+
+    pub(crate) fn saturating_duration_since(self, earlier: Stamp) -> Duration {
+        self.checked_duration_since(earlier).unwrap_or_default()
+    }
+}
+
+#[cfg(feature = "compact")]
+impl Add<Duration> for Stamp {
+    type Output = Stamp;
+
+    fn add(self, duration: Duration) -> Stamp {
+        self.checked_add(duration)
+            .expect("overflow when adding duration to stamp")
+    }
+}
+
+#[cfg(feature = "compact")]
+fn ceil_millis(duration: Duration) -> u128 {
+    let nanos = duration.as_nanos();
+    (nanos + 999_999) / 1_000_000
+}
+
+/// What stamps count from.  Every cache has its own, and entries only move between caches
+/// that share one.
+#[derive(Clone, Copy)]
+#[cfg_attr(not(feature = "compact"), derive(Default))]
+pub(crate) struct Epoch {
+    #[cfg(feature = "compact")]
+    origin: Instant,
+}
+
+#[cfg(feature = "compact")]
+impl Default for Epoch {
+    fn default() -> Self {
+        Epoch::starting_at(Instant::now())
+    }
+}
+
+impl Epoch {
+    #[cfg(not(feature = "compact"))]
+    pub(crate) fn starting_at(_origin: Instant) -> Self {
+        Epoch {}
+    }
+
+    #[cfg(feature = "compact")]
+    pub(crate) fn starting_at(origin: Instant) -> Self {
+        Epoch { origin }
+    }
+
+    #[cfg(not(feature = "compact"))]
+    pub(crate) fn stamp(&self, instant: Instant) -> Stamp {
+        instant
+    }
+
+    /// Instants from before the epoch come out as the epoch itself, which every stamp handed
+    /// out since has already reached.
+    #[cfg(feature = "compact")]
+    pub(crate) fn stamp(&self, instant: Instant) -> Stamp {
+        let millis = instant.saturating_duration_since(self.origin).as_millis();
+        u64::try_from(millis)
+            .ok()
+            .and_then(Stamp::from_millis)
+            .unwrap_or(Stamp(NonZeroU64::new(u64::MAX).unwrap()))
+    }
+
+    /// The instant a stamp stands for, or `None` if it's too far out for an `Instant`
+    #[cfg(not(feature = "compact"))]
+    pub(crate) fn instant(&self, stamp: Stamp) -> Option<Instant> {
+        Some(stamp)
+    }
+
+    #[cfg(feature = "compact")]
+    pub(crate) fn instant(&self, stamp: Stamp) -> Option<Instant> {
+        self.origin
+            .checked_add(Duration::from_millis(stamp.millis()))
+    }
+}
+
+/// A stored duration
+#[cfg(not(feature = "compact"))]
+#[derive(Clone, Copy)]
+pub(crate) struct Span(Duration);
+
+/// A stored duration in milliseconds, rounded up and saturating at `u64::MAX`
+#[cfg(feature = "compact")]
+#[derive(Clone, Copy)]
+pub(crate) struct Span(u64);
+
+impl Span {
+    #[cfg(not(feature = "compact"))]
+    pub(crate) fn new(duration: Duration) -> Span {
+        Span(duration)
+    }
+
+    #[cfg(feature = "compact")]
+    pub(crate) fn new(duration: Duration) -> Span {
+        Span(u64::try_from(ceil_millis(duration)).unwrap_or(u64::MAX))
+    }
+
+    #[cfg(not(feature = "compact"))]
+    pub(crate) fn get(self) -> Duration {
+        self.0
+    }
+
+    #[cfg(feature = "compact")]
+    pub(crate) fn get(self) -> Duration {
+        Duration::from_millis(self.0)
+    }
+}
+
+#[cfg(all(test, feature = "compact"))]
+mod tests {
+    use super::{Epoch, Span, Stamp};
+    use std::mem::size_of;
+    use std::time::Duration;
+    use time::Instant;
+    use InternalEntry;
+
+    #[test]
+    fn compact_entries() {
+        assert_eq!(size_of::<Option<Stamp>>(), 8);
+        assert_eq!(size_of::<Span>(), 8);
+        // The stats feature adds the insertion stamp, and access counts a counter on top
+        #[cfg(not(feature = "stats"))]
+        assert_eq!(size_of::<InternalEntry<u64>>(), 40);
+        #[cfg(all(feature = "stats", not(feature = "access-counts")))]
+        assert_eq!(size_of::<InternalEntry<u64>>(), 48);
+        #[cfg(feature = "access-counts")]
+        assert_eq!(size_of::<InternalEntry<u64>>(), 56);
+    }
+
+    #[test]
+    fn rounding() {
+        let now = Epoch::default().stamp(Instant::now());
+        assert_eq!(now.checked_add(Duration::ZERO), Some(now));
+        assert_eq!(
+            now.checked_add(Duration::from_micros(1)),
+            Some(now + Duration::from_millis(1))
+        );
+        assert_eq!(now.checked_add(Duration::MAX), None);
+        assert_eq!(Span::new(Duration::from_micros(1500)).get(), Duration::from_millis(2));
+        assert_eq!(Span::new(Duration::MAX).get(), Duration::from_millis(u64::MAX));
+    }
+}
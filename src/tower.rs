@@ -0,0 +1,433 @@
+//! A tower layer caching the responses of the service it wraps.  Only available with the
+//! `tower` feature.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::hash::Hash;
+use std::mem;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::task::{Context, Poll, Waker};
+use std::time::Duration;
+
+use tower_layer::Layer;
+use tower_service::Service;
+
+use sync::SharedTtlCache;
+use TtlCache;
+
+/// What a `CacheService` does with a request that misses the cache while a request for the
+/// same key is already on its way to the inner service.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Default)]
+pub enum InFlight {
+    /// Waits for the request under way and answers with a clone of its response, so the
+    /// inner service sees one request per key at a time.  This is the default.
+    #[default]
+    Coalesce,
+    /// Sends the request to the inner service as well, as if the other weren't there.
+    PassThrough,
+}
+
+/// Wraps a service in a `CacheService`, which answers requests from a `TtlCache` of earlier
+/// responses.
+///
+/// `key_fn` derives the cache key from a request, and requests with the same key get the same
+/// response.  `ttl_fn` decides from a successful response how long to keep it, or whether to
+/// keep it at all: a response it returns `None` for is passed on without being cached.  Errors
+/// are never cached.
+///
+/// Every service the layer wraps shares its cache, so the key and response types are part of
+/// the layer's type.
+///
+/// # Examples
+///
+/// ```
+/// # extern crate tokio;
+/// # extern crate tower_layer;
+/// # extern crate tower_service;
+/// # extern crate ttl_cache;
+/// use std::convert::Infallible;
+/// use std::future::{self, Ready};
+/// use std::sync::atomic::{AtomicUsize, Ordering};
+/// use std::sync::Arc;
+/// use std::task::{Context, Poll};
+/// use std::time::Duration;
+/// use tokio::runtime::Builder;
+/// use tower_layer::Layer;
+/// use tower_service::Service;
+/// use ttl_cache::tower::CacheLayer;
+///
+/// /// Squares numbers, counting how many it was asked for
+/// #[derive(Clone)]
+/// struct Square(Arc<AtomicUsize>);
+///
+/// impl Service<u64> for Square {
+///     type Response = u64;
+///     type Error = Infallible;
+///     type Future = Ready<Result<u64, Infallible>>;
+///
+///     fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Infallible>> {
+///         Poll::Ready(Ok(()))
+///     }
+///
+///     fn call(&mut self, n: u64) -> Self::Future {
+///         self.0.fetch_add(1, Ordering::Relaxed);
+///         future::ready(Ok(n * n))
+///     }
+/// }
+///
+/// # fn main() {
+/// let runtime = Builder::new_current_thread().build().unwrap();
+/// let calls = Arc::new(AtomicUsize::new(0));
+/// let layer = CacheLayer::new(|n: &u64| *n, |_: &u64| Some(Duration::from_secs(60)));
+/// let mut service = layer.layer(Square(calls.clone()));
+///
+/// for _ in 0..3 {
+///     runtime.block_on(future::poll_fn(|cx| service.poll_ready(cx))).unwrap();
+///     assert_eq!(runtime.block_on(service.call(12)), Ok(144));
+/// }
+/// assert_eq!(calls.load(Ordering::Relaxed), 1);
+/// # }
+/// ```
+pub struct CacheLayer<KeyFn, TtlFn, K: Eq + Hash, R> {
+    shared: Arc<Shared<KeyFn, TtlFn, K, R>>,
+    in_flight: InFlight,
+}
+
+/// What the layer and all of its services share
+struct Shared<KeyFn, TtlFn, K: Eq + Hash, R> {
+    cache: SharedTtlCache<K, R>,
+    flights: Mutex<HashMap<K, Arc<Flight<R>>>>,
+    key_fn: KeyFn,
+    ttl_fn: TtlFn,
+}
+
+impl<KeyFn, TtlFn, K: Eq + Hash, R> CacheLayer<KeyFn, TtlFn, K, R> {
+    /// Creates a layer caching responses in a new, unbounded cache.
+    pub fn new(key_fn: KeyFn, ttl_fn: TtlFn) -> Self {
+        CacheLayer::with_cache(TtlCache::new(), key_fn, ttl_fn)
+    }
+
+    /// Creates a layer caching responses in `cache`, set up any way a `TtlCache` can be, for
+    /// example with a capacity.
+    pub fn with_cache(cache: TtlCache<K, R>, key_fn: KeyFn, ttl_fn: TtlFn) -> Self {
+        CacheLayer {
+            shared: Arc::new(Shared {
+                cache: SharedTtlCache::from(cache),
+                flights: Mutex::new(HashMap::new()),
+                key_fn,
+                ttl_fn,
+            }),
+            in_flight: InFlight::default(),
+        }
+    }
+
+    /// Sets what the layer's services do with misses on keys already being requested, see
+    /// `InFlight`.
+    pub fn in_flight(mut self, in_flight: InFlight) -> Self {
+        self.in_flight = in_flight;
+        self
+    }
+
+    /// Returns the cache of responses, for looking into or invalidating them.
+    pub fn cache(&self) -> &SharedTtlCache<K, R> {
+        &self.shared.cache
+    }
+}
+
+/// Clones share the cache
+impl<KeyFn, TtlFn, K: Eq + Hash, R> Clone for CacheLayer<KeyFn, TtlFn, K, R> {
+    fn clone(&self) -> Self {
+        CacheLayer {
+            shared: self.shared.clone(),
+            in_flight: self.in_flight,
+        }
+    }
+}
+
+impl<S, KeyFn, TtlFn, K: Eq + Hash, R> Layer<S> for CacheLayer<KeyFn, TtlFn, K, R> {
+    type Service = CacheService<S, KeyFn, TtlFn, K, R>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        CacheService {
+            inner,
+            shared: self.shared.clone(),
+            in_flight: self.in_flight,
+        }
+    }
+}
+
+/// The service made by a `CacheLayer`, answering requests from the cache where it can and
+/// passing the rest on to the inner service.
+///
+/// A request is only passed on from its response future, so the service given to it is a
+/// clone of the inner service swapped in for the one `poll_ready` readied.
+pub struct CacheService<S, KeyFn, TtlFn, K: Eq + Hash, R> {
+    inner: S,
+    shared: Arc<Shared<KeyFn, TtlFn, K, R>>,
+    in_flight: InFlight,
+}
+
+impl<S, KeyFn, TtlFn, K: Eq + Hash, R> CacheService<S, KeyFn, TtlFn, K, R> {
+    /// Returns the cache of responses, shared with the layer and its other services.
+    pub fn cache(&self) -> &SharedTtlCache<K, R> {
+        &self.shared.cache
+    }
+}
+
+/// Clones wrap a clone of the inner service and share the cache
+impl<S: Clone, KeyFn, TtlFn, K: Eq + Hash, R> Clone for CacheService<S, KeyFn, TtlFn, K, R> {
+    fn clone(&self) -> Self {
+        CacheService {
+            inner: self.inner.clone(),
+            shared: self.shared.clone(),
+            in_flight: self.in_flight,
+        }
+    }
+}
+
+impl<S, Req, KeyFn, TtlFn, K, R> Service<Req> for CacheService<S, KeyFn, TtlFn, K, R>
+where
+    S: Service<Req, Response = R> + Clone,
+    KeyFn: Fn(&Req) -> K,
+    TtlFn: Fn(&R) -> Option<Duration>,
+    K: Eq + Hash + Clone,
+    R: Clone,
+{
+    type Response = R;
+    type Error = S::Error;
+    type Future = ResponseFuture<S, Req, KeyFn, TtlFn, K>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), S::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Req) -> Self::Future {
+        let key = (self.shared.key_fn)(&req);
+        let clone = self.inner.clone();
+        ResponseFuture {
+            inner: Some(mem::replace(&mut self.inner, clone)),
+            shared: self.shared.clone(),
+            in_flight: self.in_flight,
+            key,
+            req: Some(req),
+            state: State::Start,
+            call: None,
+        }
+    }
+}
+
+/// A request on its way to the inner service, shared by the one that sent it and the ones
+/// waiting on its response
+struct Flight<R> {
+    outcome: Mutex<Outcome<R>>,
+}
+
+enum Outcome<R> {
+    Running(Vec<Waker>),
+    Answered(R),
+    Abandoned,
+}
+
+impl<R> Flight<R> {
+    fn outcome(&self) -> MutexGuard<'_, Outcome<R>> {
+        self.outcome.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn finish(&self, outcome: Outcome<R>) {
+        if let Outcome::Running(waiters) = mem::replace(&mut *self.outcome(), outcome) {
+            for waiter in waiters {
+                waiter.wake();
+            }
+        }
+    }
+}
+
+impl<KeyFn, TtlFn, K: Eq + Hash, R> Shared<KeyFn, TtlFn, K, R> {
+    fn flights(&self) -> MutexGuard<'_, HashMap<K, Arc<Flight<R>>>> {
+        self.flights.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Forgets `flight` as the key's request under way, if it still is
+    fn land(&self, k: &K, flight: &Arc<Flight<R>>) {
+        let mut flights = self.flights();
+        if flights
+            .get(k)
+            .map_or(false, |current| Arc::ptr_eq(current, flight))
+        {
+            flights.remove(k);
+        }
+    }
+}
+
+enum State<R> {
+    Start,
+    /// Sending the request for the waiters too
+    Leading(Arc<Flight<R>>),
+    /// Sending the request for itself alone
+    Calling,
+    Waiting(Arc<Flight<R>>),
+    Done,
+}
+
+/// The future returned by `CacheService::call`.
+#[must_use = "futures do nothing unless polled"]
+pub struct ResponseFuture<S, Req, KeyFn, TtlFn, K>
+where
+    S: Service<Req>,
+    K: Eq + Hash,
+{
+    /// The readied service, taken when the request is sent
+    inner: Option<S>,
+    shared: Arc<Shared<KeyFn, TtlFn, K, S::Response>>,
+    in_flight: InFlight,
+    key: K,
+    req: Option<Req>,
+    state: State<S::Response>,
+    call: Option<S::Future>,
+}
+
+impl<S, Req, KeyFn, TtlFn, K, R> ResponseFuture<S, Req, KeyFn, TtlFn, K>
+where
+    S: Service<Req, Response = R>,
+    K: Eq + Hash,
+{
+    /// Sends the request to the inner service
+    fn send(&mut self) {
+        let mut inner = self.inner.take().expect("a request is only sent once");
+        let req = self.req.take().expect("a request is only sent once");
+        self.call = Some(inner.call(req));
+    }
+}
+
+impl<S, Req, KeyFn, TtlFn, K, R> Future for ResponseFuture<S, Req, KeyFn, TtlFn, K>
+where
+    S: Service<Req, Response = R>,
+    TtlFn: Fn(&R) -> Option<Duration>,
+    K: Eq + Hash + Clone,
+    R: Clone,
+{
+    type Output = Result<R, S::Error>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // Only `call` is pinned; it is never moved out once set, and nothing else relies on
+        // pinning
+        let this = unsafe { self.get_unchecked_mut() };
+        loop {
+            match mem::replace(&mut this.state, State::Done) {
+                State::Start => {
+                    // The cache is checked with the flights locked, so a leader can't insert
+                    // and land in between
+                    let mut flights = this.shared.flights();
+                    if let Some(response) = this.shared.cache.get_cloned(&this.key) {
+                        return Poll::Ready(Ok(response));
+                    }
+                    this.state = match (this.in_flight, flights.get(&this.key)) {
+                        (InFlight::Coalesce, Some(flight)) => State::Waiting(flight.clone()),
+                        (InFlight::Coalesce, None) => {
+                            let flight = Arc::new(Flight {
+                                outcome: Mutex::new(Outcome::Running(Vec::new())),
+                            });
+                            flights.insert(this.key.clone(), flight.clone());
+                            State::Leading(flight)
+                        }
+                        (InFlight::PassThrough, _) => State::Calling,
+                    };
+                    drop(flights);
+                    if let State::Waiting(_) = this.state {
+                        continue;
+                    }
+                    this.send();
+                }
+                State::Leading(flight) => {
+                    // Left in place while the call runs, so a panic in it abandons the flight
+                    this.state = State::Leading(flight);
+                    let result = match this.poll_call(cx) {
+                        Poll::Ready(result) => result,
+                        Poll::Pending => return Poll::Pending,
+                    };
+                    let flight = match mem::replace(&mut this.state, State::Done) {
+                        State::Leading(flight) => flight,
+                        _ => unreachable!(),
+                    };
+                    this.shared.land(&this.key, &flight);
+                    return Poll::Ready(match result {
+                        Ok(response) => {
+                            flight.finish(Outcome::Answered(response.clone()));
+                            Ok(response)
+                        }
+                        Err(err) => {
+                            flight.finish(Outcome::Abandoned);
+                            Err(err)
+                        }
+                    });
+                }
+                State::Calling => {
+                    this.state = State::Calling;
+                    let result = match this.poll_call(cx) {
+                        Poll::Ready(result) => result,
+                        Poll::Pending => return Poll::Pending,
+                    };
+                    this.state = State::Done;
+                    return Poll::Ready(result);
+                }
+                State::Waiting(flight) => {
+                    let mut outcome = flight.outcome();
+                    match *outcome {
+                        Outcome::Answered(ref response) => return Poll::Ready(Ok(response.clone())),
+                        Outcome::Abandoned => this.state = State::Start,
+                        Outcome::Running(ref mut waiters) => {
+                            if !waiters.iter().any(|w| w.will_wake(cx.waker())) {
+                                waiters.push(cx.waker().clone());
+                            }
+                            drop(outcome);
+                            this.state = State::Waiting(flight);
+                            return Poll::Pending;
+                        }
+                    }
+                }
+                State::Done => panic!("`ResponseFuture` polled after completion"),
+            }
+        }
+    }
+}
+
+impl<S, Req, KeyFn, TtlFn, K, R> ResponseFuture<S, Req, KeyFn, TtlFn, K>
+where
+    S: Service<Req, Response = R>,
+    TtlFn: Fn(&R) -> Option<Duration>,
+    K: Eq + Hash + Clone,
+    R: Clone,
+{
+    /// Polls the request sent to the inner service, caching its response if `ttl_fn` keeps it
+    fn poll_call(&mut self, cx: &mut Context<'_>) -> Poll<Result<R, S::Error>> {
+        let call = self.call.as_mut().expect("the request was sent");
+        // `self` is pinned, so `call` stays where it is
+        let result = match unsafe { Pin::new_unchecked(call) }.poll(cx) {
+            Poll::Ready(result) => result,
+            Poll::Pending => return Poll::Pending,
+        };
+        if let Ok(ref response) = result {
+            if let Some(ttl) = (self.shared.ttl_fn)(response) {
+                self.shared
+                    .cache
+                    .insert(self.key.clone(), response.clone(), ttl);
+            }
+        }
+        Poll::Ready(result)
+    }
+}
+
+/// Hands the flight over to the waiters if the leader is dropped before its response comes
+impl<S, Req, KeyFn, TtlFn, K> Drop for ResponseFuture<S, Req, KeyFn, TtlFn, K>
+where
+    S: Service<Req>,
+    K: Eq + Hash,
+{
+    fn drop(&mut self) {
+        if let State::Leading(ref flight) = self.state {
+            self.shared.land(&self.key, flight);
+            flight.finish(Outcome::Abandoned);
+        }
+    }
+}
@@ -0,0 +1,25 @@
+//! Structured events for the cache's lifecycle.  Only available with the `tracing` feature.
+//! The `log` feature turns the events into `log` records when no `tracing` subscriber is
+//! installed.
+
+use std::fmt;
+
+/// How a cache formats its keys in events, set by `TtlCache::trace_keys`
+pub(crate) type KeyFmt<K> = fn(&K, &mut fmt::Formatter) -> fmt::Result;
+
+/// A key as an event field: its `Debug` output if the cache logs keys, or else its hash, so
+/// keys don't have to implement `Debug` for the cache to be traced
+pub(crate) struct KeyField<'a, K: 'a> {
+    pub(crate) key: &'a K,
+    pub(crate) hash: u64,
+    pub(crate) fmt: Option<KeyFmt<K>>,
+}
+
+impl<'a, K> fmt::Debug for KeyField<'a, K> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.fmt {
+            Some(fmt) => fmt(self.key, f),
+            None => write!(f, "#{:016x}", self.hash),
+        }
+    }
+}
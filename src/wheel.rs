@@ -0,0 +1,293 @@
+//! A hierarchical timer wheel holding `(deadline, hash)` pairs, the coarse alternative to the
+//! heap behind `TtlCache::with_timer_wheel`.
+//!
+//! Time is cut into ticks of a fixed resolution counted from when the wheel was made.  Each of
+//! the `LEVELS` wheels has 64 slots, a slot on level `n` spanning `64^n` ticks, and a pair goes
+//! on the lowest level whose slots still tell its tick apart from the current one.  As the
+//! current tick crosses into a slot of a higher level, that slot is cascaded: its pairs move
+//! down to the levels where they now belong.  A pair only comes out of the wheel once the whole
+//! tick it falls in has passed, so it's always strictly due, just possibly up to a tick late.
+//!
+//! Inserting is O(1), and advancing is amortized O(1) per pair plus a step per tick that has
+//! anything on the lowest level.  Stretches of empty slots are skipped over a level at a time.
+
+#[cfg(feature = "tokio")]
+use std::convert::TryFrom;
+use std::mem;
+use std::time::Duration;
+
+use time::Stamp;
+
+/// Bits of the tick each level covers
+const LEVEL_BITS: u32 = 6;
+const SLOTS: usize = 1 << LEVEL_BITS;
+const LEVELS: usize = 6;
+
+#[derive(Clone)]
+pub(crate) struct TimerWheel {
+    origin: Stamp,
+    resolution: Duration,
+    /// The next tick to go through; every tick before it has passed
+    current: u64,
+    levels: Vec<Vec<Vec<(Stamp, u64)>>>,
+    /// A bit per slot on each level, set while the slot holds pairs
+    occupied: [u64; LEVELS],
+    /// Pairs too far out for the top level
+    overflow: Vec<(Stamp, u64)>,
+    /// Pairs that are due, waiting to be popped
+    ready: Vec<(Stamp, u64)>,
+    len: usize,
+}
+
+impl TimerWheel {
+    pub(crate) fn new(resolution: Duration, origin: Stamp) -> Self {
+        assert!(
+            resolution > Duration::ZERO,
+            "the timer wheel resolution must be greater than zero"
+        );
+        TimerWheel {
+            origin,
+            resolution,
+            current: 0,
+            levels: vec![vec![Vec::new(); SLOTS]; LEVELS],
+            occupied: [0; LEVELS],
+            overflow: Vec::new(),
+            ready: Vec::new(),
+            len: 0,
+        }
+    }
+
+    /// An empty wheel ticking in step with this one
+    pub(crate) fn empty_like(&self) -> Self {
+        TimerWheel {
+            current: self.current,
+            ..TimerWheel::new(self.resolution, self.origin)
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+
+    pub(crate) fn insert(&mut self, expiration: Stamp, hash: u64) {
+        self.len += 1;
+        self.place(expiration, hash);
+    }
+
+    /// Pops a pair whose tick had passed by `now`.
+    pub(crate) fn pop_expired(&mut self, now: Stamp) -> Option<(Stamp, u64)> {
+        if self.ready.is_empty() {
+            let target = self.tick(now);
+            self.advance(target);
+        }
+        let pair = self.ready.pop();
+        if pair.is_some() {
+            self.len -= 1;
+        }
+        pair
+    }
+
+    /// Returns when the soonest pair's tick will have passed, or its deadline if it's already
+    /// waiting to be popped.  Goes through every pair.
+    #[cfg(feature = "tokio")]
+    pub(crate) fn next_due(&self) -> Option<Stamp> {
+        let ready = self.ready.iter().map(|&(expiration, _)| expiration).min();
+        let ticking = self
+            .levels
+            .iter()
+            .flatten()
+            .flatten()
+            .chain(&self.overflow)
+            .map(|&(expiration, _)| expiration)
+            .min()
+            .map(|expiration| self.tick_end(expiration));
+        match (ready, ticking) {
+            (Some(ready), Some(ticking)) => Some(ready.min(ticking)),
+            (ready, ticking) => ready.or(ticking),
+        }
+    }
+
+    pub(crate) fn clear(&mut self) {
+        for level in &mut self.levels {
+            for slot in level.iter_mut() {
+                slot.clear();
+            }
+        }
+        self.occupied = [0; LEVELS];
+        self.overflow.clear();
+        self.ready.clear();
+        self.len = 0;
+    }
+
+    /// The tick `time` falls in
+    fn tick(&self, time: Stamp) -> u64 {
+        let elapsed = time.saturating_duration_since(self.origin).as_nanos();
+        let tick = elapsed / self.resolution.as_nanos();
+        if tick > u128::from(u64::MAX) {
+            u64::MAX
+        } else {
+            tick as u64
+        }
+    }
+
+    /// When the tick `time` falls in ends, or `time` itself if that's out of range
+    #[cfg(feature = "tokio")]
+    fn tick_end(&self, time: Stamp) -> Stamp {
+        let ticks = u128::from(self.tick(time)) + 1;
+        u64::try_from(self.resolution.as_nanos() * ticks)
+            .ok()
+            .and_then(|elapsed| self.origin.checked_add(Duration::from_nanos(elapsed)))
+            .map_or(time, |end| end.max(time))
+    }
+
+    fn place(&mut self, expiration: Stamp, hash: u64) {
+        let tick = self.tick(expiration);
+        if tick < self.current {
+            self.ready.push((expiration, hash));
+            return;
+        }
+        // The highest bit where the tick differs from the current one picks the level
+        let masked = (self.current ^ tick) | (SLOTS as u64 - 1);
+        let level = ((63 - masked.leading_zeros()) / LEVEL_BITS) as usize;
+        if level >= LEVELS {
+            self.overflow.push((expiration, hash));
+        } else {
+            let slot = slot_of(tick, level);
+            self.levels[level][slot].push((expiration, hash));
+            self.occupied[level] |= 1 << slot;
+        }
+    }
+
+    /// Goes through every tick before `target`, moving the pairs of the ticks that passed to
+    /// `ready`.
+    fn advance(&mut self, target: u64) {
+        while self.current < target {
+            if self.current % span(LEVELS) == 0 && !self.overflow.is_empty() {
+                for (expiration, hash) in mem::take(&mut self.overflow) {
+                    self.place(expiration, hash);
+                }
+            }
+            // Higher levels first, so their pairs can land in the lower slots cascaded next
+            for level in (1..LEVELS).rev() {
+                if self.current % span(level) == 0 {
+                    self.cascade(level);
+                }
+            }
+            let slot = slot_of(self.current, 0);
+            if self.occupied[0] & (1 << slot) != 0 {
+                self.occupied[0] &= !(1 << slot);
+                self.ready.append(&mut self.levels[0][slot]);
+            }
+            self.current += 1;
+
+            // Skip ahead to the next slot boundary of the lowest level holding anything
+            let empty_levels = self.occupied.iter().take_while(|&&bits| bits == 0).count();
+            if empty_levels > 0 {
+                let span = span(empty_levels);
+                let next = self.current.saturating_add(span - 1) / span * span;
+                self.current = next.min(target).max(self.current);
+            }
+        }
+    }
+
+    /// Moves the pairs in the slot of `level` the current tick just entered down a level or
+    /// more.
+    fn cascade(&mut self, level: usize) {
+        let slot = slot_of(self.current, level);
+        if self.occupied[level] & (1 << slot) == 0 {
+            return;
+        }
+        self.occupied[level] &= !(1 << slot);
+        for (expiration, hash) in mem::take(&mut self.levels[level][slot]) {
+            self.place(expiration, hash);
+        }
+    }
+}
+
+/// How many ticks a slot on `level` spans
+fn span(level: usize) -> u64 {
+    1 << (LEVEL_BITS as usize * level)
+}
+
+fn slot_of(tick: u64, level: usize) -> usize {
+    ((tick >> (LEVEL_BITS as usize * level)) & (SLOTS as u64 - 1)) as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TimerWheel;
+    use std::time::Duration;
+    use time::Instant;
+    use time::Epoch;
+
+    // Whole milliseconds, so the ticks land the same with compact stamps
+    const TICK: Duration = Duration::from_millis(10);
+
+    fn wheel(resolution: Duration) -> TimerWheel {
+        TimerWheel::new(resolution, Epoch::default().stamp(Instant::now()))
+    }
+
+    fn drain(wheel: &mut TimerWheel, ticks: u32) -> Vec<u64> {
+        let now = wheel.origin + TICK * ticks;
+        let mut hashes = Vec::new();
+        while let Some((expiration, hash)) = wheel.pop_expired(now) {
+            assert!(expiration < now);
+            hashes.push(hash);
+        }
+        hashes.sort_unstable();
+        hashes
+    }
+
+    #[test]
+    fn pops_once_the_tick_has_passed() {
+        let mut wheel = wheel(TICK);
+        wheel.insert(wheel.origin + TICK * 3, 1);
+        wheel.insert(wheel.origin + TICK * 3 + TICK / 2, 2);
+        wheel.insert(wheel.origin + TICK * 5, 3);
+
+        assert!(drain(&mut wheel, 3).is_empty());
+        assert_eq!(drain(&mut wheel, 4), [1, 2]);
+        assert!(drain(&mut wheel, 5).is_empty());
+        assert_eq!(drain(&mut wheel, 6), [3]);
+        assert_eq!(wheel.len(), 0);
+    }
+
+    #[test]
+    fn cascades_from_higher_levels() {
+        let mut wheel = wheel(TICK);
+        // Level 1, level 2 and level 3 respectively
+        wheel.insert(wheel.origin + TICK * 100, 1);
+        wheel.insert(wheel.origin + TICK * 5_000, 2);
+        wheel.insert(wheel.origin + TICK * 300_000, 3);
+
+        assert!(drain(&mut wheel, 100).is_empty());
+        assert_eq!(drain(&mut wheel, 101), [1]);
+        assert!(drain(&mut wheel, 5_000).is_empty());
+        assert_eq!(drain(&mut wheel, 5_001), [2]);
+        assert!(drain(&mut wheel, 300_000).is_empty());
+        assert_eq!(drain(&mut wheel, 300_001), [3]);
+    }
+
+    #[test]
+    fn skips_idle_stretches() {
+        let mut wheel = wheel(TICK);
+        let far = TICK * (1 << 20) * (1 << 20);
+        wheel.insert(wheel.origin + far, 1);
+
+        // Far beyond the top level, so the pair sits in the overflow until its turn
+        assert!(wheel.pop_expired(wheel.origin + far).is_none());
+        assert_eq!(
+            wheel.pop_expired(wheel.origin + far + TICK),
+            Some((wheel.origin + far, 1))
+        );
+    }
+
+    #[test]
+    fn past_deadlines_are_due_right_away() {
+        let mut wheel = wheel(TICK);
+        assert!(drain(&mut wheel, 10).is_empty());
+        wheel.insert(wheel.origin + TICK * 2, 1);
+        wheel.insert(wheel.origin, 2);
+        assert_eq!(drain(&mut wheel, 10), [1, 2]);
+    }
+}
@@ -1,9 +1,37 @@
+#[cfg(feature = "arbitrary")]
+extern crate arbitrary;
+#[cfg(feature = "tokio")]
+extern crate futures_core;
+#[cfg(feature = "metrics")]
+extern crate metrics;
+#[cfg(feature = "metrics")]
+extern crate metrics_util;
+#[cfg(feature = "rayon")]
+extern crate rayon;
+#[cfg(feature = "serde")]
+extern crate serde_json;
+#[cfg(any(feature = "tokio", feature = "tower"))]
+extern crate tokio;
+#[cfg(feature = "tower")]
+extern crate tower_layer;
+#[cfg(feature = "tower")]
+extern crate tower_service;
+#[cfg(feature = "tracing")]
+extern crate tracing_test;
 extern crate ttl_cache;
 
+use std::sync::Arc;
 use std::thread::sleep;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use ttl_cache::TtlCache;
 
+/// How far the cache's deadlines may be from the instants they came from, which the `compact`
+/// feature rounds to the millisecond
+#[cfg(feature = "compact")]
+const SLACK: Duration = Duration::from_millis(1);
+#[cfg(not(feature = "compact"))]
+const SLACK: Duration = Duration::ZERO;
+
 #[test]
 fn test_put_and_get() {
     let duration = Duration::from_secs(60 * 60);
@@ -30,7 +58,9 @@ fn test_get_mut_change() {
     let mut cache = TtlCache::new();
     cache.insert(1, 10, duration);
     assert_eq!(cache.get(&1), Some(&10));
-    cache.get_mut(&1).map(|v| *v = 20);
+    if let Some(v) = cache.get_mut(&1) {
+        *v = 20;
+    }
     assert_eq!(cache.get(&1), Some(&20));
 }
 
@@ -48,7 +78,7 @@ fn test_contains_key() {
     let duration = Duration::from_secs(60 * 60);
     let mut cache = TtlCache::new();
     cache.insert("1", 10, duration);
-    assert_eq!(cache.contains_key("1"), true);
+    assert!(cache.contains_key("1"));
 }
 
 #[test]
@@ -57,7 +87,7 @@ fn test_expire_value() {
     let mut cache = TtlCache::new();
     cache.insert("1", 10, duration);
     sleep(Duration::from_millis(10));
-    assert_eq!(cache.contains_key("1"), false);
+    assert!(!cache.contains_key("1"));
 }
 
 #[test]
@@ -68,7 +98,7 @@ fn test_reset_ttl() {
     sleep(Duration::from_millis(5));
     cache.reset_ttl("1");
     sleep(Duration::from_millis(6));
-    assert_eq!(cache.contains_key("1"), true);
+    assert!(cache.contains_key("1"));
 }
 
 #[test]
@@ -99,7 +129,7 @@ fn test_remove() {
     cache.insert(6, 60, duration);
     cache.insert(7, 70, duration);
     cache.insert(8, 80, duration);
-    assert!(cache.get(&5).is_none());
+    assert_eq!(cache.get(&5), Some(&50));
     assert_eq!(cache.get(&6), Some(&60));
     assert_eq!(cache.get(&7), Some(&70));
     assert_eq!(cache.get(&8), Some(&80));
@@ -127,19 +157,19 @@ fn test_iter() {
     cache.insert(5, 50, duration);
     assert_eq!(
         cache.iter().collect::<Vec<_>>(),
-        [(&3, &30), (&4, &40), (&5, &50)]
+        [(&1, &10), (&2, &20), (&3, &30), (&4, &40), (&5, &50)]
     );
     assert_eq!(
         cache.iter_mut().collect::<Vec<_>>(),
-        [(&3, &mut 30), (&4, &mut 40), (&5, &mut 50)]
+        [(&1, &mut 10), (&2, &mut 20), (&3, &mut 30), (&4, &mut 40), (&5, &mut 50)]
     );
     assert_eq!(
         cache.iter().rev().collect::<Vec<_>>(),
-        [(&5, &50), (&4, &40), (&3, &30)]
+        [(&5, &50), (&4, &40), (&3, &30), (&2, &20), (&1, &10)]
     );
     assert_eq!(
         cache.iter_mut().rev().collect::<Vec<_>>(),
-        [(&5, &mut 50), (&4, &mut 40), (&3, &mut 30)]
+        [(&5, &mut 50), (&4, &mut 40), (&3, &mut 30), (&2, &mut 20), (&1, &mut 10)]
     );
 }
 
@@ -175,3 +205,4399 @@ fn test() {
     sleep(Duration::from_millis(20));
     assert_eq!(cache.iter().collect::<Vec<_>>(), [(&1, &10), (&3, &30)]);
 }
+
+#[test]
+fn test_split_off() {
+    let mut cache = TtlCache::new();
+    cache.insert(1, 10, Duration::from_secs(60));
+    cache.insert(2, 20, Duration::from_millis(10));
+    cache.insert(3, 30, Duration::from_millis(300));
+    cache.insert(4, 40, Duration::from_secs(60));
+    cache.insert(5, 50, Duration::from_millis(10));
+    cache.insert(6, 60, Duration::from_millis(300));
+    sleep(Duration::from_millis(20));
+    let odd = cache.split_off(|k, _| k % 2 == 1);
+    assert_eq!(odd.iter().collect::<Vec<_>>(), [(&1, &10), (&3, &30)]);
+    assert_eq!(cache.iter().collect::<Vec<_>>(), [(&4, &40), (&6, &60)]);
+    sleep(Duration::from_millis(300));
+    assert_eq!(odd.iter().collect::<Vec<_>>(), [(&1, &10)]);
+    assert_eq!(cache.iter().collect::<Vec<_>>(), [(&4, &40)]);
+}
+
+#[test]
+fn test_split_off_keeps_settings() {
+    let clock = ttl_cache::test_util::MockClock::new();
+    let mut cache = TtlCache::builder()
+        .clock(clock.clone())
+        .capacity(2)
+        .default_ttl(Duration::from_secs(30))
+        .timer_wheel(Duration::from_secs(1))
+        .build()
+        .unwrap();
+    cache.insert(1, 10, Duration::from_secs(5));
+    cache.insert(2, 20, Duration::from_secs(60));
+    let mut odd = cache.split_off(|k, _| k % 2 == 1);
+    assert_eq!(odd.capacity(), Some(2));
+    odd.insert_default(3, 30);
+    odd.insert_default(5, 50);
+    assert_eq!(odd.iter().collect::<Vec<_>>(), [(&3, &30), (&5, &50)]);
+    clock.advance(Duration::from_secs(31));
+    assert_eq!(odd.remove_expired_limit(10), 2);
+    assert_eq!(cache.get(&2), Some(&20));
+}
+
+#[test]
+fn test_split_off_reseeds_jitter() {
+    let clock = ttl_cache::test_util::MockClock::new();
+    let mut cache = TtlCache::with_clock(clock.clone());
+    cache.set_ttl_jitter_with_seed(0.5, 42);
+    let mut split = cache.split_off(|_, _: &i32| true);
+    assert_eq!(split.ttl_jitter(), Some(0.5));
+    for k in 0..20 {
+        cache.insert(k, k, Duration::from_secs(100));
+        split.insert(k, k, Duration::from_secs(100));
+    }
+    let differ = (0..20)
+        .filter(|k| cache.remaining_ttl(k) != split.remaining_ttl(k))
+        .count();
+    assert!(differ > 15);
+}
+
+#[test]
+fn test_swap_values() {
+    let mut cache = TtlCache::new();
+    cache.insert(1, 10, Duration::from_millis(50));
+    cache.insert(2, 20, Duration::from_secs(60));
+    cache.insert(3, 30, Duration::from_millis(1));
+    sleep(Duration::from_millis(10));
+    assert!(!cache.swap_values(&1, &3));
+    assert!(!cache.swap_values(&1, &4));
+    assert_eq!(cache.get(&1), Some(&10));
+    assert!(cache.swap_values(&1, &1));
+    assert!(cache.swap_values(&1, &2));
+    assert_eq!(cache.get(&1), Some(&20));
+    assert_eq!(cache.get(&2), Some(&10));
+    sleep(Duration::from_millis(50));
+    // The expiration stays with the key, not the value
+    assert_eq!(cache.get(&1), None);
+    assert_eq!(cache.get(&2), Some(&10));
+}
+
+#[test]
+fn test_swap_values_keeps_order() {
+    use ttl_cache::test_util::MockClock;
+
+    let clock = MockClock::new();
+    let mut cache = TtlCache::with_clock(clock.clone());
+    cache.insert(1, 10, Duration::from_secs(10));
+    cache.insert(2, 20, Duration::from_secs(60));
+    cache.insert(3, 30, Duration::from_secs(30));
+    cache.insert(4, 40, Duration::from_secs(60));
+    assert!(cache.swap_values(&3, &1));
+    assert_eq!(
+        cache.iter().collect::<Vec<_>>(),
+        [(&1, &30), (&2, &20), (&3, &10), (&4, &40)]
+    );
+    assert_eq!(cache.remaining_ttl(&1), Some(Duration::from_secs(10)));
+    assert_eq!(cache.remaining_ttl(&3), Some(Duration::from_secs(30)));
+    clock.advance(Duration::from_secs(10));
+    assert_eq!(cache.iter().collect::<Vec<_>>(), [(&2, &20), (&3, &10), (&4, &40)]);
+    clock.advance(Duration::from_secs(20));
+    cache.remove_expired();
+    assert_eq!(cache.iter().collect::<Vec<_>>(), [(&2, &20), (&4, &40)]);
+}
+
+#[test]
+fn test_swap_values_ends() {
+    let mut cache = TtlCache::new();
+    for i in 0..10_000 {
+        cache.insert(i, i * 10, Duration::from_secs(60));
+    }
+    assert!(cache.swap_values(&0, &9_999));
+    assert!(cache.swap_values(&5_000, &1));
+    assert_eq!(cache.get(&0), Some(&99_990));
+    assert_eq!(cache.get(&9_999), Some(&0));
+    assert_eq!(cache.get(&1), Some(&50_000));
+    assert_eq!(cache.get(&5_000), Some(&10));
+    // Every key stays where it was inserted
+    let keys: Vec<_> = cache.iter().map(|(&k, _)| k).collect();
+    assert_eq!(keys, (0..10_000).collect::<Vec<_>>());
+    assert_eq!(cache.iter().filter(|&(&k, &v)| v != k * 10).count(), 4);
+}
+
+#[test]
+fn test_insert_default() {
+    let mut cache = TtlCache::with_default_ttl(Duration::from_millis(10));
+    assert_eq!(cache.default_ttl(), Some(Duration::from_millis(10)));
+    cache.insert_default(1, 10);
+    cache.insert(2, 20, Duration::from_secs(60));
+    *cache.entry(3).or_insert_default_ttl(30) += 1;
+    assert_eq!(cache.get(&1), Some(&10));
+    assert_eq!(cache.get(&3), Some(&31));
+    sleep(Duration::from_millis(20));
+    assert_eq!(cache.get(&1), None);
+    assert_eq!(cache.get(&2), Some(&20));
+    assert_eq!(cache.get(&3), None);
+}
+
+#[test]
+#[should_panic(expected = "no default TTL")]
+fn test_insert_default_without_default_ttl() {
+    let mut cache = TtlCache::new();
+    cache.insert_default(1, 10);
+}
+
+#[test]
+fn test_insert_permanent() {
+    let mut cache = TtlCache::new();
+    cache.insert(1, 10, Duration::from_millis(1));
+    cache.insert_permanent(2, 20);
+    cache.insert(3, 30, Duration::from_millis(1));
+    cache.reset_ttl(&2);
+    assert_eq!(cache.get_mut_prolong(&2), Some(&mut 20));
+    sleep(Duration::from_millis(10));
+    cache.remove_expired();
+    assert_eq!(cache.get(&2), Some(&20));
+    assert_eq!(cache.remaining_ttl(&2), Some(Duration::MAX));
+    assert_eq!(cache.iter().collect::<Vec<_>>(), [(&2, &20)]);
+    assert_eq!(cache.insert(2, 21, Duration::from_millis(1)), Some(20));
+    sleep(Duration::from_millis(10));
+    assert_eq!(cache.get(&2), None);
+}
+
+#[test]
+fn test_max_ttl() {
+    let max = Duration::from_secs(60);
+    let mut cache = TtlCache::with_default_ttl(Duration::from_secs(60 * 60));
+    cache.insert(1, 10, Duration::from_secs(60 * 60));
+    cache.set_max_ttl(max);
+    assert_eq!(cache.max_ttl(), Some(max));
+    cache.insert(2, 20, Duration::from_secs(60 * 60));
+    cache.insert_default(3, 30);
+    cache.entry(4).or_insert_default_ttl(40);
+    if let ttl_cache::Entry::Vacant(entry) = cache.entry(5) {
+        entry.insert(50, Duration::from_secs(60 * 60));
+    }
+    for k in 2..6 {
+        assert!(cache.remaining_ttl(&k).unwrap() <= max);
+    }
+    // Inserted before the clamp was lowered, so it keeps its deadline until reset
+    assert!(cache.remaining_ttl(&1).unwrap() > max);
+    cache.reset_ttl(&1);
+    assert!(cache.remaining_ttl(&1).unwrap() <= max);
+    cache.clear_max_ttl();
+    cache.reset_ttl(&1);
+    assert!(cache.remaining_ttl(&1).unwrap() > max);
+}
+
+#[test]
+fn test_ttl_jitter() {
+    let ttl = Duration::from_secs(100);
+    let mut a = TtlCache::new();
+    let mut b = TtlCache::new();
+    a.set_ttl_jitter_with_seed(0.5, 42);
+    b.set_ttl_jitter_with_seed(0.5, 42);
+    assert_eq!(a.ttl_jitter(), Some(0.5));
+    for k in 0..20 {
+        a.insert(k, k, ttl);
+        if let ttl_cache::Entry::Vacant(entry) = b.entry(k) {
+            entry.insert(k, ttl);
+        }
+    }
+    let remaining: Vec<_> = (0..20).map(|k| a.remaining_ttl(&k).unwrap()).collect();
+    assert!(remaining.iter().all(|r| *r <= Duration::from_secs(150)));
+    assert!(remaining.iter().all(|r| *r >= Duration::from_secs(49)));
+    assert!(remaining.iter().any(|r| *r < Duration::from_secs(90)));
+    assert!(remaining.iter().any(|r| *r > Duration::from_secs(110)));
+    // The same seed gives the same sequence of TTLs
+    for (k, r) in remaining.iter().enumerate() {
+        let other = b.remaining_ttl(&(k as i32)).unwrap();
+        assert!((other.as_secs_f64() - r.as_secs_f64()).abs() < 1.0);
+    }
+    a.reset_ttl(&0);
+    assert!(a.remaining_ttl(&0).unwrap() != remaining[0]);
+    a.clear_ttl_jitter();
+    a.reset_ttl(&0);
+    assert!(a.remaining_ttl(&0).unwrap() <= ttl);
+    assert!(a.remaining_ttl(&0).unwrap() > Duration::from_secs(99));
+}
+
+#[test]
+fn test_entry_or_insert() {
+    let duration = Duration::from_secs(60);
+    let mut cache = TtlCache::new();
+    assert_eq!(*cache.entry(1).or_insert(10, duration), 10);
+    assert_eq!(*cache.entry(1).or_insert(11, duration), 10);
+    cache.insert(2, 20, Duration::from_millis(1));
+    sleep(Duration::from_millis(10));
+    assert_eq!(*cache.entry(2).or_insert(21, duration), 21);
+    assert_eq!(cache.get(&2), Some(&21));
+}
+
+#[test]
+fn test_entry_or_insert_expires_while_held() {
+    let mut cache = TtlCache::new();
+    cache.insert(1, 10, Duration::from_millis(5));
+    let entry = cache.entry(1);
+    sleep(Duration::from_millis(10));
+    // Liveness was settled by the lookup, so the now stale value is handed back
+    assert_eq!(*entry.or_insert(11, Duration::from_secs(60)), 10);
+    assert_eq!(cache.get(&1), None);
+}
+
+#[test]
+fn test_entry_or_insert_with() {
+    let duration = Duration::from_secs(60);
+    let mut cache = TtlCache::new();
+    assert_eq!(*cache.entry(1).or_insert_with(duration, || 10), 10);
+    assert_eq!(
+        *cache.entry(1).or_insert_with(duration, || panic!("called on an occupied entry")),
+        10
+    );
+    cache.insert(2, 20, Duration::from_millis(1));
+    sleep(Duration::from_millis(10));
+    assert_eq!(*cache.entry(2).or_insert_with(duration, || 21), 21);
+}
+
+#[test]
+fn test_entry_or_insert_with_key() {
+    let duration = Duration::from_secs(60);
+    let mut cache = TtlCache::new();
+    assert_eq!(*cache.entry(2).or_insert_with_key(duration, |k| k * 10), 20);
+    assert_eq!(
+        *cache.entry(2).or_insert_with_key(duration, |_| panic!("called on an occupied entry")),
+        20
+    );
+}
+
+#[test]
+fn test_entry_or_default() {
+    let mut cache: TtlCache<i32, u64> = TtlCache::new();
+    *cache.entry(1).or_default(Duration::from_millis(1)) += 5;
+    assert_eq!(cache.get(&1), Some(&5));
+    sleep(Duration::from_millis(10));
+    assert_eq!(*cache.entry(1).or_default(Duration::from_secs(60)), 0);
+}
+
+#[test]
+fn test_entry_and_modify() {
+    let mut cache = TtlCache::new();
+    cache.entry(1).and_modify(|v| *v += 1).or_insert(1, Duration::from_millis(50));
+    cache.entry(1).and_modify(|v| *v += 1).or_insert(1, Duration::from_millis(50));
+    assert_eq!(cache.get(&1), Some(&2));
+    sleep(Duration::from_millis(30));
+    cache.entry(1).and_modify(|v| *v += 1).or_insert(1, Duration::from_millis(50));
+    sleep(Duration::from_millis(30));
+    assert_eq!(cache.get(&1), None);
+}
+
+#[test]
+fn test_entry_and_modify_prolong() {
+    let mut cache = TtlCache::new();
+    cache.entry(1).and_modify_prolong(|v| *v += 1).or_insert(1, Duration::from_millis(50));
+    sleep(Duration::from_millis(30));
+    cache.entry(1).and_modify_prolong(|v| *v += 1).or_insert(1, Duration::from_millis(50));
+    sleep(Duration::from_millis(30));
+    assert_eq!(cache.get(&1), Some(&2));
+}
+
+#[test]
+fn test_occupied_entry_remove() {
+    let duration = Duration::from_secs(60);
+    let mut cache = TtlCache::new();
+    cache.insert(1, 10, duration);
+    cache.insert(2, 20, duration);
+    match cache.entry(1) {
+        ttl_cache::Entry::Occupied(entry) => assert_eq!(entry.remove(), 10),
+        ttl_cache::Entry::Vacant(_) => panic!("expected an occupied entry"),
+    }
+    assert!(!cache.contains_key(&1));
+    assert_eq!(cache.iter().collect::<Vec<_>>(), [(&2, &20)]);
+}
+
+#[test]
+fn test_occupied_entry_remove_entry() {
+    let duration = Duration::from_secs(60);
+    let mut cache = TtlCache::new();
+    cache.insert("1".to_string(), 10, duration);
+    cache.insert("2".to_string(), 20, duration);
+    match cache.entry("2".to_string()) {
+        ttl_cache::Entry::Occupied(entry) => {
+            assert_eq!(entry.remove_entry(), ("2".to_string(), 20))
+        }
+        ttl_cache::Entry::Vacant(_) => panic!("expected an occupied entry"),
+    }
+    assert!(!cache.contains_key("2"));
+    assert_eq!(cache.get("1"), Some(&10));
+}
+
+#[test]
+fn test_occupied_entry_into_mut() {
+    let mut cache = TtlCache::new();
+    cache.insert(1, 10, Duration::from_secs(60));
+    let value = match cache.entry(1) {
+        ttl_cache::Entry::Occupied(entry) => entry.into_mut(),
+        ttl_cache::Entry::Vacant(_) => panic!("expected an occupied entry"),
+    };
+    *value += 1;
+    assert_eq!(cache.get(&1), Some(&11));
+}
+
+#[test]
+fn test_occupied_entry_expiration() {
+    let mut cache = TtlCache::new();
+    let before = Instant::now();
+    cache.insert(1, 10, Duration::from_secs(60));
+    cache.insert_permanent(2, 20);
+    match cache.entry(1) {
+        ttl_cache::Entry::Occupied(entry) => {
+            let expiration = entry.expiration().unwrap();
+            assert!(expiration + SLACK >= before + Duration::from_secs(60));
+            assert!(entry.remaining_ttl() <= Duration::from_secs(60));
+        }
+        ttl_cache::Entry::Vacant(_) => panic!("expected an occupied entry"),
+    }
+    match cache.entry(2) {
+        ttl_cache::Entry::Occupied(entry) => {
+            assert_eq!(entry.expiration(), None);
+            assert_eq!(entry.remaining_ttl(), Duration::MAX);
+        }
+        ttl_cache::Entry::Vacant(_) => panic!("expected an occupied entry"),
+    }
+}
+
+#[test]
+fn test_occupied_entry_reset_and_set_ttl() {
+    let mut cache = TtlCache::new();
+    cache.insert(1, 10, Duration::from_millis(50));
+    cache.insert_permanent(2, 20);
+    sleep(Duration::from_millis(30));
+    if let ttl_cache::Entry::Occupied(mut entry) = cache.entry(1) {
+        entry.reset_ttl();
+    }
+    assert!(cache.remaining_ttl(&1).unwrap() > Duration::from_millis(30));
+    if let ttl_cache::Entry::Occupied(mut entry) = cache.entry(1) {
+        entry.set_ttl(Duration::from_secs(60));
+    }
+    assert!(cache.remaining_ttl(&1).unwrap() > Duration::from_secs(59));
+    if let ttl_cache::Entry::Occupied(mut entry) = cache.entry(2) {
+        entry.set_ttl(Duration::from_millis(1));
+    }
+    sleep(Duration::from_millis(10));
+    assert_eq!(cache.remaining_ttl(&2), None);
+    cache.reset_ttl(&1);
+    assert!(cache.remaining_ttl(&1).unwrap() > Duration::from_secs(59));
+}
+
+#[test]
+fn test_occupied_entry_insert_keep_ttl() {
+    let mut cache = TtlCache::new();
+    cache.insert(1, 10, Duration::from_millis(50));
+    cache.insert(2, 20, Duration::from_secs(60));
+    let expiration = match cache.entry(1) {
+        ttl_cache::Entry::Occupied(mut entry) => {
+            let expiration = entry.expiration();
+            assert_eq!(entry.insert_keep_ttl(11), 10);
+            assert_eq!(entry.expiration(), expiration);
+            expiration
+        }
+        ttl_cache::Entry::Vacant(_) => panic!("expected an occupied entry"),
+    };
+    match cache.entry(1) {
+        ttl_cache::Entry::Occupied(entry) => assert_eq!(entry.expiration(), expiration),
+        ttl_cache::Entry::Vacant(_) => panic!("expected an occupied entry"),
+    }
+    assert_eq!(cache.iter().collect::<Vec<_>>(), [(&1, &11), (&2, &20)]);
+    sleep(Duration::from_millis(60));
+    assert_eq!(cache.get(&1), None);
+}
+
+#[test]
+fn test_vacant_entry_into_key() {
+    let mut cache = TtlCache::new();
+    cache.insert("1".to_string(), 10, Duration::from_millis(1));
+    sleep(Duration::from_millis(10));
+    match cache.entry("1".to_string()) {
+        ttl_cache::Entry::Vacant(entry) => assert_eq!(entry.into_key(), "1"),
+        ttl_cache::Entry::Occupied(_) => panic!("expected a vacant entry"),
+    }
+    assert!(!cache.contains_key("1"));
+    assert_eq!(cache.iter().count(), 0);
+}
+
+#[test]
+fn test_entry_insert_with_expiration() {
+    let mut cache = TtlCache::new();
+    let now = Instant::now();
+    if let ttl_cache::Entry::Vacant(entry) = cache.entry(1) {
+        assert_eq!(*entry.insert_with_expiration(10, now + Duration::from_millis(50)), 10);
+    }
+    match cache.entry(1) {
+        ttl_cache::Entry::Occupied(mut entry) => {
+            let expiration = entry.expiration().unwrap();
+            assert!(expiration <= now + Duration::from_millis(50));
+            assert!(expiration + SLACK >= now + Duration::from_millis(50));
+            assert_eq!(entry.insert_with_expiration(11, now + Duration::from_secs(60)), 10);
+            let expiration = entry.expiration().unwrap();
+            assert!(expiration <= now + Duration::from_secs(60));
+            assert!(expiration + SLACK >= now + Duration::from_secs(60));
+        }
+        ttl_cache::Entry::Vacant(_) => panic!("expected an occupied entry"),
+    }
+    // A deadline in the past inserts an already expired entry
+    if let ttl_cache::Entry::Vacant(entry) = cache.entry(2) {
+        entry.insert_with_expiration(20, now - Duration::from_millis(1));
+    }
+    assert_eq!(cache.get(&2), None);
+    assert_eq!(cache.iter().collect::<Vec<_>>(), [(&1, &11)]);
+    // The max TTL still caps absolute deadlines
+    cache.set_max_ttl(Duration::from_secs(1));
+    if let ttl_cache::Entry::Vacant(entry) = cache.entry(3) {
+        entry.insert_with_expiration(30, now + Duration::from_secs(60));
+    }
+    assert!(cache.remaining_ttl(&3).unwrap() <= Duration::from_secs(1));
+}
+
+#[test]
+fn test_entry_reuses_expired_entry() {
+    let mut cache = TtlCache::new();
+    let stale: Arc<str> = Arc::from("1");
+    cache.insert(stale.clone(), 10, Duration::from_millis(1));
+    cache.insert(Arc::from("2"), 20, Duration::from_secs(60));
+    sleep(Duration::from_millis(10));
+    let fresh: Arc<str> = Arc::from("1");
+    match cache.entry(fresh.clone()) {
+        ttl_cache::Entry::Vacant(entry) => {
+            assert!(Arc::ptr_eq(entry.key(), &fresh));
+            assert_eq!(*entry.insert(11, Duration::from_secs(60)), 11);
+        }
+        ttl_cache::Entry::Occupied(_) => panic!("expected a vacant entry"),
+    }
+    // The expired entry was replaced in place: new key, new value, moved to the back
+    assert_eq!(Arc::strong_count(&stale), 1);
+    assert_eq!(Arc::strong_count(&fresh), 2);
+    assert_eq!(
+        cache.iter().map(|(k, v)| (&**k, *v)).collect::<Vec<_>>(),
+        [("2", 20), ("1", 11)]
+    );
+}
+
+#[test]
+fn test_entry_ref() {
+    let duration = Duration::from_secs(60);
+    let mut cache: TtlCache<String, i32> = TtlCache::new();
+    assert_eq!(*cache.entry_ref("1").or_insert(10, duration), 10);
+    assert_eq!(*cache.entry_ref("1").or_insert(11, duration), 10);
+    let owned = "1".to_string();
+    assert_eq!(*cache.entry_ref(&owned).or_insert_with(duration, || panic!()), 10);
+    match cache.entry_ref("1") {
+        ttl_cache::EntryRef::Occupied(entry) => assert_eq!(entry.key(), "1"),
+        ttl_cache::EntryRef::Vacant(_) => panic!("expected an occupied entry"),
+    }
+    *cache.entry_ref("2").and_modify(|v| *v += 1).or_default(duration) += 20;
+    *cache.entry_ref("2").and_modify(|v| *v += 1).or_default(duration) += 1;
+    assert_eq!(cache.get("2"), Some(&22));
+
+    // Expired entries show up as vacant and get replaced in place
+    cache.insert("3".to_string(), 30, Duration::from_millis(1));
+    sleep(Duration::from_millis(10));
+    match cache.entry_ref("3") {
+        ttl_cache::EntryRef::Vacant(entry) => {
+            assert_eq!(entry.key(), "3");
+            assert_eq!(*entry.insert_with(str::to_string, 31, duration), 31);
+        }
+        ttl_cache::EntryRef::Occupied(_) => panic!("expected a vacant entry"),
+    }
+    assert_eq!(
+        cache.iter().collect::<Vec<_>>(),
+        [(&"1".to_string(), &10), (&"2".to_string(), &22), (&"3".to_string(), &31)]
+    );
+}
+
+#[test]
+fn test_entry_ref_arc_key_only_built_on_insert() {
+    let mut cache: TtlCache<Arc<str>, i32> = TtlCache::new();
+    let stored: Arc<str> = Arc::from("1");
+    cache.insert(stored.clone(), 10, Duration::from_secs(60));
+    match cache.entry_ref("1") {
+        ttl_cache::EntryRef::Occupied(entry) => *entry.into_mut() += 1,
+        ttl_cache::EntryRef::Vacant(_) => panic!("expected an occupied entry"),
+    }
+    assert_eq!(Arc::strong_count(&stored), 2);
+    if let ttl_cache::EntryRef::Vacant(entry) = cache.entry_ref("2") {
+        entry.insert_with(Arc::from, 20, Duration::from_secs(60));
+    }
+    assert_eq!(cache.get("1"), Some(&11));
+    assert_eq!(cache.get("2"), Some(&20));
+}
+
+#[test]
+#[should_panic(expected = "must equal the key")]
+fn test_entry_ref_insert_with_mismatched_key() {
+    let mut cache: TtlCache<String, i32> = TtlCache::new();
+    if let ttl_cache::EntryRef::Vacant(entry) = cache.entry_ref("1") {
+        entry.insert_with(|_| "2".to_string(), 10, Duration::from_secs(60));
+    }
+}
+
+#[test]
+#[cfg(feature = "stats")]
+fn test_entry_stats() {
+    let duration = Duration::from_secs(60);
+    let mut cache = TtlCache::new();
+    cache.insert(1, 10, duration);
+    cache.insert(2, 20, Duration::from_millis(1));
+    sleep(Duration::from_millis(10));
+    // Occupied
+    *cache.entry(1).or_insert(0, duration) += 1;
+    assert_eq!((cache.hit_count(), cache.miss_count()), (1, 0));
+    // Vacant
+    cache.entry(3).or_insert(30, duration);
+    assert_eq!((cache.hit_count(), cache.miss_count()), (1, 1));
+    // Expired, then vacant
+    cache.entry(2).or_insert(21, duration);
+    assert_eq!((cache.hit_count(), cache.miss_count()), (1, 2));
+    // Same for lookups by reference
+    cache.entry_ref(&2).or_insert(22, duration);
+    cache.entry_ref(&4).or_insert(40, duration);
+    assert_eq!((cache.hit_count(), cache.miss_count()), (2, 3));
+}
+
+#[cfg(feature = "raw")]
+fn hash_of<K: std::hash::Hash, S: std::hash::BuildHasher>(cache_hasher: &S, k: &K) -> u64 {
+    use std::hash::Hasher;
+    let mut hasher = cache_hasher.build_hasher();
+    k.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[test]
+#[cfg(feature = "raw")]
+fn test_raw_entry() {
+    let duration = Duration::from_secs(60);
+    let mut cache = TtlCache::new();
+    cache.insert("1".to_string(), 10, duration);
+    cache.insert("2".to_string(), 20, Duration::from_millis(1));
+    sleep(Duration::from_millis(10));
+    let (h1, h2, h3) = (
+        hash_of(cache.hasher(), &"1"),
+        hash_of(cache.hasher(), &"2"),
+        hash_of(cache.hasher(), &"3"),
+    );
+    assert_eq!(cache.raw_entry().from_hash(h1, |k| k == "1"), Some((&"1".to_string(), &10)));
+    assert_eq!(cache.raw_entry().from_key_hashed_nocheck(h1, "1"), Some((&"1".to_string(), &10)));
+    // Expired matches are treated as vacant
+    assert_eq!(cache.raw_entry().from_hash(h2, |k| k == "2"), None);
+    assert_eq!(cache.raw_entry().from_key("2"), None);
+
+    match cache.raw_entry_mut().from_hash(h1, |k| k == "1") {
+        ttl_cache::RawEntryMut::Occupied(mut entry) => *entry.get_mut() += 1,
+        ttl_cache::RawEntryMut::Vacant(_) => panic!("expected an occupied entry"),
+    }
+    match cache.raw_entry_mut().from_key("2") {
+        ttl_cache::RawEntryMut::Vacant(entry) => {
+            entry.insert_hashed_nocheck(h2, "2".to_string(), 21, duration);
+        }
+        ttl_cache::RawEntryMut::Occupied(_) => panic!("expected a vacant entry"),
+    }
+    *cache
+        .raw_entry_mut()
+        .from_hash(h3, |k| k == "3")
+        .or_insert_with(duration, || ("3".to_string(), 30)) += 1;
+    assert_eq!(
+        cache.iter().map(|(k, v)| (k.as_str(), *v)).collect::<Vec<_>>(),
+        [("1", 11), ("2", 21), ("3", 31)]
+    );
+}
+
+#[test]
+fn test_occupied_entry_get_mut_prolong() {
+    let mut cache = TtlCache::new();
+    cache.insert(1, 10, Duration::from_millis(50));
+    cache.insert(2, 20, Duration::from_millis(50));
+    sleep(Duration::from_millis(30));
+    if let ttl_cache::Entry::Occupied(mut entry) = cache.entry(1) {
+        *entry.get_mut_prolong() += 1;
+    }
+    sleep(Duration::from_millis(30));
+    // The prolonged entry stays put and doesn't hold up purging or iteration
+    assert_eq!(cache.iter().collect::<Vec<_>>(), [(&1, &11)]);
+    assert_eq!(cache.get(&2), None);
+}
+
+#[test]
+fn test_entry_or_insert_with_ttl() {
+    let mut cache = TtlCache::new();
+    assert_eq!(*cache.entry(1).or_insert_with_ttl(|| (10, Duration::from_secs(60))), 10);
+    assert_eq!(*cache.entry(1).or_insert_with_ttl(|| panic!("called on an occupied entry")), 10);
+    assert!(cache.remaining_ttl(&1).unwrap() > Duration::from_secs(59));
+
+    // A zero TTL still hands back the loaded value, but the entry is expired right after
+    assert_eq!(*cache.entry(2).or_insert_with_ttl(|| (20, Duration::ZERO)), 20);
+    sleep(Duration::from_millis(1));
+    assert_eq!(cache.get(&2), None);
+    assert_eq!(*cache.entry(2).or_insert_with_ttl(|| (21, Duration::from_secs(60))), 21);
+}
+
+#[test]
+fn test_entry_or_try_insert_with_ttl() {
+    let mut cache = TtlCache::new();
+    assert_eq!(cache.entry(1).or_try_insert_with_ttl(|| Err("failed")), Err("failed"));
+    assert!(!cache.contains_key(&1));
+    assert_eq!(
+        cache.entry(1).or_try_insert_with_ttl::<(), _>(|| Ok((10, Duration::from_secs(60)))),
+        Ok(&mut 10)
+    );
+    assert_eq!(
+        cache.entry(1).or_try_insert_with_ttl::<(), _>(|| panic!("called on an occupied entry")),
+        Ok(&mut 10)
+    );
+}
+
+#[test]
+fn test_entry_debug() {
+    let mut cache = TtlCache::new();
+    cache.insert_permanent("a", 1);
+    cache.insert("b", 2, Duration::from_secs(60));
+    assert_eq!(
+        format!("{:?}", cache.entry("a")),
+        format!(
+            "Entry(OccupiedEntry {{ key: \"a\", value: 1, remaining_ttl: {:?} }})",
+            Duration::MAX
+        )
+    );
+    assert_eq!(format!("{:?}", cache.entry("c")), "Entry(VacantEntry(\"c\"))");
+    let debug = format!("{:?}", cache.entry("b"));
+    assert!(debug.starts_with("Entry(OccupiedEntry { key: \"b\", value: 2, remaining_ttl: "));
+    assert_eq!(format!("{:?}", cache.entry_ref("c")), "EntryRef(VacantEntryRef(\"c\"))");
+    if let ttl_cache::Entry::Vacant(entry) = cache.entry("c") {
+        assert_eq!(format!("{:?}", entry), "VacantEntry(\"c\")");
+    }
+}
+
+#[test]
+fn test_iter_size_hint_with_expired() {
+    let mut cache = TtlCache::new();
+    for k in 0..10 {
+        cache.insert(k, k, Duration::from_secs(60));
+    }
+    // Extending purges once up front, so none of these are purged while being inserted
+    cache.extend((10..1000).map(|k| (k, k, Duration::from_millis(1))));
+    sleep(Duration::from_millis(10));
+    let iter = cache.iter();
+    assert_eq!(iter.size_hint(), (0, Some(1000)));
+    assert_eq!(iter.collect::<Vec<_>>().len(), 10);
+    let iter_mut = cache.iter_mut();
+    assert_eq!(iter_mut.size_hint().0, 0);
+    assert_eq!(iter_mut.collect::<Vec<_>>().len(), 10);
+}
+
+#[test]
+fn test_iter_rev_with_mixed_ttls() {
+    let mut cache = TtlCache::new();
+    cache.insert(1, 10, Duration::from_millis(1));
+    cache.insert(2, 20, Duration::from_secs(60));
+    cache.insert(3, 30, Duration::from_millis(1));
+    cache.insert(4, 40, Duration::from_secs(60));
+    cache.insert(5, 50, Duration::from_millis(1));
+    sleep(Duration::from_millis(10));
+    assert_eq!(cache.iter().rev().collect::<Vec<_>>(), [(&4, &40), (&2, &20)]);
+    assert_eq!(
+        cache.iter_mut().rev().collect::<Vec<_>>(),
+        [(&4, &mut 40), (&2, &mut 20)]
+    );
+}
+
+#[test]
+fn test_iter_shared() {
+    let lock = std::sync::RwLock::new(TtlCache::new());
+    {
+        let mut cache = lock.write().unwrap();
+        cache.insert(1, 10, Duration::from_millis(1));
+        cache.insert(2, 20, Duration::from_secs(60));
+    }
+    sleep(Duration::from_millis(10));
+    let a = lock.read().unwrap();
+    let b = lock.read().unwrap();
+    assert_eq!(a.iter().collect::<Vec<_>>(), [(&2, &20)]);
+    assert_eq!(b.iter().rev().collect::<Vec<_>>(), [(&2, &20)]);
+    // Nothing was purged
+    assert_eq!(a.iter().size_hint(), (0, Some(2)));
+}
+
+#[test]
+fn test_iter_fused() {
+    fn assert_fused<I: std::iter::FusedIterator>(_: &I) {}
+    let mut cache = TtlCache::new();
+    cache.insert(1, 10, Duration::from_secs(60));
+    cache.insert(2, 20, Duration::from_millis(1));
+    cache.insert(3, 30, Duration::from_secs(60));
+    cache.insert(4, 40, Duration::from_secs(60));
+    sleep(Duration::from_millis(10));
+
+    let mut iter = cache.iter();
+    assert_fused(&iter);
+    assert_eq!(iter.next(), Some((&1, &10)));
+    assert_eq!(iter.next_back(), Some((&4, &40)));
+    assert_eq!(iter.next(), Some((&3, &30)));
+    assert_eq!(iter.next_back(), None);
+    for _ in 0..3 {
+        assert_eq!(iter.next(), None);
+        assert_eq!(iter.next_back(), None);
+    }
+
+    let mut iter_mut = cache.iter_mut();
+    assert_fused(&iter_mut);
+    assert_eq!(iter_mut.next_back(), Some((&4, &mut 40)));
+    assert_eq!(iter_mut.next(), Some((&1, &mut 10)));
+    assert_eq!(iter_mut.next_back(), Some((&3, &mut 30)));
+    assert_eq!(iter_mut.next(), None);
+    for _ in 0..3 {
+        assert_eq!(iter_mut.next(), None);
+        assert_eq!(iter_mut.next_back(), None);
+    }
+}
+
+#[test]
+fn test_iter_with_ttl() {
+    let mut cache = TtlCache::new();
+    let expires_at = Instant::now() + Duration::from_secs(60);
+    // Identical deadlines, so identical remaining TTLs when measured against one instant
+    for k in 1..4 {
+        if let ttl_cache::Entry::Vacant(entry) = cache.entry(k) {
+            entry.insert_with_expiration(k * 10, expires_at);
+        }
+    }
+    cache.insert(4, 40, Duration::from_millis(1));
+    cache.insert_permanent(5, 50);
+    sleep(Duration::from_millis(10));
+
+    let snapshot: Vec<_> = cache.iter_with_ttl().collect();
+    assert_eq!(snapshot.len(), 4);
+    assert!(snapshot[..3].iter().all(|&(_, _, ttl)| ttl == snapshot[0].2));
+    assert!(snapshot[0].2 < Duration::from_secs(60));
+    assert_eq!(snapshot[3], (&5, &50, Duration::MAX));
+    assert_eq!(
+        cache.iter_with_ttl().rev().map(|(k, _, _)| *k).collect::<Vec<_>>(),
+        [5, 3, 2, 1]
+    );
+
+    for (_, v, _) in cache.iter_mut_with_ttl() {
+        *v += 1;
+    }
+    let ttls: Vec<_> = cache.iter_mut_with_ttl().map(|(_, _, ttl)| ttl).collect();
+    assert!(ttls[..3].iter().all(|ttl| *ttl == ttls[0]));
+    assert_eq!(cache.get(&1), Some(&11));
+}
+
+#[test]
+fn test_iter_from() {
+    let mut cache = TtlCache::new();
+    for k in 0..6 {
+        cache.insert(k, k * 10, Duration::from_secs(60));
+    }
+    cache.insert(3, 30, Duration::from_millis(1));
+    cache.insert(6, 60, Duration::from_millis(1));
+    cache.insert(7, 70, Duration::from_secs(60));
+    sleep(Duration::from_millis(10));
+    // Re-inserting moved 3 and 6 to the back
+    assert_eq!(
+        cache.iter_from(&0).collect::<Vec<_>>(),
+        [(&1, &10), (&2, &20), (&4, &40), (&5, &50), (&7, &70)]
+    );
+    assert_eq!(cache.iter_from(&4).rev().collect::<Vec<_>>(), [(&7, &70), (&5, &50)]);
+    // An expired cursor resumes from the next younger live entry
+    assert_eq!(cache.iter_from(&5).collect::<Vec<_>>(), [(&7, &70)]);
+    assert_eq!(cache.iter_from(&6).collect::<Vec<_>>(), [(&7, &70)]);
+    assert_eq!(cache.iter_from(&7).next(), None);
+    assert_eq!(cache.iter_from(&42).next(), None);
+}
+
+#[test]
+fn test_extend() {
+    let mut cache = TtlCache::new();
+    cache.insert(1, 1, Duration::from_secs(60));
+    cache.extend(vec![
+        (1, 10, Duration::from_millis(1)),
+        (2, 20, Duration::from_secs(60)),
+        (3, 30, Duration::from_millis(1)),
+    ]);
+    let mut other = std::collections::BTreeMap::new();
+    other.insert(4, (40, Duration::from_secs(60)));
+    other.insert(5, (50, Duration::from_millis(1)));
+    cache.extend(other.into_iter().map(|(k, (v, ttl))| (k, v, ttl)));
+    assert_eq!(cache.get(&1), Some(&10));
+    sleep(Duration::from_millis(10));
+    assert_eq!(cache.iter().collect::<Vec<_>>(), [(&2, &20), (&4, &40)]);
+}
+
+#[test]
+fn test_extend_default_ttl() {
+    let mut cache = TtlCache::with_default_ttl(Duration::from_millis(50));
+    cache.extend(vec![(1, 10), (2, 20)]);
+    cache.extend(vec![(3, 30, Duration::from_secs(60))]);
+    assert!(cache.remaining_ttl(&1).unwrap() <= Duration::from_millis(50));
+    sleep(Duration::from_millis(60));
+    assert_eq!(cache.iter().collect::<Vec<_>>(), [(&3, &30)]);
+}
+
+#[test]
+#[should_panic(expected = "no default TTL")]
+fn test_extend_without_default_ttl() {
+    let mut cache = TtlCache::new();
+    cache.extend(vec![(1, 10)]);
+}
+
+#[test]
+fn test_from_iter() {
+    let cache: TtlCache<_, _> = vec![
+        (1, 10, Duration::from_millis(1)),
+        (2, 20, Duration::from_secs(60)),
+        (1, 11, Duration::from_secs(60)),
+    ]
+    .into_iter()
+    .collect();
+    sleep(Duration::from_millis(10));
+    assert_eq!(cache.iter().collect::<Vec<_>>(), [(&2, &20), (&1, &11)]);
+
+    let hasher = std::collections::hash_map::RandomState::new();
+    let cache = TtlCache::from_iter_with_hasher(vec![(3, 30, Duration::from_secs(60))], hasher);
+    assert_eq!(cache.get(&3), Some(&30));
+}
+
+#[test]
+#[cfg(feature = "rayon")]
+fn test_rayon() {
+    use rayon::prelude::*;
+
+    let mut cache = TtlCache::new();
+    cache.par_extend((0..1000).into_par_iter().map(|k| {
+        let ttl = if k % 10 == 0 { Duration::from_secs(60) } else { Duration::from_millis(1) };
+        (k, k, ttl)
+    }));
+    sleep(Duration::from_millis(10));
+    assert!(cache.par_iter().all(|(k, _)| k % 10 == 0));
+    assert_eq!(cache.par_iter().count(), 100);
+    cache.par_iter_mut().for_each(|(k, v)| {
+        assert_eq!(k % 10, 0);
+        *v += 1;
+    });
+    let sum: i32 = cache.par_iter().map(|(_, v)| *v).sum();
+    assert_eq!(sum, (0..100).map(|k| k * 10 + 1).sum::<i32>());
+}
+
+#[test]
+fn test_debug() {
+    let mut cache = TtlCache::new();
+    assert!(format!("{:?}", cache).starts_with("{}"));
+    cache.insert_permanent("a", 1);
+    cache.insert("b", 2, Duration::from_millis(1));
+    cache.insert_permanent("c", 3);
+    sleep(Duration::from_millis(10));
+    let debug = format!("{:?}", cache);
+    assert!(debug.starts_with("{\"a\": 1 (ttl: permanent), \"c\": 3 (ttl: permanent)}"));
+    cache.insert("d", 4, Duration::from_secs(60));
+    let debug = format!("{:?}", cache);
+    assert!(debug.contains(", \"d\": 4 (ttl: 60.0s)}") || debug.contains(", \"d\": 4 (ttl: 59.9"));
+}
+
+#[test]
+#[cfg(feature = "stats")]
+fn test_debug_stats() {
+    let mut cache = TtlCache::new();
+    cache.insert_permanent("a", 1);
+    cache.get("a");
+    cache.get("b");
+    assert_eq!(format!("{:?}", cache), "{\"a\": 1 (ttl: permanent)} (hits: 1, misses: 1)");
+}
+
+#[test]
+fn test_index() {
+    let mut cache = TtlCache::new();
+    cache.insert("1".to_string(), 10, Duration::from_secs(60));
+    assert_eq!(cache["1"], 10);
+    assert_eq!(cache[&"1".to_string()], 10);
+}
+
+#[test]
+#[should_panic(expected = "no unexpired entry for key")]
+fn test_index_expired() {
+    let mut cache = TtlCache::new();
+    cache.insert(1, 10, Duration::from_millis(1));
+    sleep(Duration::from_millis(10));
+    let _ = cache[&1];
+}
+
+#[test]
+#[cfg(feature = "serde")]
+fn test_serde_round_trip() {
+    use ttl_cache::test_util::MockClock;
+
+    let clock = MockClock::new();
+    let mut cache = TtlCache::with_clock(clock.clone());
+    cache.insert("a".to_string(), 1, Duration::from_secs(60));
+    cache.insert("b".to_string(), 2, Duration::from_millis(1));
+    cache.insert_permanent("c".to_string(), 3);
+    cache.insert("d".to_string(), 4, Duration::from_millis(30));
+    clock.advance(Duration::from_millis(10));
+    let json = serde_json::to_string(&cache).unwrap();
+    let load = |clock: &MockClock| -> TtlCache<String, i32> {
+        let mut de = serde_json::Deserializer::from_str(&json);
+        TtlCache::deserialize_with_clock(&mut de, clock.clone()).unwrap()
+    };
+    let restored = load(&clock);
+    assert_eq!(
+        restored.iter().collect::<Vec<_>>(),
+        [(&"a".to_string(), &1), (&"c".to_string(), &3), (&"d".to_string(), &4)]
+    );
+    assert_eq!(restored.remaining_ttl("a"), Some(Duration::from_millis(59_990)));
+    assert_eq!(restored.remaining_ttl("c"), Some(Duration::MAX));
+
+    // "d" runs out between saving and loading
+    clock.advance(Duration::from_millis(30));
+    let restored = load(&clock);
+    assert_eq!(restored.get("d"), None);
+    assert_eq!(restored.iter().count(), 2);
+    assert_eq!(restored.remaining_ttl("a"), Some(Duration::from_millis(59_960)));
+
+    // Without a clock the snapshot loads against the system clock
+    let restored: TtlCache<String, i32> = serde_json::from_str(&json).unwrap();
+    assert_eq!(restored.iter().count(), 3);
+}
+
+#[test]
+#[cfg(feature = "serde")]
+fn test_serde_format() {
+    let mut cache = TtlCache::new();
+    cache.insert_permanent("a", 1);
+    let json = serde_json::to_value(&cache).unwrap();
+    assert_eq!(json[1], serde_json::json!([["a", 1, null]]));
+    assert!(json[0]["secs"].as_u64().unwrap() > 0);
+}
+
+#[test]
+fn test_clone_with_hasher() {
+    #[derive(Clone, Default)]
+    struct FixedState;
+
+    impl std::hash::BuildHasher for FixedState {
+        type Hasher = std::collections::hash_map::DefaultHasher;
+
+        fn build_hasher(&self) -> Self::Hasher {
+            std::collections::hash_map::DefaultHasher::new()
+        }
+    }
+
+    let mut cache = TtlCache::with_hasher(FixedState);
+    cache.insert(1, 10, Duration::from_secs(60));
+    cache.set_max_ttl(Duration::from_secs(30));
+    let mut clone = cache.clone();
+    clone.insert(2, 20, Duration::from_secs(60));
+    assert_eq!(clone.iter().collect::<Vec<_>>(), [(&1, &10), (&2, &20)]);
+    assert_eq!(clone.max_ttl(), Some(Duration::from_secs(30)));
+    assert_eq!(cache.get(&2), None);
+}
+
+#[test]
+fn test_clone_skips_expired() {
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    struct Counted(Rc<Cell<usize>>);
+
+    impl Clone for Counted {
+        fn clone(&self) -> Counted {
+            self.0.set(self.0.get() + 1);
+            Counted(self.0.clone())
+        }
+    }
+
+    let clones = Rc::new(Cell::new(0));
+    let mut cache = TtlCache::new();
+    for i in 0..100 {
+        let ttl = if i % 10 == 0 {
+            Duration::from_secs(60)
+        } else {
+            Duration::from_millis(5)
+        };
+        cache.insert(i, Counted(clones.clone()), ttl);
+    }
+    sleep(Duration::from_millis(10));
+
+    let clone = cache.clone();
+    assert_eq!(clones.get(), 10);
+    assert_eq!(clone.iter().count(), 10);
+    for i in (0..100).step_by(10) {
+        let original = cache.remaining_ttl(&i).unwrap();
+        let cloned = clone.remaining_ttl(&i).unwrap();
+        assert!(cloned <= original && original - cloned < Duration::from_secs(1));
+    }
+}
+
+#[test]
+fn test_auto_traits() {
+    use std::cell::Cell;
+    use ttl_cache::sharded::ShardedTtlCache;
+    use ttl_cache::sync::SharedTtlCache;
+
+    fn assert_send<T: Send>() {}
+    fn assert_sync<T: Sync>() {}
+
+    assert_send::<TtlCache<String, Vec<u8>>>();
+    assert_send::<TtlCache<i32, Cell<i32>>>();
+    assert_send::<TtlCache<i32, Arc<i32>>>();
+    // Lookups bump `Cell` counters through `&self` with the stats feature, so the cache is
+    // only `Sync` without it
+    #[cfg(not(feature = "stats"))]
+    {
+        assert_sync::<TtlCache<String, Vec<u8>>>();
+        assert_sync::<TtlCache<i32, Arc<i32>>>();
+    }
+    #[cfg(feature = "stats")]
+    {
+        // Only resolves to a single impl, and so compiles, when the type isn't `Sync`
+        trait AmbiguousIfSync<A> {
+            fn check() {}
+        }
+        impl<T: ?Sized> AmbiguousIfSync<()> for T {}
+        impl<T: ?Sized + Sync> AmbiguousIfSync<u8> for T {}
+        <TtlCache<String, Vec<u8>> as AmbiguousIfSync<_>>::check();
+    }
+    // The wrappers lock the cache for lookups, so they're shareable either way
+    assert_send::<SharedTtlCache<String, Vec<u8>>>();
+    assert_sync::<SharedTtlCache<String, Vec<u8>>>();
+    assert_send::<ShardedTtlCache<String, Vec<u8>>>();
+    assert_sync::<ShardedTtlCache<String, Vec<u8>>>();
+    #[cfg(feature = "arc-swap")]
+    {
+        use ttl_cache::snapshot::{Snapshot, SnapshotTtlCache};
+
+        // Lookups through a snapshot cache count in atomics, so it's shareable too
+        assert_send::<SnapshotTtlCache<String, Vec<u8>>>();
+        assert_sync::<SnapshotTtlCache<String, Vec<u8>>>();
+        assert_send::<Arc<Snapshot<String, Vec<u8>>>>();
+        assert_sync::<Arc<Snapshot<String, Vec<u8>>>>();
+    }
+}
+
+#[cfg(feature = "stats")]
+#[test]
+fn test_concurrent_lookups_counted() {
+    use std::thread;
+    use ttl_cache::sync::SharedTtlCache;
+
+    let cache = SharedTtlCache::new();
+    cache.insert(1, 10, Duration::from_secs(60));
+    let lookups: Vec<_> = (0..4)
+        .map(|_| {
+            let cache = cache.clone();
+            thread::spawn(move || {
+                for _ in 0..10_000 {
+                    assert_eq!(cache.get_cloned(&1), Some(10));
+                    assert_eq!(cache.get_cloned(&2), None);
+                }
+            })
+        })
+        .collect();
+    for lookup in lookups {
+        lookup.join().unwrap();
+    }
+    assert_eq!(cache.read(|cache| cache.hit_count()), 40_000);
+    assert_eq!(cache.read(|cache| cache.miss_count()), 40_000);
+}
+
+#[test]
+fn test_from_entries_at() {
+    let base = Instant::now();
+    let entries = || {
+        vec![
+            (1, 10, Duration::from_secs(60)),
+            (2, 20, Duration::ZERO),
+            (3, 30, Duration::from_secs(30)),
+        ]
+    };
+    let a = TtlCache::from_entries_at(entries(), base);
+    sleep(Duration::from_millis(1));
+    let b = TtlCache::from_entries_at(entries(), base);
+
+    assert_eq!(a.iter().collect::<Vec<_>>(), [(&1, &10), (&3, &30)]);
+    assert_eq!(b.iter().collect::<Vec<_>>(), [(&1, &10), (&3, &30)]);
+    // b was built later, yet its deadlines are the same as a's
+    let (ttl_a, ttl_b) = (a.remaining_ttl(&3).unwrap(), b.remaining_ttl(&3).unwrap());
+    assert!(ttl_b <= ttl_a && ttl_a - ttl_b < Duration::from_millis(500));
+    assert!(ttl_a < Duration::from_secs(30));
+}
+
+#[test]
+#[cfg(feature = "arbitrary")]
+fn test_arbitrary() {
+    use arbitrary::{Arbitrary, Unstructured};
+
+    // Each entry is a continue flag, a key, a value and a TTL in milliseconds from a base 2^15
+    // milliseconds in the past.  Even keys get a TTL of zero and odd ones the longest there is.
+    let bytes: Vec<u8> = (0..100u8)
+        .flat_map(|k| {
+            let ttl = if k % 2 == 0 { 0 } else { 0xff };
+            vec![1, k, k, 0, ttl, ttl]
+        })
+        .collect();
+    let cache = TtlCache::<u8, u16>::arbitrary(&mut Unstructured::new(&bytes)).unwrap();
+
+    assert_eq!(cache.iter().count(), 50);
+    assert!(cache.iter().all(|(k, v)| k % 2 == 1 && u16::from(*k) == *v));
+}
+
+#[test]
+fn test_into_iter_with_ttl_round_trip() {
+    let mut cache = TtlCache::new();
+    cache.insert(1, String::from("a"), Duration::from_secs(60));
+    cache.insert(2, String::from("b"), Duration::from_millis(1));
+    cache.insert_permanent(3, String::from("c"));
+    cache.insert(4, String::from("d"), Duration::from_secs(30));
+    sleep(Duration::from_millis(10));
+    let before: Vec<_> = [1, 4].iter().map(|k| cache.remaining_ttl(k).unwrap()).collect();
+
+    let moved = TtlCache::from_entries(cache.into_iter_with_ttl());
+
+    assert_eq!(
+        moved.iter().collect::<Vec<_>>(),
+        [(&1, &"a".to_string()), (&3, &"c".to_string()), (&4, &"d".to_string())]
+    );
+    assert_eq!(moved.remaining_ttl(&3), Some(Duration::MAX));
+    for (k, before) in [1, 4].iter().zip(before) {
+        let after = moved.remaining_ttl(k).unwrap();
+        assert!(after <= before && before - after < Duration::from_millis(50));
+    }
+}
+
+#[test]
+#[cfg(feature = "stats")]
+fn test_stats_display() {
+    let mut cache = TtlCache::new();
+    assert_eq!(cache.stats().to_string(), "hits=0 misses=0 hit_ratio=0.0% window=0s");
+
+    cache.insert(1, 10, Duration::from_secs(60));
+    for _ in 0..2 {
+        let _ = cache.get(&1);
+    }
+    let _ = cache.get(&2);
+    let stats = cache.stats();
+    assert_eq!((stats.hits, stats.misses), (2, 1));
+    assert_eq!(stats.since, cache.stats_since());
+    assert_eq!(stats.to_string(), "hits=2 misses=1 hit_ratio=66.7% window=0s");
+    assert!(format!("{:?}", stats).starts_with("CacheStats { hits: 2, misses: 1, since: "));
+
+    // The snapshot no longer changes with the cache
+    let _ = cache.get(&1);
+    assert_eq!(stats.hits, 2);
+    let handle = std::thread::spawn(move || stats.to_string());
+    assert_eq!(handle.join().unwrap(), "hits=2 misses=1 hit_ratio=66.7% window=0s");
+}
+
+#[test]
+fn test_remove_expired_behind_live_head() {
+    use std::rc::Rc;
+
+    let a = Rc::new("a");
+    let b = Rc::new("b");
+    let mut cache = TtlCache::new();
+    cache.insert(1, a.clone(), Duration::from_secs(60));
+    cache.insert(2, b.clone(), Duration::from_millis(1));
+    sleep(Duration::from_millis(10));
+    assert_eq!(Rc::strong_count(&b), 2);
+
+    cache.remove_expired();
+    assert_eq!(Rc::strong_count(&b), 1);
+    assert_eq!(Rc::strong_count(&a), 2);
+    assert_eq!(cache.iter().map(|(k, _)| *k).collect::<Vec<_>>(), [1]);
+}
+
+#[test]
+fn test_remove_expired_uses_index() {
+    use std::cell::Cell;
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{BuildHasher, Hasher};
+
+    thread_local!(static HASHES: Cell<usize> = const { Cell::new(0) });
+
+    #[derive(Clone, Default)]
+    struct CountingState;
+
+    struct CountingHasher(DefaultHasher);
+
+    impl BuildHasher for CountingState {
+        type Hasher = CountingHasher;
+
+        fn build_hasher(&self) -> CountingHasher {
+            HASHES.with(|hashes| hashes.set(hashes.get() + 1));
+            CountingHasher(DefaultHasher::new())
+        }
+    }
+
+    impl Hasher for CountingHasher {
+        fn finish(&self) -> u64 {
+            self.0.finish()
+        }
+
+        fn write(&mut self, bytes: &[u8]) {
+            self.0.write(bytes)
+        }
+    }
+
+    let mut cache = TtlCache::with_hasher(CountingState);
+    for i in 0..100_000 {
+        // The short lived entries are spread out between long lived ones
+        let ttl = if i % 10_000 == 5_000 {
+            Duration::from_millis(1)
+        } else {
+            Duration::from_secs(60)
+        };
+        cache.insert(i, i, ttl);
+    }
+    sleep(Duration::from_millis(10));
+
+    let before = HASHES.with(Cell::get);
+    cache.remove_expired();
+    // Going through the index needs no hashing at all, a scan would have rehashed every key
+    assert!(HASHES.with(Cell::get) - before < 100);
+    assert_eq!(cache.iter().count(), 99_990);
+    assert_eq!(cache.get(&5_000), None);
+    assert_eq!(cache.get(&5_001), Some(&5_001));
+}
+
+#[test]
+fn test_remove_expired_after_ttl_changes() {
+    use std::rc::Rc;
+
+    let value = Rc::new(0);
+    let mut cache = TtlCache::new();
+    cache.insert(1, value.clone(), Duration::from_secs(60));
+    cache.insert(2, value.clone(), Duration::from_millis(1));
+    cache.insert(3, value.clone(), Duration::from_secs(60));
+    // Prolonged past its first deadline, and cut short from a long one
+    if let ttl_cache::Entry::Occupied(mut entry) = cache.entry(2) {
+        entry.set_ttl(Duration::from_secs(60));
+    }
+    if let ttl_cache::Entry::Occupied(mut entry) = cache.entry(3) {
+        entry.set_ttl(Duration::from_millis(1));
+    }
+    sleep(Duration::from_millis(10));
+
+    cache.remove_expired();
+    assert_eq!(Rc::strong_count(&value), 3);
+    assert_eq!(cache.iter().map(|(k, _)| *k).collect::<Vec<_>>(), [1, 2]);
+    let _ = cache.remove(&2);
+    cache.insert(2, value.clone(), Duration::from_millis(1));
+    sleep(Duration::from_millis(10));
+    cache.remove_expired();
+    assert_eq!(Rc::strong_count(&value), 2);
+}
+
+#[test]
+fn test_timer_wheel() {
+    use std::rc::Rc;
+
+    let value = Rc::new(0);
+    let mut cache = TtlCache::with_timer_wheel(Duration::from_millis(5));
+    for i in 0..1000 {
+        let ttl = if i % 2 == 0 {
+            Duration::from_millis(1)
+        } else {
+            Duration::from_secs(60)
+        };
+        cache.insert(i, value.clone(), ttl);
+    }
+    sleep(Duration::from_millis(20));
+    assert_eq!(cache.get(&0), None);
+
+    cache.remove_expired();
+    assert_eq!(Rc::strong_count(&value), 501);
+    assert_eq!(cache.iter().count(), 500);
+
+    // Clones keep the wheel, and still purge through it
+    if let ttl_cache::Entry::Occupied(mut entry) = cache.entry(1) {
+        entry.set_ttl(Duration::from_millis(1));
+    }
+    let mut clone = cache.clone();
+    sleep(Duration::from_millis(20));
+    clone.remove_expired();
+    assert_eq!(clone.iter().count(), 499);
+    assert_eq!(Rc::strong_count(&value), 1000);
+}
+
+#[test]
+fn test_purge_policy() {
+    use std::rc::Rc;
+    use ttl_cache::PurgePolicy;
+
+    let value = Rc::new(0);
+    let fill = |cache: &mut TtlCache<i32, Rc<i32>>| {
+        cache.extend((0..100_000).map(|i| (i, value.clone(), Duration::from_millis(1))));
+        sleep(Duration::from_millis(10));
+    };
+
+    let mut cache = TtlCache::new();
+    assert_eq!(cache.purge_policy(), PurgePolicy::OnInsert);
+    cache.set_purge_policy(PurgePolicy::Manual);
+    fill(&mut cache);
+    // The insert does no purging at all
+    cache.insert(-1, value.clone(), Duration::from_secs(60));
+    assert_eq!(Rc::strong_count(&value), 100_002);
+    cache.remove_expired();
+    assert_eq!(Rc::strong_count(&value), 2);
+
+    cache.set_purge_policy(PurgePolicy::Budget(100));
+    fill(&mut cache);
+    cache.insert(-2, value.clone(), Duration::from_secs(60));
+    assert_eq!(Rc::strong_count(&value), 100_003 - 100);
+    cache.clear();
+
+    cache.set_purge_policy(PurgePolicy::EveryNInserts(3));
+    // Extending counts as a single insert
+    fill(&mut cache);
+    cache.insert(-1, value.clone(), Duration::from_secs(60));
+    assert_eq!(Rc::strong_count(&value), 100_002);
+    cache.insert(-2, value.clone(), Duration::from_secs(60));
+    assert_eq!(Rc::strong_count(&value), 3);
+}
+
+#[test]
+fn test_remove_expired_limit() {
+    use std::rc::Rc;
+
+    let value = Rc::new(0);
+    let mut cache = TtlCache::new();
+    cache.set_purge_policy(ttl_cache::PurgePolicy::Manual);
+    for i in 0..10_100 {
+        // A live entry between every hundred expiring ones
+        let ttl = if i % 101 == 0 {
+            Duration::from_secs(60)
+        } else {
+            Duration::from_millis(1)
+        };
+        cache.insert(i, value.clone(), ttl);
+    }
+    sleep(Duration::from_millis(10));
+    assert_eq!(cache.remove_expired_limit(0), 0);
+
+    let mut total = 0;
+    loop {
+        let removed = cache.remove_expired_limit(100);
+        assert!(removed <= 100);
+        total += removed;
+        assert_eq!(Rc::strong_count(&value), 10_101 - total);
+        if removed < 100 {
+            break;
+        }
+    }
+    assert_eq!(total, 10_000);
+    assert_eq!(cache.remove_expired_limit(100), 0);
+    assert_eq!(cache.iter().count(), 100);
+}
+
+#[test]
+fn test_expiration_boundaries() {
+    // Entries sharing a deadline are judged against one instant per operation, so an iteration
+    // sees either all or none of them, however close to the deadline it runs
+    let deadline = Instant::now() + Duration::from_millis(5);
+    let mut cache = TtlCache::from_entries_at((0..1000).map(|i| (i, i, Duration::ZERO)), deadline);
+    loop {
+        let live = cache.iter().count();
+        assert!(live == 0 || live == 1000, "saw {} live entries", live);
+        let live_mut = cache.iter_mut().count();
+        assert!(live_mut == 0 || live_mut == 1000, "saw {} live entries", live_mut);
+        if live_mut == 0 {
+            break;
+        }
+    }
+    assert!(Instant::now() + SLACK >= deadline);
+    assert_eq!(cache.get(&0), None);
+    assert_eq!(cache.remaining_ttl(&0), None);
+
+    // An entry past its deadline but not yet purged is not handed back by insert, whatever
+    // the insert's own purge did with it
+    let past = Instant::now() - Duration::from_secs(1);
+    let mut cache = TtlCache::from_entries_at(vec![(1, "a", Duration::from_millis(1))], past);
+    cache.set_purge_policy(ttl_cache::PurgePolicy::Manual);
+    assert_eq!(cache.insert(1, "b", Duration::from_secs(60)), None);
+    assert_eq!(cache.insert(1, "c", Duration::from_secs(60)), Some("b"));
+    let mut cache = TtlCache::from_entries_at(vec![(1, "a", Duration::from_millis(1))], past);
+    assert_eq!(cache.insert(1, "b", Duration::from_secs(60)), None);
+    assert_eq!(cache.remove(&1), Some("b"));
+
+    // A deadline still ahead keeps the entry, with the time left counted from the same instant
+    let mut cache = TtlCache::new();
+    cache.insert(1, "a", Duration::from_secs(3600));
+    let remaining = cache.remaining_ttl(&1).unwrap();
+    assert!(remaining <= Duration::from_secs(3600));
+    assert!(remaining > Duration::from_secs(3599));
+    assert_eq!(cache.iter().count(), 1);
+}
+
+#[test]
+fn test_huge_ttl() {
+    let huge = [Duration::MAX, Duration::from_secs(u64::MAX)];
+    for &ttl in &huge {
+        let mut cache = TtlCache::new();
+        cache.insert(1, "a", ttl);
+        assert_eq!(cache.get(&1), Some(&"a"));
+        assert_eq!(cache.remaining_ttl(&1), Some(Duration::MAX));
+        cache.reset_ttl(&1);
+        assert_eq!(cache.get_mut_prolong(&1), Some(&mut "a"));
+        cache.remove_expired();
+        assert_eq!(cache.iter().count(), 1);
+
+        cache.insert(2, "b", Duration::from_secs(30));
+        if let ttl_cache::Entry::Occupied(mut entry) = cache.entry(2) {
+            entry.set_ttl(ttl);
+            assert_eq!(entry.expiration(), None);
+            entry.reset_ttl();
+            assert_eq!(entry.remaining_ttl(), Duration::MAX);
+        }
+        if let ttl_cache::Entry::Vacant(entry) = cache.entry(3) {
+            entry.insert("c", ttl);
+        }
+        assert_eq!(cache.remaining_ttl(&3), Some(Duration::MAX));
+
+        // Jitter may stretch the TTL further still
+        let mut cache = TtlCache::new();
+        cache.set_ttl_jitter(0.5);
+        cache.insert(1, "a", ttl);
+        assert_eq!(cache.get(&1), Some(&"a"));
+
+        // A max TTL still caps it
+        let mut cache = TtlCache::new();
+        cache.set_max_ttl(Duration::from_secs(60));
+        cache.insert(1, "a", ttl);
+        assert!(cache.remaining_ttl(&1).unwrap() <= Duration::from_secs(60));
+    }
+}
+
+#[test]
+fn test_zero_ttl() {
+    use std::rc::Rc;
+
+    let value = Rc::new(0);
+    let mut cache = TtlCache::new();
+    cache.set_purge_policy(ttl_cache::PurgePolicy::Manual);
+
+    assert_eq!(cache.insert(1, value.clone(), Duration::ZERO), None);
+    assert_eq!(cache.get(&1), None);
+    assert_eq!(cache.get_mut(&1), None);
+    assert_eq!(cache.remaining_ttl(&1), None);
+    assert!(!cache.contains_key(&1));
+    assert_eq!(cache.iter().count(), 0);
+    assert!(matches!(cache.entry(1), ttl_cache::Entry::Vacant(_)));
+
+    // A zero-TTL predecessor is not handed back, and a live one is
+    assert_eq!(cache.insert(1, value.clone(), Duration::ZERO), None);
+    assert_eq!(cache.insert(1, value.clone(), Duration::from_secs(60)), None);
+    assert_eq!(cache.insert(1, value.clone(), Duration::ZERO), Some(value.clone()));
+    assert_eq!(cache.get(&1), None);
+    assert_eq!(cache.remove(&1), None);
+
+    // Until a purge, the expired entries are still held
+    for i in 0..10 {
+        cache.insert(i, value.clone(), Duration::ZERO);
+    }
+    assert_eq!(Rc::strong_count(&value), 11);
+    assert_eq!(cache.remove_expired_limit(usize::MAX), 10);
+    assert_eq!(Rc::strong_count(&value), 1);
+
+    // A zero max TTL makes every insert expire right away
+    let mut cache = TtlCache::new();
+    cache.set_max_ttl(Duration::ZERO);
+    cache.insert(1, "a", Duration::from_secs(60));
+    assert_eq!(cache.get(&1), None);
+
+    // A deadline of the very instant the cache is loaded at is already reached
+    let now = Instant::now();
+    let cache = TtlCache::from_entries_at(vec![(1, "a", Duration::ZERO)], now);
+    assert_eq!(cache.get(&1), None);
+}
+
+#[test]
+fn test_exclusive_expiry_bound() {
+    use std::rc::Rc;
+
+    // The clock may well not move between two calls, so every check below may land exactly on
+    // the deadline of a zero-TTL entry, and must treat it as expired
+    let value = Rc::new(0);
+    let mut cache = TtlCache::new();
+    cache.insert(1, value.clone(), Duration::ZERO);
+    assert_eq!(cache.get(&1), None);
+
+    // The insert's purge drops it
+    cache.insert(2, value.clone(), Duration::from_secs(60));
+    assert_eq!(Rc::strong_count(&value), 2);
+
+    // Overwriting a zero-TTL entry hands nothing back
+    cache.insert(3, value.clone(), Duration::ZERO);
+    assert_eq!(cache.insert(3, value.clone(), Duration::from_secs(60)), None);
+
+    // And purging removes it
+    cache.set_purge_policy(ttl_cache::PurgePolicy::Manual);
+    cache.insert(4, value.clone(), Duration::ZERO);
+    assert_eq!(Rc::strong_count(&value), 4);
+    cache.remove_expired();
+    assert_eq!(Rc::strong_count(&value), 3);
+    assert_eq!(cache.iter().count(), 2);
+}
+
+#[test]
+fn test_get_mut_removes_expired() {
+    use std::rc::Rc;
+
+    let value = Rc::new(0);
+    let mut cache = TtlCache::new();
+    cache.set_purge_policy(ttl_cache::PurgePolicy::Manual);
+    cache.insert(1, value.clone(), Duration::ZERO);
+    cache.insert(2, value.clone(), Duration::ZERO);
+    cache.insert(3, value.clone(), Duration::from_secs(60));
+    // A shared lookup leaves the expired entry alone
+    assert_eq!(cache.get(&1), None);
+    assert_eq!(Rc::strong_count(&value), 4);
+
+    for _ in 0..3 {
+        assert_eq!(cache.get_mut(&1), None);
+        assert_eq!(Rc::strong_count(&value), 3);
+    }
+    assert_eq!(cache.get_mut_prolong(&2), None);
+    assert_eq!(Rc::strong_count(&value), 2);
+    assert!(cache.get_mut(&3).is_some());
+    assert!(cache.get_mut_prolong(&3).is_some());
+    assert_eq!(Rc::strong_count(&value), 2);
+}
+
+#[test]
+fn test_clock() {
+    use std::rc::Rc;
+    use std::sync::Mutex;
+    use ttl_cache::Clock;
+
+    #[derive(Default)]
+    struct ManualClock(Mutex<Option<Instant>>);
+
+    impl ManualClock {
+        fn advance(&self, by: Duration) {
+            let mut now = self.0.lock().unwrap();
+            *now = Some(now.unwrap() + by);
+        }
+    }
+
+    impl Clock for ManualClock {
+        fn now(&self) -> Instant {
+            *self.0.lock().unwrap().get_or_insert_with(Instant::now)
+        }
+    }
+
+    let clock = Arc::new(ManualClock::default());
+    let value = Rc::new(0);
+    let mut cache = TtlCache::with_clock(clock.clone());
+    cache.set_purge_policy(ttl_cache::PurgePolicy::Manual);
+    cache.insert(1, value.clone(), Duration::from_secs(30));
+    cache.insert(2, value.clone(), Duration::from_secs(30));
+
+    clock.advance(Duration::from_secs(20));
+    assert!(cache.get(&1).is_some());
+    assert_eq!(cache.remaining_ttl(&1), Some(Duration::from_secs(10)));
+    cache.reset_ttl(&2);
+
+    // Exactly at the deadline the entry is gone
+    clock.advance(Duration::from_secs(10));
+    assert_eq!(cache.get(&1), None);
+    assert_eq!(cache.remaining_ttl(&2), Some(Duration::from_secs(20)));
+    assert_eq!(cache.iter().count(), 1);
+
+    // Clones and split off caches keep the clock
+    let mut clone = cache.clone();
+    let mut split = cache.split_off(|&k, _| k == 2);
+    cache.insert(3, value.clone(), Duration::from_secs(5));
+    clock.advance(Duration::from_secs(20));
+    assert!(clone.get(&2).is_none());
+    assert!(split.get(&2).is_none());
+    assert!(cache.get(&3).is_none());
+    clone.remove_expired();
+    split.remove_expired();
+    cache.remove_expired();
+    assert_eq!(Rc::strong_count(&value), 1);
+
+    #[cfg(feature = "stats")]
+    {
+        // The stats window opened when the cache was made
+        assert_eq!(cache.stats().elapsed(), Duration::from_secs(50));
+        let since = cache.stats_since();
+        clock.advance(Duration::from_secs(7));
+        cache.reset_stats_counter();
+        assert_eq!(cache.stats_since(), since + Duration::from_secs(57));
+        assert_eq!(cache.stats().elapsed(), Duration::ZERO);
+    }
+}
+
+#[test]
+fn test_mock_clock() {
+    use ttl_cache::test_util::MockClock;
+    use ttl_cache::Clock;
+
+    let started = Instant::now();
+    let clock = MockClock::new();
+    let mut cache = TtlCache::with_clock(clock.clone());
+    cache.insert(1, "a", Duration::from_secs(30));
+    clock.advance(Duration::from_secs(29));
+    assert_eq!(cache.get(&1), Some(&"a"));
+    clock.advance(Duration::from_secs(2));
+    assert_eq!(cache.get(&1), None);
+    assert!(started.elapsed() < Duration::from_secs(1));
+
+    let later = clock.now() + Duration::from_secs(60);
+    clock.set(later);
+    assert_eq!(clock.now(), later);
+    // Setting it to where it already stands is fine
+    clock.set(later);
+}
+
+#[test]
+#[should_panic(expected = "can't be set back")]
+fn test_mock_clock_set_back() {
+    use ttl_cache::test_util::MockClock;
+    use ttl_cache::Clock;
+
+    let clock = MockClock::new();
+    let now = clock.now();
+    clock.advance(Duration::from_secs(1));
+    clock.set(now);
+}
+
+#[test]
+fn test_coarse_clock() {
+    use ttl_cache::{Clock, CoarseClock};
+
+    let clock = CoarseClock::new();
+    let mut cache = TtlCache::with_clock(clock.clone());
+    let started = clock.now();
+    cache.insert(1, "a", Duration::from_millis(20));
+    cache.insert(2, "b", Duration::from_secs(60));
+
+    sleep(Duration::from_millis(30));
+    assert_eq!(clock.now(), started);
+    assert_eq!(cache.get(&1), Some(&"a"));
+    assert_eq!(cache.remaining_ttl(&1), Some(Duration::from_millis(20)));
+
+    clock.clone().tick();
+    assert!(clock.now() >= started + Duration::from_millis(30));
+    assert_eq!(cache.get(&1), None);
+    assert_eq!(cache.get(&2), Some(&"b"));
+    cache.remove_expired();
+    assert_eq!(cache.iter().count(), 1);
+}
+
+#[test]
+fn test_stale_grace() {
+    use std::rc::Rc;
+    use ttl_cache::test_util::MockClock;
+    use ttl_cache::Freshness;
+
+    let clock = MockClock::new();
+    let mut cache = TtlCache::with_clock(clock.clone());
+    assert_eq!(cache.stale_grace(), None);
+    cache.set_stale_grace(Duration::from_secs(10));
+    assert_eq!(cache.stale_grace(), Some(Duration::from_secs(10)));
+
+    let value = Rc::new(1);
+    cache.insert(1, value.clone(), Duration::from_secs(30));
+    cache.insert(2, Rc::new(2), Duration::from_secs(60));
+    assert_eq!(cache.get_fresh_or_stale(&1), Some((&value, Freshness::Fresh)));
+    assert_eq!(cache.get_fresh_or_stale(&3), None);
+
+    clock.advance(Duration::from_secs(30));
+    assert_eq!(cache.get(&1), None);
+    assert_eq!(cache.get_mut(&1), None);
+    cache.remove_expired();
+    cache.insert(3, Rc::new(3), Duration::from_secs(60));
+    assert_eq!(Rc::strong_count(&value), 2);
+    assert_eq!(cache.get_fresh_or_stale(&1), Some((&value, Freshness::Stale)));
+
+    clock.advance(Duration::from_secs(9));
+    assert_eq!(cache.get_fresh_or_stale(&1), Some((&value, Freshness::Stale)));
+    clock.advance(Duration::from_secs(1));
+    assert_eq!(cache.get_fresh_or_stale(&1), None);
+    assert_eq!(cache.get_mut(&1), None);
+    assert_eq!(Rc::strong_count(&value), 1);
+
+    // Without a window stale entries go straight away
+    cache.insert(1, value.clone(), Duration::from_secs(30));
+    cache.clear_stale_grace();
+    clock.advance(Duration::from_secs(30));
+    assert_eq!(cache.get_fresh_or_stale(&1), None);
+    cache.remove_expired();
+    assert_eq!(Rc::strong_count(&value), 1);
+}
+
+#[test]
+fn test_split_off_keeps_stale() {
+    use ttl_cache::test_util::MockClock;
+    use ttl_cache::Freshness;
+
+    let clock = MockClock::new();
+    let mut cache = TtlCache::with_clock(clock.clone());
+    cache.set_stale_grace(Duration::from_secs(10));
+    cache.insert(1, "a", Duration::from_secs(5));
+    cache.insert(2, "b", Duration::from_secs(20));
+    cache.insert(3, "c", Duration::from_secs(60));
+    clock.advance(Duration::from_secs(20));
+    let odd = cache.split_off(|k, _| k % 2 == 1);
+    assert_eq!(odd.stale_grace(), Some(Duration::from_secs(10)));
+    assert_eq!(odd.get_fresh_or_stale(&1), None);
+    assert_eq!(odd.get_fresh_or_stale(&3), Some((&"c", Freshness::Fresh)));
+    assert_eq!(cache.get_fresh_or_stale(&2), Some((&"b", Freshness::Stale)));
+}
+
+#[test]
+fn test_clone_keeps_stale() {
+    use ttl_cache::test_util::MockClock;
+    use ttl_cache::Freshness;
+
+    let clock = MockClock::new();
+    let mut cache = TtlCache::with_clock(clock.clone());
+    cache.set_stale_grace(Duration::from_secs(10));
+    cache.insert(1, "a", Duration::from_secs(5));
+    cache.insert(2, "b", Duration::from_secs(20));
+    clock.advance(Duration::from_secs(10));
+    let clone = cache.clone();
+    assert_eq!(clone.get_fresh_or_stale(&1), Some((&"a", Freshness::Stale)));
+    assert_eq!(clone.get_fresh_or_stale(&2), Some((&"b", Freshness::Fresh)));
+    clock.advance(Duration::from_secs(5));
+    assert_eq!(clone.get_fresh_or_stale(&1), None);
+}
+
+#[test]
+fn test_time_to_idle_touched_until_ttl() {
+    use ttl_cache::test_util::MockClock;
+
+    let clock = MockClock::new();
+    let mut cache = TtlCache::with_clock(clock.clone());
+    assert_eq!(cache.time_to_idle(), None);
+    cache.set_time_to_idle(Duration::from_secs(30));
+    assert_eq!(cache.time_to_idle(), Some(Duration::from_secs(30)));
+
+    cache.insert(1, "a", Duration::from_secs(100));
+    for _ in 0..4 {
+        clock.advance(Duration::from_secs(20));
+        assert_eq!(cache.get_mut(&1), Some(&mut "a"));
+    }
+    // Accesses never reach past the TTL deadline
+    assert_eq!(cache.remaining_ttl(&1), Some(Duration::from_secs(20)));
+    clock.advance(Duration::from_secs(19));
+    assert!(cache.touch(&1));
+    assert_eq!(cache.remaining_ttl(&1), Some(Duration::from_secs(1)));
+    clock.advance(Duration::from_secs(1));
+    assert!(!cache.touch(&1));
+    assert_eq!(cache.get(&1), None);
+}
+
+#[test]
+fn test_time_to_idle_untouched() {
+    use ttl_cache::test_util::MockClock;
+
+    let clock = MockClock::new();
+    let mut cache = TtlCache::with_clock(clock.clone());
+    cache.set_time_to_idle(Duration::from_secs(30));
+    cache.insert(1, "a", Duration::from_secs(100));
+    cache.insert(2, "b", Duration::from_secs(100));
+    cache.insert_permanent(3, "c");
+    assert_eq!(cache.remaining_ttl(&1), Some(Duration::from_secs(30)));
+
+    // Reads through &self don't count as accesses
+    clock.advance(Duration::from_secs(29));
+    assert_eq!(cache.get(&1), Some(&"a"));
+    assert!(cache.touch(&2));
+    clock.advance(Duration::from_secs(1));
+    assert_eq!(cache.get(&1), None);
+    assert_eq!(cache.get(&2), Some(&"b"));
+    assert_eq!(cache.get(&3), Some(&"c"));
+    cache.remove_expired();
+    assert_eq!(cache.iter().count(), 2);
+
+    // Resetting the TTL is an access too
+    clock.advance(Duration::from_secs(28));
+    cache.reset_ttl(&2);
+    clock.advance(Duration::from_secs(29));
+    assert_eq!(cache.get_mut_prolong(&2), Some(&mut "b"));
+    assert_eq!(cache.remaining_ttl(&2), Some(Duration::from_secs(30)));
+
+    cache.clear_time_to_idle();
+    cache.touch(&2);
+    assert_eq!(cache.remaining_ttl(&2), Some(Duration::from_secs(100)));
+    assert!(!cache.touch(&1));
+}
+
+#[test]
+fn test_sliding_and_fixed() {
+    use ttl_cache::test_util::MockClock;
+
+    let clock = MockClock::new();
+    let mut cache = TtlCache::with_clock(clock.clone());
+    assert_eq!(cache.insert_sliding(1, "sliding", Duration::from_secs(30)), None);
+    cache.insert(2, "fixed", Duration::from_secs(30));
+    cache.insert_sliding(3, "untouched", Duration::from_secs(30));
+
+    for _ in 0..3 {
+        clock.advance(Duration::from_secs(20));
+        assert_eq!(cache.get_mut(&1), Some(&mut "sliding"));
+        assert_eq!(cache.remaining_ttl(&1), Some(Duration::from_secs(30)));
+    }
+    assert_eq!(cache.get(&2), None);
+    assert_eq!(cache.get(&3), None);
+
+    // Plain reads don't slide, touching does
+    clock.advance(Duration::from_secs(20));
+    assert_eq!(cache.get(&1), Some(&"sliding"));
+    assert!(cache.touch(&1));
+    clock.advance(Duration::from_secs(20));
+    assert_eq!(cache.get(&1), Some(&"sliding"));
+    clock.advance(Duration::from_secs(10));
+    assert_eq!(cache.get(&1), None);
+
+    // Overwriting takes the policy of the new insert
+    assert_eq!(cache.insert_sliding(2, "sliding", Duration::from_secs(30)), None);
+    assert_eq!(cache.insert(2, "fixed", Duration::from_secs(30)), Some("sliding"));
+    clock.advance(Duration::from_secs(20));
+    cache.get_mut(&2);
+    assert_eq!(cache.remaining_ttl(&2), Some(Duration::from_secs(10)));
+}
+
+/// Values carry their own TTL in seconds; a read of an odd value halves what's left and an
+/// update keeps the old deadline
+struct ValueTtl;
+
+impl ttl_cache::ExpiryPolicy<&'static str, u64> for ValueTtl {
+    fn expiry_after_create(&self, _: &&'static str, value: &u64) -> Option<Duration> {
+        match *value {
+            0 => None,
+            secs => Some(Duration::from_secs(secs)),
+        }
+    }
+
+    fn expiry_after_read(
+        &self,
+        _: &&'static str,
+        value: &u64,
+        remaining: Option<Duration>,
+    ) -> Option<Duration> {
+        if value % 2 == 1 {
+            remaining.map(|remaining| remaining / 2)
+        } else {
+            remaining
+        }
+    }
+}
+
+#[test]
+fn test_expiry_policy() {
+    use ttl_cache::test_util::MockClock;
+
+    let clock = MockClock::new();
+    let mut cache = TtlCache::with_clock(clock.clone());
+    cache.set_expiry_policy(ValueTtl);
+    cache.insert_default("a", 30);
+    cache.insert_default("forever", 0);
+    cache.extend(vec![("b", 60), ("c", 61)]);
+    assert_eq!(cache.remaining_ttl("a"), Some(Duration::from_secs(30)));
+    assert_eq!(cache.remaining_ttl("b"), Some(Duration::from_secs(60)));
+    assert_eq!(cache.remaining_ttl("forever"), Some(Duration::MAX));
+
+    // Updates keep the deadline by default
+    clock.advance(Duration::from_secs(10));
+    assert_eq!(cache.insert_default("a", 100), Some(30));
+    assert_eq!(cache.remaining_ttl("a"), Some(Duration::from_secs(20)));
+
+    // Reads go through the policy
+    assert_eq!(cache.get_mut("c"), Some(&mut 61));
+    assert_eq!(cache.remaining_ttl("c"), Some(Duration::from_millis(25_500)));
+    assert!(cache.touch("b"));
+    assert_eq!(cache.remaining_ttl("b"), Some(Duration::from_secs(50)));
+
+    // Explicit TTLs bypass it
+    cache.insert("d", 5, Duration::from_secs(40));
+    assert_eq!(cache.remaining_ttl("d"), Some(Duration::from_secs(40)));
+
+    // And so does the entry API unless asked for the default
+    match cache.entry("e") {
+        ttl_cache::Entry::Vacant(entry) => {
+            entry.insert_default_ttl(20);
+        }
+        ttl_cache::Entry::Occupied(_) => unreachable!(),
+    }
+    assert_eq!(cache.remaining_ttl("e"), Some(Duration::from_secs(20)));
+    *cache.entry("f").or_insert_default_ttl(8) += 1;
+    assert_eq!(cache.remaining_ttl("f"), Some(Duration::from_secs(8)));
+    if let ttl_cache::Entry::Occupied(mut entry) = cache.entry("e") {
+        assert_eq!(entry.insert_default_ttl(2), 20);
+        assert_eq!(entry.remaining_ttl(), Duration::from_secs(20));
+    }
+
+    cache.clear_expiry_policy();
+    cache.set_default_ttl(Duration::from_secs(1));
+    cache.insert_default("g", 1000);
+    assert_eq!(cache.remaining_ttl("g"), Some(Duration::from_secs(1)));
+}
+
+#[test]
+fn test_expiry_policy_capped() {
+    let mut cache = TtlCache::with_expiry_policy(ValueTtl);
+    cache.set_max_ttl(Duration::from_secs(10));
+    cache.insert_default("a", 60);
+    assert!(cache.remaining_ttl("a").unwrap() <= Duration::from_secs(10));
+}
+
+#[test]
+fn test_cleaner() {
+    use std::sync::Mutex;
+    use ttl_cache::spawn_cleaner;
+    use ttl_cache::test_util::MockClock;
+
+    let clock = MockClock::new();
+    let mut cache = TtlCache::with_clock(clock.clone());
+    cache.set_purge_policy(ttl_cache::PurgePolicy::Manual);
+    let value = Arc::new(());
+    // More than the cleaner removes under one lock
+    for i in 0..1000 {
+        cache.insert(i, value.clone(), Duration::from_secs(30));
+    }
+    cache.insert(1000, value.clone(), Duration::from_secs(60));
+    let cache = Arc::new(Mutex::new(cache));
+    let _cleaner = spawn_cleaner(cache.clone(), Duration::from_millis(5));
+
+    clock.advance(Duration::from_secs(30));
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while Arc::strong_count(&value) > 2 {
+        assert!(Instant::now() < deadline, "the cleaner never purged");
+        sleep(Duration::from_millis(5));
+    }
+    assert_eq!(cache.lock().unwrap().iter().count(), 1);
+}
+
+#[test]
+fn test_cleaner_shutdown() {
+    use std::sync::Mutex;
+    use ttl_cache::spawn_cleaner;
+
+    let cache = Arc::new(Mutex::new(TtlCache::<u32, u32>::new()));
+    let started = Instant::now();
+    let cleaner = spawn_cleaner(cache.clone(), Duration::from_secs(60));
+    drop(cleaner);
+    assert!(started.elapsed() < Duration::from_secs(5));
+    // The thread let go of its clone
+    assert_eq!(Arc::strong_count(&cache), 1);
+
+    let cleaner = spawn_cleaner(cache.clone(), Duration::from_secs(60));
+    cleaner.shutdown();
+    assert_eq!(Arc::strong_count(&cache), 1);
+}
+
+#[test]
+fn test_capacity_evicts_expired_first() {
+    use ttl_cache::test_util::MockClock;
+
+    let clock = MockClock::new();
+    let mut cache = TtlCache::with_clock(clock.clone());
+    cache.set_purge_policy(ttl_cache::PurgePolicy::Manual);
+    cache.set_capacity(6);
+    assert_eq!(cache.capacity(), Some(6));
+    // Hour-long entries at the front, second-long ones interleaved behind them
+    for i in 0..6 {
+        let ttl = if i % 2 == 0 { 60 * 60 } else { 1 };
+        cache.insert(i, i, Duration::from_secs(ttl));
+    }
+    clock.advance(Duration::from_secs(2));
+
+    for i in 6..9 {
+        cache.insert(i, i, Duration::from_secs(60 * 60));
+    }
+    let mut keys: Vec<_> = cache.iter().map(|(k, _)| *k).collect();
+    keys.sort();
+    assert_eq!(keys, vec![0, 2, 4, 6, 7, 8]);
+
+    // With nothing expired the oldest live entry goes
+    cache.insert(9, 9, Duration::from_secs(60 * 60));
+    assert_eq!(cache.get(&0), None);
+    assert_eq!(cache.iter().count(), 6);
+    // Overwriting doesn't grow the cache, but does make the entry the newest
+    cache.insert(2, 20, Duration::from_secs(60 * 60));
+    cache.insert(10, 10, Duration::from_secs(60 * 60));
+    assert_eq!(cache.get(&2), Some(&20));
+    assert_eq!(cache.get(&4), None);
+
+    cache.set_capacity(2);
+    let mut keys: Vec<_> = cache.iter().map(|(k, _)| *k).collect();
+    keys.sort();
+    assert_eq!(keys, vec![2, 10]);
+    cache.clear_capacity();
+    cache.insert(11, 11, Duration::from_secs(60 * 60));
+    assert_eq!(cache.iter().count(), 3);
+}
+
+#[test]
+fn test_capacity_evicts_expired_first_with_timer_wheel() {
+    use ttl_cache::test_util::MockClock;
+
+    let clock = MockClock::new();
+    let mut cache = TtlCache::builder()
+        .clock(clock.clone())
+        .timer_wheel(Duration::from_secs(60))
+        .capacity(3)
+        .build()
+        .unwrap();
+    cache.insert(0, 0, Duration::from_secs(60 * 60));
+    cache.insert(1, 1, Duration::from_secs(1));
+    cache.insert(2, 2, Duration::from_secs(60 * 60));
+    // Still within the wheel's first tick, so the index holds the expired entry back
+    clock.advance(Duration::from_secs(2));
+    cache.insert(3, 3, Duration::from_secs(60 * 60));
+    let mut keys: Vec<_> = cache.iter().map(|(k, _)| *k).collect();
+    keys.sort();
+    assert_eq!(keys, vec![0, 2, 3]);
+}
+
+#[test]
+fn test_iterating_many_expired() {
+    let mut cache = TtlCache::new();
+    cache.set_purge_policy(ttl_cache::PurgePolicy::Manual);
+    cache.insert(0, 0, Duration::from_secs(60));
+    for i in 1..200_000 {
+        cache.insert(i, i, Duration::from_millis(1));
+    }
+    cache.insert(200_000, 200_000, Duration::from_secs(60));
+    sleep(Duration::from_millis(5));
+
+    assert_eq!(cache.iter().map(|(k, _)| *k).collect::<Vec<_>>(), vec![0, 200_000]);
+    assert_eq!(cache.iter().rev().count(), 2);
+    assert_eq!(cache.iter_mut().count(), 2);
+    assert_eq!(cache.iter_mut().rev().count(), 2);
+    assert_eq!(cache.iter_with_ttl().count(), 2);
+    assert_eq!(cache.iter_with_ttl().rev().count(), 2);
+    assert_eq!(cache.iter_mut_with_ttl().count(), 2);
+    assert_eq!(cache.iter_mut_with_ttl().rev().count(), 2);
+    let mut iter = cache.iter();
+    assert_eq!(iter.next().map(|(k, _)| *k), Some(0));
+    assert_eq!(iter.next_back().map(|(k, _)| *k), Some(200_000));
+    assert_eq!(iter.next(), None);
+    assert_eq!(cache.into_iter_with_ttl().rev().count(), 2);
+}
+
+#[test]
+fn test_insert_purge_is_bounded() {
+    use std::rc::Rc;
+
+    let value = Rc::new(0);
+    let mut cache = TtlCache::new();
+    cache.set_purge_policy(ttl_cache::PurgePolicy::Manual);
+    cache.extend((0..100_000).map(|i| (i, value.clone(), Duration::from_millis(1))));
+    cache.set_purge_policy(ttl_cache::PurgePolicy::OnInsert);
+    sleep(Duration::from_millis(10));
+
+    // The first insert after the mass expiration only takes on a slice of the backlog
+    cache.insert(-1, value.clone(), Duration::from_secs(60));
+    let removed = 100_002 - Rc::strong_count(&value);
+    assert!(removed > 0 && removed < 1_000);
+
+    // ... and the inserts after it finish the job
+    let mut inserts = 1;
+    while Rc::strong_count(&value) > inserts + 1 {
+        inserts += 1;
+        cache.insert(-(inserts as i32), value.clone(), Duration::from_secs(60));
+        assert!(inserts < 100_000);
+    }
+    assert_eq!(cache.iter().count(), inserts);
+}
+
+#[test]
+#[cfg(feature = "stats")]
+fn test_expired_count() {
+    use ttl_cache::test_util::MockClock;
+    use ttl_cache::{Entry, PurgePolicy};
+
+    let clock = MockClock::new();
+    let mut cache = TtlCache::with_clock(clock.clone());
+    cache.set_purge_policy(PurgePolicy::Manual);
+    let expire = |cache: &mut TtlCache<i32, i32>| {
+        for i in 0..8 {
+            cache.insert(i, i, Duration::from_secs(1));
+        }
+        clock.advance(Duration::from_secs(2));
+    };
+
+    expire(&mut cache);
+    // Overwritten while expired
+    assert_eq!(cache.insert(0, 0, Duration::from_secs(60)), None);
+    assert_eq!(cache.expired_count(), 1);
+    // Removed while expired
+    assert_eq!(cache.remove(&1), None);
+    assert_eq!(cache.expired_count(), 2);
+    // Lazily removed by a lookup
+    assert_eq!(cache.get_mut(&2), None);
+    assert_eq!(cache.get_mut_prolong(&3), None);
+    assert_eq!(cache.expired_count(), 4);
+    // Replaced or given up through the entry API, but not just looked at
+    if let Entry::Vacant(entry) = cache.entry(4) {
+        entry.insert(4, Duration::from_secs(60));
+    }
+    if let Entry::Vacant(entry) = cache.entry(5) {
+        entry.into_key();
+    }
+    let _ = cache.entry(6);
+    assert_eq!(cache.expired_count(), 6);
+    // Purged
+    cache.remove_expired();
+    assert_eq!(cache.expired_count(), 8);
+
+    expire(&mut cache);
+    let split = cache.split_off(|k, _| k % 2 == 0);
+    assert_eq!(split.iter().count() + cache.iter().count(), 0);
+    assert_eq!(cache.expired_count(), 16);
+
+    cache.set_purge_policy(PurgePolicy::OnInsert);
+    expire(&mut cache);
+    cache.insert(-1, -1, Duration::from_secs(60));
+    assert_eq!(cache.expired_count(), 24);
+    assert_eq!(cache.evicted_count(), 0);
+
+    cache.reset_stats_counter();
+    assert_eq!(cache.expired_count(), 0);
+}
+
+#[test]
+#[cfg(feature = "stats")]
+fn test_evicted_count() {
+    use ttl_cache::test_util::MockClock;
+
+    let clock = MockClock::new();
+    let mut cache = TtlCache::with_clock(clock.clone());
+    cache.set_capacity(2);
+    cache.insert(1, 1, Duration::from_secs(1));
+    cache.insert(2, 2, Duration::from_secs(60));
+    clock.advance(Duration::from_secs(2));
+    // The expired entry makes room first
+    cache.insert(3, 3, Duration::from_secs(60));
+    assert_eq!((cache.expired_count(), cache.evicted_count()), (1, 0));
+    cache.insert(4, 4, Duration::from_secs(60));
+    assert_eq!((cache.expired_count(), cache.evicted_count()), (1, 1));
+}
+
+#[test]
+#[cfg(feature = "stats")]
+fn test_insert_count() {
+    use ttl_cache::test_util::MockClock;
+    use ttl_cache::Entry;
+
+    let clock = MockClock::new();
+    let mut cache = TtlCache::with_clock(clock.clone());
+    let duration = Duration::from_secs(60);
+    cache.set_default_ttl(duration);
+
+    cache.insert(1, 1, duration);
+    cache.insert_permanent(2, 2);
+    cache.insert_default(3, 3);
+    cache.insert_sliding(4, 4, duration);
+    cache.extend(vec![(5, 5, duration), (1, 10, duration)]);
+    cache.extend(vec![(2, 20)]);
+    assert_eq!((cache.insert_count(), cache.replace_count()), (7, 2));
+
+    *cache.entry(6).or_insert(6, duration) += 1;
+    *cache.entry_ref(&7).or_insert(7, duration) += 1;
+    // Finding the value there already is not an insert
+    *cache.entry(6).or_insert(6, duration) += 1;
+    if let Entry::Occupied(mut entry) = cache.entry(6) {
+        entry.insert(60, duration);
+        entry.insert_keep_ttl(61);
+    }
+    assert_eq!((cache.insert_count(), cache.replace_count()), (11, 4));
+
+    // Filling in for an expired entry is not a replacement
+    cache.insert(8, 8, Duration::from_secs(1));
+    cache.insert(9, 9, Duration::from_secs(1));
+    clock.advance(Duration::from_secs(2));
+    cache.insert(8, 80, duration);
+    cache.entry(9).or_insert(90, duration);
+    assert_eq!((cache.insert_count(), cache.replace_count()), (15, 4));
+
+    cache.reset_stats_counter();
+    assert_eq!((cache.insert_count(), cache.replace_count()), (0, 0));
+}
+
+#[test]
+#[cfg(feature = "stats")]
+fn test_hit_ratio() {
+    let mut cache = TtlCache::new();
+    assert_eq!(cache.lookup_count(), 0);
+    assert_eq!(cache.hit_ratio(), 0.0);
+
+    cache.insert(1, 1, Duration::from_secs(60));
+    for _ in 0..4 {
+        let _ = cache.get(&1);
+    }
+    assert_eq!(cache.lookup_count(), 4);
+    assert_eq!(cache.hit_ratio(), 1.0);
+
+    cache.reset_stats_counter();
+    for _ in 0..3 {
+        let _ = cache.get(&2);
+    }
+    assert_eq!(cache.lookup_count(), 3);
+    assert_eq!(cache.hit_ratio(), 0.0);
+
+    let _ = cache.get(&1);
+    assert_eq!(cache.lookup_count(), 4);
+    assert_eq!(cache.hit_ratio(), 0.25);
+    assert_eq!(cache.stats().hit_ratio(), 0.25);
+}
+
+#[test]
+#[cfg(feature = "stats")]
+fn test_stats_snapshot() {
+    use ttl_cache::test_util::MockClock;
+
+    let clock = MockClock::new();
+    let mut cache = TtlCache::with_clock(clock.clone());
+    cache.set_capacity(3);
+    cache.insert(1, 1, Duration::from_secs(1));
+    for i in 2..5 {
+        cache.insert(i, i, Duration::from_secs(60));
+    }
+    cache.insert(2, 20, Duration::from_secs(60));
+    clock.advance(Duration::from_secs(2));
+    cache.insert(5, 5, Duration::from_secs(60));
+    let _ = cache.get(&2);
+    let _ = cache.get(&1);
+
+    let stats = cache.stats();
+    assert_eq!((stats.hits, stats.misses), (1, 1));
+    assert_eq!((stats.expired, stats.evicted), (0, 2));
+    assert_eq!((stats.inserts, stats.replaces), (6, 1));
+    assert_eq!(stats.len, 3);
+    assert_eq!(stats.elapsed(), Duration::from_secs(2));
+
+    #[cfg(feature = "serde")]
+    assert_eq!(
+        serde_json::to_value(stats).unwrap(),
+        serde_json::json!({
+            "hits": 1,
+            "misses": 1,
+            "expired_misses": 0,
+            "expired": 0,
+            "evicted": 2,
+            "inserts": 6,
+            "replaces": 1,
+            "len": 3,
+            "window": {"secs": 2, "nanos": 0},
+        })
+    );
+}
+
+#[test]
+#[cfg(feature = "stats")]
+fn test_toggle_stats() {
+    use ttl_cache::test_util::MockClock;
+    use ttl_cache::Clock;
+
+    let clock = MockClock::new();
+    let mut cache = TtlCache::with_clock(clock.clone());
+    assert!(cache.stats_enabled());
+    cache.insert(1, 1, Duration::from_secs(60));
+    let _ = cache.get(&1);
+    let _ = cache.get(&2);
+    assert_eq!((cache.hit_count(), cache.miss_count(), cache.insert_count()), (1, 1, 1));
+
+    cache.disable_stats();
+    assert!(!cache.stats_enabled());
+    assert_eq!((cache.hit_count(), cache.miss_count(), cache.insert_count()), (0, 0, 0));
+    cache.insert(2, 2, Duration::from_secs(1));
+    let _ = cache.get(&1);
+    let _ = cache.get_mut(&3);
+    let _ = cache.entry(4);
+    clock.advance(Duration::from_secs(2));
+    cache.remove_expired();
+    let stats = cache.stats();
+    assert_eq!((stats.hits, stats.misses, stats.expired, stats.inserts), (0, 0, 0, 0));
+    // Resetting leaves them disabled
+    cache.reset_stats_counter();
+    let _ = cache.get(&1);
+    assert_eq!(cache.hit_count(), 0);
+
+    clock.advance(Duration::from_secs(5));
+    cache.enable_stats();
+    assert_eq!(cache.stats_since(), clock.now());
+    let _ = cache.get(&1);
+    cache.insert(5, 5, Duration::from_secs(60));
+    assert_eq!((cache.hit_count(), cache.miss_count(), cache.insert_count()), (1, 0, 1));
+    // Enabling again changes nothing
+    cache.enable_stats();
+    assert_eq!(cache.hit_count(), 1);
+}
+
+#[test]
+#[cfg(feature = "access-counts")]
+fn test_access_counts() {
+    use ttl_cache::test_util::MockClock;
+    use ttl_cache::Entry;
+
+    let clock = MockClock::new();
+    let mut cache = TtlCache::with_clock(clock.clone());
+    let duration = Duration::from_secs(60);
+    for k in 0..5 {
+        cache.insert(k, k, duration);
+    }
+    for _ in 0..3 {
+        let _ = cache.get(&1);
+    }
+    let _ = cache.get_mut(&3);
+    let _ = cache.get_mut_prolong(&3);
+    let _ = cache.entry(3);
+    let _ = cache.get(&4);
+    let _ = cache.get(&5);
+    assert_eq!(cache.top_n_by_hits(3), [(&1, 3), (&3, 3), (&4, 1)]);
+    assert_eq!(cache.top_n_by_hits(10).len(), 5);
+    assert_eq!(cache.access_count(&0), Some(0));
+    assert_eq!(cache.access_count(&5), None);
+
+    // Replacing the value starts the count over, however it is done
+    cache.insert(1, 10, duration);
+    if let Entry::Occupied(mut entry) = cache.entry(3) {
+        entry.insert_keep_ttl(30);
+    }
+    assert_eq!(cache.access_count(&1), Some(0));
+    assert_eq!(cache.access_count(&3), Some(0));
+    assert_eq!(cache.top_n_by_hits(1), [(&4, 1)]);
+
+    // Counts stop with the rest of the stats
+    cache.disable_stats();
+    let _ = cache.get(&0);
+    assert_eq!(cache.access_count(&0), Some(0));
+    cache.enable_stats();
+
+    // ... and so does an expired entry's
+    cache.insert(6, 6, Duration::from_secs(1));
+    let _ = cache.get(&6);
+    clock.advance(Duration::from_secs(2));
+    assert_eq!(cache.access_count(&6), None);
+    cache.insert(6, 6, duration);
+    assert_eq!(cache.access_count(&6), Some(0));
+}
+
+#[test]
+#[cfg(feature = "stats")]
+fn test_miss_kinds() {
+    use ttl_cache::test_util::MockClock;
+    use ttl_cache::PurgePolicy;
+
+    let clock = MockClock::new();
+    let mut cache = TtlCache::with_clock(clock.clone());
+    cache.set_purge_policy(PurgePolicy::Manual);
+    for k in 0..8 {
+        cache.insert(k, k, Duration::from_secs(1));
+    }
+    clock.advance(Duration::from_secs(2));
+    let misses = |cache: &TtlCache<i32, i32>| {
+        assert_eq!(
+            cache.miss_count(),
+            cache.miss_absent_count() + cache.miss_expired_count()
+        );
+        (cache.miss_absent_count(), cache.miss_expired_count())
+    };
+
+    let _ = cache.get(&0);
+    let _ = cache.get(&10);
+    assert_eq!(misses(&cache), (1, 1));
+    assert!(!cache.contains_key(&1));
+    assert!(!cache.contains_key(&11));
+    assert_eq!(misses(&cache), (2, 2));
+    let _ = cache.get_fresh_or_stale(&2);
+    let _ = cache.get_fresh_or_stale(&12);
+    assert_eq!(misses(&cache), (3, 3));
+    let _ = cache.get_mut(&3);
+    let _ = cache.get_mut(&13);
+    assert_eq!(misses(&cache), (4, 4));
+    let _ = cache.get_mut_prolong(&4);
+    let _ = cache.get_mut_prolong(&14);
+    assert_eq!(misses(&cache), (5, 5));
+    let _ = cache.entry(5);
+    let _ = cache.entry(15);
+    assert_eq!(misses(&cache), (6, 6));
+    let _ = cache.entry_ref(&6);
+    let _ = cache.entry_ref(&16);
+    assert_eq!(misses(&cache), (7, 7));
+    // get_mut dropped the entry, so the key is absent now
+    let _ = cache.get(&3);
+    assert_eq!(misses(&cache), (8, 7));
+
+    #[cfg(feature = "raw")]
+    {
+        let _ = cache.raw_entry().from_key(&7);
+        let _ = cache.raw_entry().from_key(&17);
+        assert_eq!(misses(&cache), (9, 8));
+        let _ = cache.raw_entry_mut().from_key(&7);
+        let _ = cache.raw_entry_mut().from_key(&17);
+        assert_eq!(misses(&cache), (10, 9));
+    }
+
+    assert_eq!(cache.stats().expired_misses, cache.miss_expired_count());
+    cache.reset_stats_counter();
+    assert_eq!(misses(&cache), (0, 0));
+}
+
+#[test]
+#[cfg(feature = "stats")]
+fn test_windowed_stats() {
+    use ttl_cache::test_util::MockClock;
+
+    let clock = MockClock::new();
+    let mut cache = TtlCache::with_clock(clock.clone());
+    let second = Duration::from_secs(1);
+    cache.set_stats_window(second, 4);
+    assert_eq!(cache.stats_window(), Some((second, 4)));
+    cache.insert(1, 1, Duration::from_secs(3600));
+    let hits = |cache: &TtlCache<i32, i32>, window: u64| {
+        let stats = cache.windowed_stats(Duration::from_secs(window));
+        (stats.hits, stats.misses)
+    };
+
+    // One hit in each of the first three seconds, and a miss in the third
+    let _ = cache.get(&1);
+    for _ in 0..2 {
+        clock.advance(second);
+        let _ = cache.get(&1);
+    }
+    let _ = cache.get(&2);
+    assert_eq!(hits(&cache, 1), (1, 1));
+    assert_eq!(hits(&cache, 2), (2, 1));
+    assert_eq!(hits(&cache, 3), (3, 1));
+    // Capped at the whole ring, which hasn't been around that long yet
+    assert_eq!(hits(&cache, 60), (3, 1));
+    assert_eq!(cache.windowed_stats(Duration::from_secs(60)).since, cache.stats_since());
+    // Partway into a bucket still counts that bucket
+    clock.advance(Duration::from_millis(500));
+    assert_eq!(hits(&cache, 0), (1, 1));
+
+    // Crossing into a bucket drops the oldest one from a full ring
+    clock.advance(Duration::from_millis(1500));
+    assert_eq!(hits(&cache, 4), (2, 1));
+    let _ = cache.get(&1);
+    assert_eq!(hits(&cache, 4), (3, 1));
+    clock.advance(second);
+    assert_eq!(hits(&cache, 4), (2, 1));
+    assert_eq!(hits(&cache, 1), (0, 0));
+
+    // After an idle gap longer than the ring nothing is left, even in buckets not reused yet
+    clock.advance(Duration::from_secs(10));
+    assert_eq!(hits(&cache, 4), (0, 0));
+    let _ = cache.get(&1);
+    assert_eq!(hits(&cache, 4), (1, 0));
+    let window = cache.windowed_stats(Duration::from_secs(4));
+    assert_eq!(window.captured_at - window.since, Duration::from_secs(3));
+    // The totals keep counting all along
+    assert_eq!((cache.hit_count(), cache.miss_count()), (5, 1));
+
+    cache.reset_stats_counter();
+    assert_eq!(hits(&cache, 4), (0, 0));
+    cache.clear_stats_window();
+    assert_eq!(cache.stats_window(), None);
+}
+
+#[test]
+#[cfg(feature = "stats")]
+fn test_stats_rates() {
+    use ttl_cache::test_util::MockClock;
+
+    let clock = MockClock::new();
+    let mut cache = TtlCache::with_clock(clock.clone());
+    assert_eq!(cache.stats_elapsed(), Duration::ZERO);
+    assert_eq!((cache.lookups_per_second(), cache.inserts_per_second()), (0.0, 0.0));
+
+    for i in 0..10 {
+        cache.insert(i, i, Duration::from_secs(60));
+        let _ = cache.get(&i);
+        let _ = cache.get(&(i + 100));
+    }
+    // No time has passed yet
+    assert_eq!((cache.lookups_per_second(), cache.inserts_per_second()), (0.0, 0.0));
+    clock.advance(Duration::from_secs(4));
+    assert_eq!(cache.stats_elapsed(), Duration::from_secs(4));
+    assert_eq!((cache.lookups_per_second(), cache.inserts_per_second()), (5.0, 2.5));
+    clock.advance(Duration::from_millis(1000));
+    assert_eq!((cache.lookups_per_second(), cache.inserts_per_second()), (4.0, 2.0));
+
+    // Resetting starts the clock over
+    cache.reset_stats_counter();
+    clock.advance(Duration::from_secs(2));
+    let _ = cache.get(&1);
+    assert_eq!(cache.stats_elapsed(), Duration::from_secs(2));
+    assert_eq!((cache.lookups_per_second(), cache.inserts_per_second()), (0.5, 0.0));
+}
+
+#[test]
+#[cfg(feature = "stats")]
+fn test_age_at_hit() {
+    use ttl_cache::test_util::MockClock;
+    use ttl_cache::Entry;
+
+    let clock = MockClock::new();
+    let mut cache = TtlCache::with_clock(clock.clone());
+    let second = Duration::from_secs(1);
+    cache.insert(1, 1, Duration::from_secs(60));
+    cache.insert_permanent(2, 2);
+    let _ = cache.get(&3);
+    assert_eq!((cache.avg_age_at_hit(), cache.max_age_at_hit()), (None, None));
+
+    // A fresh hit is zero seconds old
+    let _ = cache.get(&1);
+    assert_eq!(cache.max_age_at_hit(), Some(Duration::ZERO));
+    clock.advance(second * 4);
+    let _ = cache.get(&2);
+    clock.advance(second * 4);
+    let _ = cache.get_mut(&1);
+    assert_eq!(cache.avg_age_at_hit(), Some(second * 4));
+    assert_eq!(cache.max_age_at_hit(), Some(second * 8));
+
+    // Resetting the TTL leaves the age alone, a new value starts over
+    cache.reset_ttl(&1);
+    let _ = cache.get_mut_prolong(&1);
+    cache.insert(2, 20, Duration::from_secs(60));
+    if let Entry::Occupied(entry) = cache.entry(2) {
+        assert_eq!(*entry.get(), 20);
+    }
+    assert_eq!(cache.avg_age_at_hit(), Some(second * 4));
+    assert_eq!(cache.max_age_at_hit(), Some(second * 8));
+
+    clock.advance(second);
+    if let Entry::Occupied(mut entry) = cache.entry(1) {
+        entry.insert_keep_ttl(10);
+    }
+    cache.reset_stats_counter();
+    assert_eq!((cache.avg_age_at_hit(), cache.max_age_at_hit()), (None, None));
+    clock.advance(second);
+    let _ = cache.get(&1);
+    assert_eq!(cache.max_age_at_hit(), Some(second));
+}
+
+#[test]
+#[cfg(feature = "stats")]
+fn test_ttl_histogram() {
+    use ttl_cache::test_util::MockClock;
+
+    let clock = MockClock::new();
+    let mut cache = TtlCache::with_clock(clock.clone());
+    let counts = |cache: &TtlCache<i32, i32, _>| -> Vec<u64> {
+        cache.ttl_histogram().iter().map(|&(_, n)| n).collect()
+    };
+    assert_eq!(counts(&cache), vec![0; 7]);
+
+    cache.insert(1, 1, Duration::ZERO);
+    cache.insert(2, 2, Duration::from_millis(999));
+    cache.insert(3, 3, Duration::from_secs(1));
+    cache.insert(4, 4, Duration::from_secs(10));
+    cache.insert(5, 5, Duration::from_secs(24 * 60 * 60));
+    cache.insert_permanent(6, 6);
+    assert_eq!(counts(&cache), vec![2, 1, 1, 0, 0, 0, 2]);
+
+    // Inserts through the entry API count too, and `insert_keep_ttl` counts what was left
+    cache.insert(7, 7, Duration::from_secs(30));
+    clock.advance(Duration::from_secs(25));
+    match cache.entry(7) {
+        ttl_cache::Entry::Occupied(mut entry) => {
+            entry.insert_keep_ttl(8);
+        }
+        ttl_cache::Entry::Vacant(_) => unreachable!(),
+    }
+    match cache.entry(8) {
+        ttl_cache::Entry::Occupied(_) => unreachable!(),
+        ttl_cache::Entry::Vacant(entry) => {
+            entry.insert(8, Duration::from_secs(120));
+        }
+    }
+    assert_eq!(counts(&cache), vec![2, 2, 2, 1, 0, 0, 2]);
+
+    cache.reset_stats_counter();
+    assert_eq!(counts(&cache), vec![0; 7]);
+    cache.disable_stats();
+    cache.insert(9, 9, Duration::from_secs(5));
+    assert_eq!(counts(&cache), vec![0; 7]);
+}
+
+#[test]
+#[cfg(all(feature = "stats", feature = "serde"))]
+fn test_stats_serde() {
+    use ttl_cache::test_util::MockClock;
+    use ttl_cache::CacheStats;
+
+    let clock = MockClock::new();
+    let mut cache = TtlCache::with_clock(clock.clone());
+    cache.set_capacity(1);
+    cache.insert(1, 1, Duration::from_secs(1));
+    cache.insert(1, 2, Duration::from_secs(1));
+    cache.insert(2, 2, Duration::from_millis(500));
+    let _ = cache.get(&2);
+    let _ = cache.get(&3);
+    clock.advance(Duration::from_millis(1500));
+    let _ = cache.get(&2);
+    cache.remove_expired();
+
+    let json = serde_json::to_string(&cache.stats()).unwrap();
+    assert_eq!(
+        json,
+        r#"{"hits":1,"misses":2,"expired_misses":1,"expired":1,"evicted":1,"inserts":3,"replaces":1,"len":0,"window":{"secs":1,"nanos":500000000}}"#
+    );
+
+    let stats: CacheStats = serde_json::from_str(&json).unwrap();
+    assert_eq!((stats.hits, stats.misses, stats.expired_misses), (1, 2, 1));
+    assert_eq!((stats.expired, stats.evicted), (1, 1));
+    assert_eq!((stats.inserts, stats.replaces, stats.len), (3, 1, 0));
+    assert_eq!(stats.elapsed(), Duration::from_millis(1500));
+    assert_eq!(serde_json::to_string(&stats).unwrap(), json);
+
+    // Missing counts load as zero and unknown fields are skipped
+    let stats: CacheStats = serde_json::from_str(r#"{"hits":4,"future":[1,2]}"#).unwrap();
+    assert_eq!((stats.hits, stats.misses), (4, 0));
+    assert_eq!(stats.elapsed(), Duration::ZERO);
+    assert!(serde_json::from_str::<CacheStats>(r#"{"hits":"four"}"#).is_err());
+
+    // Formats without field names load the fields in order
+    let stats: CacheStats =
+        serde_json::from_str(r#"[1,2,1,1,1,3,1,0,{"secs":2,"nanos":0}]"#).unwrap();
+    assert_eq!((stats.hits, stats.inserts), (1, 3));
+    assert_eq!(stats.elapsed(), Duration::from_secs(2));
+}
+
+#[test]
+#[cfg(feature = "metrics")]
+fn test_metrics() {
+    use metrics_util::debugging::{DebugValue, DebuggingRecorder, Snapshotter};
+    use std::collections::HashMap;
+    use ttl_cache::test_util::MockClock;
+
+    fn emitted(snapshotter: &Snapshotter) -> HashMap<String, f64> {
+        snapshotter
+            .snapshot()
+            .into_vec()
+            .into_iter()
+            .map(|(key, _, _, value)| {
+                let (_, key) = key.into_parts();
+                let labels: Vec<_> = key.labels().map(|l| (l.key(), l.value())).collect();
+                assert_eq!(labels, [("region", "eu")]);
+                let value = match value {
+                    DebugValue::Counter(n) => n as f64,
+                    DebugValue::Gauge(n) => n.into_inner(),
+                    DebugValue::Histogram(_) => unreachable!(),
+                };
+                (key.name().to_owned(), value)
+            })
+            .collect()
+    }
+
+    let recorder = DebuggingRecorder::new();
+    let snapshotter = recorder.snapshotter();
+    let clock = MockClock::new();
+    let mut cache = TtlCache::with_clock(clock.clone());
+    cache.insert(0, 0, Duration::from_secs(60));
+    metrics::with_local_recorder(&recorder, || {
+        cache.register_metrics("cache", &[("region", "eu")]);
+    });
+    cache.set_capacity(3);
+    for i in 1..4 {
+        cache.insert(i, i, Duration::from_secs(i));
+    }
+    cache.insert(3, 30, Duration::from_secs(3));
+    let _ = cache.get(&3);
+    let _ = cache.get(&9);
+    clock.advance(Duration::from_secs(2));
+    let _ = cache.get(&1);
+    cache.remove_expired();
+    assert_eq!(cache.remove(&3), Some(30));
+
+    let metrics = emitted(&snapshotter);
+    assert_eq!(metrics["cache_hits"], 1.0);
+    assert_eq!(metrics["cache_misses"], 2.0);
+    assert_eq!(metrics["cache_inserts"], 4.0);
+    assert_eq!(metrics["cache_evicted"], 1.0);
+    assert_eq!(metrics["cache_expired"], 2.0);
+    assert_eq!(metrics["cache_len"], 0.0);
+
+    // Each snapshot takes what was emitted since the last one, the gauge's moves included.  The
+    // gauge follows the entry API, splitting and clearing too.
+    if let ttl_cache::Entry::Vacant(entry) = cache.entry(4) {
+        entry.insert(4, Duration::from_secs(60));
+    }
+    cache.insert(5, 5, Duration::from_secs(60));
+    let _ = cache.split_off(|&k, _| k == 5);
+    assert_eq!(emitted(&snapshotter)["cache_len"], 1.0);
+    cache.clear();
+    assert_eq!(emitted(&snapshotter)["cache_len"], -1.0);
+
+    // Nothing is emitted by a clone, nor once cleared
+    let mut clone = cache.clone();
+    clone.insert(6, 6, Duration::from_secs(60));
+    let _ = clone.get(&6);
+    cache.clear_metrics();
+    cache.insert(6, 6, Duration::from_secs(60));
+    let _ = cache.get(&6);
+    let metrics = emitted(&snapshotter);
+    assert_eq!((metrics["cache_inserts"], metrics["cache_hits"]), (0.0, 0.0));
+}
+
+#[test]
+#[cfg(feature = "prometheus")]
+fn test_prometheus() {
+    use ttl_cache::test_util::MockClock;
+
+    let clock = MockClock::new();
+    let mut cache = TtlCache::with_clock(clock.clone());
+    cache.set_capacity(2);
+    cache.insert(1, 1, Duration::from_secs(1));
+    cache.insert(2, 2, Duration::from_secs(60));
+    cache.insert(3, 3, Duration::from_secs(60));
+    cache.insert(4, 4, Duration::from_secs(1));
+    let _ = cache.get(&3);
+    let _ = cache.get(&1);
+    clock.advance(Duration::from_secs(2));
+    cache.remove_expired();
+
+    assert_eq!(
+        cache.stats().to_prometheus("myapp_cache").lines().collect::<Vec<_>>(),
+        [
+            "# HELP myapp_cache_hits_total Lookups that found a live value.",
+            "# TYPE myapp_cache_hits_total counter",
+            "myapp_cache_hits_total 1",
+            "# HELP myapp_cache_misses_total Lookups that found no live value.",
+            "# TYPE myapp_cache_misses_total counter",
+            "myapp_cache_misses_total 1",
+            "# HELP myapp_cache_expired_total Entries dropped because they had expired.",
+            "# TYPE myapp_cache_expired_total counter",
+            "myapp_cache_expired_total 1",
+            "# HELP myapp_cache_evicted_total Live entries evicted over capacity.",
+            "# TYPE myapp_cache_evicted_total counter",
+            "myapp_cache_evicted_total 2",
+            "# HELP myapp_cache_inserts_total Values inserted.",
+            "# TYPE myapp_cache_inserts_total counter",
+            "myapp_cache_inserts_total 4",
+            "# HELP myapp_cache_len Entries held, counting expired ones not purged yet.",
+            "# TYPE myapp_cache_len gauge",
+            "myapp_cache_len 1",
+        ]
+    );
+
+    cache.set_name("users");
+    let text = cache.stats().to_prometheus("");
+    assert!(text.starts_with("# HELP hits_total Lookups that found a live value.\n"));
+    assert!(text.contains("\nhits_total{cache=\"users\"} 1\n"));
+    assert!(text.ends_with("\nlen{cache=\"users\"} 1\n"));
+
+    cache.set_name("a \"quoted\\ name\"");
+    let text = cache.stats().to_prometheus("");
+    assert!(text.contains("\nlen{cache=\"a \\\"quoted\\\\ name\\\"\"} 1\n"));
+}
+
+#[test]
+#[cfg(feature = "tracing")]
+#[tracing_test::traced_test]
+fn test_tracing() {
+    use ttl_cache::test_util::MockClock;
+
+    let clock = MockClock::new();
+    let mut cache = TtlCache::with_clock(clock.clone());
+    cache.trace_keys();
+    cache.set_capacity(2);
+    cache.insert("a", 1, Duration::from_secs(1));
+    cache.insert_permanent("b", 2);
+    cache.insert("c", 3, Duration::from_secs(60));
+    clock.advance(Duration::from_millis(1500));
+    assert_eq!(cache.remove("c"), Some(3));
+    cache.insert("d", 4, Duration::from_secs(1));
+    clock.advance(Duration::from_secs(3));
+    cache.remove_expired();
+    cache.clear();
+
+    logs_assert(|lines: &[&str]| {
+        let events: Vec<_> = lines
+            .iter()
+            .filter(|line| line.contains("ttl_cache:"))
+            .map(|line| line.split("ttl_cache: ").nth(1).unwrap())
+            .collect();
+        let expected = [
+            "inserted key=\"a\" ttl=Some(1s)",
+            "inserted key=\"b\" ttl=None",
+            "inserted key=\"c\" ttl=Some(60s)",
+            "evicted key=\"a\"",
+            "removed key=\"c\"",
+            "inserted key=\"d\" ttl=Some(1s)",
+            "expired key=\"d\" overdue=2s",
+        ];
+        assert_eq!(events[..expected.len()], expected);
+        assert!(events[expected.len()].starts_with("purged count=1 took="));
+        assert_eq!(events[expected.len() + 1..], ["cleared count=1"]);
+        Ok(())
+    });
+}
+
+#[test]
+#[cfg(feature = "tracing")]
+#[tracing_test::traced_test]
+fn test_tracing_hashes_keys() {
+    // Without `trace_keys`, keys needn't implement Debug and show up as their hash
+    #[derive(Hash, PartialEq, Eq)]
+    struct Key;
+
+    let mut cache = TtlCache::new();
+    cache.insert(Key, 1, Duration::from_secs(60));
+    assert!(logs_contain("inserted key=#"));
+}
+
+#[test]
+#[cfg(feature = "stats")]
+fn test_peak_len() {
+    use ttl_cache::test_util::MockClock;
+
+    let clock = MockClock::new();
+    let mut cache = TtlCache::with_clock(clock.clone());
+    assert_eq!(cache.peak_len(), 0);
+    for i in 0..100 {
+        let ttl = if i < 10 { 60 } else { 1 };
+        cache.insert(i, i, Duration::from_secs(ttl));
+    }
+    assert_eq!(cache.peak_len(), 100);
+
+    clock.advance(Duration::from_secs(2));
+    for i in 100..140 {
+        cache.insert(i, i, Duration::from_secs(60));
+    }
+    assert_eq!(cache.stats().len, 50);
+    assert_eq!(cache.peak_len(), 100);
+
+    // Replacing doesn't add to the count, and the entry API is caught on the next read
+    cache.insert(100, 0, Duration::from_secs(60));
+    cache.reset_stats_counter();
+    assert_eq!(cache.peak_len(), 50);
+    cache.entry(140).or_insert(140, Duration::from_secs(60));
+    assert_eq!(cache.peak_len(), 51);
+
+    // A peak between reads is kept through removals
+    cache.insert(141, 141, Duration::from_secs(60));
+    cache.clear();
+    assert_eq!(cache.peak_len(), 52);
+}
+
+#[test]
+#[cfg(feature = "stats")]
+fn test_reset_returns_snapshot() {
+    use ttl_cache::test_util::MockClock;
+
+    let clock = MockClock::new();
+    let mut cache = TtlCache::with_clock(clock.clone());
+    cache.set_name("exported");
+    let (mut hits, mut misses, mut inserts) = (0, 0, 0);
+    let mut last_reset = cache.stats().since;
+    for round in 0..5u64 {
+        for i in 0..round * 3 {
+            cache.insert(i, i, Duration::from_secs(60));
+            let _ = cache.get(&i);
+            let _ = cache.get(&(i + 1000));
+        }
+        clock.advance(Duration::from_secs(round + 1));
+        let stats = cache.reset_stats_counter();
+        assert_eq!(stats.name, Some("exported"));
+        assert_eq!(stats.since, last_reset);
+        assert_eq!(stats.elapsed(), Duration::from_secs(round + 1));
+        assert_eq!(cache.stats().since, stats.captured_at);
+        last_reset = stats.captured_at;
+        hits += stats.hits;
+        misses += stats.misses;
+        inserts += stats.inserts;
+    }
+    // Every lookup shows up in exactly one snapshot
+    assert_eq!((hits, misses, inserts), (30, 30, 30));
+    assert_eq!(cache.lookup_count(), 0);
+}
+
+#[test]
+#[cfg(feature = "stats")]
+fn test_stats_counters_are_u64() {
+    use ttl_cache::test_util::MockClock;
+
+    // Hit ages are summed in microseconds, which passes u32::MAX after 72 minutes
+    let clock = MockClock::new();
+    let mut cache = TtlCache::with_clock(clock.clone());
+    cache.insert_permanent(1, 1);
+    clock.advance(Duration::from_secs(2 * 60 * 60));
+    for _ in 0..3 {
+        let _ = cache.get(&1);
+    }
+    let total: u64 = 3 * 2 * 60 * 60 * 1_000_000;
+    assert!(total > u64::from(u32::MAX));
+    assert_eq!(cache.avg_age_at_hit(), Some(Duration::from_secs(2 * 60 * 60)));
+    let hits: u64 = cache.hit_count();
+    assert_eq!(hits, 3);
+
+    #[cfg(feature = "serde")]
+    {
+        let big = u64::from(u32::MAX) + 10;
+        let json = format!(
+            r#"{{"hits":{},"misses":{},"window":{{"secs":1,"nanos":0}}}}"#,
+            big,
+            big - 20
+        );
+        let stats: ttl_cache::CacheStats = serde_json::from_str(&json).unwrap();
+        assert_eq!((stats.hits, stats.misses), (big, big - 20));
+        assert!((stats.hit_ratio() - 0.5).abs() < 1e-6);
+        assert!(serde_json::to_string(&stats).unwrap().contains(&format!("\"hits\":{}", big)));
+    }
+}
+
+#[test]
+#[cfg(feature = "stats")]
+fn test_snapshot_rates() {
+    use ttl_cache::test_util::MockClock;
+
+    let clock = MockClock::new();
+    let mut cache = TtlCache::with_clock(clock.clone());
+    let stats = cache.stats();
+    assert_eq!(stats.elapsed(), Duration::ZERO);
+    assert_eq!((stats.hits_per_second(), stats.misses_per_second()), (0.0, 0.0));
+
+    for i in 0..20 {
+        cache.insert(i, i, Duration::from_secs(60));
+    }
+    for i in 0..40 {
+        let _ = cache.get(&i);
+    }
+    clock.advance(Duration::from_secs(8));
+    let stats = cache.stats();
+    assert_eq!(stats.captured_at, stats.since + Duration::from_secs(8));
+
+    // The rates are fixed by the snapshot, however much later they are read
+    clock.advance(Duration::from_secs(100));
+    let _ = cache.get(&0);
+    assert_eq!(stats.elapsed(), Duration::from_secs(8));
+    assert_eq!(stats.hits_per_second(), 2.5);
+    assert_eq!(stats.misses_per_second(), 2.5);
+    assert_eq!(stats.lookups_per_second(), 5.0);
+    assert_eq!(stats.inserts_per_second(), 2.5);
+
+    let windowed = {
+        cache.set_stats_window(Duration::from_secs(1), 4);
+        cache.insert(100, 100, Duration::from_secs(60));
+        let _ = cache.get(&100);
+        clock.advance(Duration::from_millis(500));
+        cache.windowed_stats(Duration::from_secs(1))
+    };
+    assert_eq!(windowed.elapsed(), Duration::from_millis(500));
+    assert_eq!(windowed.hits_per_second(), 2.0);
+}
+
+#[test]
+fn test_sharded() {
+    use ttl_cache::sharded::ShardedTtlCache;
+    use ttl_cache::test_util::MockClock;
+
+    let clock = MockClock::new();
+    let cache = ShardedTtlCache::with_shards_and_clock(4, clock.clone());
+    assert_eq!(cache.shards(), 4);
+    assert!(cache.is_empty());
+    for i in 0..100 {
+        let ttl = if i % 2 == 0 { 60 } else { 1 };
+        assert_eq!(cache.insert(i, i * 10, Duration::from_secs(ttl)), None);
+    }
+    assert_eq!(cache.insert(0, 0, Duration::from_secs(60)), Some(0));
+    assert_eq!(cache.len(), 100);
+    assert_eq!(cache.get_cloned(&3), Some(30));
+    assert_eq!(cache.get_with(&4, |v| v + 1), Some(41));
+
+    clock.advance(Duration::from_secs(2));
+    assert_eq!(cache.get_cloned(&3), None);
+    assert!(!cache.contains_key(&3));
+    assert!(cache.contains_key(&4));
+    cache.remove_expired();
+    assert_eq!(cache.len(), 50);
+    assert_eq!(cache.remove(&4), Some(40));
+    assert_eq!(cache.remove(&4), None);
+
+    let mut seen = Vec::new();
+    cache.for_each(|&k, &v| seen.push((k, v)));
+    seen.sort_unstable();
+    let expected: Vec<_> = (0..100).step_by(2).filter(|&k| k != 4).map(|k| (k, k * 10)).collect();
+    assert_eq!(seen, expected);
+
+    #[cfg(feature = "stats")]
+    {
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses), (3, 2));
+        assert_eq!((stats.inserts, stats.replaces), (101, 1));
+        assert_eq!((stats.expired, stats.len), (50, 49));
+        assert_eq!(stats.elapsed(), Duration::from_secs(2));
+    }
+
+    cache.clear();
+    assert!(cache.is_empty());
+}
+
+#[test]
+fn test_sharded_threads() {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::thread;
+    use ttl_cache::sharded::ShardedTtlCache;
+    use ttl_cache::test_util::MockClock;
+
+    const THREADS: u64 = 16;
+    const KEYS: u64 = 2_000;
+
+    let clock = MockClock::new();
+    let cache = Arc::new(ShardedTtlCache::with_shards_and_clock(8, clock.clone()));
+    let done = Arc::new(AtomicBool::new(false));
+
+    // One thread keeps time moving and purging while the others work
+    let ticker = {
+        let (cache, clock, done) = (cache.clone(), clock.clone(), done.clone());
+        thread::spawn(move || {
+            while !done.load(Ordering::Relaxed) {
+                clock.advance(Duration::from_millis(10));
+                cache.remove_expired();
+            }
+        })
+    };
+    let workers: Vec<_> = (0..THREADS)
+        .map(|t| {
+            let cache = cache.clone();
+            thread::spawn(move || {
+                for i in 0..KEYS {
+                    let own = t * KEYS + i;
+                    cache.insert(own, own, Duration::from_secs(24 * 60 * 60));
+                    cache.insert(u64::MAX - i, t, Duration::from_millis(1));
+                    assert_eq!(cache.get_cloned(&own), Some(own));
+                    let _ = cache.get_with(&(u64::MAX - i), |&v| assert!(v < THREADS));
+                    if i % 3 == 0 {
+                        assert_eq!(cache.remove(&own), Some(own));
+                    }
+                }
+            })
+        })
+        .collect();
+    for worker in workers {
+        worker.join().unwrap();
+    }
+    done.store(true, Ordering::Relaxed);
+    ticker.join().unwrap();
+
+    clock.advance(Duration::from_secs(1));
+    cache.remove_expired();
+    let kept = (0..KEYS).filter(|i| i % 3 != 0).count() as u64;
+    assert_eq!(cache.len() as u64, THREADS * kept);
+    for t in 0..THREADS {
+        for i in 0..KEYS {
+            let own = t * KEYS + i;
+            let expected = if i % 3 == 0 { None } else { Some(own) };
+            assert_eq!(cache.get_cloned(&own), expected);
+        }
+    }
+    #[cfg(feature = "stats")]
+    assert_eq!(cache.stats().inserts, 2 * THREADS * KEYS);
+}
+
+#[test]
+fn test_shared() {
+    use ttl_cache::sync::SharedTtlCache;
+    use ttl_cache::test_util::MockClock;
+
+    fn assert_shareable<T: Clone + Send + Sync>(_: &T) {}
+
+    let clock = MockClock::new();
+    let cache = SharedTtlCache::from(TtlCache::with_clock(clock.clone()));
+    assert_shareable(&cache);
+    let other = cache.clone();
+    assert_eq!(cache.insert("a", 1, Duration::from_secs(1)), None);
+    assert_eq!(cache.insert("b", 2, Duration::from_secs(60)), None);
+    assert_eq!(other.get_cloned("a"), Some(1));
+    assert_eq!(other.with("b", |v| v * 10), Some(20));
+
+    clock.advance(Duration::from_secs(2));
+    assert_eq!(other.get_cloned("a"), None);
+    assert!(!cache.contains_key("a"));
+    assert_eq!(cache.read(|cache| cache.iter().count()), 1);
+    cache.purge();
+    assert_eq!(cache.remove("b"), Some(2));
+    assert_eq!(other.remove("b"), None);
+    assert_eq!(other.read(|cache| cache.iter().count()), 0);
+}
+
+#[test]
+fn test_shared_threads() {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::thread;
+    use ttl_cache::sync::SharedTtlCache;
+
+    let cache = SharedTtlCache::new();
+    for i in 0..100u32 {
+        cache.insert(i, i, Duration::from_secs(60 * 60));
+    }
+    let done = Arc::new(AtomicBool::new(false));
+    let readers: Vec<_> = (0..8)
+        .map(|_| {
+            let (cache, done) = (cache.clone(), done.clone());
+            thread::spawn(move || {
+                let mut reads = 0;
+                loop {
+                    for i in 0..100 {
+                        // The writer only ever bumps values by a multiple of 100
+                        let value = cache.with(&i, |&v| v).unwrap();
+                        assert_eq!(value % 100, i);
+                        reads += 1;
+                    }
+                    if done.load(Ordering::Relaxed) {
+                        return reads;
+                    }
+                }
+            })
+        })
+        .collect();
+    let writer = {
+        let cache = cache.clone();
+        thread::spawn(move || {
+            for round in 1..=50 {
+                for i in 0..100 {
+                    cache.insert(i, i + round * 100, Duration::from_secs(60 * 60));
+                }
+                cache.purge();
+                thread::yield_now();
+            }
+        })
+    };
+    writer.join().unwrap();
+    done.store(true, Ordering::Relaxed);
+    for reader in readers {
+        assert!(reader.join().unwrap() > 0);
+    }
+    assert_eq!(cache.get_cloned(&7), Some(5007));
+}
+
+#[cfg(feature = "arc-swap")]
+#[test]
+fn test_snapshot() {
+    use ttl_cache::snapshot::SnapshotTtlCache;
+    use ttl_cache::test_util::MockClock;
+
+    fn assert_shareable<T: Clone + Send + Sync>(_: &T) {}
+
+    let clock = MockClock::new();
+    let cache = SnapshotTtlCache::from(TtlCache::with_clock(clock.clone()));
+    assert_shareable(&cache);
+    let other = cache.clone();
+    assert_eq!(cache.insert("a", 1, Duration::from_secs(1)), None);
+    assert_eq!(cache.insert("b", 2, Duration::from_secs(60)), None);
+    assert_eq!(other.get_cloned("a"), Some(1));
+    assert_eq!(other.with("b", |v| v * 10), Some(20));
+
+    // Expiry shows up without a publish, and the snapshot keeps the entry until the next one
+    let before = cache.snapshot();
+    clock.advance(Duration::from_secs(2));
+    assert_eq!(other.get_cloned("a"), None);
+    assert!(!cache.contains_key("a"));
+    #[cfg(feature = "stats")]
+    {
+        // Lookups on a snapshot itself aren't counted
+        assert_eq!(before.get("b"), Some(&2));
+        let stats = other.stats();
+        assert_eq!((stats.hits, stats.misses, stats.expired_misses), (2, 2, 2));
+        assert_eq!(stats.inserts, 2);
+    }
+    assert_eq!(before.iter().count(), 1);
+    cache.purge();
+    assert_eq!(cache.snapshot().iter().count(), 1);
+    assert_eq!(Arc::strong_count(&before), 1);
+
+    // A batch is published all at once
+    let removed = cache.write(|cache| {
+        cache.insert("c", 3, Duration::from_secs(60));
+        cache.remove("b")
+    });
+    assert_eq!(removed, Some(2));
+    assert_eq!(before.get("b"), Some(&2));
+    assert_eq!(other.get_cloned("b"), None);
+    assert_eq!(other.remove("c"), Some(3));
+    assert_eq!(cache.remove("c"), None);
+    assert_eq!(cache.snapshot().iter().count(), 0);
+
+    let arcs = SnapshotTtlCache::from(TtlCache::with_clock(clock.clone()));
+    arcs.insert_arc(1, String::from("arc"), Duration::from_secs(10));
+    let handle = arcs.get_arc(&1).unwrap();
+    clock.advance(Duration::from_secs(10));
+    arcs.purge();
+    assert_eq!(arcs.get_arc(&1), None);
+    assert_eq!(*handle, "arc");
+    assert_eq!(Arc::strong_count(&handle), 1);
+}
+
+#[cfg(feature = "arc-swap")]
+#[test]
+fn test_snapshot_threads() {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::thread;
+    use ttl_cache::snapshot::SnapshotTtlCache;
+    use ttl_cache::test_util::MockClock;
+
+    const KEYS: u32 = 100;
+    const EXPIRING: u32 = u32::MAX;
+
+    let clock = MockClock::new();
+    let cache = SnapshotTtlCache::from(TtlCache::with_clock(clock.clone()));
+    let done = Arc::new(AtomicBool::new(false));
+    let readers: Vec<_> = (0..8)
+        .map(|_| {
+            let (cache, done) = (cache.clone(), done.clone());
+            thread::spawn(move || {
+                let mut last = None;
+                let mut reads = 0;
+                while !done.load(Ordering::Relaxed) {
+                    // Every round is written as one batch, so a snapshot never mixes two
+                    let snapshot = cache.snapshot();
+                    let round = snapshot.get(&0).cloned();
+                    for i in 0..KEYS {
+                        assert_eq!(snapshot.get(&i).cloned(), round);
+                    }
+                    assert!(round >= last);
+                    last = round;
+                    let _ = cache.with(&(KEYS / 2), |&v| assert!(v >= round.unwrap_or(0)));
+                    reads += 1;
+                }
+                // The last entry expired after its publish, and the lookups see that anyway
+                assert_eq!(cache.get_cloned(&EXPIRING), None);
+                assert!(cache.snapshot().get(&EXPIRING).is_none());
+                assert_eq!(cache.get_cloned(&0), Some(200));
+                reads
+            })
+        })
+        .collect();
+    let writer = {
+        let (cache, clock) = (cache.clone(), clock.clone());
+        thread::spawn(move || {
+            for round in 1..=200 {
+                cache.write(|cache| {
+                    for i in 0..KEYS {
+                        cache.insert(i, round, Duration::from_secs(60 * 60));
+                    }
+                });
+                thread::yield_now();
+            }
+            cache.insert(EXPIRING, 0, Duration::from_secs(1));
+            clock.advance(Duration::from_secs(1));
+        })
+    };
+    writer.join().unwrap();
+    done.store(true, Ordering::Relaxed);
+    for reader in readers {
+        assert!(reader.join().unwrap() > 0);
+    }
+    assert_eq!(cache.snapshot().iter().count(), KEYS as usize);
+}
+
+#[test]
+fn test_expiry_receiver() {
+    use ttl_cache::test_util::MockClock;
+    use ttl_cache::RemovalCause::{Evicted, Expired, Removed, Replaced};
+
+    let clock = MockClock::new();
+    let mut cache = TtlCache::with_clock(clock.clone());
+    cache.set_purge_policy(ttl_cache::PurgePolicy::Manual);
+    let removals = cache.expiry_receiver();
+
+    // A batch expires and is purged
+    for i in 0..5 {
+        cache.insert(i, i, Duration::from_secs(10));
+    }
+    cache.insert(5, 5, Duration::from_secs(60));
+    clock.advance(Duration::from_secs(10));
+    cache.remove_expired();
+    let mut expired: Vec<_> = removals.try_iter().collect();
+    expired.sort_by_key(|&(k, _)| k);
+    assert_eq!(expired, [(0, Expired), (1, Expired), (2, Expired), (3, Expired), (4, Expired)]);
+
+    // Each way out reports its own cause
+    cache.insert(5, 50, Duration::from_secs(60));
+    cache.insert(6, 6, Duration::from_millis(500));
+    cache.insert(7, 7, Duration::from_secs(60));
+    clock.advance(Duration::from_secs(1));
+    assert_eq!(cache.remove(&7), Some(7));
+    assert_eq!(cache.get_mut(&6), None);
+    if let ttl_cache::Entry::Occupied(entry) = cache.entry(5) {
+        assert_eq!(entry.remove(), 50);
+    }
+    assert_eq!(
+        removals.try_iter().collect::<Vec<_>>(),
+        [(5, Replaced), (7, Removed), (6, Expired), (5, Removed)]
+    );
+
+    // An expired entry taken over through the entry API, evictions and clearing
+    cache.insert(8, 8, Duration::from_millis(500));
+    clock.advance(Duration::from_secs(1));
+    cache.entry(8).or_insert(80, Duration::from_secs(60));
+    cache.set_capacity(2);
+    cache.insert(9, 9, Duration::from_secs(60));
+    cache.insert(10, 10, Duration::from_secs(60));
+    cache.clear();
+    assert_eq!(
+        removals.try_iter().collect::<Vec<_>>(),
+        [(8, Expired), (8, Evicted), (9, Removed), (10, Removed)]
+    );
+
+    // In-place changes and clones aren't reported
+    cache.insert(11, 11, Duration::from_secs(60));
+    *cache.get_mut(&11).unwrap() += 1;
+    let mut clone = cache.clone();
+    clone.remove(&11);
+    assert_eq!(removals.try_recv(), Err(std::sync::mpsc::TryRecvError::Empty));
+
+    cache.clear_expiry_receiver();
+    assert_eq!(removals.try_recv(), Err(std::sync::mpsc::TryRecvError::Disconnected));
+    // Nothing is sent once the receiver is gone either
+    let removals = cache.expiry_receiver();
+    drop(removals);
+    cache.remove(&11);
+}
+
+#[test]
+fn test_expiry_receiver_bounded() {
+    use ttl_cache::test_util::MockClock;
+
+    let clock = MockClock::new();
+    let mut cache = TtlCache::with_clock(clock.clone());
+    cache.set_purge_policy(ttl_cache::PurgePolicy::Manual);
+    let removals = cache.expiry_receiver_bounded(3);
+    for i in 0..10 {
+        cache.insert(i, i, Duration::from_secs(10));
+    }
+    clock.advance(Duration::from_secs(10));
+    cache.remove_expired();
+    // The rest were dropped rather than blocking the purge
+    assert_eq!(removals.try_iter().count(), 3);
+    assert_eq!(cache.iter().count(), 0);
+
+    cache.insert(10, 10, Duration::from_secs(10));
+    cache.remove(&10);
+    assert_eq!(
+        removals.try_iter().collect::<Vec<_>>(),
+        [(10, ttl_cache::RemovalCause::Removed)]
+    );
+}
+
+#[test]
+fn test_builder() {
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::BuildHasher;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Mutex;
+    use ttl_cache::test_util::MockClock;
+    use ttl_cache::RemovalCause::{Evicted, Expired};
+    use ttl_cache::{Freshness, PurgePolicy, TtlCacheBuilder};
+
+    #[derive(Clone)]
+    struct CountingState(Arc<AtomicUsize>);
+
+    impl BuildHasher for CountingState {
+        type Hasher = DefaultHasher;
+
+        fn build_hasher(&self) -> DefaultHasher {
+            self.0.fetch_add(1, Ordering::Relaxed);
+            DefaultHasher::new()
+        }
+    }
+
+    let clock = MockClock::new();
+    let hashes = Arc::new(AtomicUsize::new(0));
+    let removals = Arc::new(Mutex::new(Vec::new()));
+    let sink = removals.clone();
+    let mut cache = TtlCacheBuilder::new()
+        .hasher(CountingState(hashes.clone()))
+        .clock(clock.clone())
+        .capacity(2)
+        .default_ttl(Duration::from_secs(30))
+        .max_ttl(Duration::from_secs(60))
+        .purge_policy(PurgePolicy::Manual)
+        .eviction_listener(move |k, cause| sink.lock().unwrap().push((k, cause)))
+        .build()
+        .unwrap();
+    assert_eq!(cache.capacity(), Some(2));
+    assert_eq!(cache.purge_policy(), PurgePolicy::Manual);
+    cache.insert_default(1, "a");
+    assert!(hashes.load(Ordering::Relaxed) > 0);
+    assert_eq!(cache.remaining_ttl(&1), Some(Duration::from_secs(30)));
+    cache.insert(2, "b", Duration::from_secs(60 * 60));
+    assert_eq!(cache.remaining_ttl(&2), Some(Duration::from_secs(60)));
+    cache.insert(3, "c", Duration::from_secs(10));
+    assert_eq!(cache.get(&1), None);
+    clock.advance(Duration::from_secs(10));
+    assert_eq!(cache.get(&3), None);
+    assert_eq!(*removals.lock().unwrap(), [(1, Evicted)]);
+    cache.remove_expired();
+    assert_eq!(*removals.lock().unwrap(), [(1, Evicted), (3, Expired)]);
+
+    let mut cache = TtlCache::builder()
+        .clock(clock.clone())
+        .time_to_idle(Duration::from_secs(10))
+        .build()
+        .unwrap();
+    cache.insert(1, "a", Duration::from_secs(60));
+    clock.advance(Duration::from_secs(10));
+    assert_eq!(cache.get(&1), None);
+
+    let mut cache = TtlCache::builder()
+        .clock(clock.clone())
+        .stale_grace(Duration::from_secs(10))
+        .build()
+        .unwrap();
+    cache.insert(1, "a", Duration::from_secs(5));
+    clock.advance(Duration::from_secs(5));
+    cache.remove_expired();
+    assert_eq!(cache.get_fresh_or_stale(&1), Some((&"a", Freshness::Stale)));
+
+    let mut jittered = TtlCache::builder()
+        .clock(clock.clone())
+        .ttl_jitter_with_seed(0.5, 7)
+        .build()
+        .unwrap();
+    let mut by_setter = TtlCache::with_clock(clock.clone());
+    by_setter.set_ttl_jitter_with_seed(0.5, 7);
+    assert_eq!(jittered.ttl_jitter(), Some(0.5));
+    for i in 0..10 {
+        jittered.insert(i, i, Duration::from_secs(60));
+        by_setter.insert(i, i, Duration::from_secs(60));
+        assert_eq!(jittered.remaining_ttl(&i), by_setter.remaining_ttl(&i));
+    }
+
+    // The wheel only purges an entry once the whole tick its deadline falls in has passed
+    let expired = Arc::new(AtomicUsize::new(0));
+    let counter = expired.clone();
+    let mut cache = TtlCache::builder()
+        .clock(clock.clone())
+        .timer_wheel(Duration::from_secs(10))
+        .eviction_listener(move |_, _| {
+            counter.fetch_add(1, Ordering::Relaxed);
+        })
+        .build()
+        .unwrap();
+    cache.insert(1, "a", Duration::from_secs(1));
+    clock.advance(Duration::from_secs(2));
+    cache.remove_expired();
+    assert_eq!(expired.load(Ordering::Relaxed), 0);
+    clock.advance(Duration::from_secs(10));
+    cache.remove_expired();
+    assert_eq!(expired.load(Ordering::Relaxed), 1);
+
+    let mut cache = TtlCache::builder()
+        .clock(clock.clone())
+        .expiry_policy(ValueTtl)
+        .build()
+        .unwrap();
+    cache.insert_default("a", 4);
+    assert_eq!(cache.remaining_ttl("a"), Some(Duration::from_secs(4)));
+
+    #[cfg(feature = "stats")]
+    {
+        let cache = TtlCache::<i32, i32>::builder().stats(false).build().unwrap();
+        assert!(!cache.stats_enabled());
+        assert!(TtlCache::<i32, i32>::builder().build().unwrap().stats_enabled());
+    }
+}
+
+#[test]
+fn test_builder_errors() {
+    use ttl_cache::{BuildError, PurgePolicy};
+
+    let builder = || TtlCache::<i32, i32>::builder();
+    assert_eq!(builder().ttl_jitter(1.5).build().err(), Some(BuildError::TtlJitter(1.5)));
+    assert_eq!(
+        builder().ttl_jitter_with_seed(-0.5, 1).build().err(),
+        Some(BuildError::TtlJitter(-0.5))
+    );
+    assert!(builder().ttl_jitter(1.0).build().is_ok());
+    assert_eq!(
+        builder().purge_policy(PurgePolicy::EveryNInserts(0)).build().err(),
+        Some(BuildError::PurgePeriod)
+    );
+    assert_eq!(
+        builder().timer_wheel(Duration::ZERO).build().err(),
+        Some(BuildError::WheelResolution)
+    );
+    assert_eq!(
+        BuildError::TtlJitter(2.0).to_string(),
+        "TTL jitter must be between 0.0 and 1.0, got 2"
+    );
+}
+
+#[test]
+#[should_panic(expected = "timer wheel resolution must be greater than zero")]
+fn test_timer_wheel_zero_resolution() {
+    let _: TtlCache<i32, i32> = TtlCache::with_timer_wheel(Duration::ZERO);
+}
+
+#[cfg(feature = "http")]
+#[test]
+fn test_ttl_from_cache_control() {
+    use ttl_cache::http::ttl_from_cache_control;
+
+    let secs = |secs| Some(Duration::from_secs(secs));
+    let cases = [
+        ("max-age=60", secs(60)),
+        ("public, max-age=60", secs(60)),
+        ("max-age=60, s-maxage=300", secs(300)),
+        ("s-maxage=300, max-age=60", secs(300)),
+        ("s-maxage=0, max-age=60", secs(0)),
+        ("max-age=0", secs(0)),
+        ("MAX-AGE=60", secs(60)),
+        ("Max-Age=60, S-MaxAge=5", secs(5)),
+        // Whitespace and empty directives
+        ("  max-age=60  ", secs(60)),
+        ("\tpublic ,\tmax-age=60\t", secs(60)),
+        ("max-age = 60", secs(60)),
+        (",,, max-age=60,,", secs(60)),
+        ("public,,max-age=60", secs(60)),
+        // Unknown directives and values
+        ("public, immutable, max-age=60, stale-while-revalidate=30", secs(60)),
+        ("foo=\"bar, max-age=5\", max-age=60", secs(60)),
+        ("private=\"set-cookie, x\", max-age=60", secs(60)),
+        ("x-ext=\"a \\\" b, max-age=1\", max-age=60", secs(60)),
+        ("must-revalidate", None),
+        ("public", None),
+        ("", None),
+        ("   ", None),
+        (",", None),
+        // Forbidding caching wins over any lifetime
+        ("no-store", None),
+        ("no-cache", None),
+        ("max-age=60, no-store", None),
+        ("NO-STORE, s-maxage=60", None),
+        ("no-cache=\"set-cookie\", max-age=60", None),
+        // Odd lifetimes
+        ("max-age=\"60\"", secs(60)),
+        ("max-age=\"60", secs(0)),
+        ("max-age=", secs(0)),
+        ("max-age", secs(0)),
+        ("max-age=-1", secs(0)),
+        ("max-age=+60", secs(0)),
+        ("max-age=6 0", secs(0)),
+        ("max-age=1.5", secs(0)),
+        ("max-age=abc, s-maxage=10", secs(10)),
+        ("max-age=007", secs(7)),
+        ("max-age=2147483647", secs(2_147_483_647)),
+        ("max-age=2147483649", secs(1 << 31)),
+        ("max-age=18446744073709551615", secs(1 << 31)),
+        ("max-age=99999999999999999999999999999999", secs(1 << 31)),
+        // The first of a repeated directive counts
+        ("max-age=60, max-age=5", secs(60)),
+        ("s-maxage=1, s-maxage=2, max-age=3", secs(1)),
+        ("max-age=ü, ünïcode=1, max-age=9", secs(0)),
+    ];
+    for &(header, expected) in cases.iter() {
+        assert_eq!(ttl_from_cache_control(header), expected, "{:?}", header);
+    }
+
+    // Inserting a response under each header, over one cached before it
+    let default = Duration::from_secs(60 * 60);
+    let mut cache = TtlCache::with_clock(ttl_cache::test_util::MockClock::new());
+    for &(header, expected) in cases.iter() {
+        cache.insert(header, 0, default);
+        let stored = cache.insert_with_cache_control(header, 1, header, default);
+        let forbidden = header.to_ascii_lowercase().contains("no-");
+        match expected.or(if forbidden { None } else { Some(default) }) {
+            Some(ttl) if ttl > Duration::ZERO => {
+                assert_eq!(stored, Ok(Some(0)), "{:?}", header);
+                assert_eq!(cache.remaining_ttl(header), Some(ttl), "{:?}", header);
+            }
+            _ => {
+                assert_eq!(stored, Err(1), "{:?}", header);
+                assert!(!cache.contains_key(header), "{:?}", header);
+            }
+        }
+    }
+}
+
+#[cfg(feature = "http")]
+#[test]
+fn test_ttl_from_expires() {
+    use std::time::SystemTime;
+    use ttl_cache::http::ttl_from_expires;
+
+    // Sun, 06 Nov 1994 08:49:37 GMT
+    let date = SystemTime::UNIX_EPOCH + Duration::from_secs(784_111_777);
+    let now = date - Duration::from_secs(60);
+    let cases = [
+        ("Sun, 06 Nov 1994 08:49:37 GMT", 60),
+        ("Sunday, 06-Nov-94 08:49:37 GMT", 60),
+        ("Sun Nov  6 08:49:37 1994", 60),
+        ("  Sun,   06 Nov 1994\t08:49:37 GMT ", 60),
+        ("Sun, 06 Nov 1994 08:50:37 GMT", 120),
+        ("Mon, 07 Nov 1994 08:49:37 GMT", 86_460),
+        ("Fri, 01 Mar 1996 00:00:00 GMT", 41_526_683),
+        ("Sat, 01 Jan 2000 00:00:00 GMT", 162_573_083),
+        ("Sun, 06 Nov 1994 08:48:37 GMT", 0),
+        ("Thu, 01 Jan 1970 00:00:00 GMT", 0),
+        // Invalid dates have already passed
+        ("0", 0),
+        ("-1", 0),
+        ("", 0),
+        ("yesterday", 0),
+        ("Sun, 06 Nov 1994 08:49:37 UTC", 0),
+        ("Sun, 06 Nov 1994 08:49:37", 0),
+        ("Sun, 06 Foo 1994 08:49:37 GMT", 0),
+        ("Sun, 32 Nov 1994 08:49:37 GMT", 0),
+        ("Sun, 06 Nov 1994 24:00:00 GMT", 0),
+        ("Sun, 06 Nov 1994 08:49 GMT", 0),
+        ("Sun, 06 Nov 1994 08:49:37:00 GMT", 0),
+        ("Sun, 06 Nov 99999999999999999999 08:49:37 GMT", 0),
+        ("Sunday, 06-Nov-1994 08:49:37 GMT", 0),
+        ("Sun 06 Nov 1994 08:49:37 GMT", 0),
+    ];
+    for &(header, secs) in cases.iter() {
+        assert_eq!(ttl_from_expires(header, now), Duration::from_secs(secs), "{:?}", header);
+    }
+    let future = ttl_from_expires("Fri, 31 Dec 9999 23:59:59 GMT", now);
+    assert!(future > Duration::from_secs(250_000_000_000));
+}
+
+#[cfg(feature = "http")]
+#[test]
+fn test_insert_with_cache_control() {
+    use ttl_cache::test_util::MockClock;
+
+    let clock = MockClock::new();
+    let mut cache = TtlCache::with_clock(clock.clone());
+    let default = Duration::from_secs(60);
+    assert_eq!(cache.insert_with_cache_control(1, "a", "max-age=10", default), Ok(None));
+    assert_eq!(cache.insert_with_cache_control(2, "b", "public", default), Ok(None));
+    assert_eq!(cache.insert_with_cache_control(3, "c", "no-store, max-age=10", default), Err("c"));
+    assert_eq!(cache.insert_with_cache_control(4, "d", "max-age=0", default), Err("d"));
+    assert_eq!(cache.remaining_ttl(&1), Some(Duration::from_secs(10)));
+    assert_eq!(cache.remaining_ttl(&2), Some(default));
+    assert!(!cache.contains_key(&3));
+    assert!(!cache.contains_key(&4));
+
+    assert_eq!(cache.insert_with_cache_control(1, "y", "s-maxage=5", default), Ok(Some("a")));
+    assert_eq!(cache.remaining_ttl(&1), Some(Duration::from_secs(5)));
+    clock.advance(Duration::from_secs(5));
+    assert_eq!(cache.get(&1), None);
+
+    // A response that may not be cached takes the cached one out
+    assert_eq!(cache.insert_with_cache_control(2, "x", "no-cache", default), Err("x"));
+    assert_eq!(cache.get(&2), None);
+}
+
+#[test]
+fn test_arc_values() {
+    use std::sync::Weak;
+    use ttl_cache::sharded::ShardedTtlCache;
+    use ttl_cache::sync::SharedTtlCache;
+    use ttl_cache::test_util::MockClock;
+
+    let clock = MockClock::new();
+    let mut cache = TtlCache::with_clock(clock.clone());
+    cache.set_purge_policy(ttl_cache::PurgePolicy::Manual);
+    assert_eq!(cache.insert_arc("a", vec![1, 2, 3], Duration::from_secs(10)), None);
+    let handle = cache.get_arc("a").unwrap();
+    assert_eq!(cache.get_arc("b"), None);
+    assert_eq!(Arc::strong_count(&handle), 2);
+
+    // The value outlives its entry while a handle is held
+    clock.advance(Duration::from_secs(10));
+    assert_eq!(cache.get_arc("a"), None);
+    cache.remove_expired();
+    assert_eq!(cache.iter().count(), 0);
+    assert_eq!(*handle, [1, 2, 3]);
+    let weak: Weak<Vec<i32>> = Arc::downgrade(&handle);
+    drop(handle);
+    assert!(weak.upgrade().is_none());
+
+    // Replacing hands back the old handle
+    cache.insert_arc("a", vec![4], Duration::from_secs(10));
+    let old = cache.insert_arc("a", vec![5], Duration::from_secs(10)).unwrap();
+    assert_eq!(*old, [4]);
+    assert_eq!(Arc::strong_count(&old), 1);
+
+    let shared = SharedTtlCache::from(TtlCache::with_clock(clock.clone()));
+    shared.insert_arc(1, String::from("shared"), Duration::from_secs(10));
+    let handle = shared.get_arc(&1).unwrap();
+    clock.advance(Duration::from_secs(10));
+    shared.purge();
+    assert_eq!(shared.get_arc(&1), None);
+    assert_eq!(*handle, "shared");
+    assert_eq!(Arc::strong_count(&handle), 1);
+
+    let sharded = ShardedTtlCache::with_shards_and_clock(4, clock.clone());
+    sharded.insert_arc(1, String::from("sharded"), Duration::from_secs(10));
+    let handle = sharded.get_arc(&1).unwrap();
+    clock.advance(Duration::from_secs(10));
+    sharded.remove_expired();
+    assert_eq!(sharded.len(), 0);
+    assert_eq!(*handle, "sharded");
+    assert_eq!(Arc::strong_count(&handle), 1);
+}
+
+#[test]
+fn test_insert_until() {
+    use ttl_cache::test_util::MockClock;
+    use ttl_cache::Clock;
+
+    let clock = MockClock::new();
+    let mut cache = TtlCache::with_clock(clock.clone());
+    cache.set_purge_policy(ttl_cache::PurgePolicy::Manual);
+    let now = clock.system_time_at(clock.now());
+    assert_eq!(cache.insert_until(1, "a", now + Duration::from_secs(30)), None);
+    cache.insert_until(2, "b", now - Duration::from_secs(30));
+    assert_eq!(cache.get(&1), Some(&"a"));
+    assert_eq!(cache.get(&2), None);
+    assert_eq!(cache.expiration_system_time(&2), None);
+
+    let expiration = cache.expiration_system_time(&1).unwrap();
+    assert!(expiration > now + Duration::from_secs(29));
+    assert!(expiration <= now + Duration::from_secs(30) + SLACK);
+    if let ttl_cache::Entry::Occupied(entry) = cache.entry(1) {
+        // The mock clock's wall time only moves with the clock
+        assert_eq!(entry.expiration_system_time(), Some(expiration));
+    }
+
+    clock.advance(Duration::from_secs(30));
+    assert_eq!(cache.get(&1), None);
+    assert_eq!(cache.insert_until(1, "c", clock.system_time_at(clock.now())), None);
+    assert_eq!(cache.get(&1), None);
+}
+
+#[test]
+fn test_insert_until_max_ttl() {
+    use std::time::SystemTime;
+
+    let mut cache = TtlCache::new();
+    cache.set_max_ttl(Duration::from_secs(10));
+    cache.insert_until(1, "a", SystemTime::now() + Duration::from_secs(60));
+    assert!(cache.remaining_ttl(&1).unwrap() <= Duration::from_secs(10));
+    cache.insert_until(2, "b", SystemTime::now() + Duration::from_secs(1 << 40));
+    assert!(cache.remaining_ttl(&2).unwrap() <= Duration::from_secs(10));
+}
+
+#[test]
+fn test_system_time_clock() {
+    use std::time::SystemTime;
+    use ttl_cache::{Clock, SystemTimeClock};
+
+    let clock = SystemTimeClock::new();
+    let mut cache = TtlCache::with_clock(clock.clone());
+    let deadline = SystemTime::now() + Duration::from_millis(50);
+    cache.insert_until(1, "a", deadline);
+    cache.insert(2, "b", Duration::from_secs(60));
+
+    let expiration = cache.expiration_system_time(&1).unwrap();
+    assert!(deadline <= expiration && expiration <= deadline + SLACK);
+    // Instants of the clock stand for fixed wall-clock times
+    let instant = clock.now();
+    assert_eq!(clock.system_time_at(instant), clock.system_time_at(instant));
+    assert_eq!(
+        clock.system_time_at(instant + Duration::from_secs(5)),
+        clock.system_time_at(instant) + Duration::from_secs(5)
+    );
+
+    assert_eq!(cache.get(&1), Some(&"a"));
+    sleep(Duration::from_millis(60));
+    assert!(SystemTime::now() >= deadline);
+    assert_eq!(cache.get(&1), None);
+    assert_eq!(cache.get(&2), Some(&"b"));
+    // A clone reads the same clock
+    let read = clock.now();
+    assert!(clock.clone().now() >= read);
+}
+
+#[cfg(feature = "tokio")]
+#[test]
+fn test_tokio_clock() {
+    use tokio::runtime::Builder;
+    use ttl_cache::TokioClock;
+
+    let runtime = Builder::new_current_thread()
+        .enable_time()
+        .start_paused(true)
+        .build()
+        .unwrap();
+    let _guard = runtime.enter();
+    let started = Instant::now();
+    let mut cache = TtlCache::with_clock(TokioClock);
+    cache.insert(1, "a", Duration::from_secs(5));
+    cache.insert(2, "b", Duration::from_secs(60));
+
+    runtime.block_on(tokio::time::advance(Duration::from_secs(4)));
+    assert_eq!(cache.get(&1), Some(&"a"));
+    // Paused time also skips ahead on its own while the runtime waits on a timer
+    runtime.block_on(tokio::time::sleep(Duration::from_secs(2)));
+    assert_eq!(cache.get(&1), None);
+    assert_eq!(cache.remaining_ttl(&2), Some(Duration::from_secs(54)));
+    assert!(started.elapsed() < Duration::from_secs(1));
+}
+
+/// A loader that counts the times it's started, then sleeps and returns its value, or panics
+/// if it has none
+#[cfg(feature = "tokio")]
+struct Loader {
+    calls: Arc<std::sync::atomic::AtomicUsize>,
+    delay: Duration,
+    sleep: Option<std::pin::Pin<Box<tokio::time::Sleep>>>,
+    value: Option<u32>,
+}
+
+#[cfg(feature = "tokio")]
+impl Loader {
+    fn new(calls: &Arc<std::sync::atomic::AtomicUsize>, secs: u64, value: Option<u32>) -> Self {
+        Loader {
+            calls: calls.clone(),
+            delay: Duration::from_secs(secs),
+            sleep: None,
+            value,
+        }
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl std::future::Future for Loader {
+    type Output = u32;
+
+    fn poll(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<u32> {
+        use std::sync::atomic::Ordering;
+
+        if self.sleep.is_none() {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            self.sleep = Some(Box::pin(tokio::time::sleep(self.delay)));
+        }
+        match self.sleep.as_mut().unwrap().as_mut().poll(cx) {
+            std::task::Poll::Ready(()) => {
+                std::task::Poll::Ready(self.value.expect("loader failed"))
+            }
+            std::task::Poll::Pending => std::task::Poll::Pending,
+        }
+    }
+}
+
+#[cfg(feature = "tokio")]
+#[test]
+fn test_loading_coalesces() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tokio::runtime::Builder;
+    use ttl_cache::loading::LoadingTtlCache;
+    use ttl_cache::TokioClock;
+
+    let runtime = Builder::new_current_thread()
+        .enable_time()
+        .start_paused(true)
+        .build()
+        .unwrap();
+    let _guard = runtime.enter();
+    let started = tokio::time::Instant::now();
+    let cache = LoadingTtlCache::from(TtlCache::with_clock(TokioClock));
+    let calls = Arc::new(AtomicUsize::new(0));
+    let ttl = Duration::from_secs(60);
+
+    // The first task polled leads, the others get its value
+    let tasks: Vec<_> = (0..10)
+        .map(|i| runtime.spawn(cache.get_or_insert_with(1, ttl, Loader::new(&calls, 10, Some(i)))))
+        .collect();
+    for task in tasks {
+        assert_eq!(runtime.block_on(task).unwrap(), 0);
+    }
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+    assert_eq!(started.elapsed(), Duration::from_secs(10));
+    assert_eq!(cache.cache().get_cloned(&1), Some(0));
+
+    // Other keys load on their own
+    let other = runtime.spawn(cache.get_or_insert_with(2, ttl, Loader::new(&calls, 5, Some(20))));
+    assert_eq!(runtime.block_on(other).unwrap(), 20);
+    assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+    // Cached values are returned without loading
+    let hit = cache.get_or_insert_with(1, ttl, Loader::new(&calls, 5, Some(5)));
+    assert_eq!(runtime.block_on(hit), 0);
+    assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+    // Once the value expires the next caller loads it again
+    runtime.block_on(tokio::time::sleep(ttl));
+    let tasks: Vec<_> = (0..3)
+        .map(|i| Loader::new(&calls, 1, Some(i + 100)))
+        .map(|load| runtime.spawn(cache.get_or_insert_with(1, ttl, load)))
+        .collect();
+    for task in tasks {
+        assert_eq!(runtime.block_on(task).unwrap(), 100);
+    }
+    assert_eq!(calls.load(Ordering::SeqCst), 3);
+}
+
+#[cfg(feature = "tokio")]
+#[test]
+fn test_loading_cancelled_leader() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tokio::runtime::Builder;
+    use ttl_cache::loading::LoadingTtlCache;
+    use ttl_cache::TokioClock;
+
+    let runtime = Builder::new_current_thread()
+        .enable_time()
+        .start_paused(true)
+        .build()
+        .unwrap();
+    let _guard = runtime.enter();
+    let started = tokio::time::Instant::now();
+    let cache = LoadingTtlCache::from(TtlCache::with_clock(TokioClock));
+    let calls = Arc::new(AtomicUsize::new(0));
+    let ttl = Duration::from_secs(60);
+
+    let leader = runtime.spawn(cache.get_or_insert_with(1, ttl, Loader::new(&calls, 10, Some(1))));
+    let waiters: Vec<_> = (2..4)
+        .map(|i| runtime.spawn(cache.get_or_insert_with(1, ttl, Loader::new(&calls, 10, Some(i)))))
+        .collect();
+    runtime.block_on(tokio::time::sleep(Duration::from_secs(5)));
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+    // The first waiter takes over with its own loader and the other one waits on it
+    leader.abort();
+    assert!(runtime.block_on(leader).unwrap_err().is_cancelled());
+    for waiter in waiters {
+        assert_eq!(runtime.block_on(waiter).unwrap(), 2);
+    }
+    assert_eq!(calls.load(Ordering::SeqCst), 2);
+    assert_eq!(started.elapsed(), Duration::from_secs(15));
+    assert_eq!(cache.cache().get_cloned(&1), Some(2));
+
+    // A leader dropped before it's ever polled leaves nothing behind either
+    let abandoned = cache.get_or_insert_with(3, ttl, Loader::new(&calls, 1, Some(30)));
+    drop(abandoned);
+    let load = cache.get_or_insert_with(3, ttl, Loader::new(&calls, 1, Some(31)));
+    assert_eq!(runtime.block_on(load), 31);
+    assert_eq!(calls.load(Ordering::SeqCst), 3);
+}
+
+#[cfg(feature = "tokio")]
+#[test]
+fn test_loading_panicked_leader() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tokio::runtime::Builder;
+    use ttl_cache::loading::LoadingTtlCache;
+    use ttl_cache::TokioClock;
+
+    let runtime = Builder::new_current_thread()
+        .enable_time()
+        .start_paused(true)
+        .build()
+        .unwrap();
+    let _guard = runtime.enter();
+    let cache = LoadingTtlCache::from(TtlCache::with_clock(TokioClock));
+    let calls = Arc::new(AtomicUsize::new(0));
+    let ttl = Duration::from_secs(60);
+
+    let leader = runtime.spawn(cache.get_or_insert_with(1, ttl, Loader::new(&calls, 10, None)));
+    let waiter = runtime.spawn(cache.get_or_insert_with(1, ttl, Loader::new(&calls, 10, Some(2))));
+    assert!(runtime.block_on(leader).unwrap_err().is_panic());
+    assert_eq!(runtime.block_on(waiter).unwrap(), 2);
+    assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+    // A later panic doesn't stop the key from loading afterwards
+    let failed = runtime.spawn(cache.get_or_insert_with(5, ttl, Loader::new(&calls, 1, None)));
+    assert!(runtime.block_on(failed).unwrap_err().is_panic());
+    let load = cache.get_or_insert_with(5, ttl, Loader::new(&calls, 1, Some(50)));
+    assert_eq!(runtime.block_on(load), 50);
+    assert_eq!(calls.load(Ordering::SeqCst), 4);
+}
+
+#[cfg(feature = "tokio")]
+#[test]
+fn test_purger() {
+    use tokio::runtime::Builder;
+    use tokio::sync::Mutex;
+    use ttl_cache::{spawn_purger, TokioClock};
+
+    let runtime = Builder::new_current_thread()
+        .enable_time()
+        .start_paused(true)
+        .build()
+        .unwrap();
+    let _guard = runtime.enter();
+    let mut cache = TtlCache::with_clock(TokioClock);
+    cache.set_purge_policy(ttl_cache::PurgePolicy::Manual);
+    let value = Arc::new(());
+    // More than the purger removes under one lock
+    for i in 0..1000 {
+        cache.insert(i, value.clone(), Duration::from_secs(30));
+    }
+    cache.insert(1000, value.clone(), Duration::from_secs(90));
+    let cache = Arc::new(Mutex::new(cache));
+    let _purger = spawn_purger(cache.clone(), Duration::from_secs(20));
+
+    // The first tick comes before anything has expired
+    runtime.block_on(tokio::time::sleep(Duration::from_secs(25)));
+    assert_eq!(Arc::strong_count(&value), 1002);
+    runtime.block_on(tokio::time::sleep(Duration::from_secs(20)));
+    assert_eq!(Arc::strong_count(&value), 2);
+    assert_eq!(cache.try_lock().unwrap().iter().count(), 1);
+    runtime.block_on(tokio::time::sleep(Duration::from_secs(60)));
+    assert_eq!(Arc::strong_count(&value), 1);
+}
+
+#[cfg(feature = "tokio")]
+#[test]
+fn test_purger_shutdown() {
+    use tokio::runtime::Builder;
+    use tokio::sync::Mutex;
+    use ttl_cache::spawn_purger;
+
+    let runtime = Builder::new_current_thread()
+        .enable_time()
+        .start_paused(true)
+        .build()
+        .unwrap();
+    let _guard = runtime.enter();
+    let cache = Arc::new(Mutex::new(TtlCache::<u32, u32>::new()));
+    let purger = spawn_purger(cache.clone(), Duration::from_secs(60));
+    runtime.block_on(purger.shutdown());
+    // The task let go of its clone
+    assert_eq!(Arc::strong_count(&cache), 1);
+
+    // Dropping the handle stops the task the next time the runtime gets to it
+    let purger = spawn_purger(cache.clone(), Duration::from_secs(60));
+    drop(purger);
+    runtime.block_on(tokio::task::yield_now());
+    assert_eq!(Arc::strong_count(&cache), 1);
+}
+
+/// Resolves to the stream's next item, like `StreamExt::next` from the futures crate
+#[cfg(feature = "tokio")]
+struct Next<'a, S: 'a>(&'a mut S);
+
+#[cfg(feature = "tokio")]
+impl<'a, S: futures_core::Stream + Unpin> std::future::Future for Next<'a, S> {
+    type Output = Option<S::Item>;
+
+    fn poll(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Self::Output> {
+        std::pin::Pin::new(&mut *self.0).poll_next(cx)
+    }
+}
+
+/// Resolves to every item of the stream once it ends, each with when it came
+#[cfg(feature = "tokio")]
+struct Collect<S: futures_core::Stream> {
+    stream: S,
+    items: Vec<(S::Item, tokio::time::Instant)>,
+}
+
+#[cfg(feature = "tokio")]
+impl<S: futures_core::Stream + Unpin> std::future::Future for Collect<S>
+where
+    S::Item: Unpin,
+{
+    type Output = Vec<(S::Item, tokio::time::Instant)>;
+
+    fn poll(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Self::Output> {
+        use std::task::Poll;
+
+        loop {
+            match std::pin::Pin::new(&mut self.stream).poll_next(cx) {
+                Poll::Ready(Some(item)) => self.items.push((item, tokio::time::Instant::now())),
+                Poll::Ready(None) => return Poll::Ready(std::mem::take(&mut self.items)),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+#[cfg(feature = "tokio")]
+#[test]
+fn test_expired_stream() {
+    use std::sync::atomic::AtomicUsize;
+    use tokio::runtime::Builder;
+    use tokio::time::Instant;
+    use ttl_cache::loading::LoadingTtlCache;
+    use ttl_cache::TokioClock;
+
+    let runtime = Builder::new_current_thread()
+        .enable_time()
+        .start_paused(true)
+        .build()
+        .unwrap();
+    let _guard = runtime.enter();
+    let started = Instant::now();
+    let cache = LoadingTtlCache::from(TtlCache::with_clock(TokioClock));
+    let mut expired = cache.expired_stream();
+
+    cache.cache().insert(1, 10, Duration::from_secs(30));
+    cache.cache().insert(2, 20, Duration::from_secs(10));
+    cache.cache().write(|cache| cache.insert_permanent(3, 30));
+    cache.cache().insert(4, 40, Duration::from_secs(20));
+    // Moving a deadline later leaves its old pair behind in the index
+    cache.cache().insert(2, 21, Duration::from_secs(25));
+
+    assert_eq!(runtime.block_on(Next(&mut expired)), Some((4, 40)));
+    assert_eq!(started.elapsed(), Duration::from_secs(20));
+    assert_eq!(runtime.block_on(Next(&mut expired)), Some((2, 21)));
+    assert_eq!(started.elapsed(), Duration::from_secs(25));
+    assert_eq!(runtime.block_on(Next(&mut expired)), Some((1, 10)));
+    assert_eq!(started.elapsed(), Duration::from_secs(30));
+    // The entries are gone from the cache
+    assert_eq!(cache.cache().read(|cache| cache.iter().count()), 1);
+
+    // Entries expiring together come out in deadline order in one go
+    for i in (5..10).rev() {
+        cache.cache().insert(i, i * 10, Duration::from_millis(100 * u64::from(i)));
+    }
+    runtime.block_on(tokio::time::sleep(Duration::from_secs(5)));
+    let batch: Vec<_> = (5..10).map(|_| runtime.block_on(Next(&mut expired)).unwrap()).collect();
+    assert_eq!(batch, [(5, 50), (6, 60), (7, 70), (8, 80), (9, 90)]);
+
+    // An entry due sooner than the one the stream is waiting on wakes it up early
+    let started = Instant::now();
+    cache.cache().insert(10, 100, Duration::from_secs(100));
+    let calls = Arc::new(AtomicUsize::new(0));
+    let load = Loader::new(&calls, 2, Some(110));
+    let loaded = runtime.spawn(cache.get_or_insert_with(11, Duration::from_secs(1), load));
+    assert_eq!(runtime.block_on(Next(&mut expired)), Some((11, 110)));
+    assert_eq!(started.elapsed(), Duration::from_secs(3));
+    assert_eq!(runtime.block_on(loaded).unwrap(), 110);
+
+    // So does one inserted while nothing at all was due
+    cache.cache().remove(&10);
+    let started = Instant::now();
+    let load = Loader::new(&calls, 2, Some(120));
+    runtime.spawn(cache.get_or_insert_with(12, Duration::from_secs(1), load));
+    assert_eq!(runtime.block_on(Next(&mut expired)), Some((12, 120)));
+    assert_eq!(started.elapsed(), Duration::from_secs(3));
+}
+
+#[cfg(feature = "tokio")]
+#[test]
+fn test_expired_stream_consumers() {
+    use tokio::runtime::Builder;
+    use tokio::time::Instant;
+    use ttl_cache::loading::LoadingTtlCache;
+    use ttl_cache::TokioClock;
+
+    let runtime = Builder::new_current_thread()
+        .enable_time()
+        .start_paused(true)
+        .build()
+        .unwrap();
+    let _guard = runtime.enter();
+    let started = Instant::now();
+    let cache = LoadingTtlCache::from(TtlCache::with_clock(TokioClock));
+    for i in 0..20 {
+        cache.cache().insert(i, i, Duration::from_secs(1 + i / 2));
+    }
+    cache.cache().insert(100, 100, Duration::from_secs(60));
+    let first = runtime.spawn(Collect {
+        stream: cache.expired_stream(),
+        items: Vec::new(),
+    });
+    let second = runtime.spawn(Collect {
+        stream: cache.expired_stream(),
+        items: Vec::new(),
+    });
+
+    // Each entry goes to one stream, and both end once the cache is dropped
+    runtime.block_on(tokio::time::sleep(Duration::from_secs(30)));
+    drop(cache);
+    let first = runtime.block_on(first).unwrap();
+    let second = runtime.block_on(second).unwrap();
+    assert_eq!(started.elapsed(), Duration::from_secs(30));
+    for items in [&first, &second] {
+        for pair in items.windows(2) {
+            assert!(pair[0].1 <= pair[1].1);
+        }
+        for &((k, v), at) in items {
+            assert_eq!(k, v);
+            assert_eq!(at - started, Duration::from_secs(1 + k / 2));
+        }
+    }
+    let mut keys: Vec<_> = first.iter().chain(&second).map(|&((k, _), _)| k).collect();
+    keys.sort_unstable();
+    assert_eq!(keys, (0..20).collect::<Vec<_>>());
+}
+
+/// A service that counts its requests and answers a `(key, ttl in seconds)` request a second
+/// later with `(request number, ttl in seconds)`, or fails if the key is zero
+#[cfg(feature = "tower")]
+#[derive(Clone)]
+struct Backend {
+    calls: Arc<std::sync::atomic::AtomicUsize>,
+}
+
+#[cfg(feature = "tower")]
+impl tower_service::Service<(u32, u64)> for Backend {
+    type Response = (usize, u64);
+    type Error = &'static str;
+    type Future = BackendCall;
+
+    fn poll_ready(
+        &mut self,
+        _: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Result<(), &'static str>> {
+        std::task::Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, (key, secs): (u32, u64)) -> BackendCall {
+        let n = self.calls.fetch_add(1, std::sync::atomic::Ordering::SeqCst) + 1;
+        BackendCall {
+            sleep: Box::pin(tokio::time::sleep(Duration::from_secs(1))),
+            result: Some(if key == 0 { Err("unavailable") } else { Ok((n, secs)) }),
+        }
+    }
+}
+
+#[cfg(feature = "tower")]
+struct BackendCall {
+    sleep: std::pin::Pin<Box<tokio::time::Sleep>>,
+    result: Option<Result<(usize, u64), &'static str>>,
+}
+
+#[cfg(feature = "tower")]
+impl std::future::Future for BackendCall {
+    type Output = Result<(usize, u64), &'static str>;
+
+    fn poll(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Self::Output> {
+        match self.sleep.as_mut().poll(cx) {
+            std::task::Poll::Ready(()) => std::task::Poll::Ready(self.result.take().unwrap()),
+            std::task::Poll::Pending => std::task::Poll::Pending,
+        }
+    }
+}
+
+/// Waits for `service` to be ready and sends it `req`
+#[cfg(feature = "tower")]
+fn send<S, Req>(runtime: &tokio::runtime::Runtime, service: &mut S, req: Req) -> S::Future
+where
+    S: tower_service::Service<Req>,
+    S::Error: std::fmt::Debug,
+{
+    runtime
+        .block_on(std::future::poll_fn(|cx| service.poll_ready(cx)))
+        .unwrap();
+    service.call(req)
+}
+
+/// Keeps a `Backend` response for as many seconds as it says, or not at all for zero
+#[cfg(feature = "tower")]
+fn backend_ttl(&(_, secs): &(usize, u64)) -> Option<Duration> {
+    match secs {
+        0 => None,
+        secs => Some(Duration::from_secs(secs)),
+    }
+}
+
+#[cfg(feature = "tower")]
+#[test]
+fn test_tower_cache() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tokio::runtime::Builder;
+    use tower_layer::Layer;
+    use ttl_cache::test_util::MockClock;
+    use ttl_cache::tower::CacheLayer;
+
+    let runtime = Builder::new_current_thread()
+        .enable_time()
+        .start_paused(true)
+        .build()
+        .unwrap();
+    let _guard = runtime.enter();
+    let clock = MockClock::new();
+    let calls = Arc::new(AtomicUsize::new(0));
+    let backend = Backend {
+        calls: calls.clone(),
+    };
+    let layer = CacheLayer::with_cache(
+        TtlCache::with_clock(clock.clone()),
+        |&(key, _): &(u32, u64)| key,
+        backend_ttl,
+    );
+    let mut service = layer.layer(backend.clone());
+
+    // Identical requests in flight together share one call
+    let tasks: Vec<_> = (0..5)
+        .map(|_| runtime.spawn(send(&runtime, &mut service, (1, 60))))
+        .collect();
+    for task in tasks {
+        assert_eq!(runtime.block_on(task).unwrap(), Ok((1, 60)));
+    }
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+    // The response is served from the cache until it expires, also to other services
+    let response = send(&runtime, &mut service, (1, 60));
+    assert_eq!(runtime.block_on(response), Ok((1, 60)));
+    let mut other = layer.layer(backend.clone());
+    assert_eq!(runtime.block_on(send(&runtime, &mut other, (1, 60))), Ok((1, 60)));
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+    clock.advance(Duration::from_secs(60));
+    assert_eq!(runtime.block_on(send(&runtime, &mut service, (1, 60))), Ok((2, 60)));
+    assert_eq!(calls.load(Ordering::SeqCst), 2);
+    assert_eq!(layer.cache().get_cloned(&1), Some((2, 60)));
+
+    // Responses the TTL function turns down are shared while in flight but not cached
+    assert_eq!(runtime.block_on(send(&runtime, &mut service, (2, 0))), Ok((3, 0)));
+    let tasks: Vec<_> = (0..3)
+        .map(|_| runtime.spawn(send(&runtime, &mut service, (2, 0))))
+        .collect();
+    for task in tasks {
+        assert_eq!(runtime.block_on(task).unwrap(), Ok((4, 0)));
+    }
+    assert!(!layer.cache().contains_key(&2));
+
+    // A failed call hands over to the next waiter, and errors are never cached
+    let tasks: Vec<_> = (0..3)
+        .map(|_| runtime.spawn(send(&runtime, &mut service, (0, 60))))
+        .collect();
+    for task in tasks {
+        assert_eq!(runtime.block_on(task).unwrap(), Err("unavailable"));
+    }
+    assert_eq!(calls.load(Ordering::SeqCst), 7);
+    assert!(!layer.cache().contains_key(&0));
+}
+
+#[cfg(feature = "tower")]
+#[test]
+fn test_tower_pass_through() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tokio::runtime::Builder;
+    use tower_layer::Layer;
+    use ttl_cache::tower::{CacheLayer, InFlight};
+
+    let runtime = Builder::new_current_thread()
+        .enable_time()
+        .start_paused(true)
+        .build()
+        .unwrap();
+    let _guard = runtime.enter();
+    let calls = Arc::new(AtomicUsize::new(0));
+    let layer = CacheLayer::new(|&(key, _): &(u32, u64)| key, backend_ttl)
+        .in_flight(InFlight::PassThrough);
+    let mut service = layer.layer(Backend {
+        calls: calls.clone(),
+    });
+
+    // Every request in flight makes its own call, and the last response is kept
+    let tasks: Vec<_> = (0..3)
+        .map(|_| runtime.spawn(send(&runtime, &mut service, (1, 60))))
+        .collect();
+    let mut responses: Vec<_> = tasks
+        .into_iter()
+        .map(|task| runtime.block_on(task).unwrap().unwrap())
+        .collect();
+    responses.sort_unstable();
+    assert_eq!(responses, [(1, 60), (2, 60), (3, 60)]);
+    assert_eq!(calls.load(Ordering::SeqCst), 3);
+
+    let cached = runtime.block_on(send(&runtime, &mut service, (1, 60))).unwrap();
+    assert!(responses.contains(&cached));
+    assert_eq!(calls.load(Ordering::SeqCst), 3);
+}
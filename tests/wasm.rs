@@ -0,0 +1,33 @@
+//! Run with `wasm-pack test --node -- --features wasm`.
+
+#![cfg(all(target_arch = "wasm32", feature = "wasm"))]
+
+extern crate ttl_cache;
+extern crate wasm_bindgen_test;
+
+use std::time::Duration;
+
+use ttl_cache::test_util::MockClock;
+use ttl_cache::TtlCache;
+use wasm_bindgen_test::wasm_bindgen_test;
+
+#[wasm_bindgen_test]
+fn test_insert_and_expire() {
+    let mut cache = TtlCache::new();
+    cache.insert(1, "a", Duration::from_secs(60));
+    cache.insert(2, "b", Duration::ZERO);
+    assert_eq!(cache.get(&1), Some(&"a"));
+    assert_eq!(cache.get(&2), None);
+    assert!(cache.remaining_ttl(&1).unwrap() <= Duration::from_secs(60));
+}
+
+#[wasm_bindgen_test]
+fn test_mock_clock() {
+    let clock = MockClock::new();
+    let mut cache = TtlCache::with_clock(clock.clone());
+    cache.insert(1, "a", Duration::from_secs(5));
+    clock.advance(Duration::from_secs(4));
+    assert_eq!(cache.get(&1), Some(&"a"));
+    clock.advance(Duration::from_secs(1));
+    assert_eq!(cache.get(&1), None);
+}